@@ -0,0 +1,473 @@
+use std::{
+    fs::File,
+    io::{self, BufRead, BufReader, Write},
+    path::PathBuf,
+};
+
+use clap::{Args as ClapArgs, Subcommand};
+use ethers::types::Address;
+use serde::Serialize;
+use thiserror::Error;
+
+use crate::{
+    audit::{Action, AuditEvent},
+    redis::Redis,
+    results::{GovernanceRules, VoteResults},
+    snapshot::{Snapshot, SnapshotError, VoteSnapshot, VoterSnapshot},
+    storage::Network,
+    store::{self, StoreError, VoteStatus, VoteStore},
+    Args,
+};
+
+#[derive(Debug, Error)]
+pub enum AdminError {
+    #[error(transparent)]
+    Store(#[from] StoreError),
+    #[error(transparent)]
+    Snapshot(#[from] SnapshotError),
+    #[error(transparent)]
+    Io(#[from] io::Error),
+    #[error(transparent)]
+    Json(#[from] serde_json::Error),
+    #[error("Network {0} is not configured")]
+    UnknownNetwork(String),
+    #[error("No vote on FIP {0} was started")]
+    UnknownVote(u32),
+    #[error("Aborted, nothing was changed")]
+    Aborted,
+}
+
+#[derive(Subcommand, Clone, Debug, PartialEq)]
+pub enum Command {
+    /// Serve the API, what happens without a subcommand
+    Serve,
+    /// Manage the addresses allowed to start votes
+    #[command(subcommand)]
+    Starters(StarterCommand),
+    /// Inspect or delete the vote on a FIP
+    #[command(subcommand)]
+    Vote(VoteCommand),
+    /// Inspect registered voters
+    #[command(subcommand)]
+    Voters(VoterCommand),
+    /// Operate on the whole database
+    #[command(subcommand)]
+    Db(DbCommand),
+}
+
+#[derive(ClapArgs, Clone, Debug, PartialEq)]
+pub struct NetworkArg {
+    /// Name of a configured network
+    #[arg(short, long, default_value = "mainnet")]
+    pub network: String,
+}
+
+#[derive(ClapArgs, Clone, Debug, PartialEq)]
+pub struct YesArg {
+    /// Skip the confirmation prompt
+    #[arg(short, long)]
+    pub yes: bool,
+}
+
+#[derive(Subcommand, Clone, Debug, PartialEq)]
+pub enum StarterCommand {
+    /// Allow an address to start votes
+    Add {
+        address: Address,
+        #[command(flatten)]
+        network: NetworkArg,
+    },
+    /// Stop an address from starting votes
+    Remove {
+        address: Address,
+        #[command(flatten)]
+        network: NetworkArg,
+        #[command(flatten)]
+        yes: YesArg,
+    },
+    /// Print the addresses allowed to start votes
+    List {
+        #[command(flatten)]
+        network: NetworkArg,
+    },
+}
+
+#[derive(Subcommand, Clone, Debug, PartialEq)]
+pub enum VoteCommand {
+    /// Delete the vote with its ballots and tally, so it can be started again
+    Flush {
+        fip: u32,
+        #[command(flatten)]
+        network: NetworkArg,
+        #[command(flatten)]
+        yes: YesArg,
+    },
+    /// Print the vote, its ballots and its results as JSON
+    Inspect {
+        fip: u32,
+        #[command(flatten)]
+        network: NetworkArg,
+    },
+}
+
+#[derive(Subcommand, Clone, Debug, PartialEq)]
+pub enum VoterCommand {
+    /// Print the addresses with a registration on the network
+    List {
+        #[command(flatten)]
+        network: NetworkArg,
+    },
+    /// Print the registrations of a voter as JSON
+    Show {
+        address: Address,
+        #[command(flatten)]
+        network: NetworkArg,
+    },
+}
+
+#[derive(Subcommand, Clone, Debug, PartialEq)]
+pub enum DbCommand {
+    /// Delete everything the service stored
+    Flush {
+        #[command(flatten)]
+        yes: YesArg,
+    },
+    /// Move Redis keys written by older versions to the current key schema
+    Migrate,
+    /// Write every network and the audit log to a JSON snapshot
+    Export { file: PathBuf },
+    /// Load a JSON snapshot into an empty database
+    Import { file: PathBuf },
+}
+
+/// What `vote inspect` prints
+#[derive(Serialize)]
+struct Inspection {
+    #[serde(flatten)]
+    vote: VoteSnapshot,
+    /// Seconds until the vote concludes, missing once it has
+    time_left: Option<u64>,
+    results: VoteResults,
+}
+
+impl NetworkArg {
+    fn network(&self) -> Result<Network, AdminError> {
+        Network::from_name(&self.network)
+            .ok_or_else(|| AdminError::UnknownNetwork(self.network.clone()))
+    }
+}
+
+impl YesArg {
+    fn confirm(&self, action: &str) -> Result<(), AdminError> {
+        if self.yes {
+            return Ok(());
+        }
+        confirm(action, &mut io::stdin().lock(), &mut io::stdout())
+    }
+}
+
+/// Asks before a destructive action, anything but `yes` aborts
+fn confirm(
+    action: &str,
+    input: &mut impl BufRead,
+    output: &mut impl Write,
+) -> Result<(), AdminError> {
+    write!(output, "This will {}. Type yes to continue: ", action)?;
+    output.flush()?;
+
+    let mut answer = String::new();
+    input.read_line(&mut answer)?;
+    if answer.trim() == "yes" {
+        Ok(())
+    } else {
+        Err(AdminError::Aborted)
+    }
+}
+
+/// Runs an operator command against the configured database
+///
+/// `Serve` is left to the binary. Changes to starters and votes are
+/// recorded in the audit log, from which a running server notifies its
+/// webhooks.
+pub async fn run(command: Command, args: &Args, rules: &GovernanceRules) -> Result<(), AdminError> {
+    // Databases with an old key schema cannot be opened until migrated
+    if command == Command::Db(DbCommand::Migrate) {
+        let url = args.redis_path();
+        if !matches!(url.scheme(), "redis" | "rediss") {
+            println!("Only Redis databases have a key schema to migrate");
+            return Ok(());
+        }
+
+        let migration = Redis::new(url).await?.migrate().await?;
+        println!("{}", migration);
+        return Ok(());
+    }
+
+    let store = store::open(&args.redis_path()).await?;
+    let store = &*store;
+
+    match command {
+        Command::Serve | Command::Db(DbCommand::Migrate) => Ok(()),
+        Command::Starters(command) => starters(command, store).await,
+        Command::Vote(command) => vote(command, store, args, rules).await,
+        Command::Voters(command) => voters(command, store).await,
+        Command::Db(command) => db(command, store).await,
+    }
+}
+
+async fn starters(command: StarterCommand, store: &dyn VoteStore) -> Result<(), AdminError> {
+    match command {
+        StarterCommand::Add { address, network } => {
+            let ntw = network.network()?;
+            store.register_voter_starter(address, ntw).await?;
+            println!("{:?} can start votes on {}", address, ntw.name());
+
+            let mut event = AuditEvent::admin(
+                Action::RegisterStarter,
+                ntw,
+                format!("starters add {:?} --network {}", address, ntw.name()),
+            );
+            event.subject(address);
+            store.append_event(event).await?;
+        }
+        StarterCommand::Remove {
+            address,
+            network,
+            yes,
+        } => {
+            let ntw = network.network()?;
+            yes.confirm(&format!(
+                "stop {:?} from starting votes on {}",
+                address,
+                ntw.name()
+            ))?;
+            store.revoke_voter_starter(address, ntw).await?;
+            println!("{:?} can no longer start votes on {}", address, ntw.name());
+
+            let mut event = AuditEvent::admin(
+                Action::RemoveStarter,
+                ntw,
+                format!("starters remove {:?} --network {}", address, ntw.name()),
+            );
+            event.subject(address);
+            store.append_event(event).await?;
+        }
+        StarterCommand::List { network } => {
+            for starter in store.voter_starters(network.network()?).await? {
+                println!("{:?}", starter);
+            }
+        }
+    }
+    Ok(())
+}
+
+async fn vote(
+    command: VoteCommand,
+    store: &dyn VoteStore,
+    args: &Args,
+    rules: &GovernanceRules,
+) -> Result<(), AdminError> {
+    match command {
+        VoteCommand::Flush { fip, network, yes } => {
+            let ntw = network.network()?;
+            if !store.vote_exists(ntw, fip).await? {
+                return Err(AdminError::UnknownVote(fip));
+            }
+            yes.confirm(&format!(
+                "delete the vote on FIP {} on {} with its ballots and tally",
+                fip,
+                ntw.name()
+            ))?;
+            store.flush_vote(fip, ntw).await?;
+            println!(
+                "Deleted the vote on FIP {} on {}, it can be started again",
+                fip,
+                ntw.name()
+            );
+
+            let mut event = AuditEvent::admin(
+                Action::FlushVote,
+                ntw,
+                format!("vote flush {} --network {}", fip, ntw.name()),
+            );
+            event.fip(fip);
+            store.append_event(event).await?;
+        }
+        VoteCommand::Inspect { fip, network } => {
+            let ntw = network.network()?;
+            let vote = VoteSnapshot::read(store, fip, ntw)
+                .await?
+                .ok_or(AdminError::UnknownVote(fip))?;
+            let time_left = match store.vote_status(fip, args.vote_length(), ntw).await? {
+                VoteStatus::InProgress(time_left) => Some(time_left),
+                _ => None,
+            };
+            let inspection = Inspection {
+                vote,
+                time_left,
+                results: store.vote_results(fip, ntw, rules).await?,
+            };
+            println!("{}", serde_json::to_string_pretty(&inspection)?);
+        }
+    }
+    Ok(())
+}
+
+async fn voters(command: VoterCommand, store: &dyn VoteStore) -> Result<(), AdminError> {
+    match command {
+        VoterCommand::List { network } => {
+            for voter in store.voters(network.network()?).await? {
+                println!("{:?}", voter);
+            }
+        }
+        VoterCommand::Show { address, network } => {
+            let voter = VoterSnapshot::read(store, address, network.network()?).await?;
+            println!("{}", serde_json::to_string_pretty(&voter)?);
+        }
+    }
+    Ok(())
+}
+
+async fn db(command: DbCommand, store: &dyn VoteStore) -> Result<(), AdminError> {
+    match command {
+        DbCommand::Flush { yes } => {
+            yes.confirm("delete every vote, registration and audit event")?;
+            store.flush_all().await?;
+            println!("Flushed the database");
+        }
+        DbCommand::Migrate => (),
+        DbCommand::Export { file } => {
+            let snapshot = Snapshot::export(store).await?;
+            serde_json::to_writer_pretty(File::create(&file)?, &snapshot)?;
+            println!("Exported the database to {}", file.display());
+        }
+        DbCommand::Import { file } => {
+            let snapshot: Snapshot = serde_json::from_reader(BufReader::new(File::open(&file)?))?;
+            let import = snapshot.import(store).await?;
+            println!(
+                "Imported {} voters, {} votes, {} ballots and {} audit events from {}",
+                import.voters,
+                import.votes,
+                import.ballots,
+                import.events,
+                file.display()
+            );
+            for (ntw, fip) in import.unverified {
+                println!(
+                    "Tally of FIP {} on {} could not be checked, its ballots carry no weight",
+                    fip, ntw
+                );
+            }
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use clap::Parser;
+
+    use super::*;
+    use crate::store::memory::Memory;
+
+    #[test]
+    fn admin_parse() {
+        let args = Args::try_parse_from(["filecoin-vote"]).unwrap();
+        assert_eq!(args.command, None);
+
+        let args = Args::try_parse_from([
+            "filecoin-vote",
+            "vote",
+            "flush",
+            "5",
+            "--network",
+            "calibration",
+            "-y",
+            "-r",
+            "memory://",
+        ])
+        .unwrap();
+        assert_eq!(
+            args.command,
+            Some(Command::Vote(VoteCommand::Flush {
+                fip: 5,
+                network: NetworkArg {
+                    network: "calibration".to_string()
+                },
+                yes: YesArg { yes: true },
+            }))
+        );
+        assert_eq!(args.redis_path().scheme(), "memory");
+
+        let args = Args::try_parse_from([
+            "filecoin-vote",
+            "starters",
+            "add",
+            "0xf2361d2a9a0677e8ffd1515d65cf5190ea20eb56",
+        ])
+        .unwrap();
+        assert!(matches!(
+            args.command,
+            Some(Command::Starters(StarterCommand::Add { network, .. })) if network.network == "mainnet"
+        ));
+
+        assert!(
+            Args::try_parse_from(["filecoin-vote", "starters", "add", "not an address"]).is_err()
+        );
+    }
+
+    #[tokio::test]
+    async fn admin_starters_audit() {
+        let store = Memory::default();
+        let ntw = Network::from_name("calibration").unwrap();
+        let network = NetworkArg {
+            network: "calibration".to_string(),
+        };
+        let (first, second) = (Address::repeat_byte(1), Address::repeat_byte(2));
+        store.init_voter_starters(ntw, vec![first]).await.unwrap();
+
+        let add = StarterCommand::Add {
+            address: second,
+            network: network.clone(),
+        };
+        starters(add, &store).await.unwrap();
+        let remove = |address| StarterCommand::Remove {
+            address,
+            network: network.clone(),
+            yes: YesArg { yes: true },
+        };
+        starters(remove(first), &store).await.unwrap();
+
+        // The last starter is kept, as through the API
+        assert!(matches!(
+            starters(remove(second), &store).await,
+            Err(AdminError::Store(StoreError::LastStarter))
+        ));
+        assert_eq!(store.voter_starters(ntw).await.unwrap(), vec![second]);
+
+        // Each change is in the audit log, naming the starter
+        let events = store.events(0, 10).await.unwrap();
+        assert_eq!(events.len(), 2);
+        assert_eq!(events[0].action, Action::RegisterStarter);
+        assert_eq!(events[0].subject, Some(second));
+        assert_eq!(events[1].action, Action::RemoveStarter);
+        assert_eq!(events[1].subject, Some(first));
+        assert_eq!(events[1].network.as_deref(), Some("calibration"));
+        assert_eq!(events[1].signer, None);
+        assert_eq!(events[1].status, 200);
+    }
+
+    #[test]
+    fn admin_confirm() {
+        let mut output = Vec::new();
+        assert!(confirm("flush", &mut "yes\n".as_bytes(), &mut output).is_ok());
+        assert!(String::from_utf8(output).unwrap().contains("flush"));
+
+        for answer in ["", "y\n", "no\n"] {
+            assert!(matches!(
+                confirm("flush", &mut answer.as_bytes(), &mut Vec::new()),
+                Err(AdminError::Aborted)
+            ));
+        }
+    }
+}
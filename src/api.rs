@@ -0,0 +1,391 @@
+use actix_web::{
+    dev::Service,
+    http::header::{HeaderName, HeaderValue},
+    web, HttpRequest,
+};
+use utoipa_rapidoc::RapiDoc;
+
+use crate::{
+    get::{
+        get_active_votes, get_all_concluded_votes, get_committees, get_concluded_votes,
+        get_delegates, get_events, get_fip, get_stream, get_vote_starters, get_voter, get_votes,
+        get_voting_power,
+    },
+    openapi::get_openapi,
+    post::{
+        register_batch_vote, register_client, register_token_holder, register_vote,
+        register_vote_starter, register_voter, remove_vote_starter, set_committee, start_vote,
+        unregister_client, unregister_token_holder, unregister_voter,
+    },
+};
+
+/// Prefix of the current version of the API
+pub const API_VERSION: &str = "/v1";
+
+/// Mounts the API under `/v1`, the documentation, and the unversioned
+/// routes older frontends use
+///
+/// Responses of the unversioned routes carry a `Deprecation` header and
+/// link to the `/v1` route replacing them.
+pub fn configure(cfg: &mut web::ServiceConfig) {
+    cfg.service(web::scope(API_VERSION).configure(routes))
+        .service(get_openapi)
+        .service(RapiDoc::new("/openapi.json").path("/docs"))
+        .service(
+            web::scope("")
+                .wrap_fn(|req, srv| {
+                    let successor =
+                        format!("<{}{}>; rel=\"successor-version\"", API_VERSION, req.path());
+                    let res = srv.call(req);
+                    async move {
+                        let mut res = res.await?;
+                        let headers = res.headers_mut();
+                        headers.insert(
+                            HeaderName::from_static("deprecation"),
+                            HeaderValue::from_static("true"),
+                        );
+                        if let Ok(link) = HeaderValue::from_str(&successor) {
+                            headers.insert(HeaderName::from_static("link"), link);
+                        }
+                        Ok(res)
+                    }
+                })
+                .configure(routes),
+        );
+}
+
+/// Whether the request came through an unversioned alias
+///
+/// Endpoints whose response changed in `/v1` keep answering these with the
+/// response older frontends expect.
+pub fn is_legacy(req: &HttpRequest) -> bool {
+    !req.path().starts_with(API_VERSION)
+}
+
+/// Every API handler, relative to the version prefix
+fn routes(cfg: &mut web::ServiceConfig) {
+    cfg.service(get_votes)
+        .service(get_fip)
+        .service(get_voting_power)
+        .service(get_voter)
+        .service(get_vote_starters)
+        .service(get_delegates)
+        .service(get_concluded_votes)
+        .service(get_active_votes)
+        .service(get_all_concluded_votes)
+        .service(get_committees)
+        .service(get_events)
+        .service(get_stream)
+        .service(register_vote)
+        .service(register_batch_vote)
+        .service(register_voter)
+        .service(unregister_voter)
+        .service(register_token_holder)
+        .service(unregister_token_holder)
+        .service(register_client)
+        .service(unregister_client)
+        .service(register_vote_starter)
+        .service(remove_vote_starter)
+        .service(start_vote)
+        .service(set_committee);
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{collections::BTreeMap, str::FromStr, sync::Arc};
+
+    use actix_web::{body, test, App};
+    use clap::Parser;
+    use ethers::signers::{LocalWallet, Signer};
+    use ethers::types::Address;
+    use serde_json::Value;
+
+    use super::*;
+    use crate::{
+        audit::{Action, AuditEvent},
+        errors::query_error,
+        messages::{
+            auth::test_auth::*,
+            vote_start::FipMetadata,
+            wallet_registration::{
+                test_wallet::*, REGISTER_CLIENT, REGISTER_WALLET, UNREGISTER_CLIENT,
+                UNREGISTER_WALLET,
+            },
+        },
+        results::GovernanceRules,
+        storage::Network,
+        store::{memory::Memory, NewVote, VoteStore, WalletKind},
+        stream::Broadcaster,
+        Args,
+    };
+
+    #[actix_web::test]
+    async fn api_versioned_routes() {
+        let store: Arc<dyn VoteStore> = Arc::new(Memory::default());
+        let ntw = Network::from_name("calibration").unwrap();
+        for fip in [5u32, 6u32] {
+            let start = NewVote {
+                timestamp: fip as u64,
+                snapshot: 0,
+                committees: Vec::new(),
+                members: BTreeMap::new(),
+                metadata: FipMetadata::default(),
+            };
+            store.create_vote(fip, ntw, start).await.unwrap();
+        }
+        let app = test::init_service(
+            App::new()
+                .app_data(web::QueryConfig::default().error_handler(query_error))
+                .app_data(web::Data::new(
+                    Args::try_parse_from(["filecoin-vote"]).unwrap(),
+                ))
+                .app_data(web::Data::new(GovernanceRules::default()))
+                .app_data(web::Data::from(store.clone()))
+                .app_data(web::Data::new(Broadcaster::new(60)))
+                .configure(configure),
+        )
+        .await;
+
+        let get = |uri: &str| test::TestRequest::get().uri(uri).to_request();
+        let json = |uri: &'static str| {
+            let app = &app;
+            async move {
+                let res = test::call_service(app, get(uri)).await;
+                assert!(res.status().is_success(), "{}", uri);
+                serde_json::from_slice::<Value>(&body::to_bytes(res.into_body()).await.unwrap())
+                    .unwrap()
+            }
+        };
+
+        let res = test::call_service(&app, get("/v1/filecoin/voterstarters?network=mainnet")).await;
+        assert!(res.status().is_success());
+        assert!(res.headers().get("deprecation").is_none());
+
+        let res = test::call_service(&app, get("/filecoin/voterstarters?network=mainnet")).await;
+        assert!(res.status().is_success());
+        assert_eq!(res.headers().get("deprecation").unwrap(), "true");
+        assert_eq!(
+            res.headers().get("link").unwrap(),
+            "</v1/filecoin/voterstarters>; rel=\"successor-version\""
+        );
+
+        let res = test::call_service(&app, get("/openapi.json")).await;
+        assert!(res.status().is_success());
+
+        // Typed parameters fail with the codes of their own errors
+        for (uri, code) in [
+            ("/v1/filecoin/voterstarters?network=nope", "invalid_network"),
+            (
+                "/v1/filecoin/delegates?network=mainnet&address=0x12",
+                "invalid_address",
+            ),
+            (
+                "/v1/filecoin/vote?network=calibration&fip_number=five",
+                "invalid_query",
+            ),
+            ("/filecoin/committees", "invalid_query"),
+        ] {
+            let res = test::call_service(&app, get(uri)).await;
+            assert_eq!(res.status(), 400, "{}", uri);
+            let body: Value =
+                serde_json::from_slice(&body::to_bytes(res.into_body()).await.unwrap()).unwrap();
+            assert_eq!(body["code"], code, "{}", uri);
+        }
+
+        // History is paged under /v1 and whole on the unversioned routes
+        let page = json("/v1/filecoin/votehistory?network=calibration&limit=1").await;
+        assert_eq!(page, serde_json::json!({ "votes": [5], "next": 5 }));
+        let page = json("/v1/filecoin/votehistory?network=calibration&limit=1&after=5").await;
+        assert_eq!(page, serde_json::json!({ "votes": [6], "next": null }));
+        let page = json("/filecoin/votehistory?network=calibration&limit=1").await;
+        assert_eq!(page, serde_json::json!([5, 6]));
+
+        let page =
+            json("/v1/filecoin/allconcludedvotes?network=calibration&order=desc&summary=true")
+                .await;
+        assert_eq!(page["votes"][0]["fip_number"], 6);
+        assert_eq!(page["votes"][0]["outcome"], "no_votes");
+        assert!(page["votes"][0].get("result").is_none());
+        let page = json("/v1/filecoin/allconcludedvotes?network=calibration").await;
+        assert!(page["votes"][1]["result"]["ballot_hash"].is_string());
+        let results = json("/filecoin/allconcludedvotes?network=calibration").await;
+        assert!(results["5"]["ballot_hash"].is_string());
+
+        let res = test::call_service(
+            &app,
+            get("/v1/filecoin/votehistory?network=calibration&after=9"),
+        )
+        .await;
+        assert_eq!(res.status(), 400);
+
+        let profile = json(
+            "/v1/filecoin/voter?network=calibration&address=0xf2361d2a9a0677e8ffd1515d65cf5190ea20eb56",
+        )
+        .await;
+        assert_eq!(profile["power"], "0");
+        assert_eq!(profile["awaiting"], serde_json::json!([]));
+
+        // A batch reports the outcome of the ballot on each FIP
+        let wallet = LocalWallet::from_str(
+            "4c0883a69102937d6231471b5dbb6204fe5129617082792ae468d01a3f362318",
+        )
+        .unwrap();
+        let message = "FIP-5: YAY; FIP-9: NAY\nnetwork: calibration";
+        let signature = wallet.sign_message(message).await.unwrap();
+        let req = test::TestRequest::post()
+            .uri("/v1/filecoin/batchvote?network=calibration")
+            .set_json(serde_json::json!({
+                "signature": signature.to_string(),
+                "message": message,
+            }))
+            .to_request();
+        let res = test::call_service(&app, req).await;
+        assert!(res.status().is_success());
+        let outcome: Value =
+            serde_json::from_slice(&body::to_bytes(res.into_body()).await.unwrap()).unwrap();
+        assert_eq!(outcome["ballots"][0]["fip_number"], 5);
+        assert_eq!(outcome["ballots"][0]["status"], 403);
+        assert_eq!(outcome["ballots"][0]["error"]["code"], "vote_concluded");
+        assert_eq!(outcome["ballots"][1]["fip_number"], 9);
+        assert!(outcome["ballots"][1]["error"]["code"].is_string());
+
+        // Ballots count on the network they were signed for only
+        for (uri, message) in [
+            ("/v1/filecoin/batchvote?network=mainnet", message),
+            (
+                "/v1/filecoin/vote?network=calibration&fip_number=5",
+                "YAY: FIP-5\nnetwork: mainnet",
+            ),
+        ] {
+            let req = test::TestRequest::post()
+                .uri(uri)
+                .set_json(signed(&wallet, message).await)
+                .to_request();
+            let res = test::call_service(&app, req).await;
+            assert_eq!(res.status(), 400, "{}", uri);
+            let body: Value =
+                serde_json::from_slice(&body::to_bytes(res.into_body()).await.unwrap()).unwrap();
+            assert_eq!(body["code"], "wrong_network", "{}", uri);
+        }
+
+        // A starter may revoke another, but never the last one, and each
+        // signed message is accepted once
+        let send = |uri: &'static str, message: String| {
+            let app = &app;
+            let wallet = wallet.clone();
+            async move {
+                let req = test::TestRequest::post()
+                    .uri(uri)
+                    .set_json(signed(&wallet, &message).await)
+                    .to_request();
+                test::call_service(app, req).await.status()
+            }
+        };
+        let grant = "/v1/filecoin/registerstarter?network=calibration";
+        let revoke = "/v1/filecoin/removestarter?network=calibration";
+        let now = AuditEvent::new(Action::RegisterStarter, b"{}").timestamp;
+        let other = Address::from_low_u64_be(7);
+        store
+            .register_voter_starter(wallet.address(), ntw)
+            .await
+            .unwrap();
+
+        let added = bound_message("ADD STARTER:", other, ntw, now);
+        assert_eq!(send(grant, added.clone()).await, 200);
+        assert!(store.is_authorized_starter(other, ntw).await.unwrap());
+        let mainnet = Network::from_name("mainnet").unwrap();
+        let elsewhere = bound_message("ADD STARTER:", other, mainnet, now);
+        assert_eq!(send(grant, elsewhere).await, 400);
+        let stale = bound_message("ADD STARTER:", other, ntw, now - 3600);
+        assert_eq!(send(grant, stale).await, 400);
+
+        let removed = bound_message("REMOVE STARTER:", other, ntw, now);
+        assert_eq!(send(revoke, format!("{:?}", other)).await, 400);
+        assert_eq!(send(revoke, added.clone()).await, 400);
+        assert_eq!(send(revoke, removed.clone()).await, 200);
+        // Replaying the grant or the revocation changes nothing
+        assert_eq!(send(grant, added).await, 409);
+        assert!(!store.is_authorized_starter(other, ntw).await.unwrap());
+        assert_eq!(send(revoke, removed).await, 409);
+        let again = bound_message("REMOVE STARTER:", other, ntw, now + 1);
+        assert_eq!(send(revoke, again).await, 404);
+        let last = bound_message("REMOVE STARTER:", wallet.address(), ntw, now);
+        assert_eq!(send(revoke, last).await, 409);
+        assert_eq!(
+            store.voter_starters(ntw).await.unwrap(),
+            vec![wallet.address()]
+        );
+
+        // Committee updates are accepted once
+        let committee = "/v1/filecoin/committee?network=calibration";
+        let update = format!(
+            "COMMITTEE: core-devs {other:?}\nnetwork: {}\nissued: {now}",
+            ntw.name()
+        );
+        assert_eq!(send(committee, update.clone()).await, 200);
+        store
+            .set_committee(ntw, "core-devs".to_string(), Vec::new())
+            .await
+            .unwrap();
+        assert_eq!(send(committee, update).await, 409);
+        assert!(store.committees(ntw).await.unwrap().is_empty());
+
+        // Wallet messages are bound to their purpose and accepted once
+        let holder = |uri: &'static str, message: String| {
+            let app = &app;
+            let body = delegated_registration(&wallet, ntw, &message);
+            async move {
+                let req = test::TestRequest::post()
+                    .uri(uri)
+                    .set_json(body)
+                    .to_request();
+                test::call_service(app, req).await.status()
+            }
+        };
+        let register = "/v1/filecoin/registertokenholder";
+        let unregister = "/v1/filecoin/unregistertokenholder";
+        let voter = Address::from_low_u64_be(8);
+        let registered = bound_message(REGISTER_WALLET, voter, ntw, now);
+        assert_eq!(holder(register, registered.clone()).await, 200);
+        assert_eq!(holder(unregister, registered.clone()).await, 400);
+        let unregistered = bound_message(UNREGISTER_WALLET, voter, ntw, now);
+        assert_eq!(holder(unregister, unregistered.clone()).await, 200);
+        assert_eq!(holder(register, registered).await, 409);
+        assert_eq!(holder(unregister, unregistered).await, 409);
+        assert!(store
+            .wallets(WalletKind::Token, voter, ntw)
+            .await
+            .unwrap()
+            .is_empty());
+
+        // Client messages are told apart from token holder ones
+        let register = "/v1/filecoin/registerclient";
+        let unregister = "/v1/filecoin/unregisterclient";
+        let registered = bound_message(REGISTER_WALLET, voter, ntw, now);
+        assert_eq!(holder(register, registered).await, 400);
+        let registered = bound_message(REGISTER_CLIENT, voter, ntw, now);
+        assert_eq!(holder(register, registered.clone()).await, 200);
+        let unregistered = bound_message(UNREGISTER_WALLET, voter, ntw, now);
+        assert_eq!(holder(unregister, unregistered).await, 400);
+        assert_eq!(holder(unregister, registered.clone()).await, 400);
+        let unregistered = bound_message(UNREGISTER_CLIENT, voter, ntw, now);
+        assert_eq!(holder(unregister, unregistered.clone()).await, 200);
+        assert_eq!(holder(register, registered).await, 409);
+        assert_eq!(holder(unregister, unregistered).await, 409);
+        assert!(store
+            .wallets(WalletKind::Client, voter, ntw)
+            .await
+            .unwrap()
+            .is_empty());
+
+        // Both spellings of an address are accepted
+        for address in [
+            "0xf2361d2a9a0677e8ffd1515d65cf5190ea20eb56",
+            "f2361d2a9a0677e8ffd1515d65cf5190ea20eb56",
+        ] {
+            let uri = format!("/v1/filecoin/delegates?network=mainnet&address={}", address);
+            let res = test::call_service(&app, get(&uri)).await;
+            assert!(res.status().is_success(), "{}", address);
+        }
+    }
+}
@@ -0,0 +1,141 @@
+use std::time;
+
+use ethers::types::Address;
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+use crate::storage::Network;
+
+/// Most events returned by one page of the audit log
+pub const MAX_PAGE_SIZE: usize = 1000;
+
+/// The state changing request an audit event records
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum Action {
+    Vote,
+    BatchVote,
+    StartVote,
+    RegisterStarter,
+    RemoveStarter,
+    SetCommittee,
+    RegisterVoter,
+    UnregisterVoter,
+    RegisterTokenHolder,
+    UnregisterTokenHolder,
+    RegisterClient,
+    UnregisterClient,
+    /// A vote deleted with the `vote flush` admin command
+    FlushVote,
+}
+
+/// One entry of the append-only audit log
+///
+/// Every request that can change state is recorded, whether it succeeded or
+/// not, with the body exactly as it was received.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, ToSchema)]
+pub struct AuditEvent {
+    /// Position in the log starting at 1, assigned by the store when appended
+    #[serde(default)]
+    pub id: u64,
+    pub action: Action,
+    /// Unix timestamp the request was received at
+    pub timestamp: u64,
+    /// The signed request body, or the admin command that was run
+    pub payload: String,
+    /// The address recovered from the signature, missing if recovery failed
+    /// and for admin commands
+    #[schema(value_type = Option<String>)]
+    pub signer: Option<Address>,
+    /// The starter granted or revoked the right to start votes
+    #[schema(value_type = Option<String>)]
+    pub subject: Option<Address>,
+    pub network: Option<String>,
+    pub fip: Option<u32>,
+    /// HTTP status the request was answered with
+    pub status: u16,
+    /// Body of the answer, usually the reason a request was rejected
+    pub response: Option<String>,
+}
+
+impl AuditEvent {
+    pub fn new(action: Action, payload: &[u8]) -> Self {
+        let timestamp = time::SystemTime::now()
+            .duration_since(time::UNIX_EPOCH)
+            .map_or(0, |d| d.as_secs());
+
+        Self {
+            id: 0,
+            action,
+            timestamp,
+            payload: String::from_utf8_lossy(payload).into_owned(),
+            signer: None,
+            subject: None,
+            network: None,
+            fip: None,
+            status: 0,
+            response: None,
+        }
+    }
+
+    /// A change made by an admin command, which succeeded and has no signer
+    pub fn admin(action: Action, ntw: Network, command: String) -> Self {
+        let mut event = Self::new(action, command.as_bytes());
+        event.network(ntw);
+        event.status = 200;
+        event
+    }
+
+    pub fn signer(&mut self, signer: Address) {
+        self.signer = Some(signer);
+    }
+
+    pub fn subject(&mut self, subject: Address) {
+        self.subject = Some(subject);
+    }
+
+    pub fn network(&mut self, ntw: Network) {
+        self.network = Some(ntw.name().to_string());
+    }
+
+    pub fn fip(&mut self, fip_number: u32) {
+        self.fip = Some(fip_number);
+    }
+}
+
+/// A page of the audit log
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct EventPage {
+    pub events: Vec<AuditEvent>,
+    /// Pass as `after` to read the next page, missing on the last page
+    pub next: Option<u64>,
+}
+
+impl EventPage {
+    pub fn new(events: Vec<AuditEvent>, limit: usize) -> Self {
+        let next = match events.last() {
+            Some(last) if events.len() == limit => Some(last.id),
+            _ => None,
+        };
+        Self { events, next }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn audit_event_page() {
+        let events = (1..=3)
+            .map(|id| AuditEvent {
+                id,
+                ..AuditEvent::new(Action::Vote, b"{}")
+            })
+            .collect::<Vec<_>>();
+
+        assert_eq!(EventPage::new(events.clone(), 3).next, Some(3));
+        assert_eq!(EventPage::new(events, 10).next, None);
+        assert_eq!(EventPage::new(Vec::new(), 10).next, None);
+    }
+}
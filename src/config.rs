@@ -0,0 +1,134 @@
+use std::{fs, path::Path};
+
+use serde::Deserialize;
+use thiserror::Error;
+
+use crate::{
+    results::GovernanceRules,
+    storage::{default_networks, NetworkConfig},
+    webhooks::WebhookConfig,
+};
+
+/// Contents of the optional JSON configuration file
+///
+/// Any field left out falls back to its default
+#[derive(Deserialize, Debug)]
+pub struct Config {
+    #[serde(default = "default_networks")]
+    pub networks: Vec<NetworkConfig>,
+    /// Thresholds and combination rule vote outcomes are decided by
+    #[serde(default)]
+    pub governance: GovernanceRules,
+    /// URLs notified of vote starts, conclusions and starter changes
+    #[serde(default)]
+    pub webhooks: Vec<WebhookConfig>,
+}
+
+#[derive(Debug, Error)]
+pub enum ConfigError {
+    #[error("io error")]
+    Io(#[from] std::io::Error),
+    #[error("serde error")]
+    Serde(#[from] serde_json::Error),
+    #[error("invalid threshold {1} for {0}, expected a share from 0 up to but excluding 1")]
+    InvalidThreshold(String, f64),
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            networks: default_networks(),
+            governance: GovernanceRules::default(),
+            webhooks: Vec::new(),
+        }
+    }
+}
+
+impl Config {
+    pub fn load(path: &Path) -> Result<Config, ConfigError> {
+        let contents = fs::read_to_string(path)?;
+        let config: Config = serde_json::from_str(&contents)?;
+        config.validate()?;
+        Ok(config)
+    }
+
+    /// Thresholds are shares of the yay and nay weight, a threshold of 1 or
+    /// more could never pass
+    fn validate(&self) -> Result<(), ConfigError> {
+        let valid = |threshold: f64| (0.0..1.0).contains(&threshold);
+        let rules = &self.governance;
+
+        if !valid(rules.default_threshold) {
+            return Err(ConfigError::InvalidThreshold(
+                "default_threshold".to_string(),
+                rules.default_threshold,
+            ));
+        }
+        for (constituency, threshold) in &rules.thresholds {
+            if !valid(*threshold) {
+                return Err(ConfigError::InvalidThreshold(
+                    constituency.to_string(),
+                    *threshold,
+                ));
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn config_load_example() {
+        let config = Config::load(Path::new("config.example.json")).unwrap();
+
+        let names = config
+            .networks
+            .iter()
+            .map(|n| n.name.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(names, vec!["mainnet", "calibration", "devnet"]);
+        assert_eq!(config.webhooks.len(), 1);
+    }
+
+    #[test]
+    fn config_invalid_thresholds() {
+        let path = std::env::temp_dir().join("fip-voting-config-thresholds.json");
+
+        for governance in [
+            r#"{ "default_threshold": 1.0 }"#,
+            r#"{ "default_threshold": -0.1 }"#,
+            r#"{ "thresholds": { "committee:core-devs": 1.5 } }"#,
+        ] {
+            fs::write(&path, format!(r#"{{ "governance": {governance} }}"#)).unwrap();
+            assert!(matches!(
+                Config::load(&path),
+                Err(ConfigError::InvalidThreshold(..))
+            ));
+        }
+
+        // JSON has no NaN, so it is checked on the rules directly
+        let mut config = Config::default();
+        config.governance.default_threshold = f64::NAN;
+        assert!(config.validate().is_err());
+
+        fs::write(
+            &path,
+            r#"{ "governance": { "default_threshold": 0.0, "thresholds": { "clients": 0.99 } } }"#,
+        )
+        .unwrap();
+        assert!(Config::load(&path).is_ok());
+    }
+
+    #[test]
+    fn config_defaults() {
+        let config: Config = serde_json::from_str("{}").unwrap();
+
+        assert_eq!(config.networks, default_networks());
+        assert_eq!(config.governance, GovernanceRules::default());
+        assert!(config.webhooks.is_empty());
+    }
+}
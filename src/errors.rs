@@ -1,18 +1,53 @@
+use std::{collections::HashMap, str::FromStr};
+
+use actix_web::{
+    error::QueryPayloadError, http::StatusCode, web, HttpRequest, HttpResponse, ResponseError,
+};
+use ethers::types::Address;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use thiserror::Error;
+use utoipa::ToSchema;
+
+use crate::{
+    messages::{
+        vote_registration::VoteRegistrationError, votes::VoteError,
+        wallet_registration::WalletRegistrationError,
+    },
+    storage::{Network, StorageFetchError},
+    store::StoreError,
+};
+
 // Error messages
 pub const OPEN_CONNECTION_ERROR: &str = "Error opening connection to in-memory database";
 
 pub const VOTE_STATUS_ERROR: &str = "Error getting vote status";
 pub const VOTE_RESULTS_ERROR: &str = "Error getting vote results";
+pub const FIP_INFO_ERROR: &str = "Error getting FIP";
 pub const VOTE_DESERIALIZE_ERROR: &str = "Error deserializing vote";
 pub const VOTE_RECOVER_ERROR: &str = "Error recovering vote";
 pub const VOTE_ADD_ERROR: &str = "Error adding vote";
 
+pub const WALLET_DESERIALIZE_ERROR: &str = "Error deserializing wallet registration";
+pub const WALLET_RECOVER_ERROR: &str = "Error recovering wallet registration";
+pub const WALLET_REGISTER_ERROR: &str = "Error registering wallet";
+pub const WALLET_UNREGISTER_ERROR: &str = "Error unregistering wallet";
+
 pub const VOTER_AUTH_DESERIALIZE_ERROR: &str = "Error deserializing voter authorization";
 pub const VOTER_AUTH_RECOVER_ERROR: &str = "Error recovering voter authorization";
 pub const VOTER_NOT_AUTHORIZED_ERROR: &str = "Voter not authorized to add new signers";
 pub const VOTER_AUTH_ERROR: &str = "Error getting voter authorization";
 pub const VOTER_DELEGATES_ERROR: &str = "Error getting voter delegates";
 
+pub const STARTER_REVOCATION_DESERIALIZE_ERROR: &str = "Error deserializing starter revocation";
+pub const STARTER_REVOCATION_RECOVER_ERROR: &str = "Error recovering starter revocation";
+pub const STARTER_REMOVE_ERROR: &str = "Error removing vote starter";
+
+pub const COMMITTEE_DESERIALIZE_ERROR: &str = "Error deserializing committee update";
+pub const COMMITTEE_RECOVER_ERROR: &str = "Error recovering committee update";
+pub const COMMITTEE_UPDATE_ERROR: &str = "Error updating committee";
+pub const COMMITTEES_ERROR: &str = "Error getting committees";
+
 pub const VOTE_START_ERROR: &str = "Error starting vote";
 
 pub const VOTE_EXISTS_ERROR: &str = "Error checking if vote exists";
@@ -20,6 +55,7 @@ pub const VOTE_EXISTS_ERROR: &str = "Error checking if vote exists";
 pub const VOTE_STARTERS_ERROR: &str = "Error getting vote starters";
 
 pub const VOTING_POWER_ERROR: &str = "Error getting voting power";
+pub const VOTER_PROFILE_ERROR: &str = "Error getting voter";
 
 pub const STORAGE_ERROR: &str = "Error getting storage";
 
@@ -30,7 +66,292 @@ pub const VOTE_IS_ALREADY_STARTED: &str = "Vote is already started";
 pub const VOTE_ALREADY_EXISTS: &str = "Vote already exists";
 pub const CONCLUDED_VOTES_ERROR: &str = "Error getting concluded votes";
 
-pub const VOTER_NOT_REGISTERED_NETWORK: &str = "Voter is not registered for this network";
+pub const AUDIT_APPEND_ERROR: &str = "Error appending to the audit log";
+pub const AUDIT_EVENTS_ERROR: &str = "Error getting audit events";
 
-pub const INVALID_NETWORK: &str = "Voter is not registered for this network";
+pub const INVALID_NETWORK: &str = "Invalid network";
 pub const INVALID_ADDRESS: &str = "Invalid address";
+
+/// Every error a request can fail with, from the store, the signed messages
+/// or the chain, and the HTTP status it is answered with
+#[derive(Debug, Error)]
+pub enum DomainError {
+    #[error(transparent)]
+    Store(#[from] StoreError),
+    #[error(transparent)]
+    Vote(#[from] VoteError),
+    #[error(transparent)]
+    VoterRegistration(#[from] VoteRegistrationError),
+    #[error(transparent)]
+    WalletRegistration(#[from] WalletRegistrationError),
+    #[error(transparent)]
+    Chain(#[from] StorageFetchError),
+    /// The request body is not the expected JSON
+    #[error(transparent)]
+    Body(#[from] serde_json::Error),
+    /// The query string is missing a parameter or has one of the wrong type
+    #[error("{0}")]
+    Query(String),
+    #[error("{0}")]
+    InvalidNetwork(String),
+    #[error("{0}")]
+    InvalidAddress(String),
+    #[error("{0:?}")]
+    NotAuthorized(Address),
+    #[error("{0:?}")]
+    NotRegistered(Address),
+    #[error("FIP {0}")]
+    VoteNotFound(u32),
+    #[error("FIP {0}")]
+    VoteConcluded(u32),
+}
+
+/// Body of every error response
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct ErrorBody {
+    /// Stable machine readable code, the frontend matches on this
+    pub code: String,
+    pub message: String,
+    /// What the request failed on, for example the storage provider id
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[schema(value_type = Option<Object>)]
+    pub details: Option<Value>,
+}
+
+impl DomainError {
+    /// Stable machine readable code of the error
+    pub fn code(&self) -> &'static str {
+        match self {
+            DomainError::Store(e) => match e {
+                StoreError::UnauthorizedStarter => "unauthorized_starter",
+                StoreError::UnknownStarter => "unknown_starter",
+                StoreError::LastStarter => "last_starter",
+                StoreError::WalletClaimed => "wallet_claimed",
+                StoreError::MessageReused => "message_reused",
+                StoreError::VoteExists => "vote_exists",
+                StoreError::UnknownCommittee => "unknown_committee",
+                StoreError::VoteNotActive => "vote_not_active",
+                StoreError::NoVotingPower => "no_voting_power",
+                StoreError::DuplicateVote => "duplicate_vote",
+                StoreError::FipMismatch => "fip_mismatch",
+                StoreError::VoteFinalized => "vote_finalized",
+                StoreError::Fetch(_) => "chain_unavailable",
+                StoreError::Corrupt(_) => "corrupt_data",
+                StoreError::Redis(_)
+                | StoreError::Pool(_)
+                | StoreError::CreatePool(_)
+                | StoreError::Sqlite(_)
+                | StoreError::Serde(_)
+                | StoreError::UnsupportedUrl(_)
+                | StoreError::SchemaVersion { .. } => "database_error",
+            },
+            DomainError::Vote(e) => match e {
+                VoteError::SignatureError(_) => "invalid_signature",
+                VoteError::InvalidMessageFormat => "invalid_message",
+                VoteError::InvalidVoteOption => "invalid_vote_option",
+                VoteError::InvalidMetadata(_) => "invalid_metadata",
+                VoteError::WrongNetwork => "wrong_network",
+                VoteError::StaleMessage => "stale_message",
+            },
+            DomainError::VoterRegistration(e) => match e {
+                VoteRegistrationError::SignatureMismatch => "signature_mismatch",
+                VoteRegistrationError::InvalidMessageFormat => "invalid_message",
+                VoteRegistrationError::NotStorageProvider(..) => "not_storage_provider",
+                VoteRegistrationError::StorageFetchError(_) => "chain_unavailable",
+                VoteRegistrationError::InvalidWorkerAddress => "invalid_worker_address",
+                VoteRegistrationError::InvalidBlsEncoding(_)
+                | VoteRegistrationError::InvalidHexEncoding(_) => "invalid_encoding",
+                VoteRegistrationError::InvalidAddress => "invalid_address",
+                VoteRegistrationError::InvalidStorageProviderId(_) => "invalid_storage_provider_id",
+                VoteRegistrationError::InvalidNetwork => "invalid_network",
+            },
+            DomainError::WalletRegistration(e) => match e {
+                WalletRegistrationError::SignatureMismatch => "signature_mismatch",
+                WalletRegistrationError::InvalidMessageFormat => "invalid_message",
+                WalletRegistrationError::InvalidWalletAddress => "invalid_wallet_address",
+                WalletRegistrationError::InvalidSignatureType => "invalid_signature_type",
+                WalletRegistrationError::InvalidBlsEncoding(_)
+                | WalletRegistrationError::InvalidHexEncoding(_) => "invalid_encoding",
+                WalletRegistrationError::InvalidAddress => "invalid_address",
+                WalletRegistrationError::InvalidNetwork => "invalid_network",
+                WalletRegistrationError::StaleMessage => "stale_message",
+            },
+            DomainError::Chain(_) => "chain_unavailable",
+            DomainError::Body(_) => "invalid_body",
+            DomainError::Query(_) => "invalid_query",
+            DomainError::InvalidNetwork(_) => "invalid_network",
+            DomainError::InvalidAddress(_) => "invalid_address",
+            DomainError::NotAuthorized(_) => "not_authorized",
+            DomainError::NotRegistered(_) => "not_registered",
+            DomainError::VoteNotFound(_) => "vote_not_found",
+            DomainError::VoteConcluded(_) => "vote_concluded",
+        }
+    }
+
+    /// What the request failed on, if there is more to it than the code
+    pub fn details(&self) -> Option<Value> {
+        match self {
+            DomainError::Store(StoreError::Fetch(what)) => Some(json!({ "fetching": what })),
+            DomainError::VoterRegistration(VoteRegistrationError::NotStorageProvider(
+                worker,
+                sp_id,
+            )) => Some(json!({ "worker_address": worker, "sp_id": sp_id })),
+            DomainError::Vote(VoteError::InvalidMetadata(field)) => Some(json!({ "field": field })),
+            DomainError::InvalidNetwork(network) => Some(json!({ "network": network })),
+            DomainError::NotAuthorized(address) | DomainError::NotRegistered(address) => {
+                Some(json!({ "address": address }))
+            }
+            DomainError::VoteNotFound(fip) | DomainError::VoteConcluded(fip) => {
+                Some(json!({ "fip_number": fip }))
+            }
+            _ => None,
+        }
+    }
+
+    fn body(&self, message: String) -> ErrorBody {
+        ErrorBody {
+            code: self.code().to_string(),
+            message,
+            details: self.details(),
+        }
+    }
+}
+
+impl ResponseError for DomainError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            DomainError::Store(e) => match e {
+                StoreError::UnauthorizedStarter
+                | StoreError::VoteNotActive
+                | StoreError::NoVotingPower => StatusCode::FORBIDDEN,
+                StoreError::VoteExists
+                | StoreError::DuplicateVote
+                | StoreError::VoteFinalized
+                | StoreError::LastStarter
+                | StoreError::WalletClaimed
+                | StoreError::MessageReused => StatusCode::CONFLICT,
+                StoreError::UnknownStarter => StatusCode::NOT_FOUND,
+                StoreError::UnknownCommittee | StoreError::FipMismatch => StatusCode::BAD_REQUEST,
+                StoreError::Fetch(_) => StatusCode::BAD_GATEWAY,
+                StoreError::Redis(_)
+                | StoreError::Pool(_)
+                | StoreError::CreatePool(_)
+                | StoreError::Sqlite(_)
+                | StoreError::Serde(_)
+                | StoreError::UnsupportedUrl(_)
+                | StoreError::Corrupt(_)
+                | StoreError::SchemaVersion { .. } => StatusCode::INTERNAL_SERVER_ERROR,
+            },
+            DomainError::VoterRegistration(e) => match e {
+                VoteRegistrationError::NotStorageProvider(..) => StatusCode::FORBIDDEN,
+                VoteRegistrationError::StorageFetchError(_) => StatusCode::BAD_GATEWAY,
+                _ => StatusCode::BAD_REQUEST,
+            },
+            DomainError::Vote(_)
+            | DomainError::WalletRegistration(_)
+            | DomainError::Body(_)
+            | DomainError::Query(_)
+            | DomainError::InvalidNetwork(_)
+            | DomainError::InvalidAddress(_) => StatusCode::BAD_REQUEST,
+            DomainError::Chain(_) => StatusCode::BAD_GATEWAY,
+            DomainError::NotAuthorized(_)
+            | DomainError::NotRegistered(_)
+            | DomainError::VoteConcluded(_) => StatusCode::FORBIDDEN,
+            DomainError::VoteNotFound(_) => StatusCode::NOT_FOUND,
+        }
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        HttpResponse::build(self.status_code()).json(self.body(self.to_string()))
+    }
+}
+
+/// Logs the error and answers with its status and JSON body, the context
+/// prefixed to its message
+pub fn error_response(context: &str, e: impl Into<DomainError>) -> HttpResponse {
+    let (status, body) = error_body(context, e);
+    HttpResponse::build(status).json(body)
+}
+
+/// Logs the error and returns the status and body `error_response` answers
+/// with, for responses reporting several outcomes
+pub fn error_body(context: &str, e: impl Into<DomainError>) -> (StatusCode, ErrorBody) {
+    let e = e.into();
+    let message = format!("{}: {}", context, e);
+    println!("{}", message);
+    (e.status_code(), e.body(message))
+}
+
+/// Answers requests whose query string does not parse
+///
+/// Networks and addresses are checked first so they keep their own codes.
+pub fn query_error(err: QueryPayloadError, req: &HttpRequest) -> actix_web::Error {
+    let query = web::Query::<HashMap<String, String>>::from_query(req.query_string())
+        .map(|q| q.into_inner())
+        .unwrap_or_default();
+
+    if let Some(name) = query.get("network") {
+        if Network::from_name(name).is_none() {
+            return DomainError::InvalidNetwork(name.clone()).into();
+        }
+    }
+    if let Some(address) = query.get("address") {
+        if let Err(e) = Address::from_str(address) {
+            return DomainError::InvalidAddress(e.to_string()).into();
+        }
+    }
+    DomainError::Query(err.to_string()).into()
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::body;
+
+    use super::*;
+
+    #[test]
+    fn domain_error_status() {
+        let status = |e: DomainError| e.status_code().as_u16();
+
+        assert_eq!(status(StoreError::DuplicateVote.into()), 409);
+        assert_eq!(status(StoreError::VoteExists.into()), 409);
+        assert_eq!(status(StoreError::UnauthorizedStarter.into()), 403);
+        assert_eq!(status(StoreError::LastStarter.into()), 409);
+        assert_eq!(status(StoreError::UnknownStarter.into()), 404);
+        assert_eq!(status(StoreError::WalletClaimed.into()), 409);
+        assert_eq!(status(StoreError::MessageReused.into()), 409);
+        assert_eq!(status(VoteError::StaleMessage.into()), 400);
+        assert_eq!(status(StoreError::Fetch("chain head").into()), 502);
+        assert_eq!(status(StoreError::Corrupt("vote option").into()), 500);
+        assert_eq!(status(VoteError::InvalidVoteOption.into()), 400);
+        assert_eq!(status(StorageFetchError::NoResult.into()), 502);
+        assert_eq!(
+            status(VoteRegistrationError::StorageFetchError(StorageFetchError::NoResult).into()),
+            502
+        );
+        assert_eq!(status(DomainError::NotAuthorized(Address::zero())), 403);
+        assert_eq!(status(DomainError::VoteNotFound(5)), 404);
+    }
+
+    #[tokio::test]
+    async fn domain_error_body() {
+        let res = error_response(
+            VOTER_AUTH_ERROR,
+            VoteRegistrationError::NotStorageProvider("f3abc".to_string(), "f01234".to_string()),
+        );
+        assert_eq!(res.status(), StatusCode::FORBIDDEN);
+
+        let bytes = body::to_bytes(res.into_body()).await.unwrap();
+        let body: ErrorBody = serde_json::from_slice(&bytes).unwrap();
+        assert_eq!(body.code, "not_storage_provider");
+        assert!(body.message.starts_with(VOTER_AUTH_ERROR));
+        assert_eq!(body.details.unwrap()["sp_id"], "f01234");
+
+        let res = DomainError::InvalidNetwork("devnet".to_string()).error_response();
+        let bytes = body::to_bytes(res.into_body()).await.unwrap();
+        let body: ErrorBody = serde_json::from_slice(&bytes).unwrap();
+        assert_eq!(body.code, "invalid_network");
+        assert_eq!(body.message, "devnet");
+        assert!(body.details.is_some());
+    }
+}
@@ -1,47 +1,45 @@
-use std::{collections::HashMap, str::FromStr};
+use std::collections::HashMap;
 
-use actix_web::{get, web, HttpResponse, Responder};
-use ethers::types::Address;
+use actix_web::{get, web, HttpRequest, HttpResponse, Responder};
 
 use crate::{
+    api::is_legacy,
+    audit::{EventPage, MAX_PAGE_SIZE},
     errors::*,
-    redis::{Redis, VoteStatus},
-    storage::{fetch_storage_amount, Network},
-    Args, NtwAddrParams, NtwFipParams, NtwParams, STARTING_AUTHORIZED_VOTERS,
+    history::{ConcludedVote, FipPage, ResultPage},
+    results::GovernanceRules,
+    store::{FinalResult, FipInfo, VoteStatus, VoteStore, VoterProfile},
+    stream::{Broadcaster, StreamEvent, StreamFilter},
+    Args, EventParams, HistoryParams, NtwAddrParams, NtwFipParams, NtwParams, StreamParams,
 };
 
+#[utoipa::path(
+    tag = "votes",
+    params(NtwFipParams),
+    responses(
+        (status = 200, description = "Seconds left while the vote runs as plain text, the final result once it concluded", body = FinalResult),
+        (status = 400, description = "Malformed request", body = ErrorBody),
+        (status = 404, description = "Vote does not exist", body = ErrorBody),
+        (status = 500, description = "Database error", body = ErrorBody),
+        (status = 502, description = "Chain could not be queried", body = ErrorBody),
+    )
+)]
 #[get("/filecoin/vote")]
 async fn get_votes(
     query_params: web::Query<NtwFipParams>,
     config: web::Data<Args>,
+    rules: web::Data<GovernanceRules>,
+    store: web::Data<dyn VoteStore>,
 ) -> impl Responder {
     println!("votes requested");
 
-    let ntw = match query_params.network.as_str() {
-        "mainnet" => Network::Mainnet,
-        "calibration" => Network::Testnet,
-        _ => return HttpResponse::BadRequest().body(INVALID_NETWORK),
-    };
+    let ntw = query_params.network;
     let num = query_params.fip_number;
 
-    // Open a connection to the redis database
-    let mut redis = match Redis::new(config.redis_path()) {
-        Ok(redis) => redis,
-        Err(e) => {
-            let res = format!("{}: {}", OPEN_CONNECTION_ERROR, e);
-            println!("{}", res);
-            return HttpResponse::InternalServerError().body(res);
-        }
-    };
-
     // Get the status of the vote from the database
-    let status = match redis.vote_status(num, config.vote_length(), ntw) {
+    let status = match store.vote_status(num, config.vote_length(), ntw).await {
         Ok(status) => status,
-        Err(e) => {
-            let res = format!("{}: {}", VOTE_STATUS_ERROR, e);
-            println!("{}", res);
-            return HttpResponse::InternalServerError().body(res);
-        }
+        Err(e) => return error_response(VOTE_STATUS_ERROR, e),
     };
 
     println!("Vote status: {:?} for FIP: {}", status, num);
@@ -50,108 +48,104 @@ async fn get_votes(
     match status {
         VoteStatus::InProgress(time_left) => HttpResponse::Ok().body(time_left.to_string()),
         VoteStatus::Concluded => {
-            let vote_results = match redis.vote_results(num, ntw) {
-                Ok(results) => results,
-                Err(e) => {
-                    let res = format!("{}: {}", VOTE_RESULTS_ERROR, e);
-                    println!("{}", res);
-                    return HttpResponse::InternalServerError().body(res);
+            let result = match store.finalize(num, ntw, &rules, config.vote_length()).await {
+                Ok(Some(result)) => result,
+                Ok(None) => {
+                    return error_response(VOTE_RESULTS_ERROR, DomainError::VoteNotFound(num))
                 }
+                Err(e) => return error_response(VOTE_RESULTS_ERROR, e),
             };
-            println!("Vote results: {:?}", vote_results);
-            HttpResponse::Ok().json(vote_results)
+            println!("Vote results: {:?}", result);
+            HttpResponse::Ok().json(result)
+        }
+        VoteStatus::DoesNotExist => {
+            error_response(VOTE_RESULTS_ERROR, DomainError::VoteNotFound(num))
         }
-        VoteStatus::DoesNotExist => HttpResponse::NotFound().finish(),
     }
 }
 
+#[utoipa::path(
+    tag = "votes",
+    params(NtwFipParams),
+    responses(
+        (status = 200, description = "Metadata, schedule and status of the vote on the FIP", body = FipInfo),
+        (status = 400, description = "Malformed request", body = ErrorBody),
+        (status = 404, description = "Vote does not exist", body = ErrorBody),
+        (status = 500, description = "Database error", body = ErrorBody),
+    )
+)]
+#[get("/filecoin/fip")]
+async fn get_fip(
+    query_params: web::Query<NtwFipParams>,
+    config: web::Data<Args>,
+    store: web::Data<dyn VoteStore>,
+) -> impl Responder {
+    println!("FIP requested");
+
+    let ntw = query_params.network;
+    let num = query_params.fip_number;
+
+    match store.fip_info(num, ntw, config.vote_length()).await {
+        Ok(Some(info)) => HttpResponse::Ok().json(info),
+        Ok(None) => error_response(FIP_INFO_ERROR, DomainError::VoteNotFound(num)),
+        Err(e) => error_response(FIP_INFO_ERROR, e),
+    }
+}
+
+#[utoipa::path(
+    tag = "voters",
+    params(NtwAddrParams),
+    responses(
+        (status = 200, description = "Storage provider ids the voter votes for", body = Vec<String>),
+        (status = 400, description = "Malformed request", body = ErrorBody),
+        (status = 500, description = "Database error", body = ErrorBody),
+    )
+)]
 #[get("/filecoin/delegates")]
 async fn get_delegates(
     query_params: web::Query<NtwAddrParams>,
-    config: web::Data<Args>,
+    store: web::Data<dyn VoteStore>,
 ) -> impl Responder {
     println!("Delegates requested");
 
-    let ntw = match query_params.network.as_str() {
-        "mainnet" => Network::Mainnet,
-        "calibration" => Network::Testnet,
-        _ => return HttpResponse::BadRequest().body(INVALID_NETWORK),
-    };
-    let address = query_params.address.clone();
-
-    let address = match Address::from_str(address.as_str()) {
-        Ok(address) => address,
-        Err(e) => {
-            let res = format!("{}: {}", INVALID_ADDRESS, e);
-            println!("{}", res);
-            return HttpResponse::BadRequest().body(res);
-        }
-    };
-
-    // Open a connection to the redis database
-    let mut redis = match Redis::new(config.redis_path()) {
-        Ok(redis) => redis,
-        Err(e) => {
-            let res = format!("{}: {}", OPEN_CONNECTION_ERROR, e);
-            println!("{}", res);
-            return HttpResponse::InternalServerError().body(res);
-        }
-    };
+    let ntw = query_params.network;
+    let address = query_params.address;
 
     // Get the status of the vote from the database
-    let delegates = match redis.voter_delegates(address, ntw) {
+    let delegates = match store.voter_delegates(address, ntw).await {
         Ok(delegates) => delegates,
-        Err(e) => {
-            let res = format!("{}: {}", VOTER_DELEGATES_ERROR, e);
-            println!("{}", res);
-            return HttpResponse::InternalServerError().body(res);
-        }
+        Err(e) => return error_response(VOTER_DELEGATES_ERROR, e),
     };
 
     println!("Delegates: {:?} for address: {}", delegates, address);
 
-    let mut dgts: Vec<String> = Vec::new();
-    let prefix = match ntw {
-        Network::Mainnet => "f",
-        Network::Testnet => "t",
-    };
-    for delegate in delegates {
-        dgts.push(format!("{}0{}", prefix, delegate));
-    }
+    let dgts: Vec<String> = delegates.into_iter().map(|d| ntw.id_address(d)).collect();
 
     HttpResponse::Ok().json(dgts)
 }
 
+#[utoipa::path(
+    tag = "votes",
+    params(NtwParams),
+    responses(
+        (status = 200, description = "FIPs being voted on", body = Vec<u32>),
+        (status = 400, description = "Malformed request", body = ErrorBody),
+        (status = 500, description = "Database error", body = ErrorBody),
+    )
+)]
 #[get("/filecoin/activevotes")]
 async fn get_active_votes(
     query_params: web::Query<NtwParams>,
     config: web::Data<Args>,
+    store: web::Data<dyn VoteStore>,
 ) -> impl Responder {
     println!("Active votes requested");
-    let ntw = match query_params.network.as_str() {
-        "mainnet" => Network::Mainnet,
-        "calibration" => Network::Testnet,
-        _ => return HttpResponse::BadRequest().body(INVALID_NETWORK),
-    };
-
-    // Open a connection to the Redis Database
-    let mut redis = match Redis::new(config.redis_path()) {
-        Ok(redis) => redis,
-        Err(e) => {
-            let res = format!("{}: {}", OPEN_CONNECTION_ERROR, e);
-            println!("{}", res);
-            return HttpResponse::InternalServerError().body(res);
-        }
-    };
+    let ntw = query_params.network;
 
     // Get active votes
-    let active_votes = match redis.active_votes(ntw, config.vote_length()) {
+    let active_votes = match store.active_votes(ntw, config.vote_length()).await {
         Ok(active_votes) => active_votes,
-        Err(e) => {
-            let res = format!("{}: {}", ACTIVE_VOTES_ERROR, e);
-            println!("{}", res);
-            return HttpResponse::InternalServerError().body(res);
-        }
+        Err(e) => return error_response(ACTIVE_VOTES_ERROR, e),
     };
 
     println!("Active votes: {:?}", active_votes);
@@ -159,193 +153,299 @@ async fn get_active_votes(
     HttpResponse::Ok().json(active_votes)
 }
 
+#[utoipa::path(
+    tag = "votes",
+    params(HistoryParams),
+    responses(
+        (status = 200, description = "A page of the FIPs whose vote concluded", body = FipPage),
+        (status = 400, description = "Malformed request", body = ErrorBody),
+        (status = 500, description = "Database error", body = ErrorBody),
+    )
+)]
 #[get("/filecoin/votehistory")]
 async fn get_concluded_votes(
-    query_params: web::Query<NtwParams>,
+    req: HttpRequest,
+    query_params: web::Query<HistoryParams>,
     config: web::Data<Args>,
+    store: web::Data<dyn VoteStore>,
 ) -> impl Responder {
     println!("Concluded votes requested");
-    let ntw = match query_params.network.as_str() {
-        "mainnet" => Network::Mainnet,
-        "calibration" => Network::Testnet,
-        _ => return HttpResponse::BadRequest().body(INVALID_NETWORK),
-    };
+    let ntw = query_params.network;
 
-    // Open a connection to the Redis Database
-    let mut redis = match Redis::new(config.redis_path()) {
-        Ok(redis) => redis,
-        Err(e) => {
-            let res = format!("{}: {}", OPEN_CONNECTION_ERROR, e);
-            println!("{}", res);
-            return HttpResponse::InternalServerError().body(res);
-        }
-    };
+    // The unversioned route answers with every concluded vote
+    if is_legacy(&req) {
+        return match store.concluded_votes(ntw, config.vote_length()).await {
+            Ok(concluded_votes) => HttpResponse::Ok().json(concluded_votes),
+            Err(e) => error_response(CONCLUDED_VOTES_ERROR, e),
+        };
+    }
 
-    // Get concluded votes
-    let concluded_votes = match redis.concluded_votes(ntw, config.vote_length()) {
-        Ok(concluded_votes) => concluded_votes,
-        Err(e) => {
-            let res = format!("{}: {}", CONCLUDED_VOTES_ERROR, e);
-            println!("{}", res);
-            return HttpResponse::InternalServerError().body(res);
-        }
+    let (page, next) = match query_params
+        .concluded_page(&**store, ntw, config.vote_length())
+        .await
+    {
+        Ok(Some(page)) => page,
+        Ok(None) => return error_response(CONCLUDED_VOTES_ERROR, unknown_cursor(&query_params)),
+        Err(e) => return error_response(CONCLUDED_VOTES_ERROR, e),
     };
 
-    println!("Concluded votes: {:?}", concluded_votes);
+    println!("Concluded votes: {:?}", page);
 
-    HttpResponse::Ok().json(concluded_votes)
+    HttpResponse::Ok().json(FipPage {
+        votes: page.into_iter().map(|v| v.fip_number).collect(),
+        next,
+    })
 }
 
+#[utoipa::path(
+    tag = "votes",
+    params(HistoryParams),
+    responses(
+        (status = 200, description = "A page of the concluded votes with their final result", body = ResultPage),
+        (status = 400, description = "Malformed request", body = ErrorBody),
+        (status = 500, description = "Database error", body = ErrorBody),
+        (status = 502, description = "Chain could not be queried", body = ErrorBody),
+    )
+)]
 #[get("/filecoin/allconcludedvotes")]
 async fn get_all_concluded_votes(
-    query_params: web::Query<NtwParams>,
+    req: HttpRequest,
+    query_params: web::Query<HistoryParams>,
     config: web::Data<Args>,
+    rules: web::Data<GovernanceRules>,
+    store: web::Data<dyn VoteStore>,
 ) -> impl Responder {
     println!("All concluded votes requested");
 
-    let ntw = match query_params.network.as_str() {
-        "mainnet" => Network::Mainnet,
-        "calibration" => Network::Testnet,
-        _ => return HttpResponse::BadRequest().body(INVALID_NETWORK),
-    };
+    let ntw = query_params.network;
 
-    // Open a connection to the Redis Database
-    let mut redis = match Redis::new(config.redis_path()) {
-        Ok(redis) => redis,
-        Err(e) => {
-            let res = format!("{}: {}", OPEN_CONNECTION_ERROR, e);
-            println!("{}", res);
-            return HttpResponse::InternalServerError().body(res);
-        }
-    };
+    // The unversioned route answers with the result of every concluded vote
+    if is_legacy(&req) {
+        let concluded_votes = match store.concluded_votes(ntw, config.vote_length()).await {
+            Ok(concluded_votes) => concluded_votes,
+            Err(e) => return error_response(CONCLUDED_VOTES_ERROR, e),
+        };
 
-    // Get concluded votes
-    let concluded_votes = match redis.concluded_votes(ntw, config.vote_length()) {
-        Ok(concluded_votes) => concluded_votes,
-        Err(e) => {
-            let res = format!("{}: {}", CONCLUDED_VOTES_ERROR, e);
-            println!("{}", res);
-            return HttpResponse::InternalServerError().body(res);
+        let mut vote_res_map = HashMap::new();
+        for vote in concluded_votes.into_iter() {
+            let results = match store
+                .finalize(vote, ntw, &rules, config.vote_length())
+                .await
+            {
+                Ok(Some(results)) => results,
+                Ok(None) => continue,
+                Err(e) => return error_response(VOTE_RESULTS_ERROR, e),
+            };
+            vote_res_map.insert(vote, results);
         }
+        return HttpResponse::Ok().json(vote_res_map);
+    }
+
+    let (page, next) = match query_params
+        .concluded_page(&**store, ntw, config.vote_length())
+        .await
+    {
+        Ok(Some(page)) => page,
+        Ok(None) => return error_response(CONCLUDED_VOTES_ERROR, unknown_cursor(&query_params)),
+        Err(e) => return error_response(CONCLUDED_VOTES_ERROR, e),
     };
 
-    let mut vote_res_map = HashMap::new();
-    for vote in concluded_votes.into_iter() {
-        let results = match redis.vote_results(vote, ntw) {
-            Ok(results) => results,
-            Err(e) => {
-                let res = format!("{}: {}", VOTE_RESULTS_ERROR, e);
-                println!("{}", res);
-                return HttpResponse::InternalServerError().body(res);
-            }
+    // Only the votes on the page are finalized
+    let mut votes = Vec::with_capacity(page.len());
+    for vote in page {
+        let result = match store
+            .finalize(vote.fip_number, ntw, &rules, config.vote_length())
+            .await
+        {
+            Ok(Some(result)) => result,
+            Ok(None) => continue,
+            Err(e) => return error_response(VOTE_RESULTS_ERROR, e),
         };
-        vote_res_map.insert(vote, results);
+        votes.push(ConcludedVote::new(vote, result, query_params.summary()));
     }
 
-    println!("Concluded votes: {:?}", vote_res_map);
+    println!("Concluded votes: {:?}", votes);
+
+    HttpResponse::Ok().json(ResultPage { votes, next })
+}
 
-    HttpResponse::Ok().json(vote_res_map)
+fn unknown_cursor(query_params: &HistoryParams) -> DomainError {
+    DomainError::Query(format!(
+        "after: FIP {} is not a concluded vote",
+        query_params.after.unwrap_or_default()
+    ))
 }
 
+#[utoipa::path(
+    tag = "voters",
+    params(NtwAddrParams),
+    responses(
+        (status = 200, description = "Raw byte power of the voter's storage providers as plain text", body = String, content_type = "text/plain"),
+        (status = 400, description = "Malformed request", body = ErrorBody),
+        (status = 500, description = "Database error", body = ErrorBody),
+        (status = 502, description = "Chain could not be queried", body = ErrorBody),
+    )
+)]
 #[get("/filecoin/votingpower")]
 async fn get_voting_power(
     query_params: web::Query<NtwAddrParams>,
-    config: web::Data<Args>,
+    store: web::Data<dyn VoteStore>,
 ) -> impl Responder {
     println!("Voting power requested");
-    let address = query_params.address.clone();
-    let ntw = match query_params.network.as_str() {
-        "mainnet" => Network::Mainnet,
-        "calibration" => Network::Testnet,
-        _ => return HttpResponse::BadRequest().body(INVALID_NETWORK),
-    };
+    let address = query_params.address;
+    let ntw = query_params.network;
 
-    let address = match Address::from_str(address.as_str()) {
-        Ok(address) => address,
-        Err(e) => {
-            let res = format!("{}: {}", INVALID_ADDRESS, e);
-            println!("{}", res);
-            return HttpResponse::BadRequest().body(res);
-        }
+    let voting_power = match store.voting_power(address, ntw).await {
+        Ok(voting_power) => voting_power,
+        Err(e) => return error_response(VOTING_POWER_ERROR, e),
     };
 
-    let mut redis = match Redis::new(config.redis_path()) {
-        Ok(redis) => redis,
-        Err(e) => {
-            let res = format!("{}: {}", OPEN_CONNECTION_ERROR, e);
-            println!("{}", res);
-            return HttpResponse::InternalServerError().body(res);
-        }
-    };
+    println!("Voting power: {} for address: {}", voting_power, address);
 
-    let authorized = match redis.voter_delegates(address, ntw) {
-        Ok(delegates) => delegates,
-        Err(e) => {
-            let res = format!("{}: {}", VOTER_DELEGATES_ERROR, e);
-            println!("{}", res);
-            return HttpResponse::InternalServerError().body(res);
-        }
-    };
+    HttpResponse::Ok().body(voting_power.to_string())
+}
+
+#[utoipa::path(
+    tag = "voters",
+    params(NtwAddrParams),
+    responses(
+        (status = 200, description = "Registrations, power, ballots and votes awaiting a ballot of the address", body = VoterProfile),
+        (status = 400, description = "Malformed request", body = ErrorBody),
+        (status = 500, description = "Database error", body = ErrorBody),
+        (status = 502, description = "Chain could not be queried", body = ErrorBody),
+    )
+)]
+#[get("/filecoin/voter")]
+async fn get_voter(
+    query_params: web::Query<NtwAddrParams>,
+    config: web::Data<Args>,
+    store: web::Data<dyn VoteStore>,
+) -> impl Responder {
+    println!("Voter profile requested");
+    let address = query_params.address;
+    let ntw = query_params.network;
 
-    let mut voting_power = 0;
-    if STARTING_AUTHORIZED_VOTERS
-        .map(|s| Address::from_str(s).unwrap())
-        .contains(&address)
+    let profile = match store
+        .voter_profile(address, ntw, config.vote_length())
+        .await
     {
-        voting_power += 10240000;
-    }
-    for delegate in authorized.iter() {
-        match fetch_storage_amount(*delegate, ntw).await {
-            Ok(amount) => voting_power += amount,
-            Err(e) => {
-                let res = format!("{}: {}", VOTING_POWER_ERROR, e);
-                println!("{}", res);
-                return HttpResponse::InternalServerError().body(res);
-            }
-        }
-    }
+        Ok(profile) => profile,
+        Err(e) => return error_response(VOTER_PROFILE_ERROR, e),
+    };
 
-    println!(
-        "Voting power: {} for address: {} and delegates {:?}",
-        voting_power, address, authorized
-    );
+    println!("Voter profile: {:?}", profile);
 
-    HttpResponse::Ok().body(voting_power.to_string())
+    HttpResponse::Ok().json(profile)
 }
 
+#[utoipa::path(
+    tag = "starters",
+    params(NtwParams),
+    responses(
+        (status = 200, description = "Addresses allowed to start votes", body = Vec<String>),
+        (status = 400, description = "Malformed request", body = ErrorBody),
+        (status = 500, description = "Database error", body = ErrorBody),
+    )
+)]
 #[get("/filecoin/voterstarters")]
 async fn get_vote_starters(
     query_params: web::Query<NtwParams>,
-    config: web::Data<Args>,
+    store: web::Data<dyn VoteStore>,
 ) -> impl Responder {
     println!("Vote starters requested");
-    let ntw = match query_params.network.as_str() {
-        "mainnet" => Network::Mainnet,
-        "calibration" => Network::Testnet,
-        _ => return HttpResponse::BadRequest().body(INVALID_NETWORK),
-    };
-
-    // Open a connection to the Redis Database
-    let mut redis = match Redis::new(config.redis_path()) {
-        Ok(redis) => redis,
-        Err(e) => {
-            let res = format!("{}: {}", OPEN_CONNECTION_ERROR, e);
-            println!("{}", res);
-            return HttpResponse::InternalServerError().body(res);
-        }
-    };
+    let ntw = query_params.network;
 
     // Get authorized vote starters
-    let vote_starters = match redis.voter_starters(ntw) {
+    let vote_starters = match store.voter_starters(ntw).await {
         Ok(vote_starters) => vote_starters,
-        Err(e) => {
-            let res = format!("{}: {}", VOTE_STARTERS_ERROR, e);
-            println!("{}", res);
-            return HttpResponse::InternalServerError().body(res);
-        }
+        Err(e) => return error_response(VOTE_STARTERS_ERROR, e),
     };
 
     println!("Vote starters: {:?}", vote_starters);
 
     HttpResponse::Ok().json(vote_starters)
 }
+
+#[utoipa::path(
+    tag = "committees",
+    params(NtwParams),
+    responses(
+        (status = 200, description = "Committee names to their members", body = HashMap<String, Vec<String>>),
+        (status = 400, description = "Malformed request", body = ErrorBody),
+        (status = 500, description = "Database error", body = ErrorBody),
+    )
+)]
+#[get("/filecoin/committees")]
+async fn get_committees(
+    query_params: web::Query<NtwParams>,
+    store: web::Data<dyn VoteStore>,
+) -> impl Responder {
+    println!("Committees requested");
+    let ntw = query_params.network;
+
+    let committees = match store.committees(ntw).await {
+        Ok(committees) => committees,
+        Err(e) => return error_response(COMMITTEES_ERROR, e),
+    };
+
+    println!("Committees: {:?}", committees);
+
+    HttpResponse::Ok().json(committees)
+}
+
+#[utoipa::path(
+    tag = "audit",
+    params(EventParams),
+    responses(
+        (status = 200, description = "A page of the audit log", body = EventPage),
+        (status = 400, description = "Malformed request", body = ErrorBody),
+        (status = 500, description = "Database error", body = ErrorBody),
+    )
+)]
+#[get("/filecoin/events")]
+async fn get_events(
+    query_params: web::Query<EventParams>,
+    store: web::Data<dyn VoteStore>,
+) -> impl Responder {
+    println!("Audit events requested after: {}", query_params.after);
+    let limit = query_params
+        .limit
+        .unwrap_or(MAX_PAGE_SIZE / 10)
+        .clamp(1, MAX_PAGE_SIZE);
+
+    let events = match store.events(query_params.after, limit).await {
+        Ok(events) => events,
+        Err(e) => return error_response(AUDIT_EVENTS_ERROR, e),
+    };
+
+    HttpResponse::Ok().json(EventPage::new(events, limit))
+}
+
+#[utoipa::path(
+    tag = "votes",
+    params(StreamParams),
+    responses(
+        (status = 200, description = "Server-Sent Events as votes start, receive ballots and conclude, and as voters register. Each `data` line is one event as JSON", body = StreamEvent, content_type = "text/event-stream"),
+        (status = 400, description = "Malformed request", body = ErrorBody),
+    )
+)]
+#[get("/filecoin/stream")]
+async fn get_stream(
+    query_params: web::Query<StreamParams>,
+    feed: web::Data<Broadcaster>,
+) -> impl Responder {
+    println!(
+        "Event stream opened for network: {:?}, FIP: {:?}",
+        query_params.network.map(|ntw| ntw.name()),
+        query_params.fip_number
+    );
+    let filter = StreamFilter {
+        network: query_params.network,
+        fip: query_params.fip_number,
+    };
+
+    HttpResponse::Ok()
+        .content_type("text/event-stream")
+        .insert_header(("cache-control", "no-cache"))
+        .streaming(feed.stream(filter))
+}
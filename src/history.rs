@@ -0,0 +1,195 @@
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+use crate::{
+    audit::MAX_PAGE_SIZE,
+    results::Outcome,
+    storage::Network,
+    store::{FinalResult, StoreError, VoteStore},
+    HistoryParams,
+};
+
+/// What concluded votes are ordered by
+#[derive(Debug, Clone, Copy, Default, PartialEq, Deserialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum SortKey {
+    /// Start time of the vote, then FIP number
+    #[default]
+    Started,
+    Fip,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Deserialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum SortOrder {
+    #[default]
+    Asc,
+    Desc,
+}
+
+/// A concluded vote before its results are read
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Concluded {
+    pub fip_number: u32,
+    /// Unix timestamp the vote started at
+    pub started_at: u64,
+}
+
+/// A page of the FIP numbers of concluded votes
+#[derive(Debug, Serialize, ToSchema)]
+pub struct FipPage {
+    pub votes: Vec<u32>,
+    /// Pass as `after` to read the next page, missing on the last page
+    pub next: Option<u32>,
+}
+
+/// A concluded vote and its final result
+#[derive(Debug, Serialize, ToSchema)]
+pub struct ConcludedVote {
+    pub fip_number: u32,
+    /// Unix timestamp the vote started at
+    pub started_at: u64,
+    pub outcome: Outcome,
+    pub ballot_count: u64,
+    pub finalized_at: u64,
+    /// Left out in summary mode
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub result: Option<FinalResult>,
+}
+
+/// A page of concluded votes and their final results
+#[derive(Debug, Serialize, ToSchema)]
+pub struct ResultPage {
+    pub votes: Vec<ConcludedVote>,
+    /// Pass as `after` to read the next page, missing on the last page
+    pub next: Option<u32>,
+}
+
+impl ConcludedVote {
+    pub fn new(vote: Concluded, result: FinalResult, summary: bool) -> Self {
+        Self {
+            fip_number: vote.fip_number,
+            started_at: vote.started_at,
+            outcome: result.outcome,
+            ballot_count: result.ballot_count,
+            finalized_at: result.finalized_at,
+            result: (!summary).then_some(result),
+        }
+    }
+}
+
+impl HistoryParams {
+    /// Page size, 100 unless asked for and at most `MAX_PAGE_SIZE`
+    pub fn limit(&self) -> usize {
+        self.limit
+            .unwrap_or(MAX_PAGE_SIZE / 10)
+            .clamp(1, MAX_PAGE_SIZE)
+    }
+
+    pub fn summary(&self) -> bool {
+        self.summary
+    }
+
+    fn matches(&self, vote: &Concluded) -> bool {
+        self.from.is_none_or(|from| vote.started_at >= from)
+            && self.to.is_none_or(|to| vote.started_at <= to)
+            && self.fip_from.is_none_or(|fip| vote.fip_number >= fip)
+            && self.fip_to.is_none_or(|fip| vote.fip_number <= fip)
+    }
+
+    /// Sorts, filters and cuts out the page the parameters ask for
+    ///
+    /// Returns the page and the cursor of the next one, `None` if `after`
+    /// is not one of the votes.
+    pub fn paginate(&self, mut votes: Vec<Concluded>) -> Option<(Vec<Concluded>, Option<u32>)> {
+        match self.sort {
+            SortKey::Started => votes.sort_by_key(|v| (v.started_at, v.fip_number)),
+            SortKey::Fip => votes.sort_by_key(|v| v.fip_number),
+        }
+        if self.order == SortOrder::Desc {
+            votes.reverse();
+        }
+
+        // The cursor is looked up before filtering, so it stays valid when
+        // the filters change between pages
+        let start = match self.after {
+            Some(after) => votes.iter().position(|v| v.fip_number == after)? + 1,
+            None => 0,
+        };
+
+        let mut matching = votes[start..].iter().filter(|v| self.matches(v)).copied();
+        let page = matching.by_ref().take(self.limit()).collect::<Vec<_>>();
+        let next = match page.last() {
+            Some(last) if matching.next().is_some() => Some(last.fip_number),
+            _ => None,
+        };
+        Some((page, next))
+    }
+
+    /// The concluded votes on the network, sorted and filtered, and the
+    /// cursor of the next page
+    pub async fn concluded_page(
+        &self,
+        store: &dyn VoteStore,
+        ntw: Network,
+        vote_length: u64,
+    ) -> Result<Option<(Vec<Concluded>, Option<u32>)>, StoreError> {
+        let mut votes = Vec::new();
+        for fip_number in store.concluded_votes(ntw, vote_length).await? {
+            let started_at = store.vote_start(fip_number, ntw).await?.unwrap_or_default();
+            votes.push(Concluded {
+                fip_number,
+                started_at,
+            });
+        }
+        Ok(self.paginate(votes))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::web::Query;
+
+    use super::*;
+
+    fn params(query: &str) -> HistoryParams {
+        Query::<HistoryParams>::from_query(&format!("network=calibration&{}", query))
+            .unwrap()
+            .into_inner()
+    }
+
+    fn fips(page: &[Concluded]) -> Vec<u32> {
+        page.iter().map(|v| v.fip_number).collect()
+    }
+
+    #[test]
+    fn history_paginate() {
+        // FIP 3 started first, 1 and 4 together
+        let votes = [(1, 200), (2, 300), (3, 100), (4, 200), (5, 400)]
+            .map(|(fip_number, started_at)| Concluded {
+                fip_number,
+                started_at,
+            })
+            .to_vec();
+        let page = |query: &str| {
+            let (page, next) = params(query).paginate(votes.clone()).unwrap();
+            (fips(&page), next)
+        };
+
+        assert_eq!(page(""), (vec![3, 1, 4, 2, 5], None));
+        assert_eq!(page("sort=fip&order=desc"), (vec![5, 4, 3, 2, 1], None));
+        assert_eq!(page("from=200&to=300"), (vec![1, 4, 2], None));
+        assert_eq!(page("fip_from=2&fip_to=4"), (vec![3, 4, 2], None));
+
+        // Walk the pages with the cursor
+        assert_eq!(page("limit=2"), (vec![3, 1], Some(1)));
+        assert_eq!(page("limit=2&after=1"), (vec![4, 2], Some(2)));
+        assert_eq!(page("limit=2&after=2"), (vec![5], None));
+        assert_eq!(page("limit=2&fip_to=4&after=1"), (vec![4, 2], None));
+
+        assert!(params("after=9").paginate(votes.clone()).is_none());
+        assert_eq!(params("limit=0").limit(), 1);
+        assert_eq!(params("limit=5000").limit(), MAX_PAGE_SIZE);
+        assert!(Query::<HistoryParams>::from_query("network=calibration&sort=ballots").is_err());
+    }
+}
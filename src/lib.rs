@@ -1,21 +1,41 @@
+pub mod admin;
+pub mod api;
+pub mod audit;
+pub mod config;
+pub mod history;
 pub mod redis;
+pub mod results;
+pub mod snapshot;
 pub mod storage;
+pub mod store;
+pub mod stream;
+pub mod webhooks;
 pub mod messages {
     pub mod auth;
+    pub mod batch_vote;
+    pub mod committee;
     pub mod vote_registration;
     pub mod vote_start;
     pub mod votes;
+    pub mod wallet_registration;
 }
 pub mod errors;
 pub mod get;
+pub mod openapi;
 pub mod post;
 
-use std::str::FromStr;
+use std::{path::PathBuf, str::FromStr};
 
-use clap::{arg, command, Parser};
+use clap::Parser;
 use ethers::types::Address;
 use serde::Deserialize;
 use url::Url;
+use utoipa::IntoParams;
+
+use admin::Command;
+use config::{Config, ConfigError};
+use history::{SortKey, SortOrder};
+use storage::Network;
 
 const STARTING_AUTHORIZED_VOTERS: [&str; 3] = [
     "0x3B9705F0EF88Ee74B9924e34A5Af578d2E24F300",
@@ -31,12 +51,19 @@ const DEFAULT_SERVE_ADDRESS: &str = "http://127.0.0.1:51634";
 #[derive(Parser, Clone)]
 #[command(name = "filecoin-vote")]
 pub struct Args {
-    #[arg(short, long, default_value = DEFAULT_SERVE_ADDRESS)]
+    #[arg(short, long, global = true, default_value = DEFAULT_SERVE_ADDRESS)]
     pub serve_address: Url,
-    #[arg(short, long, default_value = REDIS_DEFAULT_PATH)]
+    /// Database to store votes in, a `redis://`, `sqlite://` or `memory://` url
+    #[arg(short, long, global = true, alias = "database", default_value = REDIS_DEFAULT_PATH)]
     pub redis_path: Url,
-    #[arg(short, long, default_value = VOTE_LENGTH)]
+    #[arg(short, long, global = true, default_value = VOTE_LENGTH)]
     pub vote_length: u64,
+    /// JSON file describing the networks to serve, see config.example.json
+    #[arg(short, long = "config", global = true)]
+    pub config_path: Option<PathBuf>,
+    /// What to do, serves the API when left out
+    #[command(subcommand)]
+    pub command: Option<Command>,
 }
 
 impl Default for Args {
@@ -61,28 +88,109 @@ impl Args {
     pub fn serve_address(&self) -> Url {
         self.serve_address.clone()
     }
+
+    pub fn config(&self) -> Result<Config, ConfigError> {
+        match &self.config_path {
+            Some(path) => Config::load(path),
+            None => Ok(Config::default()),
+        }
+    }
 }
 
-#[derive(Deserialize)]
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
 pub struct NtwFipParams {
-    network: String,
+    /// Name of a configured network, such as `mainnet` or `calibration`
+    #[param(value_type = String)]
+    network: Network,
     fip_number: u32,
 }
 
-#[derive(Deserialize)]
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
 pub struct NtwAddrParams {
-    network: String,
-    address: String,
+    /// Name of a configured network, such as `mainnet` or `calibration`
+    #[param(value_type = String)]
+    network: Network,
+    /// 20 byte hex address of the voter
+    #[param(value_type = String)]
+    address: Address,
 }
 
-#[derive(Deserialize)]
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
 pub struct FipParams {
     fip_number: u32,
+    /// Must be the network the ballot was signed for, if given
+    #[param(value_type = Option<String>)]
+    network: Option<Network>,
 }
 
-#[derive(Deserialize)]
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct BatchVoteParams {
+    /// Must be the network the ballot was signed for, if given
+    #[param(value_type = Option<String>)]
+    network: Option<Network>,
+}
+
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
 pub struct NtwParams {
-    network: String,
+    /// Name of a configured network, such as `mainnet` or `calibration`
+    #[param(value_type = String)]
+    network: Network,
+}
+
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct HistoryParams {
+    /// Name of a configured network, such as `mainnet` or `calibration`
+    #[param(value_type = String)]
+    network: Network,
+    /// FIP number the previous page ended with, as returned in `next`
+    after: Option<u32>,
+    /// Page size, 100 by default and at most 1000
+    limit: Option<usize>,
+    /// Only votes started at or after this unix timestamp
+    from: Option<u64>,
+    /// Only votes started at or before this unix timestamp
+    to: Option<u64>,
+    /// Lowest FIP number to include
+    fip_from: Option<u32>,
+    /// Highest FIP number to include
+    fip_to: Option<u32>,
+    /// `started` by default, ties are ordered by FIP number
+    #[serde(default)]
+    #[param(inline)]
+    sort: SortKey,
+    /// `asc` by default
+    #[serde(default)]
+    #[param(inline)]
+    order: SortOrder,
+    /// Leaves the results of each constituency out
+    #[serde(default)]
+    summary: bool,
+}
+
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct EventParams {
+    /// Only events with a higher id are returned
+    #[serde(default)]
+    after: u64,
+    /// Page size, 100 by default and at most 1000
+    limit: Option<usize>,
+}
+
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct StreamParams {
+    /// Only events of this network, every network when left out
+    #[param(value_type = Option<String>)]
+    network: Option<Network>,
+    /// Only events about this FIP, registrations and starters are left out when given
+    fip_number: Option<u32>,
 }
 
 pub fn authorized_voters() -> Vec<Address> {
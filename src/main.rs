@@ -1,4 +1,8 @@
-use std::{fs::File, io::BufReader};
+use std::{
+    fs::File,
+    io::BufReader,
+    time::{self, Duration},
+};
 
 use actix_cors::Cors;
 use actix_web::{web, App, HttpServer};
@@ -6,14 +10,13 @@ use rustls::ServerConfig;
 use rustls_pemfile::{certs, pkcs8_private_keys};
 
 use fip_voting::{
-    authorized_voters,
-    get::{
-        get_active_votes, get_all_concluded_votes, get_concluded_votes, get_delegates,
-        get_vote_starters, get_votes, get_voting_power,
-    },
-    post::{register_vote, register_vote_starter, register_voter, start_vote, unregister_voter},
-    redis::Redis,
-    storage::Network,
+    admin::{self, Command},
+    api, authorized_voters,
+    errors::query_error,
+    storage::{init_networks, networks},
+    store,
+    stream::Broadcaster,
+    webhooks::Webhooks,
     Args,
 };
 
@@ -56,18 +59,70 @@ async fn main() -> std::io::Result<()> {
         _ => panic!("Invalid scheme"),
     };
 
-    let mut redis = Redis::new(args.redis_path()).unwrap();
+    let config = args.config().unwrap();
+    init_networks(config.networks).unwrap();
+    let governance = config.governance;
+    let webhooks = Webhooks::new(config.webhooks, args.vote_length()).unwrap();
+
+    if let Some(command) = args.command.clone() {
+        if command != Command::Serve {
+            if let Err(e) = admin::run(command, &args, &governance).await {
+                eprintln!("{}", e);
+                std::process::exit(1);
+            }
+            return Ok(());
+        }
+    }
+
+    let store = store::open(&args.redis_path()).await.unwrap();
+
+    // A new database starts with the founders as its starters, later
+    // revocations are kept across restarts
+    for ntw in networks() {
+        store
+            .init_voter_starters(ntw, authorized_voters())
+            .await
+            .unwrap();
+    }
 
-    let ntws = vec![Network::Mainnet, Network::Testnet];
-    for ntw in ntws {
-        let voter_starters = redis.voter_starters(ntw).unwrap();
-        for voter in authorized_voters() {
-            if voter_starters.contains(&voter) {
-                continue;
-            } else {
-                redis.register_voter_starter(voter, ntw).unwrap();
+    // Record the result of every vote once it concludes, results requests
+    // finalize votes too but may come much later
+    let finalizer = (store.clone(), governance.clone(), args.vote_length());
+    actix_web::rt::spawn(async move {
+        let (store, rules, vote_length) = finalizer;
+        let mut interval = actix_web::rt::time::interval(Duration::from_secs(60));
+        loop {
+            interval.tick().await;
+            for ntw in networks() {
+                if let Err(e) = store.finalize_concluded(ntw, &rules, vote_length).await {
+                    println!("Error finalizing votes on {}: {}", ntw.name(), e);
+                }
             }
         }
+    });
+
+    // Announce votes to the event streams as they conclude, picking up
+    // where the last run stopped
+    let feed = Broadcaster::new(args.vote_length());
+    let announcer = (store.clone(), governance.clone(), feed.clone());
+    actix_web::rt::spawn(async move {
+        let (store, rules, feed) = announcer;
+        let mut interval = actix_web::rt::time::interval(Duration::from_secs(5));
+        loop {
+            interval.tick().await;
+            let now = time::SystemTime::now()
+                .duration_since(time::UNIX_EPOCH)
+                .map_or(0, |d| d.as_secs());
+            for ntw in networks() {
+                if let Err(e) = feed.announce_concluded(&*store, ntw, &rules, now).await {
+                    println!("Error announcing votes on {}: {}", ntw.name(), e);
+                }
+            }
+        }
+    });
+
+    if !webhooks.is_empty() {
+        actix_web::rt::spawn(webhooks.run(store.clone(), governance.clone()));
     }
 
     let server = HttpServer::new(move || {
@@ -79,19 +134,12 @@ async fn main() -> std::io::Result<()> {
 
         App::new()
             .wrap(cors)
+            .app_data(web::QueryConfig::default().error_handler(query_error))
             .app_data(web::Data::new(args.clone()))
-            .service(get_votes)
-            .service(get_voting_power)
-            .service(get_vote_starters)
-            .service(get_delegates)
-            .service(get_concluded_votes)
-            .service(get_active_votes)
-            .service(get_all_concluded_votes)
-            .service(register_vote)
-            .service(register_voter)
-            .service(unregister_voter)
-            .service(register_vote_starter)
-            .service(start_vote)
+            .app_data(web::Data::new(governance.clone()))
+            .app_data(web::Data::from(store.clone()))
+            .app_data(web::Data::new(feed.clone()))
+            .configure(api::configure)
     });
     /*
     .bind((serve_address.host().unwrap().to_string(), port))?
@@ -1,38 +1,215 @@
 use std::str::FromStr;
 
-use ethers::types::{Address, Signature};
+use ethers::{types::Address, utils::keccak256};
 use serde::Deserialize;
+use utoipa::ToSchema;
 
-use super::votes::VoteError;
+use super::votes::{recover_personal_sign, VoteError};
+use crate::storage::Network;
 
-#[derive(Deserialize, Debug)]
+/// Seconds a starter message may be used before or after it was issued
+pub const MESSAGE_LIFETIME: u64 = 600;
+
+/// Message scheme
+///
+/// ADD STARTER: 0xf2361d2a9a0677e8ffd1515d65cf5190ea20eb56
+/// network: mainnet
+/// issued: 1700000000
+///
+/// `issued` is the unix timestamp the message was signed at
+#[derive(Deserialize, Debug, ToSchema)]
 pub struct VoterAuthorization {
     signature: String,
     message: String,
 }
 
+/// Message scheme
+///
+/// REMOVE STARTER: 0xf2361d2a9a0677e8ffd1515d65cf5190ea20eb56
+/// network: mainnet
+/// issued: 1700000000
+///
+/// The prefix keeps a signed grant from being replayed as a revocation
+#[derive(Deserialize, Debug, ToSchema)]
+pub struct StarterRevocation {
+    signature: String,
+    message: String,
+}
+
+/// A signed grant or revocation of the right to start votes
+#[derive(Debug, PartialEq)]
+pub struct StarterChange {
+    pub signer: Address,
+    pub starter: Address,
+    /// See [`message_id`]
+    pub id: [u8; 32],
+}
+
 impl VoterAuthorization {
-    /// Returns a tuple of (signer, authorized address)
-    pub fn auth(&self) -> Result<(Address, Address), VoteError> {
-        let signer = self.pub_key()?;
-        let address = match Address::from_str(&self.message) {
-            Ok(address) => address,
-            Err(_) => return Err(VoteError::InvalidMessageFormat),
-        };
-
-        Ok((signer, address))
-    }
-    fn pub_key(&self) -> Result<Address, VoteError> {
-        let signature = Signature::from_str(&self.signature)?;
-        let msg = format!(
-            "\x19Ethereum Signed Message:\n{}{}",
-            self.message.len(),
-            self.message
-        );
-        let message_hash = ethers::utils::keccak256(msg);
-
-        let address = signature.recover(message_hash)?;
-
-        Ok(address)
+    /// The signer and the address it authorizes, if the message is for the
+    /// network and was issued around the unix timestamp `now`
+    pub fn auth(&self, ntw: Network, now: u64) -> Result<StarterChange, VoteError> {
+        starter_change(&self.signature, &self.message, "ADD STARTER: ", ntw, now)
+    }
+}
+
+impl StarterRevocation {
+    /// The signer and the address it revokes, if the message is for the
+    /// network and was issued around the unix timestamp `now`
+    pub fn revocation(&self, ntw: Network, now: u64) -> Result<StarterChange, VoteError> {
+        starter_change(&self.signature, &self.message, "REMOVE STARTER: ", ntw, now)
+    }
+}
+
+fn starter_change(
+    signature: &str,
+    message: &str,
+    prefix: &str,
+    ntw: Network,
+    now: u64,
+) -> Result<StarterChange, VoteError> {
+    let signer = recover_personal_sign(signature, message)?;
+
+    let bound = BoundMessage::parse(message, prefix).ok_or(VoteError::InvalidMessageFormat)?;
+    let starter = Address::from_str(bound.subject).map_err(|_| VoteError::InvalidMessageFormat)?;
+    bound.check(ntw, now)?;
+
+    Ok(StarterChange {
+        signer,
+        starter,
+        id: message_id(signer.as_bytes(), message),
+    })
+}
+
+/// A message bound to a network and the time it was issued at
+///
+/// PREFIX: subject
+/// network: mainnet
+/// issued: 1700000000
+#[derive(Debug, PartialEq)]
+pub struct BoundMessage<'a> {
+    /// What follows the prefix on the first line
+    pub subject: &'a str,
+    pub network: &'a str,
+    pub issued: u64,
+}
+
+impl<'a> BoundMessage<'a> {
+    /// Splits a message of the scheme, none if it does not start with the prefix
+    pub fn parse(message: &'a str, prefix: &str) -> Option<BoundMessage<'a>> {
+        let mut lines = message.lines();
+        let subject = lines.next()?.strip_prefix(prefix)?.trim();
+        let network = lines.next()?.strip_prefix("network:")?.trim();
+        let issued = lines.next()?.strip_prefix("issued:")?.trim().parse().ok()?;
+        if lines.next().is_some() {
+            return None;
+        }
+
+        Some(BoundMessage {
+            subject,
+            network,
+            issued,
+        })
+    }
+
+    /// Whether the message was issued within `MESSAGE_LIFETIME` of `now`
+    pub fn is_fresh(&self, now: u64) -> bool {
+        self.issued.abs_diff(now) <= MESSAGE_LIFETIME
+    }
+
+    /// Fails unless the message is for the network and fresh
+    pub fn check(&self, ntw: Network, now: u64) -> Result<(), VoteError> {
+        if self.network != ntw.name() {
+            return Err(VoteError::WrongNetwork);
+        }
+        if !self.is_fresh(now) {
+            return Err(VoteError::StaleMessage);
+        }
+        Ok(())
+    }
+}
+
+/// Keccak-256 of the signer and the message, recorded so that a signed
+/// message is accepted once
+pub fn message_id(signer: &[u8], message: &str) -> [u8; 32] {
+    keccak256([signer, message.as_bytes()].concat())
+}
+
+#[cfg(test)]
+pub mod test_auth {
+    use ethers::signers::{LocalWallet, Signer};
+
+    use super::*;
+
+    /// A message binding the address to the network and the time it was issued
+    pub fn bound_message(prefix: &str, address: Address, ntw: Network, issued: u64) -> String {
+        format!(
+            "{prefix} {address:?}\nnetwork: {}\nissued: {issued}",
+            ntw.name()
+        )
+    }
+
+    /// The message signed by the wallet, as the body of a request
+    pub async fn signed(wallet: &LocalWallet, message: &str) -> serde_json::Value {
+        let signature = wallet.sign_message(message).await.unwrap();
+        serde_json::json!({ "signature": signature.to_string(), "message": message })
+    }
+}
+
+#[cfg(test)]
+mod auth_tests {
+    use ethers::signers::{LocalWallet, Signer};
+
+    use super::{test_auth::*, *};
+
+    #[tokio::test]
+    async fn auth_bound_messages() {
+        let wallet = LocalWallet::from_str(&"11".repeat(32)).unwrap();
+        let calibration = Network::from_name("calibration").unwrap();
+        let mainnet = Network::from_name("mainnet").unwrap();
+        let starter = Address::repeat_byte(1);
+
+        let message = bound_message("ADD STARTER:", starter, calibration, 1000);
+        let auth: VoterAuthorization =
+            serde_json::from_value(signed(&wallet, &message).await).unwrap();
+        let change = auth.auth(calibration, 1000 + MESSAGE_LIFETIME).unwrap();
+        assert_eq!(change.signer, wallet.address());
+        assert_eq!(change.starter, starter);
+
+        // Bound to the network, the time it was issued and its purpose
+        assert!(matches!(
+            auth.auth(mainnet, 1000),
+            Err(VoteError::WrongNetwork)
+        ));
+        assert!(matches!(
+            auth.auth(calibration, 1001 + MESSAGE_LIFETIME),
+            Err(VoteError::StaleMessage)
+        ));
+        assert!(matches!(
+            auth.auth(calibration, 999 - MESSAGE_LIFETIME),
+            Err(VoteError::StaleMessage)
+        ));
+        let revocation: StarterRevocation =
+            serde_json::from_value(signed(&wallet, &message).await).unwrap();
+        assert!(matches!(
+            revocation.revocation(calibration, 1000),
+            Err(VoteError::InvalidMessageFormat)
+        ));
+
+        // Messages issued at another time are told apart
+        let message = bound_message("REMOVE STARTER:", starter, calibration, 1001);
+        let revocation: StarterRevocation =
+            serde_json::from_value(signed(&wallet, &message).await).unwrap();
+        let revoked = revocation.revocation(calibration, 1000).unwrap();
+        assert_eq!(revoked.starter, starter);
+        assert_ne!(revoked.id, change.id);
+
+        // The bare address grants of before are refused
+        let bare: VoterAuthorization =
+            serde_json::from_value(signed(&wallet, &format!("{starter:?}")).await).unwrap();
+        assert!(matches!(
+            bare.auth(calibration, 1000),
+            Err(VoteError::InvalidMessageFormat)
+        ));
     }
 }
@@ -0,0 +1,168 @@
+use ethers::types::Address;
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+use super::votes::{recover_personal_sign, split_network, Vote, VoteError, VoteOption};
+use crate::{errors::ErrorBody, storage::Network};
+
+/// Most FIPs one message may cast ballots on
+pub const MAX_BATCH_SIZE: usize = 50;
+
+/// Message scheme
+///
+/// FIP-12: YAY; FIP-13: NAY; FIP-14: ABSTAIN
+/// network: mainnet
+///
+/// Casts a ballot on each FIP listed, every FIP at most once
+#[derive(Deserialize, Debug, ToSchema)]
+pub struct ReceivedBatchVote {
+    signature: String,
+    message: String,
+}
+
+/// What happened to the ballot on one FIP of a batch
+#[derive(Debug, Serialize, ToSchema)]
+pub struct BallotOutcome {
+    pub fip_number: u32,
+    /// HTTP status the ballot alone would have been answered with
+    pub status: u16,
+    /// Why the ballot was not recorded, missing if it was
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<ErrorBody>,
+}
+
+/// The outcome of every ballot of a batch, in the order they were listed
+#[derive(Debug, Serialize, ToSchema)]
+pub struct BatchVoteResult {
+    pub ballots: Vec<BallotOutcome>,
+}
+
+impl ReceivedBatchVote {
+    /// Returns the signer, the network the ballots were signed for and a
+    /// ballot for each FIP, in the order they are listed
+    pub fn votes(&self) -> Result<(Address, Network, Vec<Vote>), VoteError> {
+        let (ntw, choices) = self.msg_details()?;
+        let address = recover_personal_sign(&self.signature, &self.message)?;
+
+        let votes = choices
+            .into_iter()
+            .map(|(fip, choice)| Vote::new(choice, address, fip))
+            .collect();
+        Ok((address, ntw, votes))
+    }
+    fn msg_details(&self) -> Result<(Network, Vec<(u32, VoteOption)>), VoteError> {
+        let (ballots, ntw) = split_network(&self.message)?;
+        let mut choices: Vec<(u32, VoteOption)> = Vec::new();
+        // A trailing separator is allowed
+        for part in ballots.split(';').filter(|p| !p.trim().is_empty()) {
+            let (fip, choice) = part
+                .split_once(':')
+                .ok_or(VoteError::InvalidMessageFormat)?;
+
+            let fip = fip
+                .trim()
+                .strip_prefix("FIP-")
+                .ok_or(VoteError::InvalidMessageFormat)?
+                .parse::<u32>()
+                .map_err(|_| VoteError::InvalidMessageFormat)?;
+
+            let choice = match choice.trim() {
+                "YAY" => VoteOption::Yay,
+                "NAY" => VoteOption::Nay,
+                "ABSTAIN" => VoteOption::Abstain,
+                _ => return Err(VoteError::InvalidVoteOption),
+            };
+
+            if choices.iter().any(|(listed, _)| *listed == fip) {
+                return Err(VoteError::InvalidMessageFormat);
+            }
+            choices.push((fip, choice));
+        }
+
+        if choices.is_empty() || choices.len() > MAX_BATCH_SIZE {
+            return Err(VoteError::InvalidMessageFormat);
+        }
+        Ok((ntw, choices))
+    }
+}
+
+#[cfg(test)]
+mod batch_vote_tests {
+    use std::str::FromStr;
+
+    use ethers::signers::{LocalWallet, Signer};
+
+    use super::*;
+
+    async fn signed(message: &str) -> (Address, ReceivedBatchVote) {
+        let wallet = LocalWallet::from_str(
+            "4c0883a69102937d6231471b5dbb6204fe5129617082792ae468d01a3f362318",
+        )
+        .unwrap();
+        let signature = wallet.sign_message(message).await.unwrap();
+
+        let batch = ReceivedBatchVote {
+            signature: signature.to_string(),
+            message: message.to_string(),
+        };
+        (wallet.address(), batch)
+    }
+
+    #[tokio::test]
+    async fn batch_vote_votes() {
+        let (signer, batch) =
+            signed("FIP-12: YAY; FIP-13: NAY;FIP-14:ABSTAIN;\nnetwork: mainnet").await;
+
+        let (recovered, ntw, votes) = batch.votes().unwrap();
+
+        assert_eq!(recovered, signer);
+        assert_eq!(ntw.name(), "mainnet");
+        assert_eq!(
+            votes,
+            vec![
+                Vote::new(VoteOption::Yay, signer, 12),
+                Vote::new(VoteOption::Nay, signer, 13),
+                Vote::new(VoteOption::Abstain, signer, 14),
+            ]
+        );
+        let choices = votes.iter().map(Vote::choice).collect::<Vec<_>>();
+        assert_eq!(
+            choices,
+            vec![VoteOption::Yay, VoteOption::Nay, VoteOption::Abstain]
+        );
+    }
+
+    #[tokio::test]
+    async fn batch_vote_invalid_message() {
+        let too_many = (1..=MAX_BATCH_SIZE as u32 + 1)
+            .map(|fip| format!("FIP-{}: YAY", fip))
+            .collect::<Vec<_>>()
+            .join("; ");
+
+        for ballots in [
+            "",
+            "FIP-12 YAY",
+            "FIP-12: YAY; FIP-12: NAY",
+            "12: YAY",
+            too_many.as_str(),
+        ] {
+            let (_, batch) = signed(&format!("{ballots}\nnetwork: mainnet")).await;
+            assert!(matches!(
+                batch.votes(),
+                Err(VoteError::InvalidMessageFormat)
+            ));
+        }
+
+        // The network is named on a line of its own
+        let (_, batch) = signed("FIP-12: YAY").await;
+        assert!(matches!(
+            batch.votes(),
+            Err(VoteError::InvalidMessageFormat)
+        ));
+        let (_, batch) = signed("FIP-12: YAY\nnetwork: nope").await;
+        assert!(matches!(batch.votes(), Err(VoteError::WrongNetwork)));
+
+        let (_, batch) = signed("FIP-12: MAYBE\nnetwork: mainnet").await;
+        assert!(matches!(batch.votes(), Err(VoteError::InvalidVoteOption)));
+    }
+}
@@ -0,0 +1,147 @@
+use std::str::FromStr;
+
+use ethers::types::Address;
+use serde::Deserialize;
+use utoipa::ToSchema;
+
+use super::{
+    auth::{message_id, BoundMessage},
+    votes::{recover_personal_sign, VoteError},
+};
+use crate::storage::Network;
+
+/// Message scheme
+///
+/// COMMITTEE: core-devs 0xabcdef0123456789 0xabcdef0123456789
+/// network: mainnet
+/// issued: 1700000000
+///
+/// Replaces the members of the named committee,
+/// a message without members removes the committee
+#[derive(Deserialize, Debug, ToSchema)]
+pub struct CommitteeUpdate {
+    signature: String,
+    message: String,
+}
+
+/// A signed replacement of the members of a committee
+#[derive(Debug, PartialEq)]
+pub struct CommitteeChange {
+    pub signer: Address,
+    pub name: String,
+    pub members: Vec<Address>,
+    /// See [`message_id`]
+    pub id: [u8; 32],
+}
+
+impl CommitteeUpdate {
+    /// The signer and the committee it sets, if the message is for the
+    /// network and was issued around the unix timestamp `now`
+    pub fn auth(&self, ntw: Network, now: u64) -> Result<CommitteeChange, VoteError> {
+        let signer = recover_personal_sign(&self.signature, &self.message)?;
+        let bound = BoundMessage::parse(&self.message, "COMMITTEE:")
+            .ok_or(VoteError::InvalidMessageFormat)?;
+        let (name, members) = msg_details(bound.subject)?;
+        bound.check(ntw, now)?;
+
+        Ok(CommitteeChange {
+            signer,
+            name,
+            members,
+            id: message_id(signer.as_bytes(), &self.message),
+        })
+    }
+}
+
+fn msg_details(subject: &str) -> Result<(String, Vec<Address>), VoteError> {
+    let mut words = subject.split_whitespace();
+
+    let name = match words.next() {
+        Some(name) if is_committee_name(name) => name.to_string(),
+        _ => return Err(VoteError::InvalidMessageFormat),
+    };
+
+    let mut members = Vec::new();
+    for word in words {
+        match Address::from_str(word) {
+            Ok(member) => members.push(member),
+            Err(_) => return Err(VoteError::InvalidMessageFormat),
+        }
+    }
+    members.sort();
+    members.dedup();
+
+    Ok((name, members))
+}
+
+/// Committee names are made of lowercase letters, digits, dashes and underscores
+pub fn is_committee_name(name: &str) -> bool {
+    !name.is_empty()
+        && name
+            .chars()
+            .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-' || c == '_')
+}
+
+#[cfg(test)]
+mod committee_tests {
+    use ethers::signers::{LocalWallet, Signer};
+
+    use super::*;
+    use crate::messages::auth::MESSAGE_LIFETIME;
+
+    fn calibration() -> Network {
+        Network::from_name("calibration").unwrap()
+    }
+
+    async fn signed(message: &str) -> (Address, CommitteeUpdate) {
+        let wallet = LocalWallet::from_str(
+            "4c0883a69102937d6231471b5dbb6204fe5129617082792ae468d01a3f362318",
+        )
+        .unwrap();
+        let signature = wallet.sign_message(message).await.unwrap();
+
+        let update = CommitteeUpdate {
+            signature: signature.to_string(),
+            message: message.to_string(),
+        };
+        (wallet.address(), update)
+    }
+
+    #[tokio::test]
+    async fn committee_auth() {
+        let member = "0xf2361d2a9a0677e8ffd1515d65cf5190ea20eb56";
+        let message = format!("COMMITTEE: core-devs {member}\nnetwork: calibration\nissued: 1000");
+        let (signer, update) = signed(&message).await;
+
+        let change = update.auth(calibration(), 1000).unwrap();
+
+        assert_eq!(change.signer, signer);
+        assert_eq!(change.name, "core-devs");
+        assert_eq!(change.members, vec![Address::from_str(member).unwrap()]);
+
+        // Bound to the network and the time it was issued
+        let mainnet = Network::from_name("mainnet").unwrap();
+        assert!(matches!(
+            update.auth(mainnet, 1000),
+            Err(VoteError::WrongNetwork)
+        ));
+        assert!(matches!(
+            update.auth(calibration(), 1001 + MESSAGE_LIFETIME),
+            Err(VoteError::StaleMessage)
+        ));
+    }
+
+    #[tokio::test]
+    async fn committee_invalid_message() {
+        for message in [
+            "COMMITTEE:\nnetwork: calibration\nissued: 1000",
+            "COMMITTEE: Core 0x00\nnetwork: calibration\nissued: 1000",
+            "core-devs 0x00\nnetwork: calibration\nissued: 1000",
+            "COMMITTEE: core-devs",
+        ] {
+            let (_, update) = signed(message).await;
+
+            assert!(update.auth(calibration(), 1000).is_err());
+        }
+    }
+}
@@ -5,6 +5,7 @@ use ethers::types::Address;
 use regex::Regex;
 use serde::Deserialize;
 use thiserror::Error;
+use utoipa::ToSchema;
 
 use crate::storage::{verify_id, Network, StorageFetchError};
 
@@ -28,6 +29,8 @@ pub enum VoteRegistrationError {
     InvalidAddress,
     #[error("Invalid storage provider id")]
     InvalidStorageProviderId(#[from] ParseIntError),
+    #[error("Worker address does not belong to a configured network")]
+    InvalidNetwork,
 }
 
 /// Raw json to authorize an ethereum address
@@ -37,11 +40,16 @@ pub enum VoteRegistrationError {
 /// the list of storage provider id's delimited by spaces
 ///
 /// 0xabcdef0123456789 f0xxxx f0xxxx
-#[derive(Deserialize)]
+///
+/// `network` is only needed when several configured networks
+/// share the worker address prefix
+#[derive(Deserialize, ToSchema)]
 pub struct ReceivedVoterRegistration {
     signature: String,
     worker_address: String,
     message: String,
+    #[serde(default)]
+    network: Option<String>,
 }
 
 /// This struct represents an authorized eth address to vote on behalf
@@ -51,6 +59,8 @@ pub struct VoterRegistration {
     authorized_voter: Address,
     network: Network,
     sp_ids: Vec<u32>,
+    /// The worker address that signed the registration
+    worker_address: String,
 }
 
 impl VoterRegistration {
@@ -63,6 +73,9 @@ impl VoterRegistration {
     pub fn sp_ids(&self) -> Vec<u32> {
         self.sp_ids.clone()
     }
+    pub fn worker(&self) -> String {
+        self.worker_address.clone()
+    }
 }
 
 impl ReceivedVoterRegistration {
@@ -118,43 +131,31 @@ impl ReceivedVoterRegistration {
             authorized_voter: address,
             network: ntw,
             sp_ids: new_ids,
+            worker_address: self.worker_address.to_lowercase(),
         })
     }
 
     fn pub_key(&self) -> Result<(PublicKey, Network), VoteRegistrationError> {
-        let testnet_base32 = Regex::new(r"(?i)^[t][3][A-Z2-7]{84}$").unwrap();
-        let mainnet_base32 = Regex::new(r"(?i)^[f][3][A-Z2-7]{84}$").unwrap();
-
-        let ntw: Network;
-
-        let bytes = match testnet_base32.is_match(&self.worker_address) {
-            true => {
-                match base32::decode(
-                    base32::Alphabet::RFC4648 { padding: false },
-                    &self.worker_address[2..self.worker_address.len() - 6],
-                ) {
-                    Some(bytes) => {
-                        ntw = Network::Testnet;
-                        bytes
-                    }
-                    None => return Err(VoteRegistrationError::InvalidWorkerAddress),
-                }
-            }
-            false => match mainnet_base32.is_match(&self.worker_address) {
-                true => {
-                    match base32::decode(
-                        base32::Alphabet::RFC4648 { padding: false },
-                        &self.worker_address[2..self.worker_address.len() - 6],
-                    ) {
-                        Some(bytes) => {
-                            ntw = Network::Mainnet;
-                            bytes
-                        }
-                        None => return Err(VoteRegistrationError::InvalidWorkerAddress),
-                    }
-                }
-                false => return Err(VoteRegistrationError::InvalidWorkerAddress),
-            },
+        let base32 = Regex::new(r"(?i)^[ft][3][A-Z2-7]{84}$").unwrap();
+
+        if !base32.is_match(&self.worker_address) {
+            return Err(VoteRegistrationError::InvalidWorkerAddress);
+        }
+
+        // Networks can share a prefix so an explicitly named network takes precedence
+        let prefix = self.worker_address.as_bytes()[0].to_ascii_lowercase() as char;
+        let ntw = match &self.network {
+            Some(name) => Network::from_name(name).filter(|ntw| ntw.prefix() == prefix),
+            None => Network::from_address(&self.worker_address),
+        }
+        .ok_or(VoteRegistrationError::InvalidNetwork)?;
+
+        let bytes = match base32::decode(
+            base32::Alphabet::RFC4648 { padding: false },
+            &self.worker_address[2..self.worker_address.len() - 6],
+        ) {
+            Some(bytes) => bytes,
+            None => return Err(VoteRegistrationError::InvalidWorkerAddress),
         };
 
         Ok((PublicKey::from_bytes(bytes.as_slice())?, ntw))
@@ -173,7 +174,8 @@ pub mod test_voter_registration {
         ReceivedVoterRegistration {
             signature: "0299f5c42a957809d0bd80cb29986b811fbacd1ed84b5995f1d21c6a7063cada725fe0c643bbcdc4082b078d1420fc9e7d08f9c28c9dbf4597183dd92c2fa2ff7727eee2e6f84fb24134051005ea93b3bfe5e294d2e1413bf111440afdadfa0744".to_string(), 
             worker_address: "t3qejyqmrirddrsb2w2thbaco3q6emuljumlhuonp3al35g3kkzx4zpeecycw7gim2meegemwot3gp3qr6alpa".to_string(), 
-            message: "2030784632333631443241394130363737653866664431353135643635434635313930654132306542353620743036303234".to_string() 
+            message: "2030784632333631443241394130363737653866664431353135643635434635313930654132306542353620743036303234".to_string(),
+            network: None,
         }
     }
 }
@@ -200,7 +202,7 @@ mod vote_registration_tests {
 
         let (_, ntw) = pub_key.unwrap();
 
-        assert_eq!(ntw, Network::Testnet);
+        assert_eq!(ntw, Network::from_name("calibration").unwrap());
     }
 
     #[tokio::test]
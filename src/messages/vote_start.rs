@@ -1,27 +1,122 @@
-use std::str::FromStr;
+use ethers::types::Address;
+use serde::{Deserialize, Serialize};
+use url::Url;
+use utoipa::ToSchema;
 
-use ethers::types::{Address, Signature};
-use serde::Deserialize;
+use super::votes::{recover_personal_sign, VoteError};
 
-use super::votes::VoteError;
+/// Longest value a metadata line may carry, in characters
+const MAX_METADATA_LENGTH: usize = 2000;
 
-#[derive(Deserialize, Debug)]
+/// Message scheme
+///
+/// FIP-xxx core-devs lotus
+/// title: Sector duration multiplier
+/// summary: Rewards sectors committed for longer
+/// discussion: https://github.com/filecoin-project/FIPs/discussions/554
+/// hash: 0x<32 byte hash of the FIP document>
+/// author: Jane Doe
+///
+/// The FIP number is optionally followed by the committees taking part in the
+/// vote. Every metadata line is optional.
+#[derive(Deserialize, Debug, ToSchema)]
 pub struct VoteStart {
     signature: String,
     pub message: String,
 }
 
+/// Describes the FIP a vote is on, signed along with the vote start
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq, Eq, ToSchema)]
+#[serde(default)]
+pub struct FipMetadata {
+    pub title: Option<String>,
+    pub summary: Option<String>,
+    /// Where the FIP is discussed, an http or https url
+    pub discussion_url: Option<String>,
+    /// Hex encoded 32 byte hash of the FIP document
+    pub document_hash: Option<String>,
+    pub author: Option<String>,
+}
+
+impl FipMetadata {
+    pub fn is_empty(&self) -> bool {
+        *self == Self::default()
+    }
+}
+
 impl VoteStart {
     /// Returns a tuple of (signer, fip)
     pub fn auth(&self) -> Result<(Address, u32), VoteError> {
-        let signer = self.pub_key()?;
+        let signer = recover_personal_sign(&self.signature, &self.message)?;
         let fip = self.fip()?;
 
         Ok((signer, fip))
     }
+    /// The committees named after the FIP number
+    pub fn committees(&self) -> Vec<String> {
+        self.message
+            .lines()
+            .next()
+            .unwrap_or_default()
+            .split_whitespace()
+            .skip(1)
+            .map(|s| s.to_string())
+            .collect()
+    }
+    /// The metadata lines following the first line of the message
+    pub fn metadata(&self) -> Result<FipMetadata, VoteError> {
+        let mut metadata = FipMetadata::default();
+        for line in self.message.lines().skip(1) {
+            if line.trim().is_empty() {
+                continue;
+            }
+            let (key, value) = line
+                .split_once(':')
+                .ok_or(VoteError::InvalidMessageFormat)?;
+            let value = value.trim();
+
+            let (field, name, value) = match key.trim() {
+                "title" => (&mut metadata.title, "title", value.to_string()),
+                "summary" => (&mut metadata.summary, "summary", value.to_string()),
+                "discussion" => {
+                    match Url::parse(value) {
+                        Ok(url) if matches!(url.scheme(), "http" | "https") => (),
+                        _ => return Err(VoteError::InvalidMetadata("discussion")),
+                    }
+                    (
+                        &mut metadata.discussion_url,
+                        "discussion",
+                        value.to_string(),
+                    )
+                }
+                "hash" => {
+                    let digits = value.strip_prefix("0x").unwrap_or(value);
+                    if digits.len() != 64 || !digits.chars().all(|c| c.is_ascii_hexdigit()) {
+                        return Err(VoteError::InvalidMetadata("hash"));
+                    }
+                    let hash = format!("0x{}", digits.to_lowercase());
+                    (&mut metadata.document_hash, "hash", hash)
+                }
+                "author" => (&mut metadata.author, "author", value.to_string()),
+                _ => return Err(VoteError::InvalidMessageFormat),
+            };
+
+            // Each line appears once
+            if value.is_empty() || value.chars().count() > MAX_METADATA_LENGTH || field.is_some() {
+                return Err(VoteError::InvalidMetadata(name));
+            }
+            *field = Some(value);
+        }
+        Ok(metadata)
+    }
     fn fip(&self) -> Result<u32, VoteError> {
-        // Message is in the format "FIP-XXX"
-        let fip = match self.message.split('-').nth(1) {
+        // Message starts with "FIP-XXX"
+        let fip = match self
+            .message
+            .split_whitespace()
+            .next()
+            .and_then(|fip| fip.split('-').nth(1))
+        {
             Some(fip) => fip,
             None => return Err(VoteError::InvalidMessageFormat),
         };
@@ -32,17 +127,79 @@ impl VoteStart {
         };
         Ok(fip)
     }
-    fn pub_key(&self) -> Result<Address, VoteError> {
-        let signature = Signature::from_str(&self.signature)?;
-        let msg = format!(
-            "\x19Ethereum Signed Message:\n{}{}",
-            self.message.len(),
-            self.message
+}
+
+#[cfg(test)]
+mod vote_start_tests {
+    use super::*;
+
+    #[test]
+    fn vote_start_committees() {
+        let start = VoteStart {
+            signature: String::new(),
+            message: "FIP-12 core-devs lotus".to_string(),
+        };
+
+        assert_eq!(start.fip().unwrap(), 12);
+        assert_eq!(start.committees(), vec!["core-devs", "lotus"]);
+
+        let start = VoteStart {
+            signature: String::new(),
+            message: "FIP-12".to_string(),
+        };
+
+        assert_eq!(start.fip().unwrap(), 12);
+        assert!(start.committees().is_empty());
+        assert!(start.metadata().unwrap().is_empty());
+    }
+
+    #[test]
+    fn vote_start_metadata() {
+        let start = VoteStart {
+            signature: String::new(),
+            message: format!(
+                "FIP-12 core-devs\ntitle: Sector duration multiplier\ndiscussion: https://github.com/filecoin-project/FIPs/discussions/554\nhash: 0x{}\nauthor: Jane Doe",
+                "AB".repeat(32)
+            ),
+        };
+
+        assert_eq!(start.fip().unwrap(), 12);
+        assert_eq!(start.committees(), vec!["core-devs"]);
+        assert_eq!(
+            start.metadata().unwrap(),
+            FipMetadata {
+                title: Some("Sector duration multiplier".to_string()),
+                summary: None,
+                discussion_url: Some(
+                    "https://github.com/filecoin-project/FIPs/discussions/554".to_string()
+                ),
+                document_hash: Some(format!("0x{}", "ab".repeat(32))),
+                author: Some("Jane Doe".to_string()),
+            }
         );
-        let message_hash = ethers::utils::keccak256(msg);
 
-        let address = signature.recover(message_hash)?;
+        for (line, error) in [
+            ("discussion: ftp://example.com", "discussion"),
+            ("hash: 0x1234", "hash"),
+            ("title:", "title"),
+            ("title: a\ntitle: b", "title"),
+        ] {
+            let start = VoteStart {
+                signature: String::new(),
+                message: format!("FIP-12\n{}", line),
+            };
+            assert!(matches!(start.metadata(), Err(VoteError::InvalidMetadata(e)) if e == error));
+        }
 
-        Ok(address)
+        for line in ["no colon", "status: accepted"] {
+            let start = VoteStart {
+                signature: String::new(),
+                message: format!("FIP-12\n{}", line),
+            };
+            assert!(matches!(
+                start.metadata(),
+                Err(VoteError::InvalidMessageFormat)
+            ));
+        }
     }
 }
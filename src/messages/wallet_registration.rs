@@ -0,0 +1,419 @@
+use std::str::FromStr;
+
+use bls_signatures::{PublicKey, Serialize, Signature as BlsSignature};
+use ethers::{
+    core::k256::ecdsa::{RecoveryId, Signature as SecpSignature, VerifyingKey},
+    types::{Address, Signature},
+    utils::keccak256,
+};
+use serde::Deserialize;
+use thiserror::Error;
+use utoipa::ToSchema;
+
+use super::auth::{message_id, BoundMessage};
+use crate::storage::Network;
+
+/// Prefix of messages registering a token holder's wallet
+pub const REGISTER_WALLET: &str = "REGISTER WALLET:";
+/// Prefix of messages removing a token holder's wallet registration
+pub const UNREGISTER_WALLET: &str = "UNREGISTER WALLET:";
+/// Prefix of messages registering a storage client's wallet
+pub const REGISTER_CLIENT: &str = "REGISTER CLIENT:";
+/// Prefix of messages removing a storage client's wallet registration
+pub const UNREGISTER_CLIENT: &str = "UNREGISTER CLIENT:";
+
+// Signature type bytes prepended by `lotus wallet sign`
+const SIG_TYPE_SECP256K1: u8 = 1;
+const SIG_TYPE_BLS: u8 = 2;
+const SIG_TYPE_DELEGATED: u8 = 3;
+
+#[derive(Debug, Error)]
+pub enum WalletRegistrationError {
+    #[error("Signature does not match message")]
+    SignatureMismatch,
+    #[error("Invalid message format")]
+    InvalidMessageFormat,
+    #[error("Invalid wallet address")]
+    InvalidWalletAddress,
+    #[error("Signature type does not match wallet address")]
+    InvalidSignatureType,
+    #[error(transparent)]
+    InvalidBlsEncoding(#[from] bls_signatures::Error),
+    #[error(transparent)]
+    InvalidHexEncoding(#[from] hex::FromHexError),
+    #[error("Invalid address")]
+    InvalidAddress,
+    #[error("Wallet address does not belong to a configured network")]
+    InvalidNetwork,
+    #[error("Message was issued too long ago or in the future")]
+    StaleMessage,
+}
+
+/// Raw json to authorize an ethereum address to vote
+/// with the holdings of a Filecoin wallet
+///
+/// Message scheme, hex encoded and signed by the wallet with
+/// `lotus wallet sign`. f1, f3 and f4 wallets are supported
+///
+/// REGISTER WALLET: 0xf2361d2a9a0677e8ffd1515d65cf5190ea20eb56
+/// network: mainnet
+/// issued: 1700000000
+///
+/// Registrations are removed with an `UNREGISTER WALLET:` prefix instead,
+/// storage clients use `REGISTER CLIENT:` and `UNREGISTER CLIENT:`
+#[derive(Deserialize, ToSchema)]
+pub struct ReceivedWalletRegistration {
+    signature: String,
+    wallet_address: String,
+    message: String,
+}
+
+/// This struct represents an authorized eth address to vote
+/// on behalf of a Filecoin wallet
+#[derive(Debug)]
+pub struct WalletRegistration {
+    authorized_voter: Address,
+    network: Network,
+    wallet: String,
+    id: [u8; 32],
+}
+
+impl WalletRegistration {
+    pub fn address(&self) -> Address {
+        self.authorized_voter
+    }
+    pub fn ntw(&self) -> Network {
+        self.network
+    }
+    pub fn wallet(&self) -> String {
+        self.wallet.clone()
+    }
+    /// Recorded so that the signed message is accepted once
+    pub fn id(&self) -> [u8; 32] {
+        self.id
+    }
+}
+
+impl ReceivedWalletRegistration {
+    /// The registration if the wallet signed a message with the prefix
+    /// for the network of the wallet around the unix timestamp `now`
+    pub fn recover_wallet_registration(
+        &self,
+        prefix: &str,
+        now: u64,
+    ) -> Result<WalletRegistration, WalletRegistrationError> {
+        let wallet = self.wallet_address.to_ascii_lowercase();
+        let wallet_prefix = wallet
+            .chars()
+            .next()
+            .ok_or(WalletRegistrationError::InvalidWalletAddress)?;
+
+        let msg = hex::decode(&self.message)?;
+        let sig = hex::decode(self.signature.trim_start_matches("0x"))?;
+        let (sig_type, sig) = match sig.split_first() {
+            Some((sig_type, sig)) => (*sig_type, sig),
+            None => return Err(WalletRegistrationError::InvalidSignatureType),
+        };
+
+        let verified = match (wallet.get(1..2), sig_type) {
+            (Some("1"), SIG_TYPE_SECP256K1) => verify_secp256k1(&wallet, sig, &msg)?,
+            (Some("3"), SIG_TYPE_BLS) => verify_bls(&wallet, sig, &msg)?,
+            (Some("4"), SIG_TYPE_DELEGATED) => verify_delegated(&wallet, sig, &msg)?,
+            _ => return Err(WalletRegistrationError::InvalidSignatureType),
+        };
+
+        if !verified {
+            return Err(WalletRegistrationError::SignatureMismatch);
+        }
+
+        let original = match String::from_utf8(msg) {
+            Ok(original) => original,
+            Err(_) => return Err(WalletRegistrationError::InvalidMessageFormat),
+        };
+
+        let bound = BoundMessage::parse(&original, prefix)
+            .ok_or(WalletRegistrationError::InvalidMessageFormat)?;
+        let address = Address::from_str(bound.subject)
+            .map_err(|_| WalletRegistrationError::InvalidAddress)?;
+        // Networks can share a prefix so the message names the network
+        let ntw = Network::from_name(bound.network)
+            .filter(|ntw| ntw.prefix() == wallet_prefix)
+            .ok_or(WalletRegistrationError::InvalidNetwork)?;
+        if !bound.is_fresh(now) {
+            return Err(WalletRegistrationError::StaleMessage);
+        }
+
+        Ok(WalletRegistration {
+            authorized_voter: address,
+            network: ntw,
+            id: message_id(wallet.as_bytes(), &original),
+            wallet,
+        })
+    }
+}
+
+/// Decodes `len` bytes of address payload from the base32 part of the address
+///
+/// The payload is followed by a 4 byte blake2b checksum over the protocol
+/// bytes and the payload, which must match
+fn payload(
+    protocol: &[u8],
+    encoded: Option<&str>,
+    len: usize,
+) -> Result<Vec<u8>, WalletRegistrationError> {
+    let bytes = encoded
+        .and_then(|encoded| base32::decode(base32::Alphabet::RFC4648 { padding: false }, encoded))
+        .ok_or(WalletRegistrationError::InvalidWalletAddress)?;
+
+    if bytes.len() != len + 4 {
+        return Err(WalletRegistrationError::InvalidWalletAddress);
+    }
+    let (payload, checksum) = bytes.split_at(len);
+
+    if address_checksum(protocol, payload) != checksum {
+        return Err(WalletRegistrationError::InvalidWalletAddress);
+    }
+
+    Ok(payload.to_vec())
+}
+
+/// Filecoin address checksum, blake2b-32 of the address bytes
+fn address_checksum(protocol: &[u8], payload: &[u8]) -> [u8; 4] {
+    let hash = blake2b_simd::Params::new()
+        .hash_length(4)
+        .to_state()
+        .update(protocol)
+        .update(payload)
+        .finalize();
+
+    let mut checksum = [0; 4];
+    checksum.copy_from_slice(hash.as_bytes());
+    checksum
+}
+
+/// f1 wallets sign the blake2b-256 hash of the message and are
+/// addressed by the blake2b-160 hash of their uncompressed public key
+fn verify_secp256k1(wallet: &str, sig: &[u8], msg: &[u8]) -> Result<bool, WalletRegistrationError> {
+    let payload = payload(&[1], wallet.get(2..), 20)?;
+
+    if sig.len() != 65 {
+        return Err(WalletRegistrationError::SignatureMismatch);
+    }
+    let signature = SecpSignature::from_slice(&sig[..64])
+        .map_err(|_| WalletRegistrationError::SignatureMismatch)?;
+    let recovery_id =
+        RecoveryId::from_byte(sig[64]).ok_or(WalletRegistrationError::SignatureMismatch)?;
+
+    let hash = blake2b_simd::Params::new().hash_length(32).hash(msg);
+    let key = match VerifyingKey::recover_from_prehash(hash.as_bytes(), &signature, recovery_id) {
+        Ok(key) => key,
+        Err(_) => return Ok(false),
+    };
+
+    let pub_key = key.to_encoded_point(false);
+    let recovered = blake2b_simd::Params::new()
+        .hash_length(20)
+        .hash(pub_key.as_bytes());
+
+    Ok(recovered.as_bytes() == payload.as_slice())
+}
+
+/// f3 wallets are addressed by their BLS public key
+fn verify_bls(wallet: &str, sig: &[u8], msg: &[u8]) -> Result<bool, WalletRegistrationError> {
+    let payload = payload(&[3], wallet.get(2..), 48)?;
+
+    let pub_key = PublicKey::from_bytes(&payload)?;
+
+    Ok(pub_key.verify(BlsSignature::from_bytes(sig)?, msg))
+}
+
+/// f410 wallets sign the keccak-256 hash of the message
+/// and are addressed by their ethereum address
+fn verify_delegated(wallet: &str, sig: &[u8], msg: &[u8]) -> Result<bool, WalletRegistrationError> {
+    if wallet.get(2..5) != Some("10f") {
+        return Err(WalletRegistrationError::InvalidWalletAddress);
+    }
+    // Protocol 4 with the Ethereum address manager actor id 10
+    let payload = payload(&[4, 10], wallet.get(5..), 20)?;
+
+    let signature = match Signature::try_from(sig) {
+        Ok(signature) => signature,
+        Err(_) => return Err(WalletRegistrationError::SignatureMismatch),
+    };
+
+    match signature.recover(keccak256(msg)) {
+        Ok(recovered) => Ok(recovered == Address::from_slice(&payload)),
+        Err(_) => Ok(false),
+    }
+}
+
+#[cfg(test)]
+pub mod test_wallet {
+    use ethers::signers::{LocalWallet, Signer};
+
+    use super::*;
+
+    /// The f410 address of the wallet on the network
+    pub fn delegated_address(wallet: &LocalWallet, ntw: Network) -> String {
+        let mut payload = wallet.address().as_bytes().to_vec();
+        payload.extend_from_slice(&address_checksum(&[4, 10], &payload));
+        let encoded = base32::encode(base32::Alphabet::RFC4648 { padding: false }, &payload);
+        format!("{}410f{}", ntw.prefix(), encoded.to_ascii_lowercase())
+    }
+
+    /// The message signed by the f410 wallet, as the body of a wallet request
+    pub fn delegated_registration(
+        wallet: &LocalWallet,
+        ntw: Network,
+        message: &str,
+    ) -> serde_json::Value {
+        let sig = wallet.sign_hash(keccak256(message).into()).unwrap();
+        let mut sig_bytes = vec![SIG_TYPE_DELEGATED];
+        sig_bytes.extend_from_slice(&sig.to_vec());
+
+        serde_json::json!({
+            "signature": hex::encode(sig_bytes),
+            "wallet_address": delegated_address(wallet, ntw),
+            "message": hex::encode(message),
+        })
+    }
+}
+
+#[cfg(test)]
+mod wallet_registration_tests {
+    use ethers::signers::LocalWallet;
+
+    use super::{test_wallet::*, *};
+    use crate::messages::auth::{test_auth::bound_message, MESSAGE_LIFETIME};
+
+    fn eth_voter() -> Address {
+        Address::from_str("0xf2361d2a9a0677e8ffd1515d65cf5190ea20eb56").unwrap()
+    }
+
+    fn calibration() -> Network {
+        Network::from_name("calibration").unwrap()
+    }
+
+    #[test]
+    fn wallet_registration_delegated() {
+        let wallet = LocalWallet::from_str(
+            "4c0883a69102937d6231471b5dbb6204fe5129617082792ae468d01a3f362318",
+        )
+        .unwrap();
+        let message = bound_message(REGISTER_WALLET, eth_voter(), calibration(), 1000);
+        let reg: ReceivedWalletRegistration =
+            serde_json::from_value(delegated_registration(&wallet, calibration(), &message))
+                .unwrap();
+
+        let res = reg.recover_wallet_registration(REGISTER_WALLET, 1000);
+
+        println!("{:?}", res);
+        assert!(res.is_ok());
+
+        let registration = res.unwrap();
+        assert_eq!(registration.address(), eth_voter());
+        assert_eq!(registration.ntw(), calibration());
+
+        // Bound to its purpose, the network of the wallet and the time it was issued
+        assert!(matches!(
+            reg.recover_wallet_registration(UNREGISTER_WALLET, 1000),
+            Err(WalletRegistrationError::InvalidMessageFormat)
+        ));
+        assert!(matches!(
+            reg.recover_wallet_registration(REGISTER_WALLET, 1001 + MESSAGE_LIFETIME),
+            Err(WalletRegistrationError::StaleMessage)
+        ));
+        let mainnet = Network::from_name("mainnet").unwrap();
+        let message = bound_message(REGISTER_WALLET, eth_voter(), mainnet, 1000);
+        let reg: ReceivedWalletRegistration =
+            serde_json::from_value(delegated_registration(&wallet, calibration(), &message))
+                .unwrap();
+        assert!(matches!(
+            reg.recover_wallet_registration(REGISTER_WALLET, 1000),
+            Err(WalletRegistrationError::InvalidNetwork)
+        ));
+
+        // The bare address registrations of before are refused
+        let message = format!("{:?}", eth_voter());
+        let reg: ReceivedWalletRegistration =
+            serde_json::from_value(delegated_registration(&wallet, calibration(), &message))
+                .unwrap();
+        assert!(matches!(
+            reg.recover_wallet_registration(REGISTER_WALLET, 1000),
+            Err(WalletRegistrationError::InvalidMessageFormat)
+        ));
+    }
+
+    #[test]
+    fn wallet_registration_secp256k1() {
+        use ethers::core::k256::ecdsa::SigningKey;
+
+        let key = SigningKey::from_slice(&[7u8; 32]).unwrap();
+        let mainnet = Network::from_name("mainnet").unwrap();
+        let msg = bound_message(REGISTER_WALLET, eth_voter(), mainnet, 1000).into_bytes();
+
+        let hash = blake2b_simd::Params::new().hash_length(32).hash(&msg);
+        let (sig, recovery_id) = key.sign_prehash_recoverable(hash.as_bytes()).unwrap();
+        let mut sig_bytes = vec![SIG_TYPE_SECP256K1];
+        sig_bytes.extend_from_slice(&sig.to_bytes());
+        sig_bytes.push(recovery_id.to_byte());
+
+        let pub_key = key.verifying_key().to_encoded_point(false);
+        let mut payload = blake2b_simd::Params::new()
+            .hash_length(20)
+            .hash(pub_key.as_bytes())
+            .as_bytes()
+            .to_vec();
+        payload.extend_from_slice(&address_checksum(&[1], &payload));
+        let encoded = base32::encode(base32::Alphabet::RFC4648 { padding: false }, &payload);
+
+        let reg = ReceivedWalletRegistration {
+            signature: hex::encode(sig_bytes),
+            wallet_address: format!("f1{}", encoded.to_ascii_lowercase()),
+            message: hex::encode(&msg),
+        };
+
+        let res = reg.recover_wallet_registration(REGISTER_WALLET, 1000);
+
+        println!("{:?}", res);
+        assert!(res.is_ok());
+        assert_eq!(res.unwrap().ntw(), mainnet);
+    }
+
+    #[test]
+    fn wallet_registration_checksum() {
+        let encoded = "d2xrzcslx7xlbbylc5c3d5lvandqw4iwl6epxba";
+        assert!(payload(&[1], Some(encoded), 20).is_ok());
+
+        // Same payload as a BLS protocol address, and with one character changed
+        assert!(matches!(
+            payload(&[3], Some(encoded), 20),
+            Err(WalletRegistrationError::InvalidWalletAddress)
+        ));
+        assert!(matches!(
+            payload(&[1], Some("e2xrzcslx7xlbbylc5c3d5lvandqw4iwl6epxba"), 20),
+            Err(WalletRegistrationError::InvalidWalletAddress)
+        ));
+    }
+
+    #[test]
+    fn wallet_registration_signature_type_mismatch() {
+        let reg = ReceivedWalletRegistration {
+            signature: format!("02{}", "00".repeat(96)),
+            wallet_address: "t1d2xrzcslx7xlbbylc5c3d5lvandqw4iwl6epxba".to_string(),
+            message: hex::encode(bound_message(
+                REGISTER_WALLET,
+                eth_voter(),
+                calibration(),
+                1000,
+            )),
+        };
+
+        let res = reg.recover_wallet_registration(REGISTER_WALLET, 1000);
+
+        assert!(matches!(
+            res,
+            Err(WalletRegistrationError::InvalidSignatureType)
+        ));
+    }
+}
@@ -0,0 +1,118 @@
+use actix_web::{get, HttpResponse, Responder};
+use utoipa::OpenApi;
+
+use crate::{get, post};
+
+/// OpenAPI 3 document of every endpoint, derived from the handlers and the
+/// types they read and answer with
+#[derive(OpenApi)]
+#[openapi(
+    info(
+        title = "FIP Voting",
+        description = "Registers voters and records their signed ballots on Filecoin Improvement Proposals"
+    ),
+    servers((url = "/v1", description = "Current version, the same paths without it are deprecated aliases")),
+    paths(
+        get::get_votes,
+        get::get_fip,
+        get::get_delegates,
+        get::get_active_votes,
+        get::get_concluded_votes,
+        get::get_all_concluded_votes,
+        get::get_voting_power,
+        get::get_voter,
+        get::get_vote_starters,
+        get::get_committees,
+        get::get_events,
+        get::get_stream,
+        post::register_vote,
+        post::register_batch_vote,
+        post::start_vote,
+        post::register_vote_starter,
+        post::remove_vote_starter,
+        post::set_committee,
+        post::register_voter,
+        post::unregister_voter,
+        post::register_token_holder,
+        post::unregister_token_holder,
+        post::register_client,
+        post::unregister_client,
+    )
+)]
+pub struct ApiDoc;
+
+#[get("/openapi.json")]
+async fn get_openapi() -> impl Responder {
+    HttpResponse::Ok().json(ApiDoc::openapi())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Routes of the handlers in the source, as `(method, path)`
+    fn routes() -> Vec<(&'static str, String)> {
+        let mut routes = Vec::new();
+        for (method, source) in [
+            ("get", include_str!("get.rs")),
+            ("post", include_str!("post.rs")),
+        ] {
+            let attr = format!("#[{}(\"", method);
+            for line in source.lines() {
+                if let Some(path) = line.trim().strip_prefix(&attr) {
+                    let end = path.find('"').unwrap();
+                    routes.push((method, path[..end].to_string()));
+                }
+            }
+        }
+        routes
+    }
+
+    #[test]
+    fn openapi_covers_every_route() {
+        let doc = serde_json::to_value(ApiDoc::openapi()).unwrap();
+        let routes = routes();
+        assert!(routes.len() >= 19);
+
+        for (method, path) in routes {
+            assert!(
+                doc["paths"][&path][method].is_object(),
+                "{} {} is missing from the OpenAPI document",
+                method.to_uppercase(),
+                path
+            );
+        }
+
+        // Every referenced schema is part of the document
+        let mut refs = Vec::new();
+        collect_refs(&doc, &mut refs);
+        assert!(!refs.is_empty());
+        for r in refs {
+            let name = r.trim_start_matches("#/components/schemas/");
+            assert!(
+                doc["components"]["schemas"][name].is_object(),
+                "schema {} is missing",
+                name
+            );
+        }
+    }
+
+    fn collect_refs(value: &serde_json::Value, refs: &mut Vec<String>) {
+        match value {
+            serde_json::Value::Object(map) => {
+                for (key, value) in map {
+                    match value {
+                        serde_json::Value::String(r) if key == "$ref" => refs.push(r.clone()),
+                        _ => collect_refs(value, refs),
+                    }
+                }
+            }
+            serde_json::Value::Array(values) => {
+                for value in values {
+                    collect_refs(value, refs);
+                }
+            }
+            _ => (),
+        }
+    }
+}
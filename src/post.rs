@@ -1,332 +1,878 @@
-use actix_web::{post, web, HttpResponse, Responder};
+use actix_web::{body, post, web, HttpResponse, Responder};
 
 use crate::{
+    audit::{Action, AuditEvent},
     errors::*,
     messages::{
-        auth::VoterAuthorization, vote_registration::ReceivedVoterRegistration,
-        vote_start::VoteStart, votes::ReceivedVote,
+        auth::{StarterRevocation, VoterAuthorization},
+        batch_vote::{BallotOutcome, BatchVoteResult, ReceivedBatchVote},
+        committee::CommitteeUpdate,
+        vote_registration::ReceivedVoterRegistration,
+        vote_start::VoteStart,
+        votes::{ReceivedVote, Vote, VoteError},
+        wallet_registration::{
+            ReceivedWalletRegistration, REGISTER_CLIENT, REGISTER_WALLET, UNREGISTER_CLIENT,
+            UNREGISTER_WALLET,
+        },
     },
-    redis::{Redis, VoteStatus},
     storage::Network,
-    Args, FipParams, NtwParams,
+    store::{StoreError, VoteStatus, VoteStore},
+    stream::Broadcaster,
+    Args, BatchVoteParams, FipParams, NtwParams,
 };
 
+#[utoipa::path(
+    tag = "votes",
+    params(FipParams),
+    request_body(content = ReceivedVote, content_type = "application/json"),
+    responses(
+        (status = 200, description = "Ballot recorded"),
+        (status = 400, description = "Malformed request", body = ErrorBody),
+        (status = 403, description = "Signer may not do this", body = ErrorBody),
+        (status = 409, description = "Conflicts with what is stored", body = ErrorBody),
+        (status = 500, description = "Database error", body = ErrorBody),
+        (status = 502, description = "Chain could not be queried", body = ErrorBody),
+    )
+)]
 #[post("/filecoin/vote")]
 async fn register_vote(
     body: web::Bytes,
     query_params: web::Query<FipParams>,
     config: web::Data<Args>,
+    store: web::Data<dyn VoteStore>,
+    feed: web::Data<Broadcaster>,
 ) -> impl Responder {
+    let mut event = AuditEvent::new(Action::Vote, &body);
+    let res = handle_register_vote(body, query_params, config, store.clone(), &mut event).await;
+    audit(&store, &feed, event, res).await
+}
+
+async fn handle_register_vote(
+    body: web::Bytes,
+    query_params: web::Query<FipParams>,
+    config: web::Data<Args>,
+    store: web::Data<dyn VoteStore>,
+    event: &mut AuditEvent,
+) -> HttpResponse {
     let num = query_params.fip_number;
 
     println!("Vote received for FIP: {}, {:?}", num, body);
     // Deserialize the body into the vote struct
     let vote: ReceivedVote = match serde_json::from_slice(&body) {
         Ok(v) => v,
-        Err(e) => {
-            let res = format!("{}: {}", VOTE_DESERIALIZE_ERROR, e);
-            println!("{}", res);
-            return HttpResponse::BadRequest().body(res);
-        }
+        Err(e) => return error_response(VOTE_DESERIALIZE_ERROR, e),
     };
 
     // Recover the vote
-    let vote = match vote.vote() {
+    let (ntw, vote) = match vote.vote() {
         Ok(vote) => vote,
-        Err(e) => {
-            let res = format!("{}: {}", VOTE_RECOVER_ERROR, e);
-            println!("{}", res);
-            return HttpResponse::BadRequest().body(res);
-        }
+        Err(e) => return error_response(VOTE_RECOVER_ERROR, e),
     };
 
+    event.signer(vote.voter());
+    event.fip(num);
+    event.network(ntw);
+
+    if let Err(e) = ballot_network(ntw, query_params.network) {
+        return error_response(VOTE_RECOVER_ERROR, e);
+    }
+
+    let choice = vote.choice();
+    if let Err((context, e)) = cast_ballot(&**store, num, vote, ntw, config.vote_length()).await {
+        return error_response(context, e);
+    }
+
+    println!("Vote ({:?}) added for FIP: {}", choice, num);
+
+    HttpResponse::Ok().finish()
+}
+
+/// Fails if the query names another network than the ballots were signed for
+fn ballot_network(signed: Network, named: Option<Network>) -> Result<(), VoteError> {
+    match named {
+        Some(named) if named.name() != signed.name() => Err(VoteError::WrongNetwork),
+        _ => Ok(()),
+    }
+}
+
+/// Adds the ballot unless the vote on the FIP has concluded
+async fn cast_ballot(
+    store: &dyn VoteStore,
+    num: u32,
+    vote: Vote,
+    ntw: Network,
+    vote_length: u64,
+) -> Result<(), (&'static str, DomainError)> {
+    let status = store
+        .vote_status(num, vote_length, ntw)
+        .await
+        .map_err(|e| (VOTE_STATUS_ERROR, e.into()))?;
+
+    if let VoteStatus::Concluded = status {
+        return Err((VOTE_ADD_ERROR, DomainError::VoteConcluded(num)));
+    }
+
     let voter = vote.voter();
+    store
+        .add_vote(num, vote, voter, ntw, vote_length)
+        .await
+        .map_err(|e| (VOTE_ADD_ERROR, e.into()))
+}
 
-    // Open a connection to the redis database
-    let mut redis = match Redis::new(config.redis_path()) {
-        Ok(redis) => redis,
-        Err(e) => {
-            let res = format!("{}: {}", OPEN_CONNECTION_ERROR, e);
-            println!("{}", res);
-            return HttpResponse::InternalServerError().body(res);
-        }
-    };
+#[utoipa::path(
+    tag = "votes",
+    params(BatchVoteParams),
+    request_body(content = ReceivedBatchVote, content_type = "application/json"),
+    responses(
+        (status = 200, description = "Outcome of the ballot on each FIP", body = BatchVoteResult),
+        (status = 400, description = "Malformed request", body = ErrorBody),
+        (status = 403, description = "Signer may not do this", body = ErrorBody),
+        (status = 500, description = "Database error", body = ErrorBody),
+    )
+)]
+#[post("/filecoin/batchvote")]
+async fn register_batch_vote(
+    body: web::Bytes,
+    query_params: web::Query<BatchVoteParams>,
+    config: web::Data<Args>,
+    store: web::Data<dyn VoteStore>,
+    feed: web::Data<Broadcaster>,
+) -> impl Responder {
+    let mut event = AuditEvent::new(Action::BatchVote, &body);
+    let res =
+        handle_register_batch_vote(body, query_params, config, store.clone(), &feed, &mut event)
+            .await;
+    audit(&store, &feed, event, res).await
+}
 
-    let ntw = match redis.network(voter) {
-        Ok(ntw) => ntw,
-        Err(e) => {
-            let res = format!("{}: {}", VOTER_NOT_REGISTERED_NETWORK, e);
-            println!("{}", res);
-            return HttpResponse::InternalServerError().body(res);
-        }
+async fn handle_register_batch_vote(
+    body: web::Bytes,
+    query_params: web::Query<BatchVoteParams>,
+    config: web::Data<Args>,
+    store: web::Data<dyn VoteStore>,
+    feed: &Broadcaster,
+    event: &mut AuditEvent,
+) -> HttpResponse {
+    println!("Batch vote received: {:?}", body);
+    let batch: ReceivedBatchVote = match serde_json::from_slice(&body) {
+        Ok(v) => v,
+        Err(e) => return error_response(VOTE_DESERIALIZE_ERROR, e),
     };
 
-    let status = match redis.vote_status(num, config.vote_length(), ntw) {
-        Ok(status) => status,
-        Err(e) => {
-            let res = format!("{}: {}", VOTE_STATUS_ERROR, e);
-            println!("{}", res);
-            return HttpResponse::InternalServerError().body(res);
-        }
+    let (voter, ntw, votes) = match batch.votes() {
+        Ok(votes) => votes,
+        Err(e) => return error_response(VOTE_RECOVER_ERROR, e),
     };
+    event.signer(voter);
+    event.network(ntw);
 
-    match status {
-        VoteStatus::InProgress(_) => (),
-        VoteStatus::Concluded => {
-            let resp = format!("Vote concluded for FIP: {}", num);
-            println!("{}", resp);
-            return HttpResponse::Forbidden().body(resp);
-        }
-        VoteStatus::DoesNotExist => (),
+    if let Err(e) = ballot_network(ntw, query_params.network) {
+        return error_response(VOTE_RECOVER_ERROR, e);
     }
 
-    let choice = vote.choice();
-
-    // Add the vote to the database
-    match redis.add_vote(num, vote, voter, config.vote_length()).await {
-        Ok(_) => (),
-        Err(e) => {
-            let res = format!("{}: {}", VOTE_ADD_ERROR, e);
-            println!("{}", res);
-            return HttpResponse::InternalServerError().body(res);
-        }
+    // Each ballot stands on its own, one failing leaves the others recorded
+    let mut ballots = Vec::with_capacity(votes.len());
+    for vote in votes {
+        let fip_number = vote.fip();
+        let choice = vote.choice();
+        let outcome = match cast_ballot(&**store, fip_number, vote, ntw, config.vote_length()).await
+        {
+            Ok(()) => {
+                println!("Vote ({:?}) added for FIP: {}", choice, fip_number);
+                feed.announce_ballot(&**store, fip_number, ntw).await;
+                BallotOutcome {
+                    fip_number,
+                    status: 200,
+                    error: None,
+                }
+            }
+            Err((context, e)) => {
+                let (status, error) = error_body(context, e);
+                BallotOutcome {
+                    fip_number,
+                    status: status.as_u16(),
+                    error: Some(error),
+                }
+            }
+        };
+        ballots.push(outcome);
     }
 
-    println!("Vote ({:?}) added for FIP: {}", choice, num);
-
-    HttpResponse::Ok().finish()
+    HttpResponse::Ok().json(BatchVoteResult { ballots })
 }
 
+#[utoipa::path(
+    tag = "votes",
+    params(NtwParams),
+    request_body(content = VoteStart, content_type = "application/json"),
+    responses(
+        (status = 200, description = "Length of the vote in seconds as plain text", body = String, content_type = "text/plain"),
+        (status = 400, description = "Malformed request", body = ErrorBody),
+        (status = 403, description = "Signer may not do this", body = ErrorBody),
+        (status = 409, description = "Conflicts with what is stored", body = ErrorBody),
+        (status = 500, description = "Database error", body = ErrorBody),
+        (status = 502, description = "Chain could not be queried", body = ErrorBody),
+    )
+)]
 #[post("/filecoin/startvote")]
 async fn start_vote(
     body: web::Bytes,
     query_params: web::Query<NtwParams>,
     config: web::Data<Args>,
+    store: web::Data<dyn VoteStore>,
+    feed: web::Data<Broadcaster>,
 ) -> impl Responder {
+    let mut event = AuditEvent::new(Action::StartVote, &body);
+    let res = handle_start_vote(body, query_params, config, store.clone(), &mut event).await;
+    audit(&store, &feed, event, res).await
+}
+
+async fn handle_start_vote(
+    body: web::Bytes,
+    query_params: web::Query<NtwParams>,
+    config: web::Data<Args>,
+    store: web::Data<dyn VoteStore>,
+    event: &mut AuditEvent,
+) -> HttpResponse {
     println!("Vote start received");
 
-    let ntw = match query_params.network.as_str() {
-        "mainnet" => Network::Mainnet,
-        "calibration" => Network::Testnet,
-        _ => {
-            let res = format!("{}: {}", INVALID_NETWORK, query_params.network);
-            println!("{}", res);
-            return HttpResponse::BadRequest().body(res);
-        }
-    };
+    let ntw = query_params.network;
+    event.network(ntw);
 
     // Deserialize the body into the vote start struct
     let start: VoteStart = match serde_json::from_slice(&body) {
         Ok(v) => v,
-        Err(e) => {
-            let res = format!("{}: {}", VOTE_DESERIALIZE_ERROR, e);
-            println!("{}", res);
-            return HttpResponse::BadRequest().body(res);
-        }
+        Err(e) => return error_response(VOTE_DESERIALIZE_ERROR, e),
     };
 
     println!("Vote start received for FIP: {}", start.message);
 
-    // Open a connection to the redis database
-    let mut redis = match Redis::new(config.redis_path()) {
-        Ok(redis) => redis,
-        Err(e) => {
-            let res = format!("{}: {}", OPEN_CONNECTION_ERROR, e);
-            println!("{}", res);
-            return HttpResponse::InternalServerError().body(res);
-        }
-    };
-
     let (starter, fip) = match start.auth() {
         Ok(auth) => auth,
-        Err(e) => {
-            let res = format!("{}: {}", VOTER_AUTH_ERROR, e);
-            println!("{}", res);
-            return HttpResponse::BadRequest().body(res);
-        }
+        Err(e) => return error_response(VOTER_AUTH_ERROR, e),
     };
+    event.signer(starter);
+    event.fip(fip);
 
-    match redis.vote_exists(ntw, fip) {
-        Ok(true) => {
-            let res = format!("{}: {}", VOTE_ALREADY_EXISTS, fip);
-            println!("{}", res);
-            return HttpResponse::Ok().body(res);
-        }
+    let metadata = match start.metadata() {
+        Ok(metadata) => metadata,
+        Err(e) => return error_response(VOTE_START_ERROR, e),
+    };
+
+    match store.vote_exists(ntw, fip).await {
+        Ok(true) => return error_response(VOTE_START_ERROR, StoreError::VoteExists),
         Ok(false) => (),
-        Err(e) => {
-            let res = format!("{}: {}", VOTE_EXISTS_ERROR, e);
-            println!("{}", res);
-            return HttpResponse::InternalServerError().body(res);
-        }
+        Err(e) => return error_response(VOTE_EXISTS_ERROR, e),
     }
 
-    match redis.start_vote(fip, starter, ntw) {
+    match store
+        .start_vote(fip, starter, ntw, start.committees(), metadata)
+        .await
+    {
         Ok(_) => (),
-        Err(e) => {
-            let res = format!("{}: {}", VOTE_START_ERROR, e);
-            println!("{}", res);
-            return HttpResponse::InternalServerError().body(res);
-        }
+        Err(e) => return error_response(VOTE_START_ERROR, e),
     }
 
     HttpResponse::Ok().body(config.vote_length().to_string())
 }
 
+#[utoipa::path(
+    tag = "starters",
+    params(NtwParams),
+    request_body(content = VoterAuthorization, content_type = "application/json"),
+    responses(
+        (status = 200, description = "Address may start votes"),
+        (status = 400, description = "Malformed request", body = ErrorBody),
+        (status = 403, description = "Signer may not do this", body = ErrorBody),
+        (status = 500, description = "Database error", body = ErrorBody),
+    )
+)]
 #[post("/filecoin/registerstarter")]
 async fn register_vote_starter(
     query_params: web::Query<NtwParams>,
     body: web::Bytes,
-    config: web::Data<Args>,
+    store: web::Data<dyn VoteStore>,
+    feed: web::Data<Broadcaster>,
 ) -> impl Responder {
+    let mut event = AuditEvent::new(Action::RegisterStarter, &body);
+    let res = handle_register_vote_starter(query_params, body, store.clone(), &mut event).await;
+    audit(&store, &feed, event, res).await
+}
+
+async fn handle_register_vote_starter(
+    query_params: web::Query<NtwParams>,
+    body: web::Bytes,
+    store: web::Data<dyn VoteStore>,
+    event: &mut AuditEvent,
+) -> HttpResponse {
     println!("Vote starter registration received");
-    let ntw = match query_params.network.as_str() {
-        "mainnet" => Network::Mainnet,
-        "calibration" => Network::Testnet,
-        _ => return HttpResponse::BadRequest().body(INVALID_NETWORK),
-    };
+    let ntw = query_params.network;
+    event.network(ntw);
 
     let auth: VoterAuthorization = match serde_json::from_slice(&body) {
         Ok(auth) => auth,
-        Err(e) => {
-            let res = format!("{}: {}", VOTER_AUTH_DESERIALIZE_ERROR, e);
-            println!("{}", res);
-            return HttpResponse::BadRequest().body(res);
-        }
+        Err(e) => return error_response(VOTER_AUTH_DESERIALIZE_ERROR, e),
     };
 
-    let (signer, new_signer) = match auth.auth() {
-        Ok(signer) => signer,
-        Err(e) => {
-            let res = format!("{}: {}", VOTER_AUTH_RECOVER_ERROR, e);
-            println!("{}", res);
-            return HttpResponse::BadRequest().body(res);
-        }
+    let grant = match auth.auth(ntw, event.timestamp) {
+        Ok(grant) => grant,
+        Err(e) => return error_response(VOTER_AUTH_RECOVER_ERROR, e),
     };
+    let (signer, new_signer) = (grant.signer, grant.starter);
+    event.signer(signer);
+    event.subject(new_signer);
 
-    let mut redis = match Redis::new(config.redis_path()) {
-        Ok(redis) => redis,
-        Err(e) => {
-            let res = format!("{}: {}", OPEN_CONNECTION_ERROR, e);
-            println!("{}", res);
-            return HttpResponse::InternalServerError().body(res);
+    match store.is_authorized_starter(signer, ntw).await {
+        Ok(true) => (),
+        Ok(false) => {
+            return error_response(
+                VOTER_NOT_AUTHORIZED_ERROR,
+                DomainError::NotAuthorized(signer),
+            )
         }
+        Err(e) => return error_response(VOTER_AUTH_ERROR, e),
+    }
+
+    if let Err(e) = store.use_message(ntw, grant.id).await {
+        return error_response(VOTER_AUTH_ERROR, e);
+    }
+
+    match store.register_voter_starter(new_signer, ntw).await {
+        Ok(_) => (),
+        Err(e) => return error_response(VOTE_ADD_ERROR, e),
+    }
+
+    HttpResponse::Ok().finish()
+}
+
+#[utoipa::path(
+    tag = "starters",
+    params(NtwParams),
+    request_body(content = StarterRevocation, content_type = "application/json"),
+    responses(
+        (status = 200, description = "Address may no longer start votes"),
+        (status = 400, description = "Malformed request", body = ErrorBody),
+        (status = 403, description = "Signer may not do this", body = ErrorBody),
+        (status = 404, description = "Address is not a starter", body = ErrorBody),
+        (status = 409, description = "Address is the last starter", body = ErrorBody),
+        (status = 500, description = "Database error", body = ErrorBody),
+    )
+)]
+#[post("/filecoin/removestarter")]
+async fn remove_vote_starter(
+    query_params: web::Query<NtwParams>,
+    body: web::Bytes,
+    store: web::Data<dyn VoteStore>,
+    feed: web::Data<Broadcaster>,
+) -> impl Responder {
+    let mut event = AuditEvent::new(Action::RemoveStarter, &body);
+    let res = handle_remove_vote_starter(query_params, body, store.clone(), &mut event).await;
+    audit(&store, &feed, event, res).await
+}
+
+async fn handle_remove_vote_starter(
+    query_params: web::Query<NtwParams>,
+    body: web::Bytes,
+    store: web::Data<dyn VoteStore>,
+    event: &mut AuditEvent,
+) -> HttpResponse {
+    println!("Vote starter revocation received");
+    let ntw = query_params.network;
+    event.network(ntw);
+
+    let revocation: StarterRevocation = match serde_json::from_slice(&body) {
+        Ok(revocation) => revocation,
+        Err(e) => return error_response(STARTER_REVOCATION_DESERIALIZE_ERROR, e),
+    };
+
+    let revocation = match revocation.revocation(ntw, event.timestamp) {
+        Ok(revocation) => revocation,
+        Err(e) => return error_response(STARTER_REVOCATION_RECOVER_ERROR, e),
     };
+    let (signer, starter) = (revocation.signer, revocation.starter);
+    event.signer(signer);
+    event.subject(starter);
 
-    match redis.is_authorized_starter(signer, ntw) {
+    match store.is_authorized_starter(signer, ntw).await {
         Ok(true) => (),
         Ok(false) => {
-            let res = format!("{}: {}", VOTER_NOT_AUTHORIZED_ERROR, signer);
-            println!("{}", res);
-            return HttpResponse::BadRequest().body(res);
+            return error_response(
+                VOTER_NOT_AUTHORIZED_ERROR,
+                DomainError::NotAuthorized(signer),
+            )
         }
-        Err(e) => {
-            let res = format!("{}: {}", VOTER_AUTH_ERROR, e);
-            println!("{}", res);
-            return HttpResponse::InternalServerError().body(res);
+        Err(e) => return error_response(VOTER_AUTH_ERROR, e),
+    }
+
+    if let Err(e) = store.use_message(ntw, revocation.id).await {
+        return error_response(STARTER_REMOVE_ERROR, e);
+    }
+
+    if let Err(e) = store.revoke_voter_starter(starter, ntw).await {
+        return error_response(STARTER_REMOVE_ERROR, e);
+    }
+    println!("{:?} can no longer start votes on {}", starter, ntw.name());
+
+    HttpResponse::Ok().finish()
+}
+
+#[utoipa::path(
+    tag = "committees",
+    params(NtwParams),
+    request_body(content = CommitteeUpdate, content_type = "application/json"),
+    responses(
+        (status = 200, description = "Committee replaced"),
+        (status = 400, description = "Malformed request", body = ErrorBody),
+        (status = 403, description = "Signer may not do this", body = ErrorBody),
+        (status = 500, description = "Database error", body = ErrorBody),
+    )
+)]
+#[post("/filecoin/committee")]
+async fn set_committee(
+    query_params: web::Query<NtwParams>,
+    body: web::Bytes,
+    store: web::Data<dyn VoteStore>,
+    feed: web::Data<Broadcaster>,
+) -> impl Responder {
+    let mut event = AuditEvent::new(Action::SetCommittee, &body);
+    let res = handle_set_committee(query_params, body, store.clone(), &mut event).await;
+    audit(&store, &feed, event, res).await
+}
+
+async fn handle_set_committee(
+    query_params: web::Query<NtwParams>,
+    body: web::Bytes,
+    store: web::Data<dyn VoteStore>,
+    event: &mut AuditEvent,
+) -> HttpResponse {
+    println!("Committee update received");
+    let ntw = query_params.network;
+    event.network(ntw);
+
+    let update: CommitteeUpdate = match serde_json::from_slice(&body) {
+        Ok(update) => update,
+        Err(e) => return error_response(COMMITTEE_DESERIALIZE_ERROR, e),
+    };
+
+    let change = match update.auth(ntw, event.timestamp) {
+        Ok(change) => change,
+        Err(e) => return error_response(COMMITTEE_RECOVER_ERROR, e),
+    };
+    let signer = change.signer;
+    event.signer(signer);
+
+    // Committees are managed by the vote starters
+    match store.is_authorized_starter(signer, ntw).await {
+        Ok(true) => (),
+        Ok(false) => {
+            return error_response(
+                VOTER_NOT_AUTHORIZED_ERROR,
+                DomainError::NotAuthorized(signer),
+            )
         }
+        Err(e) => return error_response(VOTER_AUTH_ERROR, e),
     }
 
-    match redis.register_voter_starter(new_signer, ntw) {
+    if let Err(e) = store.use_message(ntw, change.id).await {
+        return error_response(COMMITTEE_UPDATE_ERROR, e);
+    }
+
+    match store.set_committee(ntw, change.name, change.members).await {
         Ok(_) => (),
-        Err(e) => {
-            let res = format!("{}: {}", VOTE_ADD_ERROR, e);
-            println!("{}", res);
-            return HttpResponse::InternalServerError().body(res);
-        }
+        Err(e) => return error_response(COMMITTEE_UPDATE_ERROR, e),
     }
 
     HttpResponse::Ok().finish()
 }
 
+#[utoipa::path(
+    tag = "voters",
+    request_body(content = ReceivedVoterRegistration, content_type = "application/json"),
+    responses(
+        (status = 200, description = "Voter registered for the storage providers"),
+        (status = 400, description = "Malformed request", body = ErrorBody),
+        (status = 403, description = "Signer may not do this", body = ErrorBody),
+        (status = 500, description = "Database error", body = ErrorBody),
+        (status = 502, description = "Chain could not be queried", body = ErrorBody),
+    )
+)]
 #[post("/filecoin/register")]
-async fn register_voter(body: web::Bytes, config: web::Data<Args>) -> impl Responder {
+async fn register_voter(
+    body: web::Bytes,
+    store: web::Data<dyn VoteStore>,
+    feed: web::Data<Broadcaster>,
+) -> impl Responder {
+    let mut event = AuditEvent::new(Action::RegisterVoter, &body);
+    let res = handle_register_voter(body, store.clone(), &mut event).await;
+    audit(&store, &feed, event, res).await
+}
+
+async fn handle_register_voter(
+    body: web::Bytes,
+    store: web::Data<dyn VoteStore>,
+    event: &mut AuditEvent,
+) -> HttpResponse {
     println!("Voter registration received");
 
     // Deserialize the body into the vote struct
     let reg: ReceivedVoterRegistration = match serde_json::from_slice(&body) {
         Ok(v) => v,
-        Err(e) => {
-            let res = format!("{}: {}", VOTE_DESERIALIZE_ERROR, e);
-            println!("{}", res);
-            return HttpResponse::BadRequest().body(res);
-        }
+        Err(e) => return error_response(VOTE_DESERIALIZE_ERROR, e),
     };
 
     let registration = match reg.recover_vote_registration().await {
         Ok(registration) => registration,
-        Err(e) => {
-            let res = format!("{}: {}", VOTE_RECOVER_ERROR, e);
-            println!("{}", res);
-            return HttpResponse::BadRequest().body(res);
-        }
-    };
-
-    // Open a connection to the redis database
-    let mut redis = match Redis::new(config.redis_path()) {
-        Ok(redis) => redis,
-        Err(e) => {
-            let res = format!("{}: {}", OPEN_CONNECTION_ERROR, e);
-            println!("{}", res);
-            return HttpResponse::InternalServerError().body(res);
-        }
+        Err(e) => return error_response(VOTE_RECOVER_ERROR, e),
     };
+    event.signer(registration.address());
+    event.network(registration.ntw());
 
     // Add the vote to the database
-    match redis.register_voter(
-        registration.address(),
-        registration.ntw(),
-        registration.sp_ids(),
-    ) {
+    match store
+        .register_voter(
+            registration.address(),
+            registration.ntw(),
+            registration.sp_ids(),
+        )
+        .await
+    {
         Ok(_) => (),
-        Err(e) => {
-            let res = format!("{}: {}", VOTE_ADD_ERROR, e);
-            println!("{}", res);
-            return HttpResponse::InternalServerError().body(res);
-        }
+        Err(e) => return error_response(VOTE_ADD_ERROR, e),
+    }
+    match store
+        .set_voter_worker(
+            registration.address(),
+            registration.ntw(),
+            Some(registration.worker()),
+        )
+        .await
+    {
+        Ok(_) => (),
+        Err(e) => return error_response(VOTE_ADD_ERROR, e),
     }
 
     HttpResponse::Ok().finish()
 }
 
+#[utoipa::path(
+    tag = "voters",
+    request_body(content = ReceivedVoterRegistration, content_type = "application/json"),
+    responses(
+        (status = 200, description = "Storage provider registration removed"),
+        (status = 400, description = "Malformed request", body = ErrorBody),
+        (status = 403, description = "Signer may not do this", body = ErrorBody),
+        (status = 500, description = "Database error", body = ErrorBody),
+        (status = 502, description = "Chain could not be queried", body = ErrorBody),
+    )
+)]
 #[post("/filecoin/unregister")]
-async fn unregister_voter(body: web::Bytes, config: web::Data<Args>) -> impl Responder {
+async fn unregister_voter(
+    body: web::Bytes,
+    store: web::Data<dyn VoteStore>,
+    feed: web::Data<Broadcaster>,
+) -> impl Responder {
+    let mut event = AuditEvent::new(Action::UnregisterVoter, &body);
+    let res = handle_unregister_voter(body, store.clone(), &mut event).await;
+    audit(&store, &feed, event, res).await
+}
+
+async fn handle_unregister_voter(
+    body: web::Bytes,
+    store: web::Data<dyn VoteStore>,
+    event: &mut AuditEvent,
+) -> HttpResponse {
     println!("Voter unregistration received");
 
     let reg: ReceivedVoterRegistration = match serde_json::from_slice(&body) {
         Ok(v) => v,
-        Err(e) => {
-            let res = format!("{}: {}", VOTE_DESERIALIZE_ERROR, e);
-            println!("{}", res);
-            return HttpResponse::BadRequest().body(res);
-        }
+        Err(e) => return error_response(VOTE_DESERIALIZE_ERROR, e),
     };
 
     let registration = match reg.recover_vote_registration().await {
         Ok(registration) => registration,
-        Err(e) => {
-            let res = format!("{}: {}", VOTE_RECOVER_ERROR, e);
-            println!("{}", res);
-            return HttpResponse::BadRequest().body(res);
-        }
+        Err(e) => return error_response(VOTE_RECOVER_ERROR, e),
     };
+    event.signer(registration.address());
+    event.network(registration.ntw());
 
-    let mut redis = match Redis::new(config.redis_path()) {
-        Ok(redis) => redis,
-        Err(e) => {
-            let res = format!("{}: {}", OPEN_CONNECTION_ERROR, e);
-            println!("{}", res);
-            return HttpResponse::InternalServerError().body(res);
-        }
+    match store
+        .unregister_voter(registration.address(), registration.ntw())
+        .await
+    {
+        Ok(_) => (),
+        Err(e) => return error_response(VOTE_ADD_ERROR, e),
+    }
+
+    HttpResponse::Ok().finish()
+}
+
+#[utoipa::path(
+    tag = "voters",
+    request_body(content = ReceivedWalletRegistration, content_type = "application/json"),
+    responses(
+        (status = 200, description = "Voter registered for the wallet"),
+        (status = 400, description = "Malformed request", body = ErrorBody),
+        (status = 409, description = "Wallet is registered to another voter", body = ErrorBody),
+        (status = 500, description = "Database error", body = ErrorBody),
+    )
+)]
+#[post("/filecoin/registertokenholder")]
+async fn register_token_holder(
+    body: web::Bytes,
+    store: web::Data<dyn VoteStore>,
+    feed: web::Data<Broadcaster>,
+) -> impl Responder {
+    let mut event = AuditEvent::new(Action::RegisterTokenHolder, &body);
+    let res = handle_register_token_holder(body, store.clone(), &mut event).await;
+    audit(&store, &feed, event, res).await
+}
+
+async fn handle_register_token_holder(
+    body: web::Bytes,
+    store: web::Data<dyn VoteStore>,
+    event: &mut AuditEvent,
+) -> HttpResponse {
+    println!("Token holder registration received");
+
+    let reg: ReceivedWalletRegistration = match serde_json::from_slice(&body) {
+        Ok(v) => v,
+        Err(e) => return error_response(WALLET_DESERIALIZE_ERROR, e),
     };
 
-    match redis.unregister_voter(registration.address(), registration.ntw()) {
+    let registration = match reg.recover_wallet_registration(REGISTER_WALLET, event.timestamp) {
+        Ok(registration) => registration,
+        Err(e) => return error_response(WALLET_RECOVER_ERROR, e),
+    };
+    event.signer(registration.address());
+    event.network(registration.ntw());
+
+    if let Err(e) = store
+        .use_message(registration.ntw(), registration.id())
+        .await
+    {
+        return error_response(WALLET_REGISTER_ERROR, e);
+    }
+
+    match store
+        .register_token_holder(
+            registration.address(),
+            registration.ntw(),
+            registration.wallet(),
+        )
+        .await
+    {
         Ok(_) => (),
-        Err(e) => {
-            let res = format!("{}: {}", VOTE_ADD_ERROR, e);
-            println!("{}", res);
-            return HttpResponse::InternalServerError().body(res);
-        }
+        Err(e) => return error_response(WALLET_REGISTER_ERROR, e),
+    }
+
+    HttpResponse::Ok().finish()
+}
+
+#[utoipa::path(
+    tag = "voters",
+    request_body(content = ReceivedWalletRegistration, content_type = "application/json"),
+    responses(
+        (status = 200, description = "Wallet registration removed"),
+        (status = 400, description = "Malformed request", body = ErrorBody),
+        (status = 500, description = "Database error", body = ErrorBody),
+    )
+)]
+#[post("/filecoin/unregistertokenholder")]
+async fn unregister_token_holder(
+    body: web::Bytes,
+    store: web::Data<dyn VoteStore>,
+    feed: web::Data<Broadcaster>,
+) -> impl Responder {
+    let mut event = AuditEvent::new(Action::UnregisterTokenHolder, &body);
+    let res = handle_unregister_token_holder(body, store.clone(), &mut event).await;
+    audit(&store, &feed, event, res).await
+}
+
+async fn handle_unregister_token_holder(
+    body: web::Bytes,
+    store: web::Data<dyn VoteStore>,
+    event: &mut AuditEvent,
+) -> HttpResponse {
+    println!("Token holder unregistration received");
+
+    let reg: ReceivedWalletRegistration = match serde_json::from_slice(&body) {
+        Ok(v) => v,
+        Err(e) => return error_response(WALLET_DESERIALIZE_ERROR, e),
+    };
+
+    let registration = match reg.recover_wallet_registration(UNREGISTER_WALLET, event.timestamp) {
+        Ok(registration) => registration,
+        Err(e) => return error_response(WALLET_RECOVER_ERROR, e),
+    };
+    event.signer(registration.address());
+    event.network(registration.ntw());
+
+    if let Err(e) = store
+        .use_message(registration.ntw(), registration.id())
+        .await
+    {
+        return error_response(WALLET_UNREGISTER_ERROR, e);
+    }
+
+    match store
+        .unregister_token_holder(
+            registration.address(),
+            registration.ntw(),
+            registration.wallet(),
+        )
+        .await
+    {
+        Ok(_) => (),
+        Err(e) => return error_response(WALLET_UNREGISTER_ERROR, e),
+    }
+
+    HttpResponse::Ok().finish()
+}
+
+#[utoipa::path(
+    tag = "voters",
+    request_body(content = ReceivedWalletRegistration, content_type = "application/json"),
+    responses(
+        (status = 200, description = "Voter registered for the storage client"),
+        (status = 400, description = "Malformed request", body = ErrorBody),
+        (status = 409, description = "Wallet is registered to another voter", body = ErrorBody),
+        (status = 500, description = "Database error", body = ErrorBody),
+    )
+)]
+#[post("/filecoin/registerclient")]
+async fn register_client(
+    body: web::Bytes,
+    store: web::Data<dyn VoteStore>,
+    feed: web::Data<Broadcaster>,
+) -> impl Responder {
+    let mut event = AuditEvent::new(Action::RegisterClient, &body);
+    let res = handle_register_client(body, store.clone(), &mut event).await;
+    audit(&store, &feed, event, res).await
+}
+
+async fn handle_register_client(
+    body: web::Bytes,
+    store: web::Data<dyn VoteStore>,
+    event: &mut AuditEvent,
+) -> HttpResponse {
+    println!("Client registration received");
+
+    let reg: ReceivedWalletRegistration = match serde_json::from_slice(&body) {
+        Ok(v) => v,
+        Err(e) => return error_response(WALLET_DESERIALIZE_ERROR, e),
+    };
+
+    let registration = match reg.recover_wallet_registration(REGISTER_CLIENT, event.timestamp) {
+        Ok(registration) => registration,
+        Err(e) => return error_response(WALLET_RECOVER_ERROR, e),
+    };
+    event.signer(registration.address());
+    event.network(registration.ntw());
+
+    if let Err(e) = store
+        .use_message(registration.ntw(), registration.id())
+        .await
+    {
+        return error_response(WALLET_REGISTER_ERROR, e);
+    }
+
+    match store
+        .register_client(
+            registration.address(),
+            registration.ntw(),
+            registration.wallet(),
+        )
+        .await
+    {
+        Ok(_) => (),
+        Err(e) => return error_response(WALLET_REGISTER_ERROR, e),
     }
 
     HttpResponse::Ok().finish()
 }
+
+#[utoipa::path(
+    tag = "voters",
+    request_body(content = ReceivedWalletRegistration, content_type = "application/json"),
+    responses(
+        (status = 200, description = "Client registration removed"),
+        (status = 400, description = "Malformed request", body = ErrorBody),
+        (status = 500, description = "Database error", body = ErrorBody),
+    )
+)]
+#[post("/filecoin/unregisterclient")]
+async fn unregister_client(
+    body: web::Bytes,
+    store: web::Data<dyn VoteStore>,
+    feed: web::Data<Broadcaster>,
+) -> impl Responder {
+    let mut event = AuditEvent::new(Action::UnregisterClient, &body);
+    let res = handle_unregister_client(body, store.clone(), &mut event).await;
+    audit(&store, &feed, event, res).await
+}
+
+async fn handle_unregister_client(
+    body: web::Bytes,
+    store: web::Data<dyn VoteStore>,
+    event: &mut AuditEvent,
+) -> HttpResponse {
+    println!("Client unregistration received");
+
+    let reg: ReceivedWalletRegistration = match serde_json::from_slice(&body) {
+        Ok(v) => v,
+        Err(e) => return error_response(WALLET_DESERIALIZE_ERROR, e),
+    };
+
+    let registration = match reg.recover_wallet_registration(UNREGISTER_CLIENT, event.timestamp) {
+        Ok(registration) => registration,
+        Err(e) => return error_response(WALLET_RECOVER_ERROR, e),
+    };
+    event.signer(registration.address());
+    event.network(registration.ntw());
+
+    if let Err(e) = store
+        .use_message(registration.ntw(), registration.id())
+        .await
+    {
+        return error_response(WALLET_UNREGISTER_ERROR, e);
+    }
+
+    match store
+        .unregister_client(
+            registration.address(),
+            registration.ntw(),
+            registration.wallet(),
+        )
+        .await
+    {
+        Ok(_) => (),
+        Err(e) => return error_response(WALLET_UNREGISTER_ERROR, e),
+    }
+
+    HttpResponse::Ok().finish()
+}
+
+/// Appends the request to the audit log along with the answer it got, and
+/// announces what it changed to the event streams
+async fn audit(
+    store: &web::Data<dyn VoteStore>,
+    feed: &Broadcaster,
+    mut event: AuditEvent,
+    res: HttpResponse,
+) -> HttpResponse {
+    let (res, body) = res.into_parts();
+    // Boxed bodies of handler responses are in memory, reading them cannot fail
+    let body = body::to_bytes(body).await.unwrap_or_default();
+
+    event.status = res.status().as_u16();
+    if !body.is_empty() {
+        event.response = Some(String::from_utf8_lossy(&body).into_owned());
+    }
+
+    feed.announce(&***store, &event).await;
+    if let Err(e) = store.append_event(event).await {
+        println!("{}: {}", AUDIT_APPEND_ERROR, e);
+    }
+
+    res.set_body(body::BoxBody::new(body))
+}
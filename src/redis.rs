@@ -1,671 +1,1421 @@
 extern crate redis;
 
-use std::{mem::MaybeUninit, time};
+mod legacy;
 
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fmt,
+};
+
+use async_trait::async_trait;
+use deadpool::managed::{self, Object, RecycleResult};
 use ethers::types::Address;
-use redis::{Commands, Connection, RedisError};
-use serde::{Deserialize, Serialize};
+use redis::{aio::Connection, AsyncCommands, RedisError};
 use url::Url;
 
 use crate::{
-    authorized_voters,
-    messages::votes::{Vote, VoteOption},
-    storage::{fetch_storage_amount, Network},
+    audit::AuditEvent,
+    messages::{
+        vote_start::FipMetadata,
+        votes::{Vote, VoteOption},
+    },
+    results::{Constituency, Tally},
+    storage::Network,
+    store::{
+        past_deadline, Ballot, BuildResult, FinalResult, NewVote, StoreError, VoteStore, WalletKind,
+    },
+    webhooks::DeadLetter,
 };
 
+/// Redis store sharing a pool of async connections between requests
 pub struct Redis {
-    con: Connection,
+    pool: managed::Pool<Manager>,
 }
 
-#[derive(Debug, PartialEq)]
-pub enum VoteStatus {
-    DoesNotExist,
-    InProgress(u64),
-    Concluded,
+/// Opens and health checks the pooled connections
+pub struct Manager {
+    client: redis::Client,
 }
 
+/// Version of the key schema, bumped whenever keys or the shape of their values change
+pub const SCHEMA_VERSION: u32 = 1;
+
+/// Holds the schema version of the database, outside of the versioned namespace
+const SCHEMA_KEY: &str = "fipvote:schema";
+
+/// Keys of the current schema
+///
+/// Every key starts with `fipvote:v<SCHEMA_VERSION>:<kind>`, followed for
+/// most kinds by the network name and, for per FIP and per voter keys, the
+/// FIP number or the voter address in lower case hex:
+///
+/// * `fipvote:v1:ballots:<network>:<fip>` hash of voter address to JSON ballot
+/// * `fipvote:v1:tally:<network>:<fip>` JSON tally of the vote
+/// * `fipvote:v1:started:<network>:<fip>` unix timestamp the vote started at
+/// * `fipvote:v1:snapshot:<network>:<fip>` chain epoch balances are measured at
+/// * `fipvote:v1:fip-committees:<network>:<fip>` JSON committee names
+/// * `fipvote:v1:fip-committee-members:<network>:<fip>` JSON committee members when the vote started
+/// * `fipvote:v1:result:<network>:<fip>` JSON final result, written once
+/// * `fipvote:v1:fip-metadata:<network>:<fip>` JSON FIP metadata, missing if none was signed
+/// * `fipvote:v1:delegates:<network>:<voter>` storage provider ids
+/// * `fipvote:v1:worker:<network>:<voter>` worker address that signed the storage provider registration
+/// * `fipvote:v1:token-wallets:<network>:<voter>` JSON wallet addresses
+/// * `fipvote:v1:client-wallets:<network>:<voter>` JSON wallet addresses
+/// * `fipvote:v1:network:<voter>` network name
+/// * `fipvote:v1:starters:<network>` packed vote starter addresses
+/// * `fipvote:v1:committees:<network>` JSON committee members
+/// * `fipvote:v1:all-votes:<network>` JSON FIP numbers
+/// * `fipvote:v1:token-wallet-owners:<network>` hash of token holder wallet to voter address
+/// * `fipvote:v1:client-wallet-owners:<network>` hash of storage client wallet to voter address
+/// * `fipvote:v1:used-messages:<network>` set of the ids of used starter messages
+/// * `fipvote:v1:events` list of JSON audit events, shared by all networks
+/// * `fipvote:v1:cursors` hash of background task to how far it got
+/// * `fipvote:v1:dead-letters` list of JSON webhook deliveries given up on
+///
+/// The last part of a key never contains a colon, so keys stay unique even
+/// for network names that do.
+#[derive(Debug, PartialEq)]
 enum LookupKey {
-    /// FIP number to vector of all votes
-    Votes(u32, Network),
+    /// FIP number to the hash of voter address to ballot
+    Ballots(u32, Network),
     /// FIP number to timestamp of vote start
     Timestamp(u32, Network),
     /// Network and voter address to voter registration
     Voter(Network, Address),
+    /// Network and voter address to the worker that signed the registration
+    Worker(Network, Address),
     /// The voter authorized to start a vote on that network
     VoteStarters(Network),
     /// All FIP votes on the network,
     AllVotes(Network),
-    /// VoteChoice and FIP number to total storage amount
-    Storage(VoteOption, Network, u32),
+    /// FIP number to the ballots and weight of every constituency
+    Tally(u32, Network),
     /// The network the address belongs to
     Network(Address),
+    /// Network and voter address to the token holder wallets delegated to the voter
+    TokenWallets(Network, Address),
+    /// Network and voter address to the storage client wallets delegated to the voter
+    ClientWallets(Network, Address),
+    /// Token holder wallets on the network to the voter they are delegated to
+    TokenWalletOwners(Network),
+    /// Storage client wallets on the network to the voter they are delegated to
+    ClientWalletOwners(Network),
+    /// Ids of the signed starter messages used on the network
+    UsedMessages(Network),
+    /// FIP number to the chain epoch balances are measured at
+    Snapshot(u32, Network),
+    /// Committee names to their members on the network
+    Committees(Network),
+    /// FIP number to the committees taking part in the vote
+    FipCommittees(u32, Network),
+    /// FIP number to the members of those committees when the vote started
+    FipCommitteeMembers(u32, Network),
+    /// FIP number to the result the vote was finalized with
+    Result(u32, Network),
+    /// FIP number to the metadata signed with the vote start
+    FipMetadata(u32, Network),
+    /// The audit log of every network
+    Events,
+    /// Background tasks to how far they got
+    Cursors,
+    /// Webhook deliveries given up on
+    DeadLetters,
+}
+
+/// What `Redis::migrate` did to the keys of an older schema
+#[derive(Debug, Default, PartialEq)]
+pub struct Migration {
+    /// Schema version the database was at
+    pub from: u32,
+    /// Keys moved to their name in the current schema
+    pub renamed: usize,
+    /// FIPs whose JSON list of ballots became a hash and a tally
+    pub ballots: usize,
+    /// Per choice weights folded into the tallies and removed
+    pub weights: usize,
+    /// Old keys left in place because their new key already exists, or
+    /// because a shared weight could not be told apart between networks
+    pub kept: usize,
+}
+
+/// The ballots and per choice weights of a vote, which the old schema kept
+/// in separate keys
+#[derive(Default)]
+struct LegacyVote {
+    fip_number: u32,
+    votes: Vec<Vote>,
+    /// Old keys folded into the tally
+    keys: Vec<Vec<u8>>,
+    weights: Vec<(VoteOption, u128)>,
+}
+
+impl fmt::Display for Migration {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Migrated from key schema {} to {}: {} keys renamed, {} ballot lists converted, {} weights folded into tallies, {} keys kept",
+            self.from, SCHEMA_VERSION, self.renamed, self.ballots, self.weights, self.kept
+        )
+    }
 }
 
 impl Redis {
-    pub fn new(path: impl Into<Url>) -> Result<Redis, RedisError> {
+    pub async fn new(path: impl Into<Url>) -> Result<Redis, StoreError> {
         let client = redis::Client::open(path.into())?;
-        let con = client.get_connection()?;
+        let pool = managed::Pool::builder(Manager { client }).build()?;
+        // Fail early if the database cannot be reached
+        let _ = pool.get().await?;
 
-        Ok(Self { con })
+        Ok(Self { pool })
     }
 
-    /*~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~/
-    /                                 INITIALIZATION                                 /
-    /~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~*/
-
-    /// Starts a new vote in the database but does not add any votes into the database
-    pub fn start_vote(
-        &mut self,
-        fip_number: impl Into<u32>,
-        signer: Address,
-        ntw: Network,
-    ) -> Result<(), RedisError> {
-        let num = fip_number.into();
+    /// The key schema version of the database, `None` if it was never recorded
+    pub async fn schema_version(&self) -> Result<Option<u32>, StoreError> {
+        Ok(self.con().await?.get(SCHEMA_KEY).await?)
+    }
 
-        // Check if signer is authorized to start a vote
-        if !self.is_authorized_starter(signer, ntw)? && !authorized_voters().contains(&signer) {
-            return Err(RedisError::from((
-                redis::ErrorKind::TypeError,
-                "Signer is not authorized to start a vote",
-            )));
-        }
+    /// Makes sure the database uses the current key schema
+    ///
+    /// Databases without any keys of an older schema are marked as current,
+    /// older databases have to be moved with `migrate` first.
+    pub async fn check_schema(&self) -> Result<(), StoreError> {
+        let found = match self.schema_version().await? {
+            Some(version) => version,
+            None => {
+                let keys: Vec<Vec<u8>> = self.con().await?.keys("*").await?;
+                if keys.iter().any(|key| legacy::parse(key).is_some()) {
+                    0
+                } else {
+                    SCHEMA_VERSION
+                }
+            }
+        };
 
-        // Check if vote already exists
-        if self.vote_exists(ntw, num)? {
-            return Err(RedisError::from((
-                redis::ErrorKind::TypeError,
-                "Vote already exists",
-            )));
+        if found != SCHEMA_VERSION {
+            return Err(StoreError::SchemaVersion {
+                found,
+                expected: SCHEMA_VERSION,
+            });
         }
 
-        self.register_vote_to_all_votes(num, ntw)?;
-
-        // Set a map of FIP to timestamp of vote start
-        let time_key = LookupKey::Timestamp(num, ntw).to_bytes();
-        let timestamp = time::SystemTime::now()
-            .duration_since(time::UNIX_EPOCH)
-            .unwrap()
-            .as_secs();
-        // After this is set then the vote is considered started
-        self.con.set::<Vec<u8>, u64, ()>(time_key, timestamp)?;
-
+        self.con()
+            .await?
+            .set::<_, _, ()>(SCHEMA_KEY, SCHEMA_VERSION)
+            .await?;
         Ok(())
     }
 
-    /// Registers a voter in the database
+    /// Rewrites the keys of an older schema to the current one
     ///
-    /// * Creates a lookup from voters address to their respective network
-    /// * Creates a lookup from voters address to their authorized storage providers
-    pub fn register_voter(
-        &mut self,
-        voter: Address,
-        ntw: Network,
-        sp_ids: Vec<u32>,
-    ) -> Result<(), RedisError> {
-        let key = LookupKey::Voter(ntw, voter).to_bytes();
+    /// Meant to run while no server uses the database. The ballots and per
+    /// choice weights of each vote become a ballot hash and a tally. Keys of
+    /// other applications and of networks that are not configured are left
+    /// alone.
+    pub async fn migrate(&self) -> Result<Migration, StoreError> {
+        let mut migration = Migration {
+            from: self.schema_version().await?.unwrap_or(0),
+            ..Default::default()
+        };
+        if migration.from == SCHEMA_VERSION {
+            return Ok(migration);
+        }
 
-        self.set_network(ntw, voter)?;
+        let mut con = self.con().await?;
+        let keys: Vec<Vec<u8>> = con.keys("*").await?;
+        // Votes by network name and FIP number
+        let mut votes: BTreeMap<(&'static str, u32), LegacyVote> = BTreeMap::new();
+        let mut shared = Vec::new();
+        for key in keys {
+            match legacy::parse(&key) {
+                Some(legacy::LegacyKey::Renamed(new)) => {
+                    if con.rename_nx(&key, new.to_bytes()).await? {
+                        migration.renamed += 1;
+                    } else {
+                        migration.kept += 1;
+                    }
+                }
+                Some(legacy::LegacyKey::Votes(fip_number, ntw)) => {
+                    let list: Option<String> = con.get(&key).await?;
+                    let vote = votes.entry((ntw.name(), fip_number)).or_default();
+                    vote.fip_number = fip_number;
+                    if let Some(list) = list {
+                        vote.votes = serde_json::from_str(&list)?;
+                    }
+                    vote.keys.push(key);
+                }
+                Some(legacy::LegacyKey::Weight(fip_number, ntw, choice)) => {
+                    let weight = legacy_weight(&mut con, &key).await?;
+                    let vote = votes.entry((ntw.name(), fip_number)).or_default();
+                    vote.fip_number = fip_number;
+                    vote.weights.push((choice, weight));
+                    vote.keys.push(key);
+                }
+                Some(legacy::LegacyKey::SharedWeight(fip_number)) => {
+                    shared.push((fip_number, legacy_weight(&mut con, &key).await?, key));
+                }
+                None => {}
+            }
+        }
 
-        self.con.set::<Vec<u8>, Vec<u32>, ()>(key, sp_ids)?;
+        // A shared weight belongs to the network that has ballots with its choice
+        for (fip_number, weight, key) in shared {
+            let owners = legacy::shared_weight_owners()
+                .into_iter()
+                .filter(|(ntw, choice)| {
+                    votes
+                        .get(&(ntw.name(), fip_number))
+                        .is_some_and(|v| v.votes.iter().any(|vote| vote.choice() == *choice))
+                })
+                .collect::<Vec<_>>();
+            match owners.as_slice() {
+                [(ntw, choice)] => {
+                    let vote = votes.entry((ntw.name(), fip_number)).or_default();
+                    vote.weights.push((*choice, weight));
+                    vote.keys.push(key);
+                }
+                _ if weight == 0 => {
+                    con.del::<_, ()>(&key).await?;
+                    migration.weights += 1;
+                }
+                _ => {
+                    println!(
+                        "Kept the weight of FIP {} shared by mainnet abstentions and calibration approvals, its network cannot be told",
+                        fip_number
+                    );
+                    migration.kept += 1;
+                }
+            }
+        }
 
-        Ok(())
-    }
+        for ((name, _), vote) in votes {
+            let ntw = match Network::from_name(name) {
+                Some(ntw) => ntw,
+                None => continue,
+            };
+            let fip_number = vote.fip_number;
+
+            // Every ballot of the old schema voted with storage providers
+            let mut tally = Tally::default();
+            for ballot in &vote.votes {
+                tally.add_ballot(Constituency::StorageProviders, ballot.choice());
+            }
+            for (choice, weight) in &vote.weights {
+                tally.add_weight(Constituency::StorageProviders, *choice, *weight);
+            }
 
-    pub fn unregister_voter(&mut self, voter: Address, ntw: Network) -> Result<(), RedisError> {
-        let key = LookupKey::Voter(ntw, voter).to_bytes();
+            let mut pipe = redis::pipe();
+            pipe.atomic();
+            for ballot in vote.votes {
+                pipe.hset_nx(
+                    LookupKey::Ballots(fip_number, ntw).to_bytes(),
+                    ballot.voter().as_bytes(),
+                    serde_json::to_string(&Ballot {
+                        vote: ballot,
+                        weight: Tally::default(),
+                    })?,
+                )
+                .ignore();
+            }
+            pipe.set_nx(
+                LookupKey::Tally(fip_number, ntw).to_bytes(),
+                serde_json::to_string(&tally)?,
+            )
+            .ignore();
+            for key in &vote.keys {
+                pipe.del(key).ignore();
+            }
+            pipe.query_async::<_, ()>(&mut *con).await?;
+            migration.ballots += 1;
+            migration.weights += vote.weights.len();
+        }
 
-        // Remove the voter from the network lookup
-        self.remove_network(voter)?;
+        con.set::<_, _, ()>(SCHEMA_KEY, SCHEMA_VERSION).await?;
+        Ok(migration)
+    }
 
-        self.con.del::<Vec<u8>, ()>(key)?;
+    async fn con(&self) -> Result<Object<Manager>, StoreError> {
+        Ok(self.pool.get().await?)
+    }
 
+    /// Reads a JSON value, missing keys are the default value
+    async fn get_json<T>(&self, key: LookupKey) -> Result<T, StoreError>
+    where
+        T: serde::de::DeserializeOwned + Default,
+    {
+        let value = self
+            .con()
+            .await?
+            .get::<Vec<u8>, Option<String>>(key.to_bytes())
+            .await?;
+        match value {
+            Some(v) => Ok(serde_json::from_str(v.as_str())?),
+            None => Ok(T::default()),
+        }
+    }
+
+    /// Writes a JSON value, empty values remove the key
+    async fn set_json<T>(&self, key: LookupKey, value: &[T]) -> Result<(), StoreError>
+    where
+        T: serde::Serialize + Sync,
+    {
+        let mut con = self.con().await?;
+        if value.is_empty() {
+            con.del::<Vec<u8>, ()>(key.to_bytes()).await?;
+        } else {
+            con.set::<Vec<u8>, String, ()>(key.to_bytes(), serde_json::to_string(value)?)
+                .await?;
+        }
         Ok(())
     }
 
-    pub fn register_voter_starter(
-        &mut self,
-        voter: Address,
+    /// Writes the vote's keys along with its entry in all-votes, unless a
+    /// vote on the FIP already started
+    ///
+    /// The timestamp and all-votes are watched, so that of two concurrent
+    /// starts one fails and no FIP is lost from all-votes
+    async fn claim_vote(
+        &self,
+        fip_number: u32,
         ntw: Network,
-    ) -> Result<(), RedisError> {
-        let key = LookupKey::VoteStarters(ntw).to_bytes();
+        pipe: redis::Pipeline,
+    ) -> Result<(), StoreError> {
+        let timestamp_key = LookupKey::Timestamp(fip_number, ntw).to_bytes();
+        let all_votes_key = LookupKey::AllVotes(ntw).to_bytes();
+        let mut con = self.con().await?;
+
+        loop {
+            redis::cmd("WATCH")
+                .arg(&timestamp_key)
+                .arg(&all_votes_key)
+                .query_async::<_, ()>(&mut *con)
+                .await?;
+
+            let started: bool = con.exists(&timestamp_key).await?;
+            if started {
+                redis::cmd("UNWATCH")
+                    .query_async::<_, ()>(&mut *con)
+                    .await?;
+                return Err(StoreError::VoteExists);
+            }
 
-        let mut current_voters = self.voter_starters(ntw)?;
+            let json: Option<String> = con.get(&all_votes_key).await?;
+            let mut votes: Vec<u32> = match json {
+                Some(json) => serde_json::from_str(&json)?,
+                None => Vec::new(),
+            };
+            if !votes.contains(&fip_number) {
+                votes.push(fip_number);
+            }
 
-        current_voters.push(voter);
+            let mut tx = pipe.clone();
+            tx.set(&all_votes_key, serde_json::to_string(&votes)?)
+                .ignore();
+            let written: Option<()> = tx.query_async(&mut *con).await?;
+            if written.is_some() {
+                return Ok(());
+            }
+        }
+    }
+}
 
-        current_voters.sort();
-        current_voters.dedup();
+/// Reads a per choice weight of the old schema, a big endian u128
+async fn legacy_weight(con: &mut Connection, key: &[u8]) -> Result<u128, StoreError> {
+    let bytes: Vec<u8> = con.get(key).await?;
+    if bytes.is_empty() {
+        return Ok(0);
+    }
+    let bytes: [u8; 16] = bytes
+        .try_into()
+        .map_err(|_| StoreError::Corrupt("storage weight"))?;
+    Ok(u128::from_be_bytes(bytes))
+}
 
-        let new_bytes = current_voters
-            .into_iter()
-            .flat_map(|v| v.as_fixed_bytes().to_vec())
-            .collect::<Vec<u8>>();
+#[async_trait]
+impl managed::Manager for Manager {
+    type Type = Connection;
+    type Error = RedisError;
 
-        self.con.set::<Vec<u8>, Vec<u8>, ()>(key, new_bytes)?;
+    async fn create(&self) -> Result<Connection, RedisError> {
+        self.client.get_async_connection().await
+    }
 
+    async fn recycle(&self, con: &mut Connection) -> RecycleResult<RedisError> {
+        // Also drops keys left watched by a transaction that returned early
+        redis::cmd("UNWATCH").query_async::<_, ()>(con).await?;
         Ok(())
     }
+}
 
-    /// Creates a lookup from the voter to the network they are voting on
-    fn set_network(&mut self, ntw: Network, voter: Address) -> Result<(), RedisError> {
-        let key: Vec<u8> = LookupKey::Network(voter).to_bytes();
-        self.con.set::<Vec<u8>, Network, ()>(key, ntw)?;
-        Ok(())
+#[async_trait]
+impl VoteStore for Redis {
+    async fn voter_delegates(&self, voter: Address, ntw: Network) -> Result<Vec<u32>, StoreError> {
+        let key = LookupKey::Voter(ntw, voter).to_bytes();
+        let delegates: Vec<u32> = match self.con().await?.get::<Vec<u8>, Vec<u32>>(key).await {
+            Ok(d) => d,
+            Err(e) => match e.kind() {
+                redis::ErrorKind::TypeError => Vec::new(),
+                _ => return Err(e.into()),
+            },
+        };
+        Ok(delegates)
     }
 
-    /*~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~/
-    /                                     GETTERS                                    /
-    /~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~*/
+    async fn set_voter_delegates(
+        &self,
+        voter: Address,
+        ntw: Network,
+        sp_ids: Vec<u32>,
+    ) -> Result<(), StoreError> {
+        let key = LookupKey::Voter(ntw, voter).to_bytes();
+        let mut con = self.con().await?;
 
-    pub fn vote_exists(&mut self, ntw: Network, fip: u32) -> Result<bool, RedisError> {
-        let key = LookupKey::Timestamp(fip, ntw).to_bytes();
+        if sp_ids.is_empty() {
+            con.del::<Vec<u8>, ()>(key).await?;
+        } else {
+            con.set::<Vec<u8>, Vec<u32>, ()>(key, sp_ids).await?;
+        }
 
-        self.con.exists(key)
+        Ok(())
     }
 
-    pub fn is_authorized_starter(
-        &mut self,
+    async fn voter_worker(
+        &self,
         voter: Address,
         ntw: Network,
-    ) -> Result<bool, RedisError> {
-        let voters = self.voter_starters(ntw)?;
+    ) -> Result<Option<String>, StoreError> {
+        let key = LookupKey::Worker(ntw, voter).to_bytes();
+        Ok(self.con().await?.get(key).await?)
+    }
 
-        Ok(voters.contains(&voter))
+    async fn set_voter_worker(
+        &self,
+        voter: Address,
+        ntw: Network,
+        worker: Option<String>,
+    ) -> Result<(), StoreError> {
+        let key = LookupKey::Worker(ntw, voter).to_bytes();
+        let mut con = self.con().await?;
+
+        match worker {
+            Some(worker) => con.set::<Vec<u8>, String, ()>(key, worker).await?,
+            None => con.del::<Vec<u8>, ()>(key).await?,
+        }
+
+        Ok(())
     }
 
-    pub fn is_registered(&mut self, voter: Address, ntw: Network) -> bool {
-        let key = LookupKey::Voter(ntw, voter).to_bytes();
+    async fn wallets(
+        &self,
+        kind: WalletKind,
+        voter: Address,
+        ntw: Network,
+    ) -> Result<Vec<String>, StoreError> {
+        self.get_json(LookupKey::wallets(kind, ntw, voter)).await
+    }
 
-        match self.con.get::<Vec<u8>, Vec<u32>>(key) {
-            Ok(sp_ids) => !sp_ids.is_empty(),
-            Err(_) => false,
+    async fn set_wallets(
+        &self,
+        kind: WalletKind,
+        voter: Address,
+        ntw: Network,
+        wallets: Vec<String>,
+    ) -> Result<(), StoreError> {
+        self.set_json(LookupKey::wallets(kind, ntw, voter), &wallets)
+            .await
+    }
+
+    async fn claim_wallet(
+        &self,
+        kind: WalletKind,
+        ntw: Network,
+        wallet: &str,
+        voter: Address,
+    ) -> Result<Address, StoreError> {
+        let key = LookupKey::wallet_owners(kind, ntw).to_bytes();
+        let mut con = self.con().await?;
+        let (owner,): (Vec<u8>,) = redis::pipe()
+            .atomic()
+            .hset_nx(&key, wallet, voter.as_bytes())
+            .ignore()
+            .hget(&key, wallet)
+            .query_async(&mut *con)
+            .await?;
+
+        if owner.len() != 20 {
+            return Err(StoreError::Corrupt("wallet owner"));
         }
+        Ok(Address::from_slice(&owner))
     }
 
-    /// Returns a json blob of the vote results for the FIP number
-    ///
-    pub fn vote_results(
-        &mut self,
-        fip_number: impl Into<u32>,
+    async fn release_wallet(
+        &self,
+        kind: WalletKind,
         ntw: Network,
-    ) -> Result<VoteResults, RedisError> {
-        let mut yay = 0;
-        let mut nay = 0;
-        let mut abstain = 0;
+        wallet: &str,
+    ) -> Result<(), StoreError> {
+        let key = LookupKey::wallet_owners(kind, ntw).to_bytes();
+        self.con()
+            .await?
+            .hdel::<Vec<u8>, &str, ()>(key, wallet)
+            .await?;
+        Ok(())
+    }
 
-        let num = fip_number.into();
+    async fn network(&self, voter: Address) -> Result<Option<Network>, StoreError> {
+        let key = LookupKey::Network(voter).to_bytes();
+        let ntw = self
+            .con()
+            .await?
+            .get::<Vec<u8>, Option<Network>>(key)
+            .await?;
+        Ok(ntw)
+    }
 
-        let votes = self.votes(num, ntw)?;
+    async fn set_network(&self, voter: Address, ntw: Option<Network>) -> Result<(), StoreError> {
+        let key = LookupKey::Network(voter).to_bytes();
+        let mut con = self.con().await?;
 
-        for vote in votes {
-            match vote.choice() {
-                VoteOption::Yay => yay += 1,
-                VoteOption::Nay => nay += 1,
-                VoteOption::Abstain => abstain += 1,
-            }
+        match ntw {
+            Some(ntw) => con.set::<Vec<u8>, Network, ()>(key, ntw).await?,
+            None => con.del::<Vec<u8>, ()>(key).await?,
         }
 
-        let results = VoteResults {
-            yay,
-            nay,
-            abstain,
-            yay_storage_size: self.get_storage(num, VoteOption::Yay, ntw)?,
-            nay_storage_size: self.get_storage(num, VoteOption::Nay, ntw)?,
-            abstain_storage_size: self.get_storage(num, VoteOption::Abstain, ntw)?,
-        };
-
-        Ok(results)
+        Ok(())
     }
 
-    pub fn vote_status(
-        &mut self,
-        fip_number: impl Into<u32>,
-        vote_length: impl Into<u64>,
-        ntw: Network,
-    ) -> Result<VoteStatus, RedisError> {
-        let num = fip_number.into();
-
-        // Check if the FIP number has a timestamp
-        if !self.vote_exists(ntw, num)? {
-            return Ok(VoteStatus::DoesNotExist);
+    async fn voters(&self, ntw: Network) -> Result<Vec<Address>, StoreError> {
+        let mut con = self.con().await?;
+        let mut voters = BTreeSet::new();
+
+        // Registration keys end with the address of the voter
+        for kind in [
+            LookupKey::Voter(ntw, Address::zero()),
+            LookupKey::TokenWallets(ntw, Address::zero()),
+            LookupKey::ClientWallets(ntw, Address::zero()),
+        ] {
+            let parent = kind.parent();
+            let keys: Vec<String> = con.keys(format!("{}*", parent)).await?;
+            voters.extend(
+                keys.iter()
+                    .filter_map(|key| key[parent.len()..].parse::<Address>().ok()),
+            );
         }
 
-        let vote_length = vote_length.into();
+        let parent = LookupKey::Network(Address::zero()).parent();
+        let keys: Vec<String> = con.keys(format!("{}*", parent)).await?;
+        for key in keys {
+            let registered: Option<Network> = con.get(&key).await?;
+            if registered == Some(ntw) {
+                voters.extend(key[parent.len()..].parse::<Address>().ok());
+            }
+        }
 
-        let timestamp: u64 = self.vote_start(num, ntw)?;
+        Ok(voters.into_iter().collect())
+    }
 
-        let now = time::SystemTime::now()
-            .duration_since(time::UNIX_EPOCH)
-            .expect("Time went backwards")
-            .as_secs();
+    async fn voter_starters(&self, ntw: Network) -> Result<Vec<Address>, StoreError> {
+        let key = LookupKey::VoteStarters(ntw).to_bytes();
 
-        if now < timestamp + vote_length {
-            let time_left = vote_length - (now - timestamp);
-            Ok(VoteStatus::InProgress(time_left))
-        } else {
-            Ok(VoteStatus::Concluded)
-        }
+        let bytes: Vec<u8> = self.con().await?.get::<Vec<u8>, Vec<u8>>(key).await?;
+        unpack_starters(&bytes)
     }
 
-    pub fn active_votes(
-        &mut self,
+    async fn set_voter_starters(
+        &self,
         ntw: Network,
-        vote_length: impl Into<u64>,
-    ) -> Result<Vec<u32>, RedisError> {
-        let all_votes = self.all_votes(ntw)?;
+        starters: Vec<Address>,
+    ) -> Result<(), StoreError> {
+        let key = LookupKey::VoteStarters(ntw).to_bytes();
 
-        let vote_length = vote_length.into();
+        self.con()
+            .await?
+            .set::<Vec<u8>, Vec<u8>, ()>(key, pack_starters(&starters))
+            .await?;
 
-        let mut active_votes = Vec::new();
-        for vote in all_votes {
-            let status = self.vote_status(vote, vote_length, ntw)?;
-            if let VoteStatus::InProgress(_) = status {
-                active_votes.push(vote);
-            }
-        }
-        Ok(active_votes)
+        Ok(())
     }
 
-    pub fn concluded_votes(
-        &mut self,
+    async fn init_voter_starters(
+        &self,
         ntw: Network,
-        vote_length: impl Into<u64>,
-    ) -> Result<Vec<u32>, RedisError> {
-        let all_votes = self.all_votes(ntw)?;
+        starters: Vec<Address>,
+    ) -> Result<bool, StoreError> {
+        let key = LookupKey::VoteStarters(ntw).to_bytes();
+        // An empty value still marks the starters as set
+        let set: bool = self
+            .con()
+            .await?
+            .set_nx(key, pack_starters(&starters))
+            .await?;
+        Ok(set)
+    }
 
-        let vote_length = vote_length.into();
+    async fn revoke_voter_starter(&self, starter: Address, ntw: Network) -> Result<(), StoreError> {
+        let key = LookupKey::VoteStarters(ntw).to_bytes();
+        let mut con = self.con().await?;
+
+        // Only written if no starter was granted or revoked since the
+        // starters were read, otherwise read again
+        loop {
+            redis::cmd("WATCH")
+                .arg(&key)
+                .query_async::<_, ()>(&mut *con)
+                .await?;
+
+            let bytes: Vec<u8> = con.get(&key).await?;
+            let mut starters = unpack_starters(&bytes)?;
+            let check = if !starters.contains(&starter) {
+                Err(StoreError::UnknownStarter)
+            } else if starters.len() == 1 {
+                Err(StoreError::LastStarter)
+            } else {
+                Ok(())
+            };
+            if let Err(e) = check {
+                redis::cmd("UNWATCH")
+                    .query_async::<_, ()>(&mut *con)
+                    .await?;
+                return Err(e);
+            }
 
-        let mut concluded_votes = Vec::new();
-        for vote in all_votes {
-            let status = self.vote_status(vote, vote_length, ntw)?;
-            if let VoteStatus::Concluded = status {
-                concluded_votes.push(vote);
+            starters.retain(|&s| s != starter);
+            let written: Option<()> = redis::pipe()
+                .atomic()
+                .set(&key, pack_starters(&starters))
+                .ignore()
+                .query_async(&mut *con)
+                .await?;
+            if written.is_some() {
+                return Ok(());
             }
         }
-        Ok(concluded_votes)
     }
 
-    pub fn voter_delegates(
-        &mut self,
-        voter: Address,
-        ntw: Network,
-    ) -> Result<Vec<u32>, RedisError> {
-        let key = LookupKey::Voter(ntw, voter).to_bytes();
-        let delegates: Vec<u32> = match self.con.get::<Vec<u8>, Vec<u32>>(key) {
-            Ok(d) => d,
-            Err(e) => match e.kind() {
-                redis::ErrorKind::TypeError => Vec::new(),
-                _ => return Err(e),
-            },
-        };
-        Ok(delegates)
+    async fn committees(&self, ntw: Network) -> Result<BTreeMap<String, Vec<Address>>, StoreError> {
+        self.get_json(LookupKey::Committees(ntw)).await
     }
 
-    pub fn voter_starters(&mut self, ntw: Network) -> Result<Vec<Address>, RedisError> {
-        let key = LookupKey::VoteStarters(ntw).to_bytes();
+    async fn set_committees(
+        &self,
+        ntw: Network,
+        committees: BTreeMap<String, Vec<Address>>,
+    ) -> Result<(), StoreError> {
+        let key = LookupKey::Committees(ntw).to_bytes();
+        self.con()
+            .await?
+            .set::<Vec<u8>, String, ()>(key, serde_json::to_string(&committees)?)
+            .await?;
+        Ok(())
+    }
 
-        let bytes: Vec<u8> = self.con.get::<Vec<u8>, Vec<u8>>(key)?;
+    async fn set_committee(
+        &self,
+        ntw: Network,
+        name: String,
+        members: Vec<Address>,
+    ) -> Result<(), StoreError> {
+        let key = LookupKey::Committees(ntw).to_bytes();
+        let mut con = self.con().await?;
+
+        // Only written if no committee changed since they were read,
+        // otherwise read again
+        loop {
+            redis::cmd("WATCH")
+                .arg(&key)
+                .query_async::<_, ()>(&mut *con)
+                .await?;
+
+            let json: Option<String> = con.get(&key).await?;
+            let mut committees: BTreeMap<String, Vec<Address>> = match json {
+                Some(json) => serde_json::from_str(&json)?,
+                None => BTreeMap::new(),
+            };
+            if members.is_empty() {
+                committees.remove(&name);
+            } else {
+                committees.insert(name.clone(), members.clone());
+            }
 
-        if bytes.len() % 20 != 0 {
-            return Err(RedisError::from((
-                redis::ErrorKind::TypeError,
-                "Error retrieving vote starters, invalid length",
-            )));
+            let written: Option<()> = redis::pipe()
+                .atomic()
+                .set(&key, serde_json::to_string(&committees)?)
+                .ignore()
+                .query_async(&mut *con)
+                .await?;
+            if written.is_some() {
+                return Ok(());
+            }
         }
-        let addr_length = bytes.len() / 20;
+    }
 
-        let mut starters: Vec<Address> = Vec::with_capacity(addr_length);
-        for i in 0..addr_length {
-            let start = i * 20;
-            let end = start + 20;
-            let addr = Address::from_slice(&bytes[start..end]);
-            starters.push(addr);
-        }
+    async fn all_votes(&self, ntw: Network) -> Result<Vec<u32>, StoreError> {
+        self.get_json(LookupKey::AllVotes(ntw)).await
+    }
 
-        Ok(starters)
+    async fn vote_start(&self, fip_number: u32, ntw: Network) -> Result<Option<u64>, StoreError> {
+        let key = LookupKey::Timestamp(fip_number, ntw).to_bytes();
+        let timestamp = self.con().await?.get::<Vec<u8>, Option<u64>>(key).await?;
+        Ok(timestamp)
     }
 
-    fn get_storage(
-        &mut self,
+    async fn snapshot_epoch(
+        &self,
         fip_number: u32,
-        vote: VoteOption,
         ntw: Network,
-    ) -> Result<u128, RedisError> {
-        let key = LookupKey::Storage(vote, ntw, fip_number).to_bytes();
-        let storage_bytes: Vec<u8> = self.con.get::<Vec<u8>, Vec<u8>>(key)?;
-        if storage_bytes.is_empty() {
-            return Ok(0);
-        }
-        if storage_bytes.len() != 16 {
-            return Err(RedisError::from((
-                redis::ErrorKind::TypeError,
-                "Error retrieving storage size",
-            )));
-        }
-        let storage = u128::from_be_bytes(storage_bytes.try_into().unwrap());
-        Ok(storage)
+    ) -> Result<Option<i64>, StoreError> {
+        let key = LookupKey::Snapshot(fip_number, ntw).to_bytes();
+        let epoch = self.con().await?.get::<Vec<u8>, Option<i64>>(key).await?;
+        Ok(epoch)
     }
 
-    fn vote_start(&mut self, fip_number: impl Into<u32>, ntw: Network) -> Result<u64, RedisError> {
-        let key = LookupKey::Timestamp(fip_number.into(), ntw).to_bytes();
-        let timestamp: u64 = self.con.get::<Vec<u8>, u64>(key)?;
-        Ok(timestamp)
+    async fn fip_committees(
+        &self,
+        fip_number: u32,
+        ntw: Network,
+    ) -> Result<Vec<String>, StoreError> {
+        self.get_json(LookupKey::FipCommittees(fip_number, ntw))
+            .await
     }
 
-    fn votes(&mut self, fip_number: impl Into<u32>, ntw: Network) -> Result<Vec<Vote>, RedisError> {
-        let key = LookupKey::Votes(fip_number.into(), ntw).to_bytes();
-        let votes: Vec<Vote> = match self.con.get::<Vec<u8>, String>(key) {
-            Ok(v) => serde_json::from_str(v.as_str()).unwrap(),
-            Err(e) => match e.kind() {
-                redis::ErrorKind::TypeError => Vec::new(),
-                _ => return Err(e),
-            },
-        };
-        Ok(votes)
+    async fn fip_committee_members(
+        &self,
+        fip_number: u32,
+        ntw: Network,
+    ) -> Result<BTreeMap<String, Vec<Address>>, StoreError> {
+        self.get_json(LookupKey::FipCommitteeMembers(fip_number, ntw))
+            .await
     }
 
-    pub fn network(&mut self, voter: Address) -> Result<Network, RedisError> {
-        let key = LookupKey::Network(voter).to_bytes();
-        let ntw: Network = self.con.get::<Vec<u8>, Network>(key)?;
-        Ok(ntw)
+    async fn fip_metadata(&self, fip_number: u32, ntw: Network) -> Result<FipMetadata, StoreError> {
+        self.get_json(LookupKey::FipMetadata(fip_number, ntw)).await
     }
 
-    pub fn all_votes(&mut self, ntw: Network) -> Result<Vec<u32>, RedisError> {
-        let key = LookupKey::AllVotes(ntw).to_bytes();
+    async fn ballots(&self, fip_number: u32, ntw: Network) -> Result<Vec<Ballot>, StoreError> {
+        let key = LookupKey::Ballots(fip_number, ntw).to_bytes();
+        let values: Vec<String> = self.con().await?.hvals(key).await?;
 
-        let votes: Vec<u32> = match self.con.get::<Vec<u8>, String>(key) {
-            Ok(v) => serde_json::from_str(v.as_str()).unwrap(),
-            Err(e) => match e.kind() {
-                redis::ErrorKind::TypeError => Vec::new(),
-                _ => return Err(e),
-            },
-        };
-        Ok(votes)
+        let mut ballots = Vec::with_capacity(values.len());
+        for ballot in values {
+            ballots.push(serde_json::from_str(&ballot)?);
+        }
+        Ok(ballots)
     }
 
-    /*~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~/
-    /                                     SETTERS                                    /
-    /~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~*/
-
-    pub async fn add_vote<T>(
-        &mut self,
-        fip_number: T,
-        vote: Vote,
+    async fn ballot(
+        &self,
+        fip_number: u32,
+        ntw: Network,
         voter: Address,
-        vote_length: impl Into<u64>,
-    ) -> Result<(), RedisError>
-    where
-        T: Into<u32>,
-    {
-        let num: u32 = fip_number.into();
-        let ntw = self.network(voter)?;
+    ) -> Result<Option<Vote>, StoreError> {
+        let key = LookupKey::Ballots(fip_number, ntw).to_bytes();
+        let ballot: Option<String> = self.con().await?.hget(key, voter.as_bytes()).await?;
 
-        // If the vote is not active, throw an error
-        if !self.is_vote_active(num, ntw, vote_length)? {
-            return Err(RedisError::from((
-                redis::ErrorKind::TypeError,
-                "Vote is not active",
-            )));
+        match ballot {
+            Some(b) => Ok(Some(serde_json::from_str::<Ballot>(&b)?.vote)),
+            None => Ok(None),
         }
+    }
 
-        // Fetch the storage provider Id's that the voter is authorized for
-        let authorized = self.voter_delegates(voter, ntw)?;
+    async fn ballot_count(&self, fip_number: u32, ntw: Network) -> Result<u64, StoreError> {
+        let key = LookupKey::Ballots(fip_number, ntw).to_bytes();
+        Ok(self.con().await?.hlen(key).await?)
+    }
 
-        // If the voter is not authorized for any storage providers, throw an error
-        if authorized.is_empty() {
-            return Err(RedisError::from((
-                redis::ErrorKind::TypeError,
-                "Voter is not authorized for any storage providers",
-            )));
+    async fn tally(&self, fip_number: u32, ntw: Network) -> Result<Tally, StoreError> {
+        self.get_json(LookupKey::Tally(fip_number, ntw)).await
+    }
+
+    async fn create_vote(
+        &self,
+        fip_number: u32,
+        ntw: Network,
+        vote: NewVote,
+    ) -> Result<(), StoreError> {
+        // The vote is considered started once the timestamp is set
+        let mut pipe = redis::pipe();
+        pipe.atomic();
+        if !vote.metadata.is_empty() {
+            pipe.set(
+                LookupKey::FipMetadata(fip_number, ntw).to_bytes(),
+                serde_json::to_string(&vote.metadata)?,
+            )
+            .ignore();
         }
+        if !vote.members.is_empty() {
+            pipe.set(
+                LookupKey::FipCommitteeMembers(fip_number, ntw).to_bytes(),
+                serde_json::to_string(&vote.members)?,
+            )
+            .ignore();
+        }
+        pipe.set(
+            LookupKey::FipCommittees(fip_number, ntw).to_bytes(),
+            serde_json::to_string(&vote.committees)?,
+        )
+        .ignore()
+        .set(
+            LookupKey::Snapshot(fip_number, ntw).to_bytes(),
+            vote.snapshot,
+        )
+        .ignore()
+        .set(
+            LookupKey::Timestamp(fip_number, ntw).to_bytes(),
+            vote.timestamp,
+        )
+        .ignore();
+
+        self.claim_vote(fip_number, ntw, pipe).await
+    }
 
-        let key = LookupKey::Votes(num, ntw).to_bytes();
+    async fn record_ballot(
+        &self,
+        fip_number: u32,
+        ntw: Network,
+        vote: Vote,
+        weight: Tally,
+        deadline: u64,
+    ) -> Result<(), StoreError> {
+        let ballots_key = LookupKey::Ballots(fip_number, ntw).to_bytes();
+        let tally_key = LookupKey::Tally(fip_number, ntw).to_bytes();
+        let result_key = LookupKey::Result(fip_number, ntw).to_bytes();
+        let voter = vote.voter();
+        let ballot = serde_json::to_string(&Ballot {
+            vote,
+            weight: weight.clone(),
+        })?;
+        let mut con = self.con().await?;
+
+        // Optimistic transaction, EXEC is aborted and the ballot retried
+        // when another ballot changes the ballots or tally after the WATCH
+        loop {
+            redis::cmd("WATCH")
+                .arg(&ballots_key)
+                .arg(&tally_key)
+                .arg(&result_key)
+                .query_async::<_, ()>(&mut *con)
+                .await?;
+
+            // Finalized and concluded votes and duplicate ballots are errors
+            let error = if con.exists(&result_key).await? {
+                Some(StoreError::VoteFinalized)
+            } else if past_deadline(deadline) {
+                Some(StoreError::VoteNotActive)
+            } else if con.hexists(&ballots_key, voter.as_bytes()).await? {
+                Some(StoreError::DuplicateVote)
+            } else {
+                None
+            };
+            if let Some(error) = error {
+                redis::cmd("UNWATCH")
+                    .query_async::<_, ()>(&mut *con)
+                    .await?;
+                return Err(error);
+            }
 
-        let mut votes = self.votes(num, ntw)?;
+            let mut tally: Tally = match con.get::<_, Option<String>>(&tally_key).await? {
+                Some(t) => serde_json::from_str(&t)?,
+                None => Tally::default(),
+            };
+            tally.merge(&weight);
+
+            let written: Option<()> = redis::pipe()
+                .atomic()
+                .hset(&ballots_key, voter.as_bytes(), &ballot)
+                .ignore()
+                .set(&tally_key, serde_json::to_string(&tally)?)
+                .ignore()
+                .query_async(&mut *con)
+                .await?;
+            if written.is_some() {
+                return Ok(());
+            }
+        }
+    }
 
-        // If this vote is a duplicate throw an error
-        if votes.contains(&vote) {
-            return Err(RedisError::from((
-                redis::ErrorKind::TypeError,
-                "Vote already exists",
-            )));
+    async fn final_result(
+        &self,
+        fip_number: u32,
+        ntw: Network,
+    ) -> Result<Option<FinalResult>, StoreError> {
+        let key = LookupKey::Result(fip_number, ntw).to_bytes();
+        let result: Option<String> = self.con().await?.get(key).await?;
+        match result {
+            Some(r) => Ok(Some(serde_json::from_str(&r)?)),
+            None => Ok(None),
         }
+    }
 
-        // Add the storage providers power to their vote choice for the respective FIP
-        for sp_id in authorized {
-            self.add_storage(sp_id, ntw, vote.choice(), num).await?;
+    async fn finalize_vote(
+        &self,
+        fip_number: u32,
+        ntw: Network,
+        build: &BuildResult<'_>,
+    ) -> Result<FinalResult, StoreError> {
+        if self.vote_start(fip_number, ntw).await?.is_none() {
+            return Err(StoreError::VoteNotActive);
         }
 
-        // Add the vote to the list of votes
-        votes.push(vote);
-        let votes = serde_json::to_string(&votes).unwrap();
-        self.con.set::<Vec<u8>, String, ()>(key.clone(), votes)?;
+        let ballots_key = LookupKey::Ballots(fip_number, ntw).to_bytes();
+        let tally_key = LookupKey::Tally(fip_number, ntw).to_bytes();
+        let result_key = LookupKey::Result(fip_number, ntw).to_bytes();
+        let mut con = self.con().await?;
+
+        // The result is only written if no ballot and no other result was
+        // written since the ballots and tally were read, otherwise read again
+        loop {
+            redis::cmd("WATCH")
+                .arg(&ballots_key)
+                .arg(&tally_key)
+                .arg(&result_key)
+                .query_async::<_, ()>(&mut *con)
+                .await?;
+
+            if let Some(kept) = con.get::<_, Option<String>>(&result_key).await? {
+                redis::cmd("UNWATCH")
+                    .query_async::<_, ()>(&mut *con)
+                    .await?;
+                return Ok(serde_json::from_str(&kept)?);
+            }
 
-        Ok(())
+            let (values, tally): (Vec<String>, Option<String>) = redis::pipe()
+                .hvals(&ballots_key)
+                .get(&tally_key)
+                .query_async(&mut *con)
+                .await?;
+            let mut ballots = Vec::with_capacity(values.len());
+            for ballot in values {
+                ballots.push(serde_json::from_str(&ballot)?);
+            }
+            let tally = match tally {
+                Some(t) => serde_json::from_str(&t)?,
+                None => Tally::default(),
+            };
+
+            let result = build(ballots, tally)?;
+            let written: Option<()> = redis::pipe()
+                .atomic()
+                .set(&result_key, serde_json::to_string(&result)?)
+                .ignore()
+                .query_async(&mut *con)
+                .await?;
+            if written.is_some() {
+                return Ok(result);
+            }
+        }
     }
 
-    fn is_vote_active(
-        &mut self,
-        fip_number: impl Into<u32>,
+    async fn set_final_result(
+        &self,
+        fip_number: u32,
         ntw: Network,
-        vote_length: impl Into<u64>,
-    ) -> Result<bool, RedisError> {
-        let active_votes = self.active_votes(ntw, vote_length)?;
+        result: FinalResult,
+    ) -> Result<FinalResult, StoreError> {
+        if self.vote_start(fip_number, ntw).await?.is_none() {
+            return Err(StoreError::VoteNotActive);
+        }
 
-        Ok(active_votes.contains(&fip_number.into()))
+        // SET NX keeps the first result when two servers finalize at once
+        let key = LookupKey::Result(fip_number, ntw).to_bytes();
+        let mut con = self.con().await?;
+        let _: bool = con.set_nx(&key, serde_json::to_string(&result)?).await?;
+        let kept: String = con.get(&key).await?;
+        Ok(serde_json::from_str(&kept)?)
     }
 
-    fn register_vote_to_all_votes(&mut self, fip: u32, ntw: Network) -> Result<(), RedisError> {
-        let key = LookupKey::AllVotes(ntw).to_bytes();
-        let mut votes = self.all_votes(ntw)?;
+    async fn append_event(&self, event: AuditEvent) -> Result<u64, StoreError> {
+        let key = LookupKey::Events.to_bytes();
+        let len: u64 = self
+            .con()
+            .await?
+            .rpush(key, serde_json::to_string(&event)?)
+            .await?;
+        Ok(len)
+    }
 
-        if !votes.contains(&fip) {
-            votes.push(fip);
-            let str_votes = serde_json::to_string(&votes).unwrap();
-            self.con.set::<Vec<u8>, String, ()>(key, str_votes)?;
+    async fn events(&self, after: u64, limit: usize) -> Result<Vec<AuditEvent>, StoreError> {
+        if limit == 0 {
+            return Ok(Vec::new());
         }
 
-        Ok(())
+        let key = LookupKey::Events.to_bytes();
+        let start = after as isize;
+        let events: Vec<String> = self
+            .con()
+            .await?
+            .lrange(key, start, start + limit as isize - 1)
+            .await?;
+
+        // The id of an event is its position in the list
+        let mut page = Vec::with_capacity(events.len());
+        for (id, event) in (after + 1..).zip(events) {
+            page.push(AuditEvent {
+                id,
+                ..serde_json::from_str(&event)?
+            });
+        }
+        Ok(page)
     }
 
-    pub fn remove_voter_starters(
-        &mut self,
-        voter: Address,
-        ntw: Network,
-    ) -> Result<(), RedisError> {
-        let key = LookupKey::VoteStarters(ntw).to_bytes();
-        let mut starters = self.voter_starters(ntw)?;
-
-        if starters.contains(&voter) {
-            starters.retain(|&x| x != voter);
-
-            let new_bytes = starters
-                .into_iter()
-                .flat_map(|v| v.as_fixed_bytes().to_vec())
-                .collect::<Vec<u8>>();
-
-            self.con.set::<Vec<u8>, Vec<u8>, ()>(key, new_bytes)?;
+    async fn use_message(&self, ntw: Network, id: [u8; 32]) -> Result<(), StoreError> {
+        let key = LookupKey::UsedMessages(ntw).to_bytes();
+        let added: u64 = self.con().await?.sadd(key, &id[..]).await?;
+        if added == 0 {
+            return Err(StoreError::MessageReused);
         }
-
         Ok(())
     }
 
-    pub fn flush_vote(
-        &mut self,
-        fip_number: impl Into<u32>,
-        ntw: Network,
-    ) -> Result<(), RedisError> {
-        let key = LookupKey::Votes(fip_number.into(), ntw).to_bytes();
-        self.con.del::<Vec<u8>, ()>(key)?;
+    async fn cursor(&self, name: &str) -> Result<Option<u64>, StoreError> {
+        let key = LookupKey::Cursors.to_bytes();
+        Ok(self.con().await?.hget(key, name).await?)
+    }
+
+    async fn set_cursor(&self, name: &str, position: u64) -> Result<(), StoreError> {
+        let key = LookupKey::Cursors.to_bytes();
+        self.con()
+            .await?
+            .hset::<_, _, _, ()>(key, name, position)
+            .await?;
         Ok(())
     }
 
-    pub fn flush_all(&mut self) -> Result<(), RedisError> {
-        let keys: Vec<Vec<u8>> = self.con.keys("*")?;
-        for key in keys {
-            self.con.del::<Vec<u8>, ()>(key)?;
-        }
+    async fn add_dead_letter(&self, letter: DeadLetter) -> Result<(), StoreError> {
+        let key = LookupKey::DeadLetters.to_bytes();
+        self.con()
+            .await?
+            .rpush::<_, _, ()>(key, serde_json::to_string(&letter)?)
+            .await?;
         Ok(())
     }
 
-    async fn add_storage(
-        &mut self,
-        sp_id: u32,
-        ntw: Network,
-        vote: VoteOption,
+    async fn dead_letters(&self) -> Result<Vec<DeadLetter>, StoreError> {
+        let key = LookupKey::DeadLetters.to_bytes();
+        let letters: Vec<String> = self.con().await?.lrange(key, 0, -1).await?;
+        letters
+            .iter()
+            .map(|letter| Ok(serde_json::from_str(letter)?))
+            .collect()
+    }
+
+    async fn import_vote(
+        &self,
         fip_number: u32,
-    ) -> Result<(), RedisError> {
-        let key = LookupKey::Storage(vote.clone(), ntw, fip_number).to_bytes();
-
-        let current_storage = self.get_storage(fip_number, vote, ntw)?;
-
-        let new_storage = match fetch_storage_amount(sp_id, ntw).await {
-            Ok(s) => s,
-            Err(_) => {
-                return Err(RedisError::from((
-                    redis::ErrorKind::TypeError,
-                    "Error fetching storage amount",
-                )))
+        ntw: Network,
+        vote: NewVote,
+        ballots: Vec<Ballot>,
+        tally: Tally,
+    ) -> Result<(), StoreError> {
+        let mut pipe = redis::pipe();
+        pipe.atomic();
+        for ballot in ballots {
+            pipe.hset(
+                LookupKey::Ballots(fip_number, ntw).to_bytes(),
+                ballot.vote.voter().as_bytes(),
+                serde_json::to_string(&ballot)?,
+            )
+            .ignore();
+        }
+        if !vote.metadata.is_empty() {
+            pipe.set(
+                LookupKey::FipMetadata(fip_number, ntw).to_bytes(),
+                serde_json::to_string(&vote.metadata)?,
+            )
+            .ignore();
+        }
+        if !vote.members.is_empty() {
+            pipe.set(
+                LookupKey::FipCommitteeMembers(fip_number, ntw).to_bytes(),
+                serde_json::to_string(&vote.members)?,
+            )
+            .ignore();
+        }
+        pipe.set(
+            LookupKey::Tally(fip_number, ntw).to_bytes(),
+            serde_json::to_string(&tally)?,
+        )
+        .ignore()
+        .set(
+            LookupKey::FipCommittees(fip_number, ntw).to_bytes(),
+            serde_json::to_string(&vote.committees)?,
+        )
+        .ignore()
+        .set(
+            LookupKey::Snapshot(fip_number, ntw).to_bytes(),
+            vote.snapshot,
+        )
+        .ignore()
+        .set(
+            LookupKey::Timestamp(fip_number, ntw).to_bytes(),
+            vote.timestamp,
+        )
+        .ignore();
+
+        self.claim_vote(fip_number, ntw, pipe).await
+    }
+
+    async fn flush_vote(&self, fip_number: u32, ntw: Network) -> Result<(), StoreError> {
+        let result_key = LookupKey::Result(fip_number, ntw).to_bytes();
+        let all_votes_key = LookupKey::AllVotes(ntw).to_bytes();
+        let mut con = self.con().await?;
+
+        // Deleted unless the vote was finalized or another vote started
+        // since the keys were read, otherwise read again
+        loop {
+            redis::cmd("WATCH")
+                .arg(&result_key)
+                .arg(&all_votes_key)
+                .query_async::<_, ()>(&mut *con)
+                .await?;
+
+            let finalized: bool = con.exists(&result_key).await?;
+            if finalized {
+                redis::cmd("UNWATCH")
+                    .query_async::<_, ()>(&mut *con)
+                    .await?;
+                return Err(StoreError::VoteFinalized);
             }
-        };
-        let storage = current_storage + new_storage;
-        let storage_bytes = storage.to_be_bytes().to_vec();
-        self.con
-            .set::<Vec<u8>, Vec<u8>, ()>(key.clone(), storage_bytes)?;
-        Ok(())
+
+            let json: Option<String> = con.get(&all_votes_key).await?;
+            let mut votes: Vec<u32> = match json {
+                Some(json) => serde_json::from_str(&json)?,
+                None => Vec::new(),
+            };
+            votes.retain(|&fip| fip != fip_number);
+
+            let mut pipe = redis::pipe();
+            pipe.atomic()
+                .del(&[
+                    LookupKey::Ballots(fip_number, ntw).to_bytes(),
+                    LookupKey::Tally(fip_number, ntw).to_bytes(),
+                    LookupKey::Timestamp(fip_number, ntw).to_bytes(),
+                    LookupKey::Snapshot(fip_number, ntw).to_bytes(),
+                    LookupKey::FipCommittees(fip_number, ntw).to_bytes(),
+                    LookupKey::FipCommitteeMembers(fip_number, ntw).to_bytes(),
+                    LookupKey::FipMetadata(fip_number, ntw).to_bytes(),
+                ])
+                .ignore();
+            if votes.is_empty() {
+                pipe.del(&all_votes_key).ignore();
+            } else {
+                pipe.set(&all_votes_key, serde_json::to_string(&votes)?)
+                    .ignore();
+            }
+
+            let written: Option<()> = pipe.query_async(&mut *con).await?;
+            if written.is_some() {
+                return Ok(());
+            }
+        }
     }
 
-    /// Removes the lookup from the voter to the network they are voting on
-    fn remove_network(&mut self, voter: Address) -> Result<(), RedisError> {
-        let key: Vec<u8> = LookupKey::Network(voter).to_bytes();
-        self.con.del::<Vec<u8>, ()>(key)?;
+    async fn flush_all(&self) -> Result<(), StoreError> {
+        let mut con = self.con().await?;
+        let keys: Vec<Vec<u8>> = con.keys(format!("{}*", LookupKey::PREFIX)).await?;
+        for key in keys {
+            con.del::<Vec<u8>, ()>(key).await?;
+        }
         Ok(())
     }
 }
 
+/// Vote starters as stored, their 20 byte addresses one after the other
+fn pack_starters(starters: &[Address]) -> Vec<u8> {
+    starters
+        .iter()
+        .flat_map(|v| v.as_fixed_bytes().to_vec())
+        .collect()
+}
+
+fn unpack_starters(bytes: &[u8]) -> Result<Vec<Address>, StoreError> {
+    let addresses = bytes.chunks_exact(20);
+    if !addresses.remainder().is_empty() {
+        return Err(StoreError::Corrupt(
+            "Error retrieving vote starters, invalid length",
+        ));
+    }
+
+    Ok(addresses.map(Address::from_slice).collect())
+}
+
 impl LookupKey {
-    fn to_bytes(&self) -> Vec<u8> {
-        let (lookup_type, fip) = match self {
-            // The first bit will be 0 or 1
-            LookupKey::Votes(fip, ntw) => (*ntw as u8, fip),
-            // The first bit will range between 2 and 8
-            LookupKey::Storage(choice, ntw, fip) => {
-                let choice = match choice {
-                    VoteOption::Yay => 2,
-                    VoteOption::Nay => 3,
-                    VoteOption::Abstain => 4,
-                };
-                let nt = *ntw as u8 + 1; // 1 or 2
-                (choice * nt, fip)
-            }
-            // The first bit will be 9 or 10
-            LookupKey::Timestamp(fip, ntw) => (9 + *ntw as u8, fip),
-            LookupKey::Voter(ntw, voter) => {
-                let ntw = match ntw {
-                    Network::Mainnet => 0,
-                    Network::Testnet => 1,
-                };
-                let voter = voter.as_bytes();
-                let mut bytes = Vec::with_capacity(21);
-                bytes.push(ntw);
-                bytes.extend_from_slice(voter);
-                return bytes;
-            }
-            LookupKey::Network(voter) => {
-                let voter = voter.as_bytes();
-                let mut bytes = Vec::with_capacity(21);
-                bytes.push(2);
-                bytes.extend_from_slice(voter);
-                return bytes;
-            }
-            LookupKey::VoteStarters(ntw) => {
-                let bytes = vec![8, 0, 0, 8, 1, 3, 5, *ntw as u8];
-                return bytes;
-            }
-            LookupKey::AllVotes(ntw) => {
-                let bytes = vec![8, 0, 0, 8, 1, 3, 187, *ntw as u8];
-                return bytes;
-            }
-        };
-        let slice = unsafe {
-            let mut key = MaybeUninit::<[u8; 5]>::uninit();
-            let start = key.as_mut_ptr() as *mut u8;
-            (start.add(0) as *mut [u8; 4]).write(fip.to_be_bytes());
+    fn wallets(kind: WalletKind, ntw: Network, voter: Address) -> LookupKey {
+        match kind {
+            WalletKind::Token => LookupKey::TokenWallets(ntw, voter),
+            WalletKind::Client => LookupKey::ClientWallets(ntw, voter),
+        }
+    }
 
-            // This is the bit we set to 0 if we only want the token object
-            (start.add(4) as *mut [u8; 1]).write([lookup_type]);
+    fn wallet_owners(kind: WalletKind, ntw: Network) -> LookupKey {
+        match kind {
+            WalletKind::Token => LookupKey::TokenWalletOwners(ntw),
+            WalletKind::Client => LookupKey::ClientWalletOwners(ntw),
+        }
+    }
 
-            key.assume_init()
-        };
-        Vec::from(slice)
+    /// Namespace of the keys, follows `SCHEMA_VERSION`
+    const PREFIX: &'static str = "fipvote:v1:";
+
+    fn kind(&self) -> &'static str {
+        match self {
+            LookupKey::Ballots(..) => "ballots",
+            LookupKey::Tally(..) => "tally",
+            LookupKey::Timestamp(..) => "started",
+            LookupKey::Snapshot(..) => "snapshot",
+            LookupKey::FipCommittees(..) => "fip-committees",
+            LookupKey::FipCommitteeMembers(..) => "fip-committee-members",
+            LookupKey::Result(..) => "result",
+            LookupKey::FipMetadata(..) => "fip-metadata",
+            LookupKey::Voter(..) => "delegates",
+            LookupKey::Worker(..) => "worker",
+            LookupKey::TokenWallets(..) => "token-wallets",
+            LookupKey::ClientWallets(..) => "client-wallets",
+            LookupKey::TokenWalletOwners(..) => "token-wallet-owners",
+            LookupKey::ClientWalletOwners(..) => "client-wallet-owners",
+            LookupKey::UsedMessages(..) => "used-messages",
+            LookupKey::Network(..) => "network",
+            LookupKey::VoteStarters(..) => "starters",
+            LookupKey::Committees(..) => "committees",
+            LookupKey::AllVotes(..) => "all-votes",
+            LookupKey::Events => "events",
+            LookupKey::Cursors => "cursors",
+            LookupKey::DeadLetters => "dead-letters",
+        }
+    }
+
+    fn to_bytes(&self) -> Vec<u8> {
+        self.to_string().into_bytes()
+    }
+
+    /// The key up to its last part, shared by every key of the kind on the network
+    fn parent(&self) -> String {
+        let key = self.to_string();
+        match key.rfind(':') {
+            Some(end) => key[..=end].to_string(),
+            None => key,
+        }
     }
 }
 
-#[derive(Serialize, Deserialize, Debug)]
-pub struct VoteResults {
-    yay: u64,
-    nay: u64,
-    abstain: u64,
-    yay_storage_size: u128,
-    nay_storage_size: u128,
-    abstain_storage_size: u128,
+impl fmt::Display for LookupKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}{}", LookupKey::PREFIX, self.kind())?;
+        match self {
+            LookupKey::Ballots(fip, ntw)
+            | LookupKey::Tally(fip, ntw)
+            | LookupKey::Timestamp(fip, ntw)
+            | LookupKey::Snapshot(fip, ntw)
+            | LookupKey::FipCommittees(fip, ntw)
+            | LookupKey::FipCommitteeMembers(fip, ntw)
+            | LookupKey::Result(fip, ntw)
+            | LookupKey::FipMetadata(fip, ntw) => write!(f, ":{}:{fip}", ntw.name()),
+            LookupKey::Voter(ntw, voter)
+            | LookupKey::Worker(ntw, voter)
+            | LookupKey::TokenWallets(ntw, voter)
+            | LookupKey::ClientWallets(ntw, voter) => write!(f, ":{}:{voter:?}", ntw.name()),
+            LookupKey::Network(voter) => write!(f, ":{voter:?}"),
+            LookupKey::VoteStarters(ntw)
+            | LookupKey::Committees(ntw)
+            | LookupKey::AllVotes(ntw)
+            | LookupKey::TokenWalletOwners(ntw)
+            | LookupKey::ClientWalletOwners(ntw)
+            | LookupKey::UsedMessages(ntw) => write!(f, ":{}", ntw.name()),
+            LookupKey::Events | LookupKey::Cursors | LookupKey::DeadLetters => Ok(()),
+        }
+    }
 }
 
 #[cfg(test)]
 mod tests {
-    use std::str::FromStr;
+    use std::{collections::HashSet, str::FromStr, time};
+
+    use ethers::signers::{LocalWallet, Signer};
 
     use super::*;
 
-    use crate::messages::{vote_registration::test_voter_registration::*, votes::test_votes::*};
+    use crate::{
+        authorized_voters,
+        messages::{
+            vote_registration::test_voter_registration::*,
+            votes::{test_votes::*, VoteOption},
+        },
+        results::{Constituency, GovernanceRules, Outcome, VoteResults},
+        storage::{fetch_storage_amount, networks},
+        store::{
+            tests::{
+                concurrent_ballots, concurrent_committees, concurrent_revocations,
+                concurrent_vote_starts, flush_and_recast,
+            },
+            VoteStatus,
+        },
+    };
 
     async fn redis() -> Redis {
         let url = Url::parse("redis://127.0.0.1:6379").unwrap();
-        let mut redis = Redis::new(url).unwrap();
+        let redis = Redis::new(url).await.unwrap();
 
-        redis.flush_all().unwrap();
+        redis.flush_all().await.unwrap();
 
         let vote_reg = test_reg().recover_vote_registration().await.unwrap();
         redis
             .register_voter(vote_reg.address(), vote_reg.ntw(), vote_reg.sp_ids())
+            .await
             .unwrap();
 
         redis
     }
 
+    /// A database with the founders as starters, as the server starts with
+    async fn seeded_redis() -> Redis {
+        let redis = redis().await;
+        for ntw in networks() {
+            redis
+                .init_voter_starters(ntw, authorized_voters())
+                .await
+                .unwrap();
+        }
+        redis
+    }
+
+    /// Signs the ballots of `test_vote`
     fn voter() -> Address {
-        Address::from_str("0xf2361d2a9a0677e8ffd1515d65cf5190ea20eb56").unwrap()
+        test_wallet().address()
     }
 
     fn vote_starter() -> Address {
         authorized_voters()[0]
     }
 
-    fn networks() -> Vec<Network> {
-        vec![Network::Mainnet, Network::Testnet]
+    fn mainnet() -> Network {
+        Network::from_name("mainnet").unwrap()
+    }
+
+    fn testnet() -> Network {
+        Network::from_name("calibration").unwrap()
     }
 
     #[tokio::test]
     async fn redis_votes() {
-        let mut redis = redis().await;
+        let redis = redis().await;
 
-        let res = redis.votes(5u32, Network::Testnet);
+        let res = redis.votes(5u32, testnet()).await;
 
         assert!(res.is_ok());
     }
 
     #[tokio::test]
     async fn redis_start_vote() {
-        let mut redis = redis().await;
+        let redis = redis().await;
 
         let starter = voter();
 
         for ntw in networks() {
-            let res = redis.start_vote(5u32, starter, ntw);
+            let res = redis
+                .start_vote(5u32, starter, ntw, vec![], FipMetadata::default())
+                .await;
 
             assert!(res.is_ok());
 
-            let res = redis.vote_status(5u32, 60u64, ntw);
+            let res = redis.vote_status(5u32, 60u64, ntw).await;
 
             assert!(res.is_ok());
 
@@ -673,7 +1423,7 @@ mod tests {
 
             assert_eq!(status, VoteStatus::InProgress(60u64));
 
-            let res = redis.active_votes(ntw, 69u64);
+            let res = redis.active_votes(ntw, 69u64).await;
             assert!(res.is_ok());
 
             let active_votes = res.unwrap();
@@ -683,17 +1433,19 @@ mod tests {
 
     #[tokio::test]
     async fn redis_register_voter() {
-        let mut redis = redis().await;
+        let redis = redis().await;
 
-        let res = redis.register_voter(vote_starter(), Network::Mainnet, vec![1u32]);
+        let res = redis
+            .register_voter(vote_starter(), mainnet(), vec![1u32])
+            .await;
 
         assert!(res.is_ok());
 
-        let ntw = redis.network(vote_starter());
+        let ntw = redis.network(vote_starter()).await;
 
-        assert!(ntw.is_ok());
+        assert_eq!(ntw.unwrap(), Some(mainnet()));
 
-        let delegates = redis.voter_delegates(vote_starter(), Network::Mainnet);
+        let delegates = redis.voter_delegates(vote_starter(), mainnet()).await;
 
         assert!(delegates.is_ok());
 
@@ -704,36 +1456,111 @@ mod tests {
 
     #[tokio::test]
     async fn redis_unregister_voter() {
-        let mut redis = redis().await;
+        let redis = redis().await;
 
         redis
-            .register_voter(vote_starter(), Network::Mainnet, vec![1u32])
+            .register_voter(vote_starter(), mainnet(), vec![1u32])
+            .await
             .unwrap();
 
-        let res = redis.unregister_voter(vote_starter(), Network::Mainnet);
+        let res = redis.unregister_voter(vote_starter(), mainnet()).await;
 
         assert!(res.is_ok());
 
-        let ntw = redis.network(vote_starter());
+        let ntw = redis.network(vote_starter()).await;
 
-        assert!(ntw.is_err());
+        assert_eq!(ntw.unwrap(), None);
 
-        let delegates = redis.voter_delegates(vote_starter(), Network::Mainnet);
+        let delegates = redis.voter_delegates(vote_starter(), mainnet()).await;
 
         assert!(delegates.is_ok());
         assert!(delegates.unwrap().is_empty());
     }
 
+    #[tokio::test]
+    async fn redis_register_token_holder() {
+        let redis = redis().await;
+        let wallet = "t1d2xrzcslx7xlbbylc5c3d5lvandqw4iwl6epxba".to_string();
+
+        let res = redis
+            .register_token_holder(vote_starter(), testnet(), wallet.clone())
+            .await;
+
+        assert!(res.is_ok());
+        assert_eq!(
+            redis.network(vote_starter()).await.unwrap(),
+            Some(testnet())
+        );
+
+        let wallets = redis
+            .token_wallets(vote_starter(), testnet())
+            .await
+            .unwrap();
+
+        assert_eq!(wallets, vec![wallet.clone()]);
+
+        redis
+            .unregister_token_holder(vote_starter(), testnet(), wallet)
+            .await
+            .unwrap();
+
+        assert!(redis
+            .token_wallets(vote_starter(), testnet())
+            .await
+            .unwrap()
+            .is_empty());
+        assert_eq!(redis.network(vote_starter()).await.unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn redis_register_client() {
+        let redis = redis().await;
+        let client = "t1d2xrzcslx7xlbbylc5c3d5lvandqw4iwl6epxba".to_string();
+
+        redis
+            .register_client(vote_starter(), testnet(), client.clone())
+            .await
+            .unwrap();
+        redis
+            .register_voter(vote_starter(), testnet(), vec![1u32])
+            .await
+            .unwrap();
+
+        assert_eq!(
+            redis
+                .client_wallets(vote_starter(), testnet())
+                .await
+                .unwrap(),
+            vec![client.clone()]
+        );
+        assert!(redis
+            .token_wallets(vote_starter(), testnet())
+            .await
+            .unwrap()
+            .is_empty());
+
+        // The voter still votes for a storage provider so keeps their network
+        redis
+            .unregister_client(vote_starter(), testnet(), client)
+            .await
+            .unwrap();
+
+        assert_eq!(
+            redis.network(vote_starter()).await.unwrap(),
+            Some(testnet())
+        );
+    }
+
     #[tokio::test]
     async fn redis_register_voter_starter() {
-        let mut redis = redis().await;
+        let redis = redis().await;
 
         for ntw in networks() {
-            let res = redis.register_voter_starter(voter(), ntw);
+            let res = redis.register_voter_starter(voter(), ntw).await;
 
             assert!(res.is_ok());
 
-            let res = redis.voter_starters(ntw);
+            let res = redis.voter_starters(ntw).await;
 
             assert!(res.is_ok());
             assert!(res.unwrap().contains(&voter()));
@@ -742,25 +1569,25 @@ mod tests {
 
     #[tokio::test]
     async fn redis_is_registered() {
-        let mut redis = redis().await;
+        let redis = redis().await;
 
         for ntw in networks() {
-            let res = redis.is_registered(vote_starter(), ntw);
+            let res = redis.is_registered(vote_starter(), ntw).await;
 
             assert!(!res);
 
-            let res = redis.register_voter(vote_starter(), ntw, vec![1u32]);
+            let res = redis.register_voter(vote_starter(), ntw, vec![1u32]).await;
             assert!(res.is_ok());
 
-            let res = redis.is_registered(vote_starter(), ntw);
+            let res = redis.is_registered(vote_starter(), ntw).await;
 
             assert!(res);
 
-            let res = redis.unregister_voter(vote_starter(), ntw);
+            let res = redis.unregister_voter(vote_starter(), ntw).await;
 
             assert!(res.is_ok());
 
-            let res = redis.is_registered(vote_starter(), ntw);
+            let res = redis.is_registered(vote_starter(), ntw).await;
 
             assert!(!res);
         }
@@ -768,43 +1595,46 @@ mod tests {
 
     #[tokio::test]
     async fn redis_test_vote() {
-        let mut redis = redis().await;
+        let redis = seeded_redis().await;
 
         let fip = 5u32;
         let vote_length = 1u64;
-        let ntw = Network::Testnet;
+        let ntw = testnet();
 
-        redis.start_vote(fip, vote_starter(), ntw).unwrap();
+        redis
+            .start_vote(fip, vote_starter(), ntw, vec![], FipMetadata::default())
+            .await
+            .unwrap();
 
-        let active = redis.active_votes(ntw, vote_length).unwrap();
+        let active = redis.active_votes(ntw, vote_length).await.unwrap();
         println!("{:?}", active);
 
         assert!(active.contains(&fip));
 
-        let vote = test_vote(VoteOption::Yay, fip).vote().unwrap();
+        let (_, vote) = test_vote(VoteOption::Yay, fip).vote().unwrap();
 
         redis
-            .add_vote(fip, vote, voter(), vote_length)
+            .add_vote(fip, vote, voter(), ntw, vote_length)
             .await
             .unwrap();
 
         // wait 1 second
         tokio::time::sleep(time::Duration::from_secs(vote_length + 1)).await;
 
-        let active = redis.active_votes(ntw, vote_length).unwrap();
+        let active = redis.active_votes(ntw, vote_length).await.unwrap();
 
         assert!(!active.contains(&fip));
 
-        let concluded = redis.concluded_votes(ntw, vote_length).unwrap();
+        let concluded = redis.concluded_votes(ntw, vote_length).await.unwrap();
 
         assert!(concluded.contains(&fip));
     }
 
     #[tokio::test]
     async fn redis_get_storage() {
-        let mut redis = redis().await;
+        let redis = redis().await;
 
-        let res = redis.get_storage(49u32, VoteOption::Yay, Network::Testnet);
+        let res = redis.tally(49u32, testnet()).await;
 
         println!("{:?}", res);
 
@@ -813,45 +1643,84 @@ mod tests {
 
     #[tokio::test]
     async fn redis_add_storage() {
-        let mut redis = redis().await;
-
-        let res = redis
-            .add_storage(6024u32, Network::Testnet, VoteOption::Yay, 5u32)
-            .await;
+        let res = fetch_storage_amount(6024u32, testnet()).await;
 
         assert!(res.is_ok());
     }
 
+    #[tokio::test(flavor = "multi_thread", worker_threads = 8)]
+    async fn redis_concurrent_ballots() {
+        concurrent_ballots(std::sync::Arc::new(redis().await), 7u32).await;
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 8)]
+    async fn redis_concurrent_revocations() {
+        concurrent_revocations(std::sync::Arc::new(redis().await)).await;
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 8)]
+    async fn redis_concurrent_committees() {
+        concurrent_committees(std::sync::Arc::new(redis().await)).await;
+    }
+
     #[tokio::test]
     async fn redis_storage() {
-        let mut redis = redis().await;
+        let redis = redis().await;
 
-        let res = redis
-            .add_storage(6024, Network::Testnet, VoteOption::Yay, 831u32)
-            .await;
+        let power = fetch_storage_amount(6024, testnet()).await.unwrap();
 
-        assert!(res.is_ok());
+        let mut weight = Tally::default();
+        weight.add_weight(Constituency::StorageProviders, VoteOption::Yay, power);
 
-        let res = redis.get_storage(831u32, VoteOption::Yay, Network::Testnet);
+        let new_vote = NewVote {
+            timestamp: 0,
+            snapshot: 0,
+            committees: vec![],
+            members: BTreeMap::new(),
+            metadata: FipMetadata::default(),
+        };
+        redis
+            .create_vote(831u32, testnet(), new_vote)
+            .await
+            .unwrap();
+
+        let (_, vote) = test_vote(VoteOption::Yay, 1u32).vote().unwrap();
+        redis
+            .record_ballot(831u32, testnet(), vote, weight, u64::MAX)
+            .await
+            .unwrap();
+
+        let res = redis.tally(831u32, testnet()).await;
 
         assert!(res.is_ok());
-        assert_eq!(res.unwrap(), 10240000u128);
+        assert_eq!(
+            res.unwrap()
+                .weight(&Constituency::StorageProviders, VoteOption::Yay),
+            10240000u128
+        );
     }
 
     #[tokio::test]
     async fn redis_vote_start() {
-        let mut redis = redis().await;
+        let redis = seeded_redis().await;
 
-        let vote = test_vote(VoteOption::Yay, 4u32).vote().unwrap();
+        let (_, vote) = test_vote(VoteOption::Yay, 4u32).vote().unwrap();
 
         redis
-            .start_vote(4u32, vote_starter(), Network::Testnet)
+            .start_vote(
+                4u32,
+                vote_starter(),
+                testnet(),
+                vec![],
+                FipMetadata::default(),
+            )
+            .await
             .unwrap();
-        let res = redis.add_vote(4u32, vote, voter(), 69u64).await;
+        let res = redis.add_vote(4u32, vote, voter(), testnet(), 69u64).await;
         println!("{:?}", res);
         assert!(res.is_ok());
 
-        let res = redis.vote_start(4u32, Network::Testnet);
+        let res = redis.vote_start(4u32, testnet()).await;
 
         match res {
             Ok(_) => {}
@@ -861,17 +1730,24 @@ mod tests {
 
     #[tokio::test]
     async fn redis_vote_status() {
-        let mut redis = redis().await;
+        let redis = seeded_redis().await;
 
-        let vote = test_vote(VoteOption::Yay, 3u32).vote().unwrap();
+        let (_, vote) = test_vote(VoteOption::Yay, 3u32).vote().unwrap();
 
         redis
-            .start_vote(3u32, vote_starter(), Network::Testnet)
+            .start_vote(
+                3u32,
+                vote_starter(),
+                testnet(),
+                vec![],
+                FipMetadata::default(),
+            )
+            .await
             .unwrap();
-        let res = redis.add_vote(3u32, vote, voter(), 69u64).await;
+        let res = redis.add_vote(3u32, vote, voter(), testnet(), 69u64).await;
         assert!(res.is_ok());
 
-        let vote_start = redis.vote_start(3u32, Network::Testnet).unwrap();
+        let vote_start = redis.vote_start(3u32, testnet()).await.unwrap().unwrap();
 
         tokio::time::sleep(time::Duration::from_secs(2)).await;
 
@@ -883,7 +1759,7 @@ mod tests {
         let ongoing = time_now - vote_start + 1;
         let concluded = time_now - vote_start - 1;
 
-        let res = redis.vote_status(3u32, ongoing, Network::Testnet);
+        let res = redis.vote_status(3u32, ongoing, testnet()).await;
 
         match res {
             Ok(_) => {}
@@ -891,7 +1767,7 @@ mod tests {
         }
         assert_eq!(res.unwrap(), VoteStatus::InProgress(1));
 
-        let res = redis.vote_status(3u32, concluded, Network::Testnet);
+        let res = redis.vote_status(3u32, concluded, testnet()).await;
 
         match res {
             Ok(_) => {}
@@ -899,7 +1775,7 @@ mod tests {
         }
         assert_eq!(res.unwrap(), VoteStatus::Concluded);
 
-        let res = redis.vote_status(1234089398u32, concluded, Network::Testnet);
+        let res = redis.vote_status(1234089398u32, concluded, testnet()).await;
 
         match res {
             Ok(_) => {}
@@ -910,58 +1786,189 @@ mod tests {
 
     #[tokio::test]
     async fn redis_add_vote() {
-        let mut redis = redis().await;
+        let redis = seeded_redis().await;
 
-        let vote = test_vote(VoteOption::Yay, 2u32).vote().unwrap();
+        let (_, vote) = test_vote(VoteOption::Yay, 2u32).vote().unwrap();
 
         redis
-            .start_vote(2u32, vote_starter(), Network::Testnet)
+            .start_vote(
+                2u32,
+                vote_starter(),
+                testnet(),
+                vec![],
+                FipMetadata::default(),
+            )
+            .await
             .unwrap();
 
-        let res = redis.add_vote(2u32, vote, voter(), 69u64).await;
+        let res = redis.add_vote(2u32, vote, voter(), testnet(), 69u64).await;
 
         match res {
             Ok(_) => {}
             Err(e) => panic!("Error: {}", e),
         }
 
-        let res = redis.vote_results(2u32, Network::Testnet);
+        let res = redis
+            .vote_results(2u32, testnet(), &GovernanceRules::default())
+            .await;
 
         assert!(res.is_ok());
 
         let results: VoteResults = res.unwrap();
+        let sp = &results.constituencies[0];
 
-        assert_eq!(results.yay, 1);
-        assert_eq!(results.yay_storage_size, 10240000u128);
+        assert_eq!(sp.constituency, Constituency::StorageProviders);
+        assert_eq!(sp.yay, 1);
+        assert_eq!(sp.yay_weight, 10240000u128);
+        assert_eq!(results.outcome, Outcome::Passed);
     }
 
     #[tokio::test]
-    async fn redis_test_duplicate_vote_start() {
-        let mut redis = redis().await;
+    async fn redis_committee_vote() {
+        let redis = seeded_redis().await;
+        let fip = 6u32;
+        let member = LocalWallet::from_str(&"11".repeat(32)).unwrap();
 
         redis
-            .register_vote_to_all_votes(1u32, Network::Testnet)
+            .set_committee(testnet(), "core-devs".to_string(), vec![member.address()])
+            .await
+            .unwrap();
+        redis
+            .set_committee(testnet(), "lotus".to_string(), vec![vote_starter()])
+            .await
+            .unwrap();
+
+        let res = redis
+            .start_vote(
+                fip,
+                vote_starter(),
+                testnet(),
+                vec!["unknown".to_string()],
+                FipMetadata::default(),
+            )
+            .await;
+        assert!(res.is_err());
+
+        redis
+            .start_vote(
+                fip,
+                vote_starter(),
+                testnet(),
+                vec!["core-devs".to_string(), "lotus".to_string()],
+                FipMetadata::default(),
+            )
+            .await
+            .unwrap();
+
+        let vote = signed_vote(&member, VoteOption::Nay, fip).await;
+        redis
+            .add_vote(fip, vote, member.address(), testnet(), 69u64)
+            .await
             .unwrap();
 
+        let results = redis
+            .vote_results(fip, testnet(), &GovernanceRules::default())
+            .await
+            .unwrap();
+        let committee = |name: &str| {
+            results
+                .constituencies
+                .iter()
+                .find(|t| t.constituency == Constituency::Committee(name.to_string()))
+                .unwrap()
+        };
+
+        assert_eq!(committee("core-devs").nay, 1);
+        assert_eq!(committee("core-devs").outcome, Outcome::Rejected);
+        assert_eq!(committee("lotus").participation, 0);
+
         redis
-            .register_vote_to_all_votes(3u32, Network::Testnet)
+            .set_committee(testnet(), "lotus".to_string(), vec![])
+            .await
             .unwrap();
+
+        assert!(!redis
+            .committees(testnet())
+            .await
+            .unwrap()
+            .contains_key("lotus"));
+    }
+
+    #[tokio::test]
+    async fn redis_finalize() {
+        let redis = redis().await;
+        let fip = 7u32;
+        let new_vote = NewVote {
+            timestamp: 0,
+            snapshot: 12,
+            committees: vec![],
+            members: BTreeMap::new(),
+            metadata: FipMetadata::default(),
+        };
+        redis.create_vote(fip, testnet(), new_vote).await.unwrap();
+
+        let mut weight = Tally::default();
+        weight.add_ballot(Constituency::StorageProviders, VoteOption::Nay);
+        weight.add_weight(Constituency::StorageProviders, VoteOption::Nay, 1024);
+        let vote = test_ballot(VoteOption::Nay, voter(), fip);
+        redis
+            .record_ballot(fip, testnet(), vote, weight, u64::MAX)
+            .await
+            .unwrap();
+
+        let rules = GovernanceRules::default();
+        let result = redis
+            .finalize(fip, testnet(), &rules, 60)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(result.ballot_count, 1);
+        assert_eq!(result.snapshot_epoch, Some(12));
+        assert_eq!(result.outcome, Outcome::Rejected);
+        assert_eq!(result.tally, redis.tally(fip, testnet()).await.unwrap());
+
+        let vote = test_ballot(VoteOption::Yay, vote_starter(), fip);
+        let res = redis
+            .record_ballot(fip, testnet(), vote, Tally::default(), u64::MAX)
+            .await;
+        assert!(matches!(res, Err(StoreError::VoteFinalized)));
+        assert_eq!(
+            redis.finalize(fip, testnet(), &rules, 60).await.unwrap(),
+            Some(result)
+        );
+    }
+
+    #[tokio::test]
+    async fn redis_flush_vote() {
+        flush_and_recast(std::sync::Arc::new(redis().await)).await;
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 8)]
+    async fn redis_concurrent_vote_starts() {
+        concurrent_vote_starts(std::sync::Arc::new(redis().await)).await;
     }
 
     #[tokio::test]
     async fn redis_vote_exists() {
-        let mut redis = redis().await;
+        let redis = seeded_redis().await;
 
-        let res = redis.vote_exists(Network::Testnet, 129u32);
+        let res = redis.vote_exists(testnet(), 129u32).await;
 
         assert!(res.is_ok());
         assert!(!res.unwrap());
 
         redis
-            .start_vote(129u32, vote_starter(), Network::Testnet)
+            .start_vote(
+                129u32,
+                vote_starter(),
+                testnet(),
+                vec![],
+                FipMetadata::default(),
+            )
+            .await
             .unwrap();
 
-        let res = redis.vote_exists(Network::Testnet, 129u32);
+        let res = redis.vote_exists(testnet(), 129u32).await;
 
         assert!(res.is_ok());
         assert!(res.unwrap());
@@ -969,40 +1976,218 @@ mod tests {
 
     #[tokio::test]
     async fn redis_register_to_all_votes() {
-        let mut redis = redis().await;
+        let redis = redis().await;
 
-        let res = redis.all_votes(Network::Testnet).unwrap();
+        let res = redis.all_votes(testnet()).await.unwrap();
 
         assert!(res.is_empty());
 
+        let start = NewVote {
+            timestamp: 1,
+            snapshot: 0,
+            committees: Vec::new(),
+            members: BTreeMap::new(),
+            metadata: FipMetadata::default(),
+        };
         redis
-            .register_vote_to_all_votes(87u32, Network::Testnet)
+            .create_vote(87u32, testnet(), start.clone())
+            .await
             .unwrap();
 
-        let res = redis.all_votes(Network::Testnet).unwrap();
+        let res = redis.all_votes(testnet()).await.unwrap();
 
         assert_eq!(res.len(), 1);
         assert_eq!(res[0], 87u32);
 
-        redis
-            .register_vote_to_all_votes(87u32, Network::Testnet)
+        let res = redis.create_vote(87u32, testnet(), start).await;
+
+        assert!(matches!(res, Err(StoreError::VoteExists)));
+        assert_eq!(redis.all_votes(testnet()).await.unwrap(), vec![87u32]);
+    }
+
+    #[test]
+    fn redis_key_kinds_unique() {
+        let ntws = [mainnet(), testnet()];
+        let voters = [voter(), vote_starter()];
+
+        let mut keys = Vec::new();
+        for ntw in ntws {
+            keys.push(LookupKey::VoteStarters(ntw));
+            keys.push(LookupKey::Committees(ntw));
+            keys.push(LookupKey::AllVotes(ntw));
+            keys.push(LookupKey::TokenWalletOwners(ntw));
+            keys.push(LookupKey::ClientWalletOwners(ntw));
+            keys.push(LookupKey::UsedMessages(ntw));
+            for fip in [1u32, 12u32] {
+                keys.push(LookupKey::Ballots(fip, ntw));
+                keys.push(LookupKey::Tally(fip, ntw));
+                keys.push(LookupKey::Timestamp(fip, ntw));
+                keys.push(LookupKey::Snapshot(fip, ntw));
+                keys.push(LookupKey::FipCommittees(fip, ntw));
+                keys.push(LookupKey::FipCommitteeMembers(fip, ntw));
+                keys.push(LookupKey::Result(fip, ntw));
+                keys.push(LookupKey::FipMetadata(fip, ntw));
+            }
+            for voter in voters {
+                keys.push(LookupKey::Voter(ntw, voter));
+                keys.push(LookupKey::Worker(ntw, voter));
+                keys.push(LookupKey::TokenWallets(ntw, voter));
+                keys.push(LookupKey::ClientWallets(ntw, voter));
+            }
+        }
+        keys.extend(voters.map(LookupKey::Network));
+        keys.push(LookupKey::Events);
+        keys.push(LookupKey::Cursors);
+        keys.push(LookupKey::DeadLetters);
+
+        let kinds: HashSet<&str> = keys.iter().map(LookupKey::kind).collect();
+        assert_eq!(kinds.len(), 22);
+
+        let bytes: HashSet<Vec<u8>> = keys.iter().map(LookupKey::to_bytes).collect();
+        assert_eq!(bytes.len(), keys.len());
+
+        let version = format!("fipvote:v{}:", SCHEMA_VERSION);
+        assert_eq!(LookupKey::PREFIX, version);
+        for key in &keys {
+            let key = key.to_string();
+            assert!(key.starts_with(&version));
+            assert_ne!(key, SCHEMA_KEY);
+        }
+    }
+
+    #[tokio::test]
+    async fn redis_migrate() {
+        let redis = redis().await;
+        let mut con = redis.con().await.unwrap();
+        let yay = test_ballot(VoteOption::Yay, Address::from_low_u64_be(1), 4u32);
+        let nay = test_ballot(VoteOption::Nay, Address::from_low_u64_be(2), 4u32);
+        let calibration_yay = test_ballot(VoteOption::Yay, Address::from_low_u64_be(3), 4u32);
+        // Byte 3 of the address is 4, like the type byte of a per choice weight
+        let mainnet_voter = Address::from_slice(&[
+            0xab, 0xcd, 0xef, 4, 0x12, 0x34, 0x56, 0x78, 0x9a, 0xbc, 0xde, 0xf0, 0x11, 0x22, 0x33,
+            0x44, 0x55, 0x66, 0x77, 0x88,
+        ]);
+
+        // Keys as the baseline wrote them, with one network byte
+        let fip_key = |kind: u8| {
+            let mut key = 4u32.to_be_bytes().to_vec();
+            key.push(kind);
+            key
+        };
+        let list_key = |kind: u8| vec![8u8, 0, 0, 8, 1, 3, kind, 0];
+        let voter_key = |tag: u8, voter: Address| {
+            let mut key = vec![tag];
+            key.extend_from_slice(voter.as_bytes());
+            key
+        };
+
+        con.del::<_, ()>(SCHEMA_KEY).await.unwrap();
+        con.set::<_, _, ()>(fip_key(0), serde_json::to_string(&[&yay, &nay]).unwrap())
+            .await
+            .unwrap();
+        con.set::<_, _, ()>(
+            fip_key(1),
+            serde_json::to_string(&[&calibration_yay]).unwrap(),
+        )
+        .await
+        .unwrap();
+        con.set::<_, _, ()>(fip_key(2), 1000u128.to_be_bytes().to_vec())
+            .await
+            .unwrap();
+        con.set::<_, _, ()>(fip_key(3), 500u128.to_be_bytes().to_vec())
+            .await
+            .unwrap();
+        // Shared by mainnet abstentions and calibration approvals
+        con.set::<_, _, ()>(fip_key(4), 2048u128.to_be_bytes().to_vec())
+            .await
+            .unwrap();
+        con.set::<_, _, ()>(fip_key(9), 100u64).await.unwrap();
+        con.set::<_, _, ()>(list_key(187), "[4]").await.unwrap();
+        con.set::<_, _, ()>(list_key(5), mainnet_voter.as_bytes().to_vec())
+            .await
+            .unwrap();
+        con.set::<_, Vec<u32>, ()>(voter_key(0, mainnet_voter), vec![1234u32])
+            .await
             .unwrap();
+        con.set::<_, _, ()>(voter_key(2, voter()), "testnet")
+            .await
+            .unwrap();
+        drop(con);
+
+        assert!(matches!(
+            redis.check_schema().await,
+            Err(StoreError::SchemaVersion { found: 0, .. })
+        ));
+
+        let migration = redis.migrate().await.unwrap();
+        assert_eq!(migration.ballots, 2);
+        assert_eq!(migration.weights, 3);
+        assert_eq!(migration.renamed, 5);
+        assert_eq!(migration.kept, 0);
+
+        assert!(redis.check_schema().await.is_ok());
+        assert_eq!(redis.ballot_count(4u32, mainnet()).await.unwrap(), 2);
+        assert_eq!(
+            redis.ballot(4u32, mainnet(), yay.voter()).await.unwrap(),
+            Some(yay)
+        );
+        let tally = redis.tally(4u32, mainnet()).await.unwrap();
+        let sp = Constituency::StorageProviders;
+        assert_eq!(tally.weight(&sp, VoteOption::Yay), 1000);
+        assert_eq!(tally.weight(&sp, VoteOption::Nay), 500);
+        assert_eq!(tally.weight(&sp, VoteOption::Abstain), 0);
+        let tally = redis.tally(4u32, testnet()).await.unwrap();
+        assert_eq!(tally.weight(&sp, VoteOption::Yay), 2048);
+        assert_eq!(redis.ballot_count(4u32, testnet()).await.unwrap(), 1);
+
+        assert_eq!(redis.vote_start(4u32, mainnet()).await.unwrap(), Some(100));
+        assert_eq!(redis.all_votes(mainnet()).await.unwrap(), vec![4]);
+        assert_eq!(
+            redis.voter_starters(mainnet()).await.unwrap(),
+            vec![mainnet_voter]
+        );
+        assert_eq!(
+            redis
+                .voter_delegates(mainnet_voter, mainnet())
+                .await
+                .unwrap(),
+            vec![1234]
+        );
+        assert_eq!(redis.network(voter()).await.unwrap(), Some(testnet()));
+
+        // A current database has nothing to do
+        assert_eq!(
+            redis.migrate().await.unwrap(),
+            Migration {
+                from: SCHEMA_VERSION,
+                ..Default::default()
+            }
+        );
     }
 
     #[tokio::test]
     async fn redis_vote_results() {
-        let mut redis = redis().await;
-        let vote = test_vote(VoteOption::Yay, 1u32).vote().unwrap();
+        let redis = seeded_redis().await;
+        let (_, vote) = test_vote(VoteOption::Yay, 1u32).vote().unwrap();
 
         redis
-            .start_vote(1u32, vote_starter(), Network::Testnet)
+            .start_vote(
+                1u32,
+                vote_starter(),
+                testnet(),
+                vec![],
+                FipMetadata::default(),
+            )
+            .await
             .unwrap();
 
-        let res = redis.add_vote(1u32, vote, voter(), 69u64).await;
+        let res = redis.add_vote(1u32, vote, voter(), testnet(), 69u64).await;
         println!("{:?}", res);
         assert!(res.is_ok());
 
-        let res = redis.vote_results(1u32, Network::Testnet);
+        let res = redis
+            .vote_results(1u32, testnet(), &GovernanceRules::default())
+            .await;
 
         match res {
             Ok(_) => {}
@@ -1012,7 +2197,7 @@ mod tests {
 
     #[tokio::test]
     async fn redis_flush_database() {
-        let mut redis = redis().await;
-        redis.flush_all().unwrap();
+        let redis = redis().await;
+        redis.flush_all().await.unwrap();
     }
 }
@@ -0,0 +1,177 @@
+//! Keys written before the versioned key schema, only read by the migration
+//!
+//! The old keys were packed bytes with one network byte, 0 for mainnet and 1
+//! for calibration, and no network name:
+//!
+//! * `fip ++ [ntw]` JSON list of the ballots, `fip` being the big endian FIP number
+//! * `fip ++ [choice * (ntw + 1)]` raw byte power behind a choice as a big
+//!   endian u128, with choices 2 (yay), 3 (nay) and 4 (abstain)
+//! * `fip ++ [9 + ntw]` unix timestamp the vote started at
+//! * `[ntw] ++ address` storage provider ids of the voter
+//! * `[2] ++ address` network name of the voter, calibration was `testnet`
+//! * `[8, 0, 0, 8, 1, 3, 5, ntw]` packed vote starter addresses
+//! * `[8, 0, 0, 8, 1, 3, 187, ntw]` JSON FIP numbers
+//!
+//! FIP keys are 5 bytes long and voter keys 21, which tells them apart. The
+//! weight keys of type 4 are shared by mainnet abstentions and calibration
+//! approvals.
+
+use ethers::types::Address;
+
+use crate::{messages::votes::VoteOption, storage::Network};
+
+use super::LookupKey;
+
+const LIST_PREFIX: [u8; 6] = [8, 0, 0, 8, 1, 3];
+
+/// What a key from before the versioned schema holds
+#[derive(Debug, PartialEq)]
+pub enum LegacyKey {
+    /// Same value as the key of the current schema, only the key changes
+    Renamed(LookupKey),
+    /// The ballots of the FIP as one JSON list
+    Votes(u32, Network),
+    /// The raw byte power behind one choice on the FIP
+    Weight(u32, Network, VoteOption),
+    /// The power behind mainnet abstentions and calibration approvals on the
+    /// FIP, which shared a key
+    SharedWeight(u32),
+}
+
+/// Recognizes a key of the old schema, `None` for anything else
+pub fn parse(key: &[u8]) -> Option<LegacyKey> {
+    match key.len() {
+        5 => {
+            let fip = u32::from_be_bytes(key[..4].try_into().ok()?);
+            let key = match key[4] {
+                kind @ (0 | 1) => return Some(LegacyKey::Votes(fip, network(kind)?)),
+                2 => return Some(LegacyKey::Weight(fip, network(0)?, VoteOption::Yay)),
+                3 => return Some(LegacyKey::Weight(fip, network(0)?, VoteOption::Nay)),
+                4 => return Some(LegacyKey::SharedWeight(fip)),
+                6 => return Some(LegacyKey::Weight(fip, network(1)?, VoteOption::Nay)),
+                8 => return Some(LegacyKey::Weight(fip, network(1)?, VoteOption::Abstain)),
+                kind @ (9 | 10) => LookupKey::Timestamp(fip, network(kind - 9)?),
+                _ => return None,
+            };
+            Some(LegacyKey::Renamed(key))
+        }
+        8 => {
+            let rest = key.strip_prefix(&LIST_PREFIX)?;
+            let key = match rest {
+                [5, ntw] => LookupKey::VoteStarters(network(*ntw)?),
+                [187, ntw] => LookupKey::AllVotes(network(*ntw)?),
+                _ => return None,
+            };
+            Some(LegacyKey::Renamed(key))
+        }
+        21 => {
+            let voter = Address::from_slice(&key[1..]);
+            let key = match key[0] {
+                ntw @ (0 | 1) => LookupKey::Voter(network(ntw)?, voter),
+                2 => LookupKey::Network(voter),
+                _ => return None,
+            };
+            Some(LegacyKey::Renamed(key))
+        }
+        _ => None,
+    }
+}
+
+/// The networks the keys of type 4 may belong to, with the choice they counted
+pub fn shared_weight_owners() -> Vec<(Network, VoteOption)> {
+    [(0, VoteOption::Abstain), (1, VoteOption::Yay)]
+        .into_iter()
+        .filter_map(|(ntw, choice)| Some((network(ntw)?, choice)))
+        .collect()
+}
+
+/// The network of a network byte, if it is configured
+fn network(ntw: u8) -> Option<Network> {
+    match ntw {
+        0 => Network::from_name("mainnet"),
+        1 => Network::from_name("calibration"),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn mainnet() -> Network {
+        Network::from_name("mainnet").unwrap()
+    }
+
+    fn testnet() -> Network {
+        Network::from_name("calibration").unwrap()
+    }
+
+    fn fip_key(fip: u32, kind: u8) -> Vec<u8> {
+        let mut key = fip.to_be_bytes().to_vec();
+        key.push(kind);
+        key
+    }
+
+    fn voter_key(tag: u8, voter: Address) -> Vec<u8> {
+        let mut key = vec![tag];
+        key.extend_from_slice(voter.as_bytes());
+        key
+    }
+
+    #[test]
+    fn legacy_parse() {
+        let voter = Address::from_low_u64_be(7);
+
+        assert_eq!(parse(&fip_key(5, 0)), Some(LegacyKey::Votes(5, mainnet())));
+        assert_eq!(parse(&fip_key(5, 1)), Some(LegacyKey::Votes(5, testnet())));
+        assert_eq!(
+            parse(&fip_key(5, 3)),
+            Some(LegacyKey::Weight(5, mainnet(), VoteOption::Nay))
+        );
+        assert_eq!(
+            parse(&fip_key(5, 8)),
+            Some(LegacyKey::Weight(5, testnet(), VoteOption::Abstain))
+        );
+        assert_eq!(parse(&fip_key(5, 4)), Some(LegacyKey::SharedWeight(5)));
+        assert_eq!(
+            parse(&fip_key(5, 10)),
+            Some(LegacyKey::Renamed(LookupKey::Timestamp(5, testnet())))
+        );
+
+        assert_eq!(
+            parse(&voter_key(1, voter)),
+            Some(LegacyKey::Renamed(LookupKey::Voter(testnet(), voter)))
+        );
+        assert_eq!(
+            parse(&voter_key(2, voter)),
+            Some(LegacyKey::Renamed(LookupKey::Network(voter)))
+        );
+
+        // Mainnet voter keys start with 0 like FIP keys, but are never read as one
+        let voter =
+            Address::from_slice(&[0, 0, 0, 4, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 9]);
+        assert_eq!(
+            parse(&voter_key(0, voter)),
+            Some(LegacyKey::Renamed(LookupKey::Voter(mainnet(), voter)))
+        );
+
+        let mut key = LIST_PREFIX.to_vec();
+        key.extend_from_slice(&[187, 1]);
+        assert_eq!(
+            parse(&key),
+            Some(LegacyKey::Renamed(LookupKey::AllVotes(testnet())))
+        );
+        let mut key = LIST_PREFIX.to_vec();
+        key.extend_from_slice(&[5, 0]);
+        assert_eq!(
+            parse(&key),
+            Some(LegacyKey::Renamed(LookupKey::VoteStarters(mainnet())))
+        );
+
+        // Unknown network bytes, current keys and keys of other applications are left alone
+        assert_eq!(parse(&voter_key(3, voter)), None);
+        assert_eq!(parse(&fip_key(5, 12)), None);
+        assert_eq!(parse(&LookupKey::Tally(5, testnet()).to_bytes()), None);
+        assert_eq!(parse(b"session:1234"), None);
+    }
+}
@@ -0,0 +1,449 @@
+use std::{collections::BTreeMap, fmt, str::FromStr};
+
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+use crate::messages::votes::VoteOption;
+
+const DEFAULT_THRESHOLD: f64 = 0.5;
+
+/// A group of stakeholders whose ballots are tallied together
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+#[serde(into = "String", try_from = "String")]
+pub enum Constituency {
+    StorageProviders,
+    TokenHolders,
+    Clients,
+    Committee(String),
+}
+
+/// What a constituency's ballots are weighted by
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum WeightMetric {
+    /// Raw byte storage power of the delegated storage providers
+    RawBytePower,
+    /// FIL balance of the delegated wallets
+    AttoFil,
+    /// Active verified deal bytes of the delegated clients
+    VerifiedDealBytes,
+    /// One per committee member
+    Members,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum Outcome {
+    Passed,
+    Rejected,
+    /// No weight was cast for or against the FIP
+    NoVotes,
+}
+
+/// How the outcomes of the constituencies combine into the FIP's outcome
+///
+/// Constituencies without votes are left out of the combination
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CombinationRule {
+    /// Every constituency has to pass
+    #[default]
+    All,
+    /// A single constituency passing is enough
+    Any,
+    /// More than half of the constituencies have to pass
+    Majority,
+    /// Only the outcome of one constituency counts
+    Only(Constituency),
+}
+
+/// Thresholds and combination rule results are computed with
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct GovernanceRules {
+    /// Share of the yay and nay weight that must be yay for a constituency to pass
+    #[serde(default = "default_threshold")]
+    pub default_threshold: f64,
+    /// Thresholds overriding the default for single constituencies
+    #[serde(default)]
+    pub thresholds: BTreeMap<Constituency, f64>,
+    #[serde(default)]
+    pub combination_rule: CombinationRule,
+}
+
+/// Ballot count and summed weight behind one vote option
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize, ToSchema)]
+pub struct OptionTally {
+    pub ballots: u64,
+    pub weight: u128,
+}
+
+/// Running totals of one constituency
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize, ToSchema)]
+pub struct GroupTally {
+    pub yay: OptionTally,
+    pub nay: OptionTally,
+    pub abstain: OptionTally,
+}
+
+/// Running totals of a FIP vote for every constituency that took part
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize, ToSchema)]
+pub struct Tally {
+    #[schema(value_type = BTreeMap<String, GroupTally>)]
+    constituencies: BTreeMap<Constituency, GroupTally>,
+}
+
+/// Final tally of one constituency
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, ToSchema)]
+pub struct ConstituencyTally {
+    /// `storage_providers`, `token_holders`, `clients` or `committee:<name>`
+    #[schema(value_type = String)]
+    pub constituency: Constituency,
+    pub metric: WeightMetric,
+    pub yay: u64,
+    pub nay: u64,
+    pub abstain: u64,
+    pub yay_weight: u128,
+    pub nay_weight: u128,
+    pub abstain_weight: u128,
+    /// Number of ballots cast in the constituency
+    pub participation: u64,
+    pub threshold: f64,
+    pub outcome: Outcome,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, ToSchema)]
+pub struct VoteResults {
+    pub constituencies: Vec<ConstituencyTally>,
+    pub outcome: Outcome,
+}
+
+fn default_threshold() -> f64 {
+    DEFAULT_THRESHOLD
+}
+
+impl Constituency {
+    pub fn metric(&self) -> WeightMetric {
+        match self {
+            Constituency::StorageProviders => WeightMetric::RawBytePower,
+            Constituency::TokenHolders => WeightMetric::AttoFil,
+            Constituency::Clients => WeightMetric::VerifiedDealBytes,
+            Constituency::Committee(_) => WeightMetric::Members,
+        }
+    }
+}
+
+impl fmt::Display for Constituency {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Constituency::StorageProviders => write!(f, "storage_providers"),
+            Constituency::TokenHolders => write!(f, "token_holders"),
+            Constituency::Clients => write!(f, "clients"),
+            Constituency::Committee(name) => write!(f, "committee:{}", name),
+        }
+    }
+}
+
+impl FromStr for Constituency {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "storage_providers" => Ok(Constituency::StorageProviders),
+            "token_holders" => Ok(Constituency::TokenHolders),
+            "clients" => Ok(Constituency::Clients),
+            _ => match s.strip_prefix("committee:") {
+                Some(name) if !name.is_empty() => Ok(Constituency::Committee(name.to_string())),
+                _ => Err(format!("Unknown constituency: {}", s)),
+            },
+        }
+    }
+}
+
+impl From<Constituency> for String {
+    fn from(constituency: Constituency) -> Self {
+        constituency.to_string()
+    }
+}
+
+impl TryFrom<String> for Constituency {
+    type Error = String;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+impl Default for GovernanceRules {
+    fn default() -> Self {
+        Self {
+            default_threshold: DEFAULT_THRESHOLD,
+            thresholds: BTreeMap::new(),
+            combination_rule: CombinationRule::default(),
+        }
+    }
+}
+
+impl GovernanceRules {
+    pub fn threshold(&self, constituency: &Constituency) -> f64 {
+        self.thresholds
+            .get(constituency)
+            .copied()
+            .unwrap_or(self.default_threshold)
+    }
+
+    fn combine(&self, tallies: &[ConstituencyTally]) -> Outcome {
+        if let CombinationRule::Only(constituency) = &self.combination_rule {
+            return tallies
+                .iter()
+                .find(|t| t.constituency == *constituency)
+                .map_or(Outcome::NoVotes, |t| t.outcome);
+        }
+
+        let decided = tallies
+            .iter()
+            .filter(|t| t.outcome != Outcome::NoVotes)
+            .collect::<Vec<_>>();
+        if decided.is_empty() {
+            return Outcome::NoVotes;
+        }
+        let passed = decided
+            .iter()
+            .filter(|t| t.outcome == Outcome::Passed)
+            .count();
+
+        let passes = match self.combination_rule {
+            CombinationRule::All => passed == decided.len(),
+            CombinationRule::Any => passed > 0,
+            CombinationRule::Majority => passed * 2 > decided.len(),
+            CombinationRule::Only(_) => unreachable!(),
+        };
+
+        match passes {
+            true => Outcome::Passed,
+            false => Outcome::Rejected,
+        }
+    }
+}
+
+impl GroupTally {
+    pub fn option(&self, choice: VoteOption) -> OptionTally {
+        match choice {
+            VoteOption::Yay => self.yay,
+            VoteOption::Nay => self.nay,
+            VoteOption::Abstain => self.abstain,
+        }
+    }
+
+    fn option_mut(&mut self, choice: VoteOption) -> &mut OptionTally {
+        match choice {
+            VoteOption::Yay => &mut self.yay,
+            VoteOption::Nay => &mut self.nay,
+            VoteOption::Abstain => &mut self.abstain,
+        }
+    }
+
+    fn result(&self, constituency: Constituency, rules: &GovernanceRules) -> ConstituencyTally {
+        let threshold = rules.threshold(&constituency);
+
+        let decisive = self.yay.weight + self.nay.weight;
+        let outcome = if decisive == 0 {
+            Outcome::NoVotes
+        } else if self.yay.weight as f64 > threshold * decisive as f64 {
+            Outcome::Passed
+        } else {
+            Outcome::Rejected
+        };
+
+        ConstituencyTally {
+            metric: constituency.metric(),
+            constituency,
+            yay: self.yay.ballots,
+            nay: self.nay.ballots,
+            abstain: self.abstain.ballots,
+            yay_weight: self.yay.weight,
+            nay_weight: self.nay.weight,
+            abstain_weight: self.abstain.weight,
+            participation: self.yay.ballots + self.nay.ballots + self.abstain.ballots,
+            threshold,
+            outcome,
+        }
+    }
+}
+
+impl Tally {
+    /// Counts a ballot for the choice in the constituency
+    pub fn add_ballot(&mut self, constituency: Constituency, choice: VoteOption) {
+        self.group_mut(constituency).option_mut(choice).ballots += 1;
+    }
+
+    /// Adds weight behind the choice in the constituency
+    pub fn add_weight(&mut self, constituency: Constituency, choice: VoteOption, weight: u128) {
+        self.group_mut(constituency).option_mut(choice).weight += weight;
+    }
+
+    pub fn weight(&self, constituency: &Constituency, choice: VoteOption) -> u128 {
+        self.constituencies
+            .get(constituency)
+            .map_or(0, |group| group.option(choice).weight)
+    }
+
+    /// Makes sure the constituency is reported even without ballots
+    pub fn include(&mut self, constituency: Constituency) {
+        self.group_mut(constituency);
+    }
+
+    /// Adds the ballots and weight of another tally to this one
+    pub fn merge(&mut self, other: &Tally) {
+        for (constituency, group) in &other.constituencies {
+            let totals = self.group_mut(constituency.clone());
+            for choice in [VoteOption::Yay, VoteOption::Nay, VoteOption::Abstain] {
+                let option = totals.option_mut(choice);
+                option.ballots += group.option(choice).ballots;
+                option.weight += group.option(choice).weight;
+            }
+        }
+    }
+
+    pub fn results(&self, rules: &GovernanceRules) -> VoteResults {
+        let mut tally = self.clone();
+        for constituency in [
+            Constituency::StorageProviders,
+            Constituency::TokenHolders,
+            Constituency::Clients,
+        ] {
+            tally.include(constituency);
+        }
+
+        let constituencies = tally
+            .constituencies
+            .into_iter()
+            .map(|(constituency, group)| group.result(constituency, rules))
+            .collect::<Vec<_>>();
+
+        VoteResults {
+            outcome: rules.combine(&constituencies),
+            constituencies,
+        }
+    }
+
+    fn group_mut(&mut self, constituency: Constituency) -> &mut GroupTally {
+        self.constituencies.entry(constituency).or_default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tally() -> Tally {
+        let mut tally = Tally::default();
+
+        tally.add_ballot(Constituency::StorageProviders, VoteOption::Yay);
+        tally.add_weight(Constituency::StorageProviders, VoteOption::Yay, 300);
+        tally.add_ballot(Constituency::StorageProviders, VoteOption::Nay);
+        tally.add_weight(Constituency::StorageProviders, VoteOption::Nay, 100);
+
+        tally.add_ballot(Constituency::TokenHolders, VoteOption::Nay);
+        tally.add_weight(Constituency::TokenHolders, VoteOption::Nay, 50);
+
+        tally.include(Constituency::Committee("core-devs".to_string()));
+
+        tally
+    }
+
+    fn outcome(results: &VoteResults, constituency: &Constituency) -> Outcome {
+        results
+            .constituencies
+            .iter()
+            .find(|t| t.constituency == *constituency)
+            .unwrap()
+            .outcome
+    }
+
+    #[test]
+    fn results_constituency_outcomes() {
+        let results = tally().results(&GovernanceRules::default());
+
+        assert_eq!(results.constituencies.len(), 4);
+        assert_eq!(
+            outcome(&results, &Constituency::StorageProviders),
+            Outcome::Passed
+        );
+        assert_eq!(
+            outcome(&results, &Constituency::TokenHolders),
+            Outcome::Rejected
+        );
+        assert_eq!(outcome(&results, &Constituency::Clients), Outcome::NoVotes);
+        assert_eq!(
+            outcome(&results, &Constituency::Committee("core-devs".to_string())),
+            Outcome::NoVotes
+        );
+
+        let sp = &results.constituencies[0];
+        assert_eq!(sp.metric, WeightMetric::RawBytePower);
+        assert_eq!(sp.participation, 2);
+    }
+
+    #[test]
+    fn results_thresholds() {
+        let mut rules = GovernanceRules::default();
+        rules
+            .thresholds
+            .insert(Constituency::StorageProviders, 0.75);
+
+        let results = tally().results(&rules);
+
+        // 300 of 400 is not above 75%
+        assert_eq!(
+            outcome(&results, &Constituency::StorageProviders),
+            Outcome::Rejected
+        );
+    }
+
+    #[test]
+    fn results_combination_rules() {
+        let mut rules = GovernanceRules::default();
+
+        let cases = [
+            (CombinationRule::All, Outcome::Rejected),
+            (CombinationRule::Any, Outcome::Passed),
+            (CombinationRule::Majority, Outcome::Rejected),
+            (
+                CombinationRule::Only(Constituency::StorageProviders),
+                Outcome::Passed,
+            ),
+            (
+                CombinationRule::Only(Constituency::Clients),
+                Outcome::NoVotes,
+            ),
+        ];
+        for (rule, expected) in cases {
+            rules.combination_rule = rule;
+
+            assert_eq!(tally().results(&rules).outcome, expected);
+        }
+
+        assert_eq!(Tally::default().results(&rules).outcome, Outcome::NoVotes);
+    }
+
+    #[test]
+    fn results_rules_deserialize() {
+        let rules: GovernanceRules = serde_json::from_str(
+            r#"{
+                "thresholds": { "committee:core-devs": 0.66 },
+                "combination_rule": { "only": "storage_providers" }
+            }"#,
+        )
+        .unwrap();
+
+        assert_eq!(rules.default_threshold, DEFAULT_THRESHOLD);
+        assert_eq!(
+            rules.threshold(&Constituency::Committee("core-devs".to_string())),
+            0.66
+        );
+        assert_eq!(
+            rules.combination_rule,
+            CombinationRule::Only(Constituency::StorageProviders)
+        );
+    }
+}
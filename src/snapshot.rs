@@ -0,0 +1,491 @@
+use std::{collections::BTreeMap, time};
+
+use ethers::types::Address;
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+use crate::{
+    audit::{AuditEvent, MAX_PAGE_SIZE},
+    messages::vote_start::FipMetadata,
+    results::Tally,
+    storage::{networks, Network},
+    store::{ballot_hash, Ballot, FinalResult, NewVote, StoreError, VoteStore, WalletKind},
+};
+
+/// Version of the snapshot format, bumped whenever its shape changes
+pub const SNAPSHOT_VERSION: u32 = 1;
+
+#[derive(Debug, Error)]
+pub enum SnapshotError {
+    #[error(transparent)]
+    Store(#[from] StoreError),
+    #[error("Snapshot version {0} is not supported, expected {}", SNAPSHOT_VERSION)]
+    Version(u32),
+    #[error("Network {0} of the snapshot is not configured")]
+    UnknownNetwork(String),
+    #[error("Tally of FIP {fip} on {network} does not match its ballots")]
+    TallyMismatch { network: String, fip: u32 },
+    #[error("Final result of FIP {fip} on {network} does not match its ballots")]
+    ResultMismatch { network: String, fip: u32 },
+}
+
+/// Everything in a store, in a format that does not depend on the backend
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Snapshot {
+    pub version: u32,
+    /// Unix timestamp the snapshot was taken at
+    pub exported_at: u64,
+    pub networks: Vec<NetworkSnapshot>,
+    /// The audit log, oldest event first
+    pub events: Vec<AuditEvent>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct NetworkSnapshot {
+    pub network: String,
+    pub starters: Vec<Address>,
+    pub committees: BTreeMap<String, Vec<Address>>,
+    pub voters: Vec<VoterSnapshot>,
+    pub votes: Vec<VoteSnapshot>,
+}
+
+/// The registrations of one voter on the network
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct VoterSnapshot {
+    pub address: Address,
+    /// Whether the network is the one the voter is registered to
+    pub registered: bool,
+    pub delegates: Vec<u32>,
+    /// Worker address that signed the storage provider registration
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub worker: Option<String>,
+    pub token_wallets: Vec<String>,
+    pub client_wallets: Vec<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct VoteSnapshot {
+    pub fip: u32,
+    /// Unix timestamp the vote started at
+    pub started: u64,
+    /// Chain epoch balances and deal bytes are measured at
+    pub snapshot_epoch: i64,
+    pub committees: Vec<String>,
+    /// Members of the committees when the vote started, missing for votes
+    /// started before they were recorded
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub committee_members: BTreeMap<String, Vec<Address>>,
+    /// Missing for votes started without metadata
+    #[serde(default, skip_serializing_if = "FipMetadata::is_empty")]
+    pub metadata: FipMetadata,
+    pub ballots: Vec<Ballot>,
+    pub tally: Tally,
+    /// Missing for votes that were not finalized
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub result: Option<FinalResult>,
+}
+
+/// What an import wrote
+#[derive(Debug, Default, PartialEq)]
+pub struct Import {
+    pub voters: usize,
+    pub votes: usize,
+    pub ballots: usize,
+    pub events: usize,
+    /// Votes whose ballots were recorded before ballot weights were kept,
+    /// so their tally could not be recomputed
+    pub unverified: Vec<(String, u32)>,
+}
+
+impl VoterSnapshot {
+    /// Reads the registrations of the voter on the network
+    pub async fn read(
+        store: &dyn VoteStore,
+        address: Address,
+        ntw: Network,
+    ) -> Result<VoterSnapshot, StoreError> {
+        Ok(VoterSnapshot {
+            address,
+            registered: store.network(address).await? == Some(ntw),
+            delegates: store.voter_delegates(address, ntw).await?,
+            worker: store.voter_worker(address, ntw).await?,
+            token_wallets: store.wallets(WalletKind::Token, address, ntw).await?,
+            client_wallets: store.wallets(WalletKind::Client, address, ntw).await?,
+        })
+    }
+}
+
+impl VoteSnapshot {
+    /// Reads the vote on the FIP, `None` if it was never started
+    pub async fn read(
+        store: &dyn VoteStore,
+        fip: u32,
+        ntw: Network,
+    ) -> Result<Option<VoteSnapshot>, StoreError> {
+        let started = match store.vote_start(fip, ntw).await? {
+            Some(started) => started,
+            None => return Ok(None),
+        };
+        let mut ballots = store.ballots(fip, ntw).await?;
+        ballots.sort_by_key(|b| b.vote.voter());
+
+        Ok(Some(VoteSnapshot {
+            fip,
+            started,
+            snapshot_epoch: store.snapshot_epoch(fip, ntw).await?.unwrap_or_default(),
+            committees: store.fip_committees(fip, ntw).await?,
+            committee_members: store.fip_committee_members(fip, ntw).await?,
+            metadata: store.fip_metadata(fip, ntw).await?,
+            ballots,
+            tally: store.tally(fip, ntw).await?,
+            result: store.final_result(fip, ntw).await?,
+        }))
+    }
+
+    /// Sums the weights of the ballots, `None` if any of them has no weight
+    pub fn recompute_tally(&self) -> Option<Tally> {
+        if self.ballots.iter().any(|b| b.weight == Tally::default()) {
+            return None;
+        }
+
+        let mut tally = Tally::default();
+        for ballot in &self.ballots {
+            tally.merge(&ballot.weight);
+        }
+        Some(tally)
+    }
+}
+
+impl Snapshot {
+    /// Reads every network and the audit log of the store
+    pub async fn export(store: &dyn VoteStore) -> Result<Snapshot, StoreError> {
+        let mut snapshot = Snapshot {
+            version: SNAPSHOT_VERSION,
+            exported_at: time::SystemTime::now()
+                .duration_since(time::UNIX_EPOCH)
+                .map_or(0, |d| d.as_secs()),
+            networks: Vec::new(),
+            events: Vec::new(),
+        };
+
+        for ntw in networks() {
+            snapshot.networks.push(export_network(store, ntw).await?);
+        }
+
+        loop {
+            let after = snapshot.events.last().map_or(0, |e| e.id);
+            let page = store.events(after, MAX_PAGE_SIZE).await?;
+            if page.is_empty() {
+                break;
+            }
+            snapshot.events.extend(page);
+        }
+
+        Ok(snapshot)
+    }
+
+    /// Checks the version, the networks and the tally of every vote
+    ///
+    /// Returns the votes that could not be checked because their ballots
+    /// carry no weight.
+    pub fn verify(&self) -> Result<Vec<(String, u32)>, SnapshotError> {
+        if self.version != SNAPSHOT_VERSION {
+            return Err(SnapshotError::Version(self.version));
+        }
+
+        let mut unverified = Vec::new();
+        for ntw in &self.networks {
+            if Network::from_name(&ntw.network).is_none() {
+                return Err(SnapshotError::UnknownNetwork(ntw.network.clone()));
+            }
+
+            for vote in &ntw.votes {
+                match vote.recompute_tally() {
+                    Some(tally) if tally == vote.tally => (),
+                    Some(_) => {
+                        return Err(SnapshotError::TallyMismatch {
+                            network: ntw.network.clone(),
+                            fip: vote.fip,
+                        })
+                    }
+                    None => unverified.push((ntw.network.clone(), vote.fip)),
+                }
+
+                if let Some(result) = &vote.result {
+                    if result.tally != vote.tally
+                        || result.ballot_hash != ballot_hash(&vote.ballots)?
+                    {
+                        return Err(SnapshotError::ResultMismatch {
+                            network: ntw.network.clone(),
+                            fip: vote.fip,
+                        });
+                    }
+                }
+            }
+        }
+        Ok(unverified)
+    }
+
+    /// Verifies the snapshot and writes it into the store
+    ///
+    /// Meant for empty stores, votes that already exist fail the import.
+    pub async fn import(self, store: &dyn VoteStore) -> Result<Import, SnapshotError> {
+        let mut import = Import {
+            unverified: self.verify()?,
+            ..Default::default()
+        };
+
+        for snapshot in self.networks {
+            // Verified above
+            let ntw = match Network::from_name(&snapshot.network) {
+                Some(ntw) => ntw,
+                None => return Err(SnapshotError::UnknownNetwork(snapshot.network)),
+            };
+
+            store.set_voter_starters(ntw, snapshot.starters).await?;
+            store.set_committees(ntw, snapshot.committees).await?;
+
+            for voter in snapshot.voters {
+                let address = voter.address;
+                store
+                    .set_voter_delegates(address, ntw, voter.delegates)
+                    .await?;
+                store.set_voter_worker(address, ntw, voter.worker).await?;
+                let wallets = [
+                    (WalletKind::Token, voter.token_wallets),
+                    (WalletKind::Client, voter.client_wallets),
+                ];
+                for (kind, wallets) in wallets {
+                    for wallet in &wallets {
+                        if store.claim_wallet(kind, ntw, wallet, address).await? != address {
+                            return Err(StoreError::WalletClaimed.into());
+                        }
+                    }
+                    store.set_wallets(kind, address, ntw, wallets).await?;
+                }
+                if voter.registered {
+                    store.set_network(address, Some(ntw)).await?;
+                }
+                import.voters += 1;
+            }
+
+            for vote in snapshot.votes {
+                let start = NewVote {
+                    timestamp: vote.started,
+                    snapshot: vote.snapshot_epoch,
+                    committees: vote.committees,
+                    members: vote.committee_members,
+                    metadata: vote.metadata,
+                };
+                import.ballots += vote.ballots.len();
+                store
+                    .import_vote(vote.fip, ntw, start, vote.ballots, vote.tally)
+                    .await?;
+                if let Some(result) = vote.result {
+                    store.set_final_result(vote.fip, ntw, result).await?;
+                }
+                import.votes += 1;
+            }
+        }
+
+        for event in self.events {
+            store.append_event(event).await?;
+            import.events += 1;
+        }
+
+        Ok(import)
+    }
+}
+
+async fn export_network(
+    store: &dyn VoteStore,
+    ntw: Network,
+) -> Result<NetworkSnapshot, StoreError> {
+    // Sorted so snapshots of the same data match whatever the backend
+    let mut addresses = store.voters(ntw).await?;
+    addresses.sort();
+
+    let mut voters = Vec::new();
+    for address in addresses {
+        voters.push(VoterSnapshot::read(store, address, ntw).await?);
+    }
+
+    let mut votes = Vec::new();
+    for fip in store.all_votes(ntw).await? {
+        // Listed votes without a start were never started
+        if let Some(vote) = VoteSnapshot::read(store, fip, ntw).await? {
+            votes.push(vote);
+        }
+    }
+
+    Ok(NetworkSnapshot {
+        network: ntw.name().to_string(),
+        starters: store.voter_starters(ntw).await?,
+        committees: store.committees(ntw).await?,
+        voters,
+        votes,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::*;
+
+    use crate::{
+        audit::Action,
+        messages::votes::{test_votes::test_ballot, VoteOption},
+        results::{Constituency, GovernanceRules},
+        store::{memory::Memory, sqlite::Sqlite},
+    };
+
+    fn testnet() -> Network {
+        Network::from_name("calibration").unwrap()
+    }
+
+    async fn filled_store() -> Arc<dyn VoteStore> {
+        let store: Arc<dyn VoteStore> = Arc::new(Memory::default());
+        let voter = Address::from_low_u64_be(1);
+        let wallet = "t1d2xrzcslx7xlbbylc5c3d5lvandqw4iwl6epxba".to_string();
+
+        store
+            .register_voter(voter, testnet(), vec![6024u32])
+            .await
+            .unwrap();
+        store
+            .register_token_holder(voter, testnet(), wallet)
+            .await
+            .unwrap();
+        store
+            .set_voter_starters(testnet(), vec![Address::from_low_u64_be(2)])
+            .await
+            .unwrap();
+        store
+            .set_committee(testnet(), "core-devs".to_string(), vec![voter])
+            .await
+            .unwrap();
+
+        let start = NewVote {
+            timestamp: 1688390400,
+            snapshot: 12,
+            committees: vec!["core-devs".to_string()],
+            members: BTreeMap::from([("core-devs".to_string(), vec![voter])]),
+            metadata: FipMetadata {
+                title: Some("Sector duration multiplier".to_string()),
+                ..Default::default()
+            },
+        };
+        store.create_vote(5u32, testnet(), start).await.unwrap();
+        for (i, choice) in [VoteOption::Yay, VoteOption::Nay].into_iter().enumerate() {
+            let mut weight = Tally::default();
+            weight.add_ballot(Constituency::StorageProviders, choice);
+            weight.add_weight(Constituency::StorageProviders, choice, 1024 << i);
+            let vote = test_ballot(choice, Address::from_low_u64_be(i as u64 + 1), 5u32);
+            store
+                .record_ballot(5u32, testnet(), vote, weight, u64::MAX)
+                .await
+                .unwrap();
+        }
+
+        store
+            .finalize(5u32, testnet(), &GovernanceRules::default(), 60)
+            .await
+            .unwrap()
+            .unwrap();
+
+        store
+            .append_event(AuditEvent::new(Action::StartVote, b"{}"))
+            .await
+            .unwrap();
+
+        store
+    }
+
+    #[tokio::test]
+    async fn snapshot_round_trip() {
+        let snapshot = Snapshot::export(&*filled_store().await).await.unwrap();
+        assert_eq!(snapshot.version, SNAPSHOT_VERSION);
+        assert!(snapshot
+            .networks
+            .iter()
+            .flat_map(|n| &n.votes)
+            .any(|v| v.metadata.title.is_some()));
+
+        // Through JSON, as written to and read from a file
+        let json = serde_json::to_string(&snapshot).unwrap();
+        let read: Snapshot = serde_json::from_str(&json).unwrap();
+
+        let stores: Vec<Arc<dyn VoteStore>> = vec![
+            Arc::new(Memory::default()),
+            Arc::new(Sqlite::open_in_memory().unwrap()),
+        ];
+        for store in stores {
+            let import = read.clone().import(&*store).await.unwrap();
+            assert_eq!(import.votes, 1);
+            assert_eq!(import.ballots, 2);
+            assert_eq!(import.events, 1);
+            assert!(import.unverified.is_empty());
+
+            let mut exported = Snapshot::export(&*store).await.unwrap();
+            exported.exported_at = snapshot.exported_at;
+            assert_eq!(exported, snapshot);
+
+            // Importing again would start the same vote twice
+            assert!(read.clone().import(&*store).await.is_err());
+        }
+    }
+
+    #[tokio::test]
+    async fn snapshot_verify() {
+        let mut snapshot = Snapshot::export(&*filled_store().await).await.unwrap();
+        let ntw = snapshot
+            .networks
+            .iter_mut()
+            .find(|n| n.network == "calibration")
+            .unwrap();
+
+        // Ballots recorded before weights were kept cannot be checked
+        let mut legacy = ntw.votes[0].clone();
+        legacy.fip = 6;
+        legacy.result = None;
+        for ballot in &mut legacy.ballots {
+            ballot.weight = Tally::default();
+        }
+        ntw.votes.push(legacy);
+        assert_eq!(
+            snapshot.verify().unwrap(),
+            vec![("calibration".to_string(), 6)]
+        );
+
+        let ntw = snapshot
+            .networks
+            .iter_mut()
+            .find(|n| n.network == "calibration")
+            .unwrap();
+        let mut tampered = ntw.votes[0].clone();
+        ntw.votes[0].ballots.pop();
+        assert!(matches!(
+            snapshot.verify(),
+            Err(SnapshotError::TallyMismatch { fip: 5, .. })
+        ));
+
+        // The final result pins the ballots, not only the tally
+        tampered.fip = 7;
+        tampered.ballots.reverse();
+        tampered.ballots[0].vote = tampered.ballots[1].vote.clone();
+        let ntw = snapshot
+            .networks
+            .iter_mut()
+            .find(|n| n.network == "calibration")
+            .unwrap();
+        ntw.votes[0] = tampered;
+        assert!(matches!(
+            snapshot.verify(),
+            Err(SnapshotError::ResultMismatch { fip: 7, .. })
+        ));
+
+        snapshot.version = SNAPSHOT_VERSION + 1;
+        assert!(matches!(snapshot.verify(), Err(SnapshotError::Version(_))));
+    }
+}
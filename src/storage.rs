@@ -1,16 +1,47 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    sync::{Arc, Mutex, OnceLock},
+};
+
 use jsonrpc::Response;
 use redis::{FromRedisValue, ToRedisArgs};
 use reqwest::Client;
-use serde_json::{json, Value};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Map, Value};
 use thiserror::Error;
+use tokio::sync::OnceCell;
 
 const MAINNET_RPC: &str = "https://api.chain.love/rpc/v0";
 const TESTNET_RPC: &str = "https://filecoin-calibration.chainup.net/rpc/v1";
 
-#[derive(Copy, Clone, Debug, PartialEq)]
-pub enum Network {
-    Mainnet,
-    Testnet,
+/// Networks the server was configured with, set once at startup
+static NETWORKS: OnceLock<Vec<NetworkConfig>> = OnceLock::new();
+
+/// Description of a Filecoin network votes can be held on
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct NetworkConfig {
+    /// Name used in query parameters and storage keys
+    pub name: String,
+    /// Address prefix, `f` for mainnet and `t` for every other network
+    pub prefix: char,
+    /// Lotus compatible JSON-RPC endpoint
+    pub rpc: String,
+}
+
+/// Handle to one of the configured networks
+#[derive(Copy, Clone, Debug)]
+pub struct Network(&'static NetworkConfig);
+
+#[derive(Debug, Error)]
+pub enum NetworkConfigError {
+    #[error("networks have already been initialized")]
+    AlreadyInitialized,
+    #[error("no networks configured")]
+    Empty,
+    #[error("duplicate network name: {0}")]
+    DuplicateName(String),
+    #[error("invalid address prefix '{1}' for network {0}")]
+    InvalidPrefix(String, char),
 }
 
 #[derive(Debug, Error)]
@@ -95,11 +126,8 @@ pub async fn verify_id(
 
 pub async fn fetch_storage_amount(sp_id: u32, ntw: Network) -> Result<u128, StorageFetchError> {
     let client = Client::new();
-    let rpc = match ntw {
-        Network::Mainnet => MAINNET_RPC,
-        Network::Testnet => TESTNET_RPC,
-    };
-    let sp_id = sp_id_format(ntw, sp_id);
+    let rpc = ntw.rpc();
+    let sp_id = ntw.id_address(sp_id);
     let response = client
         .post(rpc)
         .header("Content-Type", "application/json")
@@ -122,7 +150,9 @@ pub async fn fetch_storage_amount(sp_id: u32, ntw: Network) -> Result<u128, Stor
             let parsed_result: Value = serde_json::from_str(result.to_string().as_str())?;
 
             if let Some(power) = parsed_result["MinerPower"]["RawBytePower"].as_str() {
-                Ok(power.parse::<u128>().unwrap())
+                power
+                    .parse::<u128>()
+                    .map_err(|_| StorageFetchError::NoResult)
             } else {
                 Err(StorageFetchError::NoResult)
             }
@@ -131,45 +161,297 @@ pub async fn fetch_storage_amount(sp_id: u32, ntw: Network) -> Result<u128, Stor
     }
 }
 
-fn sp_id_format(ntw: Network, id: u32) -> String {
-    match ntw {
-        Network::Mainnet => format!("f0{}", id),
-        Network::Testnet => format!("t0{}", id),
+/// Current height of the chain, used as the snapshot epoch of new votes
+pub async fn fetch_chain_head(ntw: Network) -> Result<i64, StorageFetchError> {
+    let result = rpc(ntw, "Filecoin.ChainHead", json!([])).await?;
+
+    result["Height"].as_i64().ok_or(StorageFetchError::NoResult)
+}
+
+/// FIL balance of an actor in attoFIL
+///
+/// Read at the tipset of `epoch`, or at the chain head if no epoch is given
+pub async fn fetch_balance(
+    address: &str,
+    ntw: Network,
+    epoch: Option<i64>,
+) -> Result<u128, StorageFetchError> {
+    let tipset = tipset_key(ntw, epoch).await?;
+    let result = rpc(ntw, "Filecoin.StateGetActor", json!([address, tipset])).await?;
+
+    match result["Balance"].as_str() {
+        Some(balance) => balance
+            .parse::<u128>()
+            .map_err(|_| StorageFetchError::NoResult),
+        None => Err(StorageFetchError::NoResult),
+    }
+}
+
+/// Verified deal bytes of a storage client
+///
+/// The total size of the client's verified claims that are active
+/// at the tipset of `epoch`, or at the chain head if no epoch is given
+pub async fn fetch_verified_deal_bytes(
+    client: &str,
+    ntw: Network,
+    epoch: Option<i64>,
+) -> Result<u128, StorageFetchError> {
+    let tipset = tipset_key(ntw, epoch).await?;
+    let height = match epoch {
+        Some(epoch) => epoch,
+        None => fetch_chain_head(ntw).await?,
+    };
+
+    // Claims refer to clients by actor id
+    let id = rpc(ntw, "Filecoin.StateLookupID", json!([client, tipset])).await?;
+    let id = match id.as_str().and_then(|id| id.get(2..)) {
+        Some(id) => id.parse::<u64>().map_err(|_| StorageFetchError::NoResult)?,
+        None => return Err(StorageFetchError::NoResult),
+    };
+
+    // Claims can only be listed for the whole network, so the totals of a
+    // snapshot epoch are fetched once and shared by every ballot of the vote
+    let bytes = match epoch {
+        Some(epoch) => {
+            let totals = cached_client_totals(ntw, epoch);
+            let totals = totals
+                .get_or_try_init(|| fetch_client_totals(ntw, &tipset, height))
+                .await?;
+            totals.get(&id).copied()
+        }
+        None => fetch_client_totals(ntw, &tipset, height)
+            .await?
+            .get(&id)
+            .copied(),
+    };
+
+    Ok(bytes.unwrap_or_default())
+}
+
+/// Verified deal bytes of every client at a snapshot epoch, by client actor id
+type ClientTotals = HashMap<u64, u128>;
+
+/// Snapshot epochs whose client totals are kept, per network
+const MAX_CACHED_SNAPSHOTS: usize = 16;
+
+static CLIENT_TOTALS: OnceLock<Mutex<ClientTotalsCache>> = OnceLock::new();
+
+type ClientTotalsCache = BTreeMap<(&'static str, i64), Arc<OnceCell<ClientTotals>>>;
+
+/// The cell holding the client totals of the epoch, evicting the oldest epoch
+/// of the network when too many are kept
+fn cached_client_totals(ntw: Network, epoch: i64) -> Arc<OnceCell<ClientTotals>> {
+    let mut cache = CLIENT_TOTALS
+        .get_or_init(Default::default)
+        .lock()
+        .unwrap_or_else(|e| e.into_inner());
+
+    let totals = cache.entry((ntw.name(), epoch)).or_default().clone();
+
+    let cached: Vec<_> = cache
+        .keys()
+        .filter(|(name, _)| *name == ntw.name())
+        .copied()
+        .collect();
+    if cached.len() > MAX_CACHED_SNAPSHOTS {
+        cache.remove(&cached[0]);
+    }
+
+    totals
+}
+
+async fn fetch_client_totals(
+    ntw: Network,
+    tipset: &Value,
+    height: i64,
+) -> Result<ClientTotals, StorageFetchError> {
+    let claims = rpc(ntw, "Filecoin.StateGetAllClaims", json!([tipset])).await?;
+    match claims.as_object() {
+        Some(claims) => Ok(client_totals(claims, height)),
+        None => Err(StorageFetchError::NoResult),
+    }
+}
+
+/// Sums the size of the claims active at the height by client
+fn client_totals(claims: &Map<String, Value>, height: i64) -> ClientTotals {
+    let mut totals = ClientTotals::new();
+
+    for claim in claims.values() {
+        let start = claim["TermStart"].as_i64().unwrap_or(i64::MAX);
+        let max = claim["TermMax"].as_i64().unwrap_or(0);
+        if start > height || height >= start.saturating_add(max) {
+            continue;
+        }
+
+        if let (Some(client), Some(size)) = (claim["Client"].as_u64(), claim["Size"].as_u64()) {
+            *totals.entry(client).or_default() += u128::from(size);
+        }
+    }
+
+    totals
+}
+
+/// Tipset key at the epoch, null selects the chain head
+async fn tipset_key(ntw: Network, epoch: Option<i64>) -> Result<Value, StorageFetchError> {
+    let epoch = match epoch {
+        Some(epoch) => epoch,
+        None => return Ok(Value::Null),
+    };
+    let result = rpc(ntw, "Filecoin.ChainGetTipSetByHeight", json!([epoch, null])).await?;
+
+    match result.get("Cids") {
+        Some(cids) => Ok(cids.clone()),
+        None => Err(StorageFetchError::NoResult),
+    }
+}
+
+/// Sends a JSON-RPC request to the network's endpoint and returns the parsed result
+async fn rpc(ntw: Network, method: &str, params: Value) -> Result<Value, StorageFetchError> {
+    let response = Client::new()
+        .post(ntw.rpc())
+        .header("Content-Type", "application/json")
+        .json(&json!({
+            "jsonrpc": "2.0",
+            "method": method,
+            "params": params,
+            "id": 1
+        }))
+        .send()
+        .await?
+        .json::<Response>()
+        .await?;
+
+    match response.result {
+        Some(result) => Ok(serde_json::from_str(result.get())?),
+        None => Err(StorageFetchError::NoResult),
+    }
+}
+
+/// The networks used when no configuration file is supplied
+pub fn default_networks() -> Vec<NetworkConfig> {
+    vec![
+        NetworkConfig {
+            name: "mainnet".to_string(),
+            prefix: 'f',
+            rpc: MAINNET_RPC.to_string(),
+        },
+        NetworkConfig {
+            name: "calibration".to_string(),
+            prefix: 't',
+            rpc: TESTNET_RPC.to_string(),
+        },
+    ]
+}
+
+/// Sets the networks the server operates on
+///
+/// Must be called before any network is looked up, otherwise the
+/// default networks are used for the lifetime of the process
+pub fn init_networks(networks: Vec<NetworkConfig>) -> Result<(), NetworkConfigError> {
+    if networks.is_empty() {
+        return Err(NetworkConfigError::Empty);
+    }
+    for (i, ntw) in networks.iter().enumerate() {
+        if ntw.prefix != 'f' && ntw.prefix != 't' {
+            return Err(NetworkConfigError::InvalidPrefix(
+                ntw.name.clone(),
+                ntw.prefix,
+            ));
+        }
+        if networks[..i].iter().any(|n| n.name == ntw.name) {
+            return Err(NetworkConfigError::DuplicateName(ntw.name.clone()));
+        }
+    }
+
+    NETWORKS
+        .set(networks)
+        .map_err(|_| NetworkConfigError::AlreadyInitialized)
+}
+
+/// All configured networks
+pub fn networks() -> Vec<Network> {
+    registry().iter().map(Network).collect()
+}
+
+fn registry() -> &'static [NetworkConfig] {
+    NETWORKS.get_or_init(default_networks)
+}
+
+/// The only network with the prefix, none when the prefix is missing or shared
+fn by_prefix(networks: &[NetworkConfig], prefix: char) -> Option<&NetworkConfig> {
+    let mut matching = networks.iter().filter(|n| n.prefix == prefix);
+    match (matching.next(), matching.next()) {
+        (Some(ntw), None) => Some(ntw),
+        _ => None,
     }
 }
 
 impl Network {
+    pub fn from_name(name: &str) -> Option<Network> {
+        registry().iter().find(|n| n.name == name).map(Network)
+    }
+
+    /// The network of the address, if no other network shares its prefix
+    ///
+    /// Addresses of networks that share a prefix must be resolved by name
+    pub fn from_address(address: &str) -> Option<Network> {
+        let prefix = address.chars().next()?.to_ascii_lowercase();
+        by_prefix(registry(), prefix).map(Network)
+    }
+
+    pub fn name(&self) -> &'static str {
+        &self.0.name
+    }
+
+    pub fn prefix(&self) -> char {
+        self.0.prefix
+    }
+
     pub fn rpc(&self) -> &'static str {
-        match self {
-            Network::Mainnet => MAINNET_RPC,
-            Network::Testnet => TESTNET_RPC,
-        }
+        &self.0.rpc
+    }
+
+    /// Formats an actor id as an address on this network, i.e. `f01234`
+    pub fn id_address(&self, id: u32) -> String {
+        format!("{}0{}", self.0.prefix, id)
+    }
+}
+
+impl PartialEq for Network {
+    fn eq(&self, other: &Self) -> bool {
+        self.0.name == other.0.name
+    }
+}
+
+/// Networks are read by name, unknown names are rejected
+impl<'de> Deserialize<'de> for Network {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let name = String::deserialize(deserializer)?;
+        Network::from_name(&name)
+            .ok_or_else(|| serde::de::Error::custom(format!("unknown network {}", name)))
     }
 }
 
 impl ToRedisArgs for Network {
-    fn write_redis_args<W: ?Sized>(&self, out: &mut W)
+    fn write_redis_args<W>(&self, out: &mut W)
     where
-        W: redis::RedisWrite,
+        W: ?Sized + redis::RedisWrite,
     {
-        match self {
-            Network::Mainnet => "mainnet".write_redis_args(out),
-            Network::Testnet => "testnet".write_redis_args(out),
-        }
+        self.name().write_redis_args(out)
     }
 }
 
 impl FromRedisValue for Network {
     fn from_redis_value(v: &redis::Value) -> redis::RedisResult<Self> {
         let s = String::from_redis_value(v)?;
-        match s.as_str() {
-            "mainnet" => Ok(Network::Mainnet),
-            "testnet" => Ok(Network::Testnet),
-            _ => Err(redis::RedisError::from((
-                redis::ErrorKind::TypeError,
-                "Unknown network",
-            ))),
-        }
+        // Calibration used to be stored as "testnet"
+        let name = match s.as_str() {
+            "testnet" => "calibration",
+            s => s,
+        };
+        Network::from_name(name).ok_or_else(|| {
+            redis::RedisError::from((redis::ErrorKind::TypeError, "Unknown network"))
+        })
     }
 }
 
@@ -177,9 +459,37 @@ impl FromRedisValue for Network {
 mod tests {
     use super::*;
 
+    fn mainnet() -> Network {
+        Network::from_name("mainnet").unwrap()
+    }
+
+    fn testnet() -> Network {
+        Network::from_name("calibration").unwrap()
+    }
+
+    #[test]
+    fn storage_network_lookup() {
+        assert_eq!(mainnet().prefix(), 'f');
+        assert_eq!(testnet().id_address(6024), "t06024");
+        assert_eq!(Network::from_address("f01240"), Some(mainnet()));
+        assert_eq!(Network::from_address("T3abc"), Some(testnet()));
+        assert!(Network::from_name("testnet").is_none());
+
+        // A prefix shared with a devnet no longer picks calibration
+        let mut configs = default_networks();
+        configs.push(NetworkConfig {
+            name: "devnet".to_string(),
+            prefix: 't',
+            rpc: "http://127.0.0.1:1234/rpc/v1".to_string(),
+        });
+        assert_eq!(by_prefix(&configs, 'f').unwrap().name, "mainnet");
+        assert!(by_prefix(&configs, 't').is_none());
+        assert!(by_prefix(&configs, 'x').is_none());
+    }
+
     #[tokio::test]
     async fn storage_fetch_storage_amount_mainnet() {
-        let res = fetch_storage_amount(1240u32, Network::Mainnet).await;
+        let res = fetch_storage_amount(1240u32, mainnet()).await;
 
         println!("{:?}", res);
         assert!(res.is_ok());
@@ -187,7 +497,46 @@ mod tests {
 
     #[tokio::test]
     async fn storage_fetch_storage_amount_testnet() {
-        let res = fetch_storage_amount(6024u32, Network::Testnet).await;
+        let res = fetch_storage_amount(6024u32, testnet()).await;
+
+        println!("{:?}", res);
+        assert!(res.is_ok());
+    }
+
+    #[test]
+    fn storage_client_totals() {
+        let claims = json!({
+            // Active from 100 until 200
+            "1": { "Client": 1001, "Size": 2048, "TermStart": 100, "TermMax": 100 },
+            "2": { "Client": 1001, "Size": 1024, "TermStart": 150, "TermMax": 1000 },
+            "3": { "Client": 1002, "Size": 4096, "TermStart": 120, "TermMax": 500 },
+            // Starts after the height
+            "4": { "Client": 1002, "Size": 8192, "TermStart": 151, "TermMax": 500 },
+            // Ended at the height
+            "5": { "Client": 1003, "Size": 512, "TermStart": 50, "TermMax": 100 },
+            // Missing term
+            "6": { "Client": 1003, "Size": 256 }
+        });
+        let claims = claims.as_object().unwrap();
+
+        let totals = client_totals(claims, 150);
+        assert_eq!(totals.get(&1001), Some(&3072));
+        assert_eq!(totals.get(&1002), Some(&4096));
+        assert_eq!(totals.get(&1003), None);
+
+        let totals = client_totals(claims, 199);
+        assert_eq!(totals.get(&1001), Some(&3072));
+        assert_eq!(totals.get(&1002), Some(&12288));
+
+        let totals = client_totals(claims, 200);
+        assert_eq!(totals.get(&1001), Some(&1024));
+    }
+
+    #[tokio::test]
+    async fn storage_fetch_balance_testnet() {
+        let head = fetch_chain_head(testnet()).await.unwrap();
+
+        let res = fetch_balance("t06024", testnet(), Some(head - 10)).await;
 
         println!("{:?}", res);
         assert!(res.is_ok());
@@ -195,14 +544,14 @@ mod tests {
 
     #[tokio::test]
     async fn storage_verify_id_testnet() {
-        let res = verify_id("t06024".to_string(), "t3qejyqmrirddrsb2w2thbaco3q6emuljumlhuonp3al35g3kkzx4zpeecycw7gim2meegemwot3gp3qr6alpa".to_string(), Network::Testnet).await.unwrap();
+        let res = verify_id("t06024".to_string(), "t3qejyqmrirddrsb2w2thbaco3q6emuljumlhuonp3al35g3kkzx4zpeecycw7gim2meegemwot3gp3qr6alpa".to_string(), testnet()).await.unwrap();
 
         assert!(res);
     }
 
     #[tokio::test]
     async fn storage_verify_id_mainnet() {
-        let res = verify_id("f01240".to_string(), "f3wzxynjiptyogm442qg4cv74czijfzj7fzymqx6gmr6yw6oojhmlg7qavplholgoeyiyxh2zostfrnc2w2mxq".to_string(), Network::Mainnet).await.unwrap();
+        let res = verify_id("f01240".to_string(), "f3wzxynjiptyogm442qg4cv74czijfzj7fzymqx6gmr6yw6oojhmlg7qavplholgoeyiyxh2zostfrnc2w2mxq".to_string(), mainnet()).await.unwrap();
 
         assert!(res);
     }
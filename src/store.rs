@@ -0,0 +1,2072 @@
+pub mod memory;
+pub mod sqlite;
+
+use std::{collections::BTreeMap, sync::Arc, time};
+
+use async_trait::async_trait;
+use deadpool::managed::{BuildError, PoolError};
+use ethers::{types::Address, utils::keccak256};
+use redis::RedisError;
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+use url::Url;
+use utoipa::ToSchema;
+
+use crate::{
+    audit::AuditEvent,
+    authorized_voters,
+    messages::{
+        vote_start::FipMetadata,
+        votes::{Vote, VoteOption},
+    },
+    redis::Redis,
+    results::{Constituency, ConstituencyTally, GovernanceRules, Outcome, Tally, VoteResults},
+    storage::{
+        fetch_balance, fetch_chain_head, fetch_storage_amount, fetch_verified_deal_bytes, Network,
+    },
+    webhooks::DeadLetter,
+};
+
+use self::{memory::Memory, sqlite::Sqlite};
+
+#[derive(Debug, PartialEq)]
+pub enum VoteStatus {
+    DoesNotExist,
+    InProgress(u64),
+    Concluded,
+}
+
+/// The wallets a voter can vote with besides storage providers
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum WalletKind {
+    /// Token holders, weighted by FIL balance
+    Token,
+    /// Storage clients, weighted by verified deal bytes
+    Client,
+}
+
+/// Everything recorded when a vote starts
+#[derive(Debug, Clone)]
+pub struct NewVote {
+    /// Unix timestamp in seconds the vote started at
+    pub timestamp: u64,
+    /// Chain epoch balances and deal bytes are measured at
+    pub snapshot: i64,
+    /// Committees taking part in the vote
+    pub committees: Vec<String>,
+    /// Members of those committees when the vote started, they are the ones
+    /// voting for their committee
+    pub members: BTreeMap<String, Vec<Address>>,
+    /// What the starter signed about the FIP, empty for votes started without
+    pub metadata: FipMetadata,
+}
+
+/// Whether a vote runs, concluded or has its final result recorded
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum FipStatus {
+    InProgress,
+    Concluded,
+    Finalized,
+}
+
+/// The metadata, schedule and status of the vote on a FIP
+#[derive(Debug, Clone, PartialEq, Serialize, ToSchema)]
+pub struct FipInfo {
+    pub fip_number: u32,
+    pub metadata: FipMetadata,
+    /// Unix timestamps in seconds the vote started and ends at
+    pub started_at: u64,
+    pub ends_at: u64,
+    /// Chain epoch balances and deal bytes are measured at
+    pub snapshot_epoch: Option<i64>,
+    pub committees: Vec<String>,
+    pub status: FipStatus,
+    /// Seconds until the vote concludes, missing once it has
+    pub time_left: Option<u64>,
+}
+
+/// Everything a voter registered and cast on a network
+#[derive(Debug, Clone, PartialEq, Serialize, ToSchema)]
+pub struct VoterProfile {
+    #[schema(value_type = String)]
+    pub address: Address,
+    pub registration: VoterRegistrations,
+    /// Raw byte power of the storage providers, as a decimal string
+    pub power: String,
+    /// Whether the address may start votes
+    pub starter: bool,
+    /// Committees the address is a member of
+    pub committees: Vec<String>,
+    /// Ballots cast, in the order the votes started
+    pub ballots: Vec<CastBallot>,
+    /// Running votes the address can still cast a ballot in
+    pub awaiting: Vec<u32>,
+}
+
+/// The registrations of a voter and who signed them
+#[derive(Debug, Clone, PartialEq, Serialize, ToSchema)]
+pub struct VoterRegistrations {
+    /// Whether this is the network the voter is registered to
+    pub registered: bool,
+    /// Id addresses of the storage providers the voter votes for
+    pub storage_providers: Vec<String>,
+    /// Worker address that signed the storage provider registration, missing
+    /// for registrations recorded before it was kept
+    pub worker: Option<String>,
+    /// Wallets that signed over their balance
+    pub token_wallets: Vec<String>,
+    /// Storage client wallets that signed over their verified deals
+    pub client_wallets: Vec<String>,
+}
+
+/// A ballot the voter cast
+#[derive(Debug, Clone, PartialEq, Serialize, ToSchema)]
+pub struct CastBallot {
+    pub fip_number: u32,
+    /// `Yay`, `Nay` or `Abstain`
+    #[schema(value_type = String)]
+    pub choice: VoteOption,
+    pub status: FipStatus,
+}
+
+/// A ballot and the weight it added to the tally of the vote
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Ballot {
+    #[serde(flatten)]
+    pub vote: Vote,
+    /// Empty for ballots recorded before their weight was kept
+    #[serde(default)]
+    pub weight: Tally,
+}
+
+/// The outcome of a concluded vote, recorded once and never changed
+///
+/// Results endpoints serve this record instead of the running tally, so
+/// nothing written after the vote concluded changes its outcome.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, ToSchema)]
+pub struct FinalResult {
+    pub constituencies: Vec<ConstituencyTally>,
+    pub outcome: Outcome,
+    pub tally: Tally,
+    /// Keccak-256 of the ballots, see `ballot_hash`
+    pub ballot_hash: String,
+    pub ballot_count: u64,
+    /// Chain epoch balances and deal bytes were measured at
+    pub snapshot_epoch: Option<i64>,
+    /// Unix timestamp the vote was finalized at
+    pub finalized_at: u64,
+}
+
+/// Builds the final result of a vote from its ballots and tally
+pub type BuildResult<'a> =
+    dyn Fn(Vec<Ballot>, Tally) -> Result<FinalResult, StoreError> + Send + Sync + 'a;
+
+/// Whether the unix timestamp has passed, ballots are refused from then on
+pub(crate) fn past_deadline(deadline: u64) -> bool {
+    let now = time::SystemTime::now()
+        .duration_since(time::UNIX_EPOCH)
+        .map_or(0, |d| d.as_secs());
+    now >= deadline
+}
+
+/// Hex encoded Keccak-256 of the JSON list of the ballots sorted by voter
+pub fn ballot_hash(ballots: &[Ballot]) -> Result<String, StoreError> {
+    let mut ballots = ballots.iter().collect::<Vec<_>>();
+    ballots.sort_by_key(|b| b.vote.voter());
+    let json = serde_json::to_vec(&ballots)?;
+    Ok(format!("0x{}", hex::encode(keccak256(json))))
+}
+
+/// The form wallets are stored in, lowercase with the prefix of the network
+pub fn canonical_wallet(ntw: Network, wallet: &str) -> String {
+    let wallet = wallet.to_ascii_lowercase();
+    match wallet.get(1..) {
+        Some(rest) if !rest.is_empty() => format!("{}{rest}", ntw.prefix()),
+        _ => wallet,
+    }
+}
+
+#[derive(Debug, Error)]
+pub enum StoreError {
+    #[error(transparent)]
+    Redis(#[from] RedisError),
+    #[error(transparent)]
+    Pool(#[from] PoolError<RedisError>),
+    #[error(transparent)]
+    CreatePool(#[from] BuildError<RedisError>),
+    #[error(transparent)]
+    Sqlite(#[from] rusqlite::Error),
+    #[error(transparent)]
+    Serde(#[from] serde_json::Error),
+    #[error("Unsupported database url: {0}")]
+    UnsupportedUrl(Url),
+    #[error("Signer is not authorized to start a vote")]
+    UnauthorizedStarter,
+    #[error("Address is not a vote starter")]
+    UnknownStarter,
+    #[error("Cannot remove the last vote starter")]
+    LastStarter,
+    #[error("Wallet is registered to another voter")]
+    WalletClaimed,
+    #[error("Message was already used")]
+    MessageReused,
+    #[error("Vote already exists")]
+    VoteExists,
+    #[error("Unknown committee")]
+    UnknownCommittee,
+    #[error("Vote is not active")]
+    VoteNotActive,
+    #[error("Voter is not authorized for any storage providers, wallets or committees")]
+    NoVotingPower,
+    #[error("Ballot was signed for another FIP")]
+    FipMismatch,
+    #[error("Voter has already voted")]
+    DuplicateVote,
+    #[error("Vote is finalized and can no longer change")]
+    VoteFinalized,
+    #[error("Error fetching {0}")]
+    Fetch(&'static str),
+    #[error("Invalid stored data: {0}")]
+    Corrupt(&'static str),
+    #[error("Database uses key schema version {found} but {expected} is expected, run db migrate")]
+    SchemaVersion { found: u32, expected: u32 },
+}
+
+/// Opens the store the url points to
+///
+/// * `redis://` and `rediss://` open a Redis database, which must use the current key schema
+/// * `sqlite://` opens or creates the SQLite database at the path of the url
+/// * `memory://` keeps everything in memory until the server stops
+pub async fn open(url: &Url) -> Result<Arc<dyn VoteStore>, StoreError> {
+    let store: Arc<dyn VoteStore> = match url.scheme() {
+        "redis" | "rediss" => {
+            let redis = Redis::new(url.clone()).await?;
+            redis.check_schema().await?;
+            Arc::new(redis)
+        }
+        "sqlite" => Arc::new(Sqlite::open(url.path())?),
+        "memory" => Arc::new(Memory::default()),
+        _ => return Err(StoreError::UnsupportedUrl(url.clone())),
+    };
+    Ok(store)
+}
+
+/// Storage for registrations, votes and ballots
+///
+/// Backends implement the primitive reads and writes, while the vote rules
+/// (who may start a vote, who may vote and with what weight) are shared.
+/// `create_vote`, `record_ballot`, `finalize_vote`, `revoke_voter_starter`,
+/// `set_committee`, `use_message` and `flush_vote` must each be applied
+/// atomically.
+#[async_trait]
+pub trait VoteStore: Send + Sync {
+    /*~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~/
+    /                                   PRIMITIVES                                   /
+    /~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~*/
+
+    /// Storage provider ids the voter votes for
+    async fn voter_delegates(&self, voter: Address, ntw: Network) -> Result<Vec<u32>, StoreError>;
+
+    /// Replaces the storage providers of the voter, none removes the registration
+    async fn set_voter_delegates(
+        &self,
+        voter: Address,
+        ntw: Network,
+        sp_ids: Vec<u32>,
+    ) -> Result<(), StoreError>;
+
+    /// Worker address that signed the voter's storage provider registration
+    async fn voter_worker(
+        &self,
+        voter: Address,
+        ntw: Network,
+    ) -> Result<Option<String>, StoreError>;
+
+    async fn set_voter_worker(
+        &self,
+        voter: Address,
+        ntw: Network,
+        worker: Option<String>,
+    ) -> Result<(), StoreError>;
+
+    async fn wallets(
+        &self,
+        kind: WalletKind,
+        voter: Address,
+        ntw: Network,
+    ) -> Result<Vec<String>, StoreError>;
+
+    /// Replaces the wallets of the voter, none removes the registration
+    async fn set_wallets(
+        &self,
+        kind: WalletKind,
+        voter: Address,
+        ntw: Network,
+        wallets: Vec<String>,
+    ) -> Result<(), StoreError>;
+
+    /// Records the voter as owner of the wallet unless another voter already
+    /// owns it, returns the owner
+    async fn claim_wallet(
+        &self,
+        kind: WalletKind,
+        ntw: Network,
+        wallet: &str,
+        voter: Address,
+    ) -> Result<Address, StoreError>;
+
+    async fn release_wallet(
+        &self,
+        kind: WalletKind,
+        ntw: Network,
+        wallet: &str,
+    ) -> Result<(), StoreError>;
+
+    /// The network the voter is registered on
+    async fn network(&self, voter: Address) -> Result<Option<Network>, StoreError>;
+
+    async fn set_network(&self, voter: Address, ntw: Option<Network>) -> Result<(), StoreError>;
+
+    /// Every address with a registration on the network or registered to it
+    async fn voters(&self, ntw: Network) -> Result<Vec<Address>, StoreError>;
+
+    async fn voter_starters(&self, ntw: Network) -> Result<Vec<Address>, StoreError>;
+
+    async fn set_voter_starters(
+        &self,
+        ntw: Network,
+        starters: Vec<Address>,
+    ) -> Result<(), StoreError>;
+
+    /// Sets the starters of the network unless they were ever set, even to
+    /// none, returns whether they were
+    async fn init_voter_starters(
+        &self,
+        ntw: Network,
+        starters: Vec<Address>,
+    ) -> Result<bool, StoreError>;
+
+    /// Stops a starter from starting votes, unless no starter would be left
+    /// to grant the right again
+    ///
+    /// Fails with `UnknownStarter` if the address is not a starter and with
+    /// `LastStarter` if it is the only one
+    async fn revoke_voter_starter(&self, starter: Address, ntw: Network) -> Result<(), StoreError>;
+
+    /// Committee names to their members
+    async fn committees(&self, ntw: Network) -> Result<BTreeMap<String, Vec<Address>>, StoreError>;
+
+    async fn set_committees(
+        &self,
+        ntw: Network,
+        committees: BTreeMap<String, Vec<Address>>,
+    ) -> Result<(), StoreError>;
+
+    /// Replaces the members of a committee, no members removes the committee
+    ///
+    /// Other committees are left as they are, even when they change at the
+    /// same time
+    async fn set_committee(
+        &self,
+        ntw: Network,
+        name: String,
+        members: Vec<Address>,
+    ) -> Result<(), StoreError>;
+
+    /// Every FIP voted on on the network, in the order the votes started
+    async fn all_votes(&self, ntw: Network) -> Result<Vec<u32>, StoreError>;
+
+    /// Unix timestamp the vote on the FIP started at
+    async fn vote_start(&self, fip_number: u32, ntw: Network) -> Result<Option<u64>, StoreError>;
+
+    /// The chain epoch token balances and deal bytes are measured at for the FIP
+    ///
+    /// Votes started before snapshots were recorded have none
+    async fn snapshot_epoch(
+        &self,
+        fip_number: u32,
+        ntw: Network,
+    ) -> Result<Option<i64>, StoreError>;
+
+    /// The committees taking part in the vote on the FIP
+    async fn fip_committees(
+        &self,
+        fip_number: u32,
+        ntw: Network,
+    ) -> Result<Vec<String>, StoreError>;
+
+    /// Members of the committees taking part in the vote, as they were when it
+    /// started, empty for votes started before members were recorded
+    async fn fip_committee_members(
+        &self,
+        fip_number: u32,
+        ntw: Network,
+    ) -> Result<BTreeMap<String, Vec<Address>>, StoreError>;
+
+    /// The metadata signed with the start of the vote on the FIP, empty if
+    /// there was none
+    async fn fip_metadata(&self, fip_number: u32, ntw: Network) -> Result<FipMetadata, StoreError>;
+
+    /// The ballots cast on the FIP with their weights
+    async fn ballots(&self, fip_number: u32, ntw: Network) -> Result<Vec<Ballot>, StoreError>;
+
+    async fn votes(&self, fip_number: u32, ntw: Network) -> Result<Vec<Vote>, StoreError> {
+        let ballots = self.ballots(fip_number, ntw).await?;
+        Ok(ballots.into_iter().map(|b| b.vote).collect())
+    }
+
+    /// The ballot the voter cast on the FIP, if any
+    async fn ballot(
+        &self,
+        fip_number: u32,
+        ntw: Network,
+        voter: Address,
+    ) -> Result<Option<Vote>, StoreError>;
+
+    /// The number of ballots cast on the FIP
+    async fn ballot_count(&self, fip_number: u32, ntw: Network) -> Result<u64, StoreError>;
+
+    /// The running totals of the vote on the FIP, empty before the first ballot
+    async fn tally(&self, fip_number: u32, ntw: Network) -> Result<Tally, StoreError>;
+
+    /// Records a new vote, failing with `VoteExists` if the FIP was already voted on
+    async fn create_vote(
+        &self,
+        fip_number: u32,
+        ntw: Network,
+        vote: NewVote,
+    ) -> Result<(), StoreError>;
+
+    /// Stores the ballot and adds its weight to the tally of the vote
+    ///
+    /// Fails with `DuplicateVote` if the voter already voted on the FIP, with
+    /// `VoteNotActive` once the unix timestamp `deadline` passed and with
+    /// `VoteFinalized` once the vote has a final result
+    async fn record_ballot(
+        &self,
+        fip_number: u32,
+        ntw: Network,
+        vote: Vote,
+        weight: Tally,
+        deadline: u64,
+    ) -> Result<(), StoreError>;
+
+    /// The result the vote on the FIP was finalized with, if it was
+    async fn final_result(
+        &self,
+        fip_number: u32,
+        ntw: Network,
+    ) -> Result<Option<FinalResult>, StoreError>;
+
+    /// Builds the final result from the ballots and tally of the vote, read
+    /// together, and records it unless the vote already has one
+    ///
+    /// Returns the result the vote keeps, which is the earlier one if the
+    /// vote was already finalized.
+    async fn finalize_vote(
+        &self,
+        fip_number: u32,
+        ntw: Network,
+        build: &BuildResult<'_>,
+    ) -> Result<FinalResult, StoreError>;
+
+    /// Records the final result unless the vote already has one
+    ///
+    /// Returns the result the vote keeps, which is the earlier one if the
+    /// vote was already finalized.
+    async fn set_final_result(
+        &self,
+        fip_number: u32,
+        ntw: Network,
+        result: FinalResult,
+    ) -> Result<FinalResult, StoreError>;
+
+    /// Appends the event to the audit log and returns its id
+    async fn append_event(&self, event: AuditEvent) -> Result<u64, StoreError>;
+
+    /// Up to `limit` events of the audit log with an id above `after`, oldest first
+    async fn events(&self, after: u64, limit: usize) -> Result<Vec<AuditEvent>, StoreError>;
+
+    /// Records that the signed message with the id was used on the network,
+    /// failing with `MessageReused` if it was before
+    async fn use_message(&self, ntw: Network, id: [u8; 32]) -> Result<(), StoreError>;
+
+    /// How far the background task of that name got, kept across restarts
+    async fn cursor(&self, name: &str) -> Result<Option<u64>, StoreError>;
+
+    async fn set_cursor(&self, name: &str, position: u64) -> Result<(), StoreError>;
+
+    /// Keeps a webhook delivery that was given up on
+    async fn add_dead_letter(&self, letter: DeadLetter) -> Result<(), StoreError>;
+
+    /// The webhook deliveries given up on, oldest first
+    async fn dead_letters(&self) -> Result<Vec<DeadLetter>, StoreError>;
+
+    /// Writes a whole vote at once, as read from a snapshot
+    ///
+    /// The tally is stored as given. Fails with `VoteExists` if the FIP was
+    /// already voted on.
+    async fn import_vote(
+        &self,
+        fip_number: u32,
+        ntw: Network,
+        vote: NewVote,
+        ballots: Vec<Ballot>,
+        tally: Tally,
+    ) -> Result<(), StoreError>;
+
+    /// Deletes the vote on the FIP with its ballots, its tally and what was
+    /// recorded when it started, so that it can be started again
+    ///
+    /// Fails with `VoteFinalized` once the vote has a final result
+    async fn flush_vote(&self, fip_number: u32, ntw: Network) -> Result<(), StoreError>;
+
+    /// Removes everything in the store
+    async fn flush_all(&self) -> Result<(), StoreError>;
+
+    /*~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~/
+    /                                 INITIALIZATION                                 /
+    /~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~*/
+
+    /// Starts a new vote in the database but does not add any votes into the database
+    ///
+    /// The current chain height is recorded as the snapshot epoch of the vote
+    async fn start_vote(
+        &self,
+        fip_number: u32,
+        signer: Address,
+        ntw: Network,
+        committees: Vec<String>,
+        metadata: FipMetadata,
+    ) -> Result<(), StoreError> {
+        // Check if signer is authorized to start a vote
+        if !self.is_authorized_starter(signer, ntw).await? {
+            return Err(StoreError::UnauthorizedStarter);
+        }
+
+        // Check if vote already exists
+        if self.vote_exists(ntw, fip_number).await? {
+            return Err(StoreError::VoteExists);
+        }
+
+        // Check that the participating committees exist, and keep who is in them
+        let known = self.committees(ntw).await?;
+        if committees.iter().any(|c| !known.contains_key(c)) {
+            return Err(StoreError::UnknownCommittee);
+        }
+        let members = known
+            .into_iter()
+            .filter(|(name, _)| committees.contains(name))
+            .collect();
+
+        let snapshot = fetch_chain_head(ntw)
+            .await
+            .map_err(|_| StoreError::Fetch("chain head"))?;
+
+        let timestamp = time::SystemTime::now()
+            .duration_since(time::UNIX_EPOCH)
+            .map_or(0, |d| d.as_secs());
+
+        self.create_vote(
+            fip_number,
+            ntw,
+            NewVote {
+                timestamp,
+                snapshot,
+                committees,
+                members,
+                metadata,
+            },
+        )
+        .await
+    }
+
+    /// Registers a voter in the database
+    ///
+    /// * Creates a lookup from voters address to their respective network
+    /// * Creates a lookup from voters address to their authorized storage providers
+    async fn register_voter(
+        &self,
+        voter: Address,
+        ntw: Network,
+        sp_ids: Vec<u32>,
+    ) -> Result<(), StoreError> {
+        self.set_network(voter, Some(ntw)).await?;
+        self.set_voter_delegates(voter, ntw, sp_ids).await
+    }
+
+    async fn unregister_voter(&self, voter: Address, ntw: Network) -> Result<(), StoreError> {
+        self.set_voter_delegates(voter, ntw, Vec::new()).await?;
+        self.set_voter_worker(voter, ntw, None).await?;
+
+        // Remove the voter from the network lookup unless they still vote for a wallet
+        if !self.has_registration(voter, ntw).await? {
+            self.set_network(voter, None).await?;
+        }
+
+        Ok(())
+    }
+
+    /// Registers a token holder's wallet to vote through the voter address
+    async fn register_token_holder(
+        &self,
+        voter: Address,
+        ntw: Network,
+        wallet: String,
+    ) -> Result<(), StoreError> {
+        self.add_wallet(WalletKind::Token, voter, ntw, wallet).await
+    }
+
+    async fn unregister_token_holder(
+        &self,
+        voter: Address,
+        ntw: Network,
+        wallet: String,
+    ) -> Result<(), StoreError> {
+        self.remove_wallet(WalletKind::Token, voter, ntw, wallet)
+            .await
+    }
+
+    /// Registers a storage client's wallet to vote through the voter address
+    async fn register_client(
+        &self,
+        voter: Address,
+        ntw: Network,
+        wallet: String,
+    ) -> Result<(), StoreError> {
+        self.add_wallet(WalletKind::Client, voter, ntw, wallet)
+            .await
+    }
+
+    async fn unregister_client(
+        &self,
+        voter: Address,
+        ntw: Network,
+        wallet: String,
+    ) -> Result<(), StoreError> {
+        self.remove_wallet(WalletKind::Client, voter, ntw, wallet)
+            .await
+    }
+
+    async fn register_voter_starter(&self, voter: Address, ntw: Network) -> Result<(), StoreError> {
+        let mut current_voters = self.voter_starters(ntw).await?;
+
+        current_voters.push(voter);
+
+        current_voters.sort();
+        current_voters.dedup();
+
+        self.set_voter_starters(ntw, current_voters).await
+    }
+
+    async fn remove_voter_starters(&self, voter: Address, ntw: Network) -> Result<(), StoreError> {
+        let mut starters = self.voter_starters(ntw).await?;
+
+        if starters.contains(&voter) {
+            starters.retain(|&x| x != voter);
+            self.set_voter_starters(ntw, starters).await?;
+        }
+
+        Ok(())
+    }
+
+    /// Adds the wallet to the voter, a wallet votes through one voter only
+    async fn add_wallet(
+        &self,
+        kind: WalletKind,
+        voter: Address,
+        ntw: Network,
+        wallet: String,
+    ) -> Result<(), StoreError> {
+        let wallet = canonical_wallet(ntw, &wallet);
+        if self.claim_wallet(kind, ntw, &wallet, voter).await? != voter {
+            return Err(StoreError::WalletClaimed);
+        }
+
+        let mut wallets = self.wallets(kind, voter, ntw).await?;
+        if !wallets.contains(&wallet) {
+            wallets.push(wallet);
+        }
+
+        self.set_network(voter, Some(ntw)).await?;
+        self.set_wallets(kind, voter, ntw, wallets).await
+    }
+
+    async fn remove_wallet(
+        &self,
+        kind: WalletKind,
+        voter: Address,
+        ntw: Network,
+        wallet: String,
+    ) -> Result<(), StoreError> {
+        let wallet = canonical_wallet(ntw, &wallet);
+        let mut wallets = self.wallets(kind, voter, ntw).await?;
+        // Only the voter the wallet is registered to releases it
+        if wallets.contains(&wallet) {
+            wallets.retain(|w| *w != wallet);
+            self.set_wallets(kind, voter, ntw, wallets).await?;
+            self.release_wallet(kind, ntw, &wallet).await?;
+        }
+
+        if !self.has_registration(voter, ntw).await? {
+            self.set_network(voter, None).await?;
+        }
+
+        Ok(())
+    }
+
+    /*~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~/
+    /                                     GETTERS                                    /
+    /~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~*/
+
+    async fn vote_exists(&self, ntw: Network, fip: u32) -> Result<bool, StoreError> {
+        Ok(self.vote_start(fip, ntw).await?.is_some())
+    }
+
+    async fn is_authorized_starter(
+        &self,
+        voter: Address,
+        ntw: Network,
+    ) -> Result<bool, StoreError> {
+        let voters = self.voter_starters(ntw).await?;
+
+        Ok(voters.contains(&voter))
+    }
+
+    async fn is_registered(&self, voter: Address, ntw: Network) -> bool {
+        match self.voter_delegates(voter, ntw).await {
+            Ok(sp_ids) => !sp_ids.is_empty(),
+            Err(_) => false,
+        }
+    }
+
+    /// Wallets whose balance the voter votes with
+    async fn token_wallets(&self, voter: Address, ntw: Network) -> Result<Vec<String>, StoreError> {
+        self.wallets(WalletKind::Token, voter, ntw).await
+    }
+
+    /// Storage client wallets whose verified deals the voter votes with
+    async fn client_wallets(
+        &self,
+        voter: Address,
+        ntw: Network,
+    ) -> Result<Vec<String>, StoreError> {
+        self.wallets(WalletKind::Client, voter, ntw).await
+    }
+
+    /// Whether the voter votes for any storage provider or wallet on the network
+    async fn has_registration(&self, voter: Address, ntw: Network) -> Result<bool, StoreError> {
+        Ok(!self.voter_delegates(voter, ntw).await?.is_empty()
+            || !self.token_wallets(voter, ntw).await?.is_empty()
+            || !self.client_wallets(voter, ntw).await?.is_empty())
+    }
+
+    /// The committees taking part in the vote that the voter is a member of
+    async fn voter_committees(
+        &self,
+        voter: Address,
+        fip_number: u32,
+        ntw: Network,
+    ) -> Result<Vec<String>, StoreError> {
+        let participating = self.fip_committees(fip_number, ntw).await?;
+        let mut committees = self.fip_committee_members(fip_number, ntw).await?;
+        if committees.is_empty() {
+            committees = self.committees(ntw).await?;
+        }
+
+        Ok(participating
+            .into_iter()
+            .filter(|name| {
+                committees
+                    .get(name)
+                    .is_some_and(|members| members.contains(&voter))
+            })
+            .collect())
+    }
+
+    /// Returns the tally of every constituency and the outcome of the vote on the FIP
+    ///
+    /// Committees taking part in the vote are reported even if none of their members voted
+    async fn vote_results(
+        &self,
+        fip_number: u32,
+        ntw: Network,
+        rules: &GovernanceRules,
+    ) -> Result<VoteResults, StoreError> {
+        let mut tally = self.tally(fip_number, ntw).await?;
+        for name in self.fip_committees(fip_number, ntw).await? {
+            tally.include(Constituency::Committee(name));
+        }
+
+        Ok(tally.results(rules))
+    }
+
+    /// Finalizes the vote on the FIP if it concluded and returns its final result
+    ///
+    /// The first call after the vote concluded records the result, every later
+    /// call returns that record. `None` while the vote runs or if it never started.
+    async fn finalize(
+        &self,
+        fip_number: u32,
+        ntw: Network,
+        rules: &GovernanceRules,
+        vote_length: u64,
+    ) -> Result<Option<FinalResult>, StoreError> {
+        if let Some(result) = self.final_result(fip_number, ntw).await? {
+            return Ok(Some(result));
+        }
+        if self.vote_status(fip_number, vote_length, ntw).await? != VoteStatus::Concluded {
+            return Ok(None);
+        }
+
+        // Committees and the snapshot epoch are fixed when the vote starts
+        let committees = self.fip_committees(fip_number, ntw).await?;
+        let snapshot_epoch = self.snapshot_epoch(fip_number, ntw).await?;
+
+        let build = |ballots: Vec<Ballot>, tally: Tally| {
+            let mut counted = tally.clone();
+            for name in &committees {
+                counted.include(Constituency::Committee(name.clone()));
+            }
+            let results = counted.results(rules);
+
+            Ok(FinalResult {
+                constituencies: results.constituencies,
+                outcome: results.outcome,
+                tally,
+                ballot_hash: ballot_hash(&ballots)?,
+                ballot_count: ballots.len() as u64,
+                snapshot_epoch,
+                finalized_at: time::SystemTime::now()
+                    .duration_since(time::UNIX_EPOCH)
+                    .map_or(0, |d| d.as_secs()),
+            })
+        };
+
+        self.finalize_vote(fip_number, ntw, &build).await.map(Some)
+    }
+
+    /// Finalizes every concluded vote on the network that was not yet
+    async fn finalize_concluded(
+        &self,
+        ntw: Network,
+        rules: &GovernanceRules,
+        vote_length: u64,
+    ) -> Result<(), StoreError> {
+        for fip_number in self.concluded_votes(ntw, vote_length).await? {
+            self.finalize(fip_number, ntw, rules, vote_length).await?;
+        }
+        Ok(())
+    }
+
+    /// Finalizes the votes on the network that ended after `since` and no
+    /// later than `until`, both unix timestamps, and returns their results
+    async fn finalize_ended(
+        &self,
+        ntw: Network,
+        rules: &GovernanceRules,
+        vote_length: u64,
+        since: u64,
+        until: u64,
+    ) -> Result<Vec<(u32, FinalResult)>, StoreError> {
+        let mut results = Vec::new();
+        for fip_number in self.all_votes(ntw).await? {
+            let ends_at = match self.vote_start(fip_number, ntw).await? {
+                Some(started_at) => started_at + vote_length,
+                None => continue,
+            };
+            if ends_at <= since || ends_at > until {
+                continue;
+            }
+
+            if let Some(result) = self.finalize(fip_number, ntw, rules, vote_length).await? {
+                results.push((fip_number, result));
+            }
+        }
+        Ok(results)
+    }
+
+    async fn vote_status(
+        &self,
+        fip_number: u32,
+        vote_length: u64,
+        ntw: Network,
+    ) -> Result<VoteStatus, StoreError> {
+        // Check if the FIP number has a timestamp
+        let timestamp = match self.vote_start(fip_number, ntw).await? {
+            Some(timestamp) => timestamp,
+            None => return Ok(VoteStatus::DoesNotExist),
+        };
+
+        let now = time::SystemTime::now()
+            .duration_since(time::UNIX_EPOCH)
+            .map_or(0, |d| d.as_secs());
+
+        if now < timestamp + vote_length {
+            let time_left = vote_length - (now - timestamp);
+            Ok(VoteStatus::InProgress(time_left))
+        } else {
+            Ok(VoteStatus::Concluded)
+        }
+    }
+
+    /// The metadata, schedule and status of the vote on the FIP, if it was started
+    async fn fip_info(
+        &self,
+        fip_number: u32,
+        ntw: Network,
+        vote_length: u64,
+    ) -> Result<Option<FipInfo>, StoreError> {
+        let started_at = match self.vote_start(fip_number, ntw).await? {
+            Some(started_at) => started_at,
+            None => return Ok(None),
+        };
+
+        let (status, time_left) = match self.vote_status(fip_number, vote_length, ntw).await? {
+            VoteStatus::InProgress(time_left) => (FipStatus::InProgress, Some(time_left)),
+            _ if self.final_result(fip_number, ntw).await?.is_some() => {
+                (FipStatus::Finalized, None)
+            }
+            _ => (FipStatus::Concluded, None),
+        };
+
+        Ok(Some(FipInfo {
+            fip_number,
+            metadata: self.fip_metadata(fip_number, ntw).await?,
+            started_at,
+            ends_at: started_at + vote_length,
+            snapshot_epoch: self.snapshot_epoch(fip_number, ntw).await?,
+            committees: self.fip_committees(fip_number, ntw).await?,
+            status,
+            time_left,
+        }))
+    }
+
+    /// Raw byte power of the voter's storage providers, fetched from the chain
+    ///
+    /// The addresses allowed to start votes from the beginning get a fixed bonus.
+    async fn voting_power(&self, voter: Address, ntw: Network) -> Result<u128, StoreError> {
+        let mut power = 0;
+        if authorized_voters().contains(&voter) {
+            power += 10240000;
+        }
+        for sp_id in self.voter_delegates(voter, ntw).await? {
+            power += fetch_storage_amount(sp_id, ntw)
+                .await
+                .map_err(|_| StoreError::Fetch("storage amount"))?;
+        }
+        Ok(power)
+    }
+
+    /// The registrations, power, ballots and open votes of the address
+    async fn voter_profile(
+        &self,
+        voter: Address,
+        ntw: Network,
+        vote_length: u64,
+    ) -> Result<VoterProfile, StoreError> {
+        let delegates = self.voter_delegates(voter, ntw).await?;
+        let registration = VoterRegistrations {
+            registered: self.network(voter).await? == Some(ntw),
+            storage_providers: delegates.iter().map(|&id| ntw.id_address(id)).collect(),
+            worker: self.voter_worker(voter, ntw).await?,
+            token_wallets: self.token_wallets(voter, ntw).await?,
+            client_wallets: self.client_wallets(voter, ntw).await?,
+        };
+        let registered = !registration.storage_providers.is_empty()
+            || !registration.token_wallets.is_empty()
+            || !registration.client_wallets.is_empty();
+
+        let committees = self
+            .committees(ntw)
+            .await?
+            .into_iter()
+            .filter(|(_, members)| members.contains(&voter))
+            .map(|(name, _)| name)
+            .collect();
+
+        let mut ballots = Vec::new();
+        let mut awaiting = Vec::new();
+        for fip_number in self.all_votes(ntw).await? {
+            let status = match self.fip_info(fip_number, ntw, vote_length).await? {
+                Some(info) => info.status,
+                None => continue,
+            };
+            match self.ballot(fip_number, ntw, voter).await? {
+                Some(vote) => ballots.push(CastBallot {
+                    fip_number,
+                    choice: vote.choice(),
+                    status,
+                }),
+                // Committee members only vote on the FIPs their committee takes part in
+                None if status == FipStatus::InProgress
+                    && (registered
+                        || !self
+                            .voter_committees(voter, fip_number, ntw)
+                            .await?
+                            .is_empty()) =>
+                {
+                    awaiting.push(fip_number)
+                }
+                None => (),
+            }
+        }
+
+        Ok(VoterProfile {
+            address: voter,
+            registration,
+            power: self.voting_power(voter, ntw).await?.to_string(),
+            starter: self.is_authorized_starter(voter, ntw).await?,
+            committees,
+            ballots,
+            awaiting,
+        })
+    }
+
+    async fn active_votes(&self, ntw: Network, vote_length: u64) -> Result<Vec<u32>, StoreError> {
+        let all_votes = self.all_votes(ntw).await?;
+
+        let mut active_votes = Vec::new();
+        for vote in all_votes {
+            let status = self.vote_status(vote, vote_length, ntw).await?;
+            if let VoteStatus::InProgress(_) = status {
+                active_votes.push(vote);
+            }
+        }
+        Ok(active_votes)
+    }
+
+    async fn concluded_votes(
+        &self,
+        ntw: Network,
+        vote_length: u64,
+    ) -> Result<Vec<u32>, StoreError> {
+        let all_votes = self.all_votes(ntw).await?;
+
+        let mut concluded_votes = Vec::new();
+        for vote in all_votes {
+            let status = self.vote_status(vote, vote_length, ntw).await?;
+            if let VoteStatus::Concluded = status {
+                concluded_votes.push(vote);
+            }
+        }
+        Ok(concluded_votes)
+    }
+
+    /*~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~/
+    /                                     SETTERS                                    /
+    /~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~*/
+
+    async fn add_vote(
+        &self,
+        fip_number: u32,
+        vote: Vote,
+        voter: Address,
+        ntw: Network,
+        vote_length: u64,
+    ) -> Result<(), StoreError> {
+        let num = fip_number;
+
+        // The signed message names the FIP, a ballot counts for that FIP only
+        if vote.fip() != num {
+            return Err(StoreError::FipMismatch);
+        }
+
+        if self.final_result(num, ntw).await?.is_some() {
+            return Err(StoreError::VoteFinalized);
+        }
+
+        // If the vote is not active, throw an error
+        if !self.is_vote_active(num, ntw, vote_length).await? {
+            return Err(StoreError::VoteNotActive);
+        }
+
+        // Fail before fetching any voting power if the voter already voted
+        if self.ballot(num, ntw, vote.voter()).await?.is_some() {
+            return Err(StoreError::DuplicateVote);
+        }
+
+        // Fetch the storage provider Id's and wallets that the voter is authorized for
+        let authorized = self.voter_delegates(voter, ntw).await?;
+        let wallets = self.token_wallets(voter, ntw).await?;
+        let clients = self.client_wallets(voter, ntw).await?;
+        let committees = self.voter_committees(voter, num, ntw).await?;
+
+        // If the voter has nothing to vote with, throw an error
+        if authorized.is_empty()
+            && wallets.is_empty()
+            && clients.is_empty()
+            && committees.is_empty()
+        {
+            return Err(StoreError::NoVotingPower);
+        }
+
+        let choice = vote.choice();
+        let mut weight = Tally::default();
+
+        // Add the storage providers power to their vote choice for the respective FIP
+        if !authorized.is_empty() {
+            weight.add_ballot(Constituency::StorageProviders, choice);
+        }
+        for sp_id in authorized {
+            let power = fetch_storage_amount(sp_id, ntw)
+                .await
+                .map_err(|_| StoreError::Fetch("storage amount"))?;
+            weight.add_weight(Constituency::StorageProviders, choice, power);
+        }
+
+        // Add the wallets balances at the snapshot to their vote choice
+        let epoch = self.snapshot_epoch(num, ntw).await?;
+        if !wallets.is_empty() {
+            weight.add_ballot(Constituency::TokenHolders, choice);
+        }
+        for wallet in wallets {
+            let balance = fetch_balance(&wallet, ntw, epoch)
+                .await
+                .map_err(|_| StoreError::Fetch("token balance"))?;
+            weight.add_weight(Constituency::TokenHolders, choice, balance);
+        }
+
+        // Add the clients verified deal bytes at the snapshot to their vote choice
+        if !clients.is_empty() {
+            weight.add_ballot(Constituency::Clients, choice);
+        }
+        for client in clients {
+            let deal_bytes = fetch_verified_deal_bytes(&client, ntw, epoch)
+                .await
+                .map_err(|_| StoreError::Fetch("verified deal bytes"))?;
+            weight.add_weight(Constituency::Clients, choice, deal_bytes);
+        }
+
+        // Committee members count once in each of their committees
+        for name in committees {
+            let committee = Constituency::Committee(name);
+            weight.add_ballot(committee.clone(), choice);
+            weight.add_weight(committee, choice, 1);
+        }
+
+        // Fetching the weight takes time, the vote may have concluded since
+        let deadline = self.vote_start(num, ntw).await?.unwrap_or_default() + vote_length;
+        self.record_ballot(num, ntw, vote, weight, deadline).await
+    }
+
+    async fn is_vote_active(
+        &self,
+        fip_number: u32,
+        ntw: Network,
+        vote_length: u64,
+    ) -> Result<bool, StoreError> {
+        let status = self.vote_status(fip_number, vote_length, ntw).await?;
+
+        Ok(matches!(status, VoteStatus::InProgress(_)))
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use std::str::FromStr;
+
+    use ethers::signers::{LocalWallet, Signer};
+
+    use super::*;
+
+    use crate::{
+        audit::Action,
+        messages::votes::{test_votes::*, VoteOption},
+        results::Constituency,
+    };
+
+    /// Signs the ballots of `test_vote`
+    fn voter() -> Address {
+        test_wallet().address()
+    }
+
+    fn vote_starter() -> Address {
+        authorized_voters()[0]
+    }
+
+    fn testnet() -> Network {
+        Network::from_name("calibration").unwrap()
+    }
+
+    fn stores() -> Vec<Arc<dyn VoteStore>> {
+        vec![
+            Arc::new(Memory::default()),
+            Arc::new(Sqlite::open_in_memory().unwrap()),
+        ]
+    }
+
+    #[tokio::test]
+    async fn store_open() {
+        for url in ["memory://", "sqlite:///tmp/fip-voting-store-open.db"] {
+            assert!(open(&Url::parse(url).unwrap()).await.is_ok());
+        }
+
+        let url = Url::parse("postgres://127.0.0.1/votes").unwrap();
+        assert!(matches!(
+            open(&url).await,
+            Err(StoreError::UnsupportedUrl(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn store_registration() {
+        let wallet = "t1d2xrzcslx7xlbbylc5c3d5lvandqw4iwl6epxba".to_string();
+
+        for store in stores() {
+            store
+                .register_voter(voter(), testnet(), vec![6024u32])
+                .await
+                .unwrap();
+            // Stored the same way whatever case the wallet was registered in
+            store
+                .register_token_holder(voter(), testnet(), wallet.to_ascii_uppercase())
+                .await
+                .unwrap();
+
+            // A wallet votes through one voter only
+            let res = store
+                .register_token_holder(vote_starter(), testnet(), wallet.clone())
+                .await;
+            assert!(matches!(res, Err(StoreError::WalletClaimed)));
+            store
+                .unregister_token_holder(vote_starter(), testnet(), wallet.clone())
+                .await
+                .unwrap();
+
+            assert!(store.is_registered(voter(), testnet()).await);
+            assert_eq!(store.network(voter()).await.unwrap(), Some(testnet()));
+            assert_eq!(
+                store.token_wallets(voter(), testnet()).await.unwrap(),
+                vec![wallet.clone()]
+            );
+            assert!(store
+                .client_wallets(voter(), testnet())
+                .await
+                .unwrap()
+                .is_empty());
+
+            // The voter still votes with a wallet so keeps their network
+            store.unregister_voter(voter(), testnet()).await.unwrap();
+
+            assert!(!store.is_registered(voter(), testnet()).await);
+            assert_eq!(store.network(voter()).await.unwrap(), Some(testnet()));
+
+            store
+                .unregister_token_holder(voter(), testnet(), wallet.clone())
+                .await
+                .unwrap();
+
+            assert_eq!(store.network(voter()).await.unwrap(), None);
+
+            // Released once unregistered
+            store
+                .register_token_holder(vote_starter(), testnet(), wallet.clone())
+                .await
+                .unwrap();
+        }
+    }
+
+    #[tokio::test]
+    async fn store_starters_and_committees() {
+        for store in stores() {
+            // Only a network whose starters were never set gets the first ones
+            assert!(store
+                .init_voter_starters(testnet(), vec![vote_starter()])
+                .await
+                .unwrap());
+            assert!(!store
+                .init_voter_starters(testnet(), vec![voter()])
+                .await
+                .unwrap());
+            store
+                .revoke_voter_starter(vote_starter(), testnet())
+                .await
+                .unwrap_err();
+            store
+                .remove_voter_starters(vote_starter(), testnet())
+                .await
+                .unwrap();
+
+            store
+                .register_voter_starter(voter(), testnet())
+                .await
+                .unwrap();
+            store
+                .register_voter_starter(voter(), testnet())
+                .await
+                .unwrap();
+
+            assert_eq!(
+                store.voter_starters(testnet()).await.unwrap(),
+                vec![voter()]
+            );
+
+            let res = store.revoke_voter_starter(voter(), testnet()).await;
+            assert!(matches!(res, Err(StoreError::LastStarter)));
+            let res = store.revoke_voter_starter(vote_starter(), testnet()).await;
+            assert!(matches!(res, Err(StoreError::UnknownStarter)));
+
+            store
+                .register_voter_starter(vote_starter(), testnet())
+                .await
+                .unwrap();
+            store
+                .revoke_voter_starter(vote_starter(), testnet())
+                .await
+                .unwrap();
+            assert_eq!(
+                store.voter_starters(testnet()).await.unwrap(),
+                vec![voter()]
+            );
+
+            store
+                .remove_voter_starters(voter(), testnet())
+                .await
+                .unwrap();
+
+            assert!(!store
+                .is_authorized_starter(voter(), testnet())
+                .await
+                .unwrap());
+            assert!(!store
+                .init_voter_starters(testnet(), vec![vote_starter()])
+                .await
+                .unwrap());
+            assert!(store.voter_starters(testnet()).await.unwrap().is_empty());
+
+            store
+                .set_committee(testnet(), "core-devs".to_string(), vec![voter()])
+                .await
+                .unwrap();
+
+            assert_eq!(
+                store.committees(testnet()).await.unwrap()["core-devs"],
+                vec![voter()]
+            );
+
+            store
+                .set_committee(testnet(), "core-devs".to_string(), vec![])
+                .await
+                .unwrap();
+
+            assert!(store.committees(testnet()).await.unwrap().is_empty());
+        }
+    }
+
+    #[tokio::test]
+    async fn store_record_ballot() {
+        let new_vote = NewVote {
+            timestamp: 0,
+            snapshot: 0,
+            committees: vec!["core-devs".to_string()],
+            members: BTreeMap::new(),
+            metadata: FipMetadata::default(),
+        };
+
+        for store in stores() {
+            store
+                .create_vote(5u32, testnet(), new_vote.clone())
+                .await
+                .unwrap();
+
+            let res = store.create_vote(5u32, testnet(), new_vote.clone()).await;
+            assert!(matches!(res, Err(StoreError::VoteExists)));
+
+            assert_eq!(store.all_votes(testnet()).await.unwrap(), vec![5u32]);
+            assert_eq!(
+                store.snapshot_epoch(5u32, testnet()).await.unwrap(),
+                Some(0)
+            );
+
+            let mut weight = Tally::default();
+            weight.add_ballot(Constituency::StorageProviders, VoteOption::Yay);
+            weight.add_weight(Constituency::StorageProviders, VoteOption::Yay, 2048);
+
+            // Ballots whose weight was fetched past the deadline are refused
+            let (_, vote) = test_vote(VoteOption::Yay, 5u32).vote().unwrap();
+            let res = store
+                .record_ballot(5u32, testnet(), vote.clone(), weight.clone(), 60)
+                .await;
+            assert!(matches!(res, Err(StoreError::VoteNotActive)));
+
+            store
+                .record_ballot(5u32, testnet(), vote, weight.clone(), u64::MAX)
+                .await
+                .unwrap();
+
+            let (_, vote) = test_vote(VoteOption::Nay, 5u32).vote().unwrap();
+            let res = store
+                .record_ballot(5u32, testnet(), vote, weight, u64::MAX)
+                .await;
+            assert!(matches!(res, Err(StoreError::DuplicateVote)));
+
+            assert_eq!(store.votes(5u32, testnet()).await.unwrap().len(), 1);
+            assert_eq!(store.ballot_count(5u32, testnet()).await.unwrap(), 1);
+
+            let ballot = store.ballot(5u32, testnet(), voter()).await.unwrap();
+            assert_eq!(ballot.map(|b| b.choice()), Some(VoteOption::Yay));
+            assert!(store
+                .ballot(5u32, testnet(), vote_starter())
+                .await
+                .unwrap()
+                .is_none());
+
+            let results = store
+                .vote_results(5u32, testnet(), &GovernanceRules::default())
+                .await
+                .unwrap();
+
+            assert_eq!(results.constituencies.len(), 4);
+            assert_eq!(results.outcome, Outcome::Passed);
+
+            // The vote started at the epoch so it is over
+            assert_eq!(
+                store.vote_status(5u32, 60, testnet()).await.unwrap(),
+                VoteStatus::Concluded
+            );
+
+            store.flush_vote(5u32, testnet()).await.unwrap();
+
+            assert!(store.votes(5u32, testnet()).await.unwrap().is_empty());
+
+            store.flush_all().await.unwrap();
+
+            assert!(!store.vote_exists(testnet(), 5u32).await.unwrap());
+        }
+    }
+
+    /// Flushes a vote with a ballot, starts it again and checks that only
+    /// the new ballot is counted
+    pub(crate) async fn flush_and_recast(store: Arc<dyn VoteStore>) {
+        let new_vote = NewVote {
+            timestamp: 0,
+            snapshot: 0,
+            committees: vec!["core-devs".to_string()],
+            members: BTreeMap::new(),
+            metadata: FipMetadata {
+                title: Some("Flushed".to_string()),
+                ..Default::default()
+            },
+        };
+        let mut weight = Tally::default();
+        weight.add_ballot(Constituency::StorageProviders, VoteOption::Yay);
+        weight.add_weight(Constituency::StorageProviders, VoteOption::Yay, 2048);
+
+        store
+            .create_vote(5u32, testnet(), new_vote.clone())
+            .await
+            .unwrap();
+        let (_, vote) = test_vote(VoteOption::Yay, 5u32).vote().unwrap();
+        store
+            .record_ballot(5u32, testnet(), vote, weight.clone(), u64::MAX)
+            .await
+            .unwrap();
+
+        store.flush_vote(5u32, testnet()).await.unwrap();
+
+        assert!(!store.vote_exists(testnet(), 5u32).await.unwrap());
+        assert!(store.all_votes(testnet()).await.unwrap().is_empty());
+        assert!(store.votes(5u32, testnet()).await.unwrap().is_empty());
+        assert_eq!(
+            store.tally(5u32, testnet()).await.unwrap(),
+            Tally::default()
+        );
+        assert_eq!(store.snapshot_epoch(5u32, testnet()).await.unwrap(), None);
+        assert!(store
+            .fip_committees(5u32, testnet())
+            .await
+            .unwrap()
+            .is_empty());
+        assert!(store
+            .fip_metadata(5u32, testnet())
+            .await
+            .unwrap()
+            .is_empty());
+
+        store.create_vote(5u32, testnet(), new_vote).await.unwrap();
+        let (_, vote) = test_vote(VoteOption::Yay, 5u32).vote().unwrap();
+        store
+            .record_ballot(5u32, testnet(), vote, weight.clone(), u64::MAX)
+            .await
+            .unwrap();
+
+        assert_eq!(store.tally(5u32, testnet()).await.unwrap(), weight);
+        assert_eq!(store.all_votes(testnet()).await.unwrap(), vec![5u32]);
+    }
+
+    #[tokio::test]
+    async fn store_flush_vote() {
+        for store in stores() {
+            flush_and_recast(store).await;
+        }
+    }
+
+    #[tokio::test]
+    async fn store_finalize() {
+        let rules = GovernanceRules::default();
+        let new_vote = NewVote {
+            timestamp: 0,
+            snapshot: 0,
+            committees: Vec::new(),
+            members: BTreeMap::new(),
+            metadata: FipMetadata::default(),
+        };
+
+        for store in stores() {
+            assert!(matches!(
+                store
+                    .set_final_result(5u32, testnet(), dummy_result())
+                    .await,
+                Err(StoreError::VoteNotActive)
+            ));
+
+            store
+                .create_vote(5u32, testnet(), new_vote.clone())
+                .await
+                .unwrap();
+
+            // Still running with a long enough vote length
+            assert!(store
+                .finalize(5u32, testnet(), &rules, u64::MAX / 2)
+                .await
+                .unwrap()
+                .is_none());
+
+            let mut weight = Tally::default();
+            weight.add_ballot(Constituency::StorageProviders, VoteOption::Yay);
+            weight.add_weight(Constituency::StorageProviders, VoteOption::Yay, 2048);
+            let (_, vote) = test_vote(VoteOption::Yay, 5u32).vote().unwrap();
+            store
+                .record_ballot(5u32, testnet(), vote, weight, u64::MAX)
+                .await
+                .unwrap();
+
+            let result = store
+                .finalize(5u32, testnet(), &rules, 60)
+                .await
+                .unwrap()
+                .unwrap();
+            assert_eq!(result.ballot_count, 1);
+            assert_eq!(result.snapshot_epoch, Some(0));
+            assert_eq!(result.outcome, Outcome::Passed);
+            assert_eq!(
+                result.ballot_hash,
+                ballot_hash(&store.ballots(5u32, testnet()).await.unwrap()).unwrap()
+            );
+
+            // Later writes are rejected and the record never changes
+            let vote = test_ballot(VoteOption::Nay, vote_starter(), 5u32);
+            let res = store
+                .record_ballot(5u32, testnet(), vote, Tally::default(), u64::MAX)
+                .await;
+            assert!(matches!(res, Err(StoreError::VoteFinalized)));
+            let res = store.flush_vote(5u32, testnet()).await;
+            assert!(matches!(res, Err(StoreError::VoteFinalized)));
+
+            let kept = store
+                .set_final_result(5u32, testnet(), dummy_result())
+                .await
+                .unwrap();
+            assert_eq!(kept, result);
+            let strict = GovernanceRules {
+                default_threshold: 1.0,
+                ..Default::default()
+            };
+            assert_eq!(
+                store.finalize(5u32, testnet(), &strict, 60).await.unwrap(),
+                Some(result)
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn store_fip_info() {
+        let metadata = FipMetadata {
+            title: Some("Sector duration multiplier".to_string()),
+            document_hash: Some(format!("0x{}", "ab".repeat(32))),
+            ..Default::default()
+        };
+        let new_vote = NewVote {
+            timestamp: 100,
+            snapshot: 12,
+            committees: vec!["core-devs".to_string()],
+            members: BTreeMap::new(),
+            metadata: metadata.clone(),
+        };
+
+        for store in stores() {
+            assert!(store.fip_info(5u32, testnet(), 60).await.unwrap().is_none());
+
+            store
+                .create_vote(5u32, testnet(), new_vote.clone())
+                .await
+                .unwrap();
+            store
+                .create_vote(
+                    6u32,
+                    testnet(),
+                    NewVote {
+                        metadata: FipMetadata::default(),
+                        ..new_vote.clone()
+                    },
+                )
+                .await
+                .unwrap();
+
+            assert_eq!(store.fip_metadata(5u32, testnet()).await.unwrap(), metadata);
+            assert!(store
+                .fip_metadata(6u32, testnet())
+                .await
+                .unwrap()
+                .is_empty());
+
+            let info = store.fip_info(5u32, testnet(), 60).await.unwrap().unwrap();
+            assert_eq!(info.metadata, metadata);
+            assert_eq!((info.started_at, info.ends_at), (100, 160));
+            assert_eq!(info.snapshot_epoch, Some(12));
+            assert_eq!(info.committees, vec!["core-devs"]);
+            assert_eq!(info.status, FipStatus::Concluded);
+            assert_eq!(info.time_left, None);
+
+            let info = store
+                .fip_info(5u32, testnet(), u64::MAX / 2)
+                .await
+                .unwrap()
+                .unwrap();
+            assert_eq!(info.status, FipStatus::InProgress);
+            assert!(info.time_left.is_some());
+
+            store
+                .set_final_result(5u32, testnet(), dummy_result())
+                .await
+                .unwrap();
+            let info = store.fip_info(5u32, testnet(), 60).await.unwrap().unwrap();
+            assert_eq!(info.status, FipStatus::Finalized);
+        }
+    }
+
+    #[tokio::test]
+    async fn store_voter_profile() {
+        let now = time::SystemTime::now()
+            .duration_since(time::UNIX_EPOCH)
+            .unwrap()
+            .as_secs();
+        let (_, vote) = test_vote(VoteOption::Yay, 5u32).vote().unwrap();
+        let voter = vote.voter();
+
+        for store in stores() {
+            for (fip, committees) in [(5u32, vec![]), (6, vec![]), (7, vec!["core-devs"])] {
+                let new_vote = NewVote {
+                    timestamp: now,
+                    snapshot: 0,
+                    committees: committees.into_iter().map(String::from).collect(),
+                    members: BTreeMap::new(),
+                    metadata: FipMetadata::default(),
+                };
+                store.create_vote(fip, testnet(), new_vote).await.unwrap();
+            }
+
+            // Nothing to vote with
+            let profile = store.voter_profile(voter, testnet(), 600).await.unwrap();
+            assert!(!profile.registration.registered);
+            assert_eq!(profile.power, "0");
+            assert!(!profile.starter);
+            assert!(profile.ballots.is_empty() && profile.awaiting.is_empty());
+
+            store
+                .set_committee(testnet(), "core-devs".to_string(), vec![voter])
+                .await
+                .unwrap();
+            let profile = store.voter_profile(voter, testnet(), 600).await.unwrap();
+            assert_eq!(profile.committees, vec!["core-devs"]);
+            assert_eq!(profile.awaiting, vec![7]);
+
+            store
+                .register_token_holder(voter, testnet(), "t1wallet".to_string())
+                .await
+                .unwrap();
+            store
+                .set_voter_worker(voter, testnet(), Some("t3worker".to_string()))
+                .await
+                .unwrap();
+            store
+                .record_ballot(5u32, testnet(), vote.clone(), Tally::default(), u64::MAX)
+                .await
+                .unwrap();
+
+            let profile = store.voter_profile(voter, testnet(), 600).await.unwrap();
+            assert!(profile.registration.registered);
+            assert_eq!(profile.registration.token_wallets, vec!["t1wallet"]);
+            assert_eq!(profile.registration.worker.as_deref(), Some("t3worker"));
+            assert_eq!(
+                profile.ballots,
+                vec![CastBallot {
+                    fip_number: 5,
+                    choice: VoteOption::Yay,
+                    status: FipStatus::InProgress,
+                }]
+            );
+            assert_eq!(profile.awaiting, vec![6, 7]);
+
+            store.unregister_voter(voter, testnet()).await.unwrap();
+            assert!(store
+                .voter_worker(voter, testnet())
+                .await
+                .unwrap()
+                .is_none());
+        }
+    }
+
+    fn dummy_result() -> FinalResult {
+        FinalResult {
+            constituencies: Vec::new(),
+            outcome: Outcome::NoVotes,
+            tally: Tally::default(),
+            ballot_hash: String::new(),
+            ballot_count: 0,
+            snapshot_epoch: None,
+            finalized_at: 0,
+        }
+    }
+
+    #[tokio::test]
+    async fn store_events() {
+        for store in stores() {
+            assert!(store.events(0, 10).await.unwrap().is_empty());
+
+            for action in [Action::StartVote, Action::Vote, Action::Vote] {
+                let mut event = AuditEvent::new(action, b"{}");
+                event.signer(voter());
+                event.network(testnet());
+                event.status = 200;
+                store.append_event(event).await.unwrap();
+            }
+
+            let events = store.events(0, 2).await.unwrap();
+            assert_eq!(events.iter().map(|e| e.id).collect::<Vec<_>>(), [1, 2]);
+            assert_eq!(events[0].action, Action::StartVote);
+            assert_eq!(events[0].signer, Some(voter()));
+            assert_eq!(events[0].network.as_deref(), Some("calibration"));
+
+            let events = store.events(2, 2).await.unwrap();
+            assert_eq!(events.len(), 1);
+            assert_eq!(events[0].id, 3);
+            assert!(store.events(3, 2).await.unwrap().is_empty());
+        }
+    }
+
+    #[tokio::test]
+    async fn store_used_messages() {
+        let mainnet = Network::from_name("mainnet").unwrap();
+        for store in stores() {
+            store.use_message(testnet(), [1; 32]).await.unwrap();
+            let res = store.use_message(testnet(), [1; 32]).await;
+            assert!(matches!(res, Err(StoreError::MessageReused)));
+            store.use_message(testnet(), [2; 32]).await.unwrap();
+            store.use_message(mainnet, [1; 32]).await.unwrap();
+        }
+    }
+
+    #[tokio::test]
+    async fn store_cursors() {
+        for store in stores() {
+            assert_eq!(store.cursor("announced:calibration").await.unwrap(), None);
+            store.set_cursor("announced:calibration", 5).await.unwrap();
+            store.set_cursor("announced:calibration", 7).await.unwrap();
+            store.set_cursor("announced:mainnet", 1).await.unwrap();
+            assert_eq!(
+                store.cursor("announced:calibration").await.unwrap(),
+                Some(7)
+            );
+            assert_eq!(store.cursor("announced:mainnet").await.unwrap(), Some(1));
+        }
+    }
+
+    #[tokio::test]
+    async fn store_dead_letters() {
+        for store in stores() {
+            assert!(store.dead_letters().await.unwrap().is_empty());
+            let letters: Vec<DeadLetter> = (1..=2)
+                .map(|i| DeadLetter {
+                    url: "http://localhost/hook".to_string(),
+                    network: "calibration".to_string(),
+                    event: "starter_added".to_string(),
+                    body: format!("{{\"timestamp\":{i}}}"),
+                    failed_at: 100 + i,
+                })
+                .collect();
+            for letter in &letters {
+                store.add_dead_letter(letter.clone()).await.unwrap();
+            }
+            assert_eq!(store.dead_letters().await.unwrap(), letters);
+        }
+    }
+
+    /// Fires ballots from many voters, and repeated ballots from one voter, at
+    /// the store at once and checks that every ballot is counted exactly once
+    pub(crate) async fn concurrent_ballots(store: Arc<dyn VoteStore>, fip_number: u32) {
+        let new_vote = NewVote {
+            timestamp: 0,
+            snapshot: 0,
+            committees: Vec::new(),
+            members: BTreeMap::new(),
+            metadata: FipMetadata::default(),
+        };
+        store
+            .create_vote(fip_number, testnet(), new_vote)
+            .await
+            .unwrap();
+
+        let mut weight = Tally::default();
+        weight.add_ballot(Constituency::StorageProviders, VoteOption::Yay);
+        weight.add_weight(Constituency::StorageProviders, VoteOption::Yay, 1024);
+
+        let mut handles = Vec::new();
+        for i in 0..64u64 {
+            // Every fourth ballot comes from the same voter
+            let address = match i % 4 {
+                0 => voter(),
+                _ => Address::from_low_u64_be(i + 1),
+            };
+            let vote = test_ballot(VoteOption::Yay, address, fip_number);
+            let (store, weight) = (store.clone(), weight.clone());
+            handles.push(tokio::spawn(async move {
+                store
+                    .record_ballot(fip_number, testnet(), vote, weight, u64::MAX)
+                    .await
+            }));
+        }
+
+        let mut recorded = 0;
+        for handle in handles {
+            match handle.await.unwrap() {
+                Ok(()) => recorded += 1,
+                Err(StoreError::DuplicateVote) => {}
+                Err(e) => panic!("{e}"),
+            }
+        }
+
+        // 48 distinct voters and one of the 16 repeated ballots
+        assert_eq!(recorded, 49);
+        assert_eq!(store.votes(fip_number, testnet()).await.unwrap().len(), 49);
+
+        let tally = store.tally(fip_number, testnet()).await.unwrap();
+        let results = tally.results(&GovernanceRules::default());
+        let storage = results
+            .constituencies
+            .iter()
+            .find(|c| c.constituency == Constituency::StorageProviders)
+            .unwrap();
+        assert_eq!(storage.yay, 49);
+        assert_eq!(
+            tally.weight(&Constituency::StorageProviders, VoteOption::Yay),
+            49 * 1024
+        );
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 8)]
+    async fn store_concurrent_ballots() {
+        for store in stores() {
+            concurrent_ballots(store, 7u32).await;
+        }
+    }
+
+    /// Revokes every starter at once, twice over, and checks that one
+    /// starter is always left
+    pub(crate) async fn concurrent_revocations(store: Arc<dyn VoteStore>) {
+        let starters = (1..=8).map(Address::from_low_u64_be).collect::<Vec<_>>();
+        store
+            .set_voter_starters(testnet(), starters.clone())
+            .await
+            .unwrap();
+
+        let mut handles = Vec::new();
+        for &starter in starters.iter().chain(&starters) {
+            let store = store.clone();
+            handles.push(tokio::spawn(async move {
+                store.revoke_voter_starter(starter, testnet()).await
+            }));
+        }
+
+        let mut revoked = 0;
+        for handle in handles {
+            match handle.await.unwrap() {
+                Ok(()) => revoked += 1,
+                Err(StoreError::UnknownStarter | StoreError::LastStarter) => {}
+                Err(e) => panic!("{e}"),
+            }
+        }
+
+        assert_eq!(revoked, 7);
+        assert_eq!(store.voter_starters(testnet()).await.unwrap().len(), 1);
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 8)]
+    async fn store_concurrent_revocations() {
+        for store in stores() {
+            concurrent_revocations(store).await;
+        }
+    }
+
+    /// Starts the same vote eight times at once, then eight different votes
+    /// at once, and checks that each FIP is started once and listed once
+    pub(crate) async fn concurrent_vote_starts(store: Arc<dyn VoteStore>) {
+        let start = |timestamp| NewVote {
+            timestamp,
+            snapshot: 0,
+            committees: Vec::new(),
+            members: BTreeMap::new(),
+            metadata: FipMetadata::default(),
+        };
+
+        let mut handles = Vec::new();
+        for i in 0..8 {
+            let store = store.clone();
+            handles.push(tokio::spawn(async move {
+                store.create_vote(1, testnet(), start(i)).await
+            }));
+        }
+        let mut started = 0;
+        for handle in handles {
+            match handle.await.unwrap() {
+                Ok(()) => started += 1,
+                Err(StoreError::VoteExists) => {}
+                Err(e) => panic!("{e}"),
+            }
+        }
+        assert_eq!(started, 1);
+
+        let mut handles = Vec::new();
+        for fip in 2..=9 {
+            let store = store.clone();
+            handles.push(tokio::spawn(async move {
+                store.create_vote(fip, testnet(), start(0)).await
+            }));
+        }
+        for handle in handles {
+            handle.await.unwrap().unwrap();
+        }
+
+        let mut votes = store.all_votes(testnet()).await.unwrap();
+        assert_eq!(votes[0], 1);
+        votes.sort();
+        assert_eq!(votes, (1..=9).collect::<Vec<_>>());
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 8)]
+    async fn store_concurrent_vote_starts() {
+        for store in stores() {
+            concurrent_vote_starts(store).await;
+        }
+    }
+
+    /// Sets eight committees at once and checks that none of them is lost
+    pub(crate) async fn concurrent_committees(store: Arc<dyn VoteStore>) {
+        let mut handles = Vec::new();
+        for i in 1..=8 {
+            let store = store.clone();
+            handles.push(tokio::spawn(async move {
+                let member = Address::from_low_u64_be(i);
+                store
+                    .set_committee(testnet(), format!("committee-{i}"), vec![member])
+                    .await
+            }));
+        }
+        for handle in handles {
+            handle.await.unwrap().unwrap();
+        }
+
+        let committees = store.committees(testnet()).await.unwrap();
+        assert_eq!(committees.len(), 8);
+        assert_eq!(committees["committee-3"], vec![Address::from_low_u64_be(3)]);
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 8)]
+    async fn store_concurrent_committees() {
+        for store in stores() {
+            concurrent_committees(store).await;
+        }
+    }
+
+    #[tokio::test]
+    async fn store_committee_vote() {
+        let member = LocalWallet::from_str(&"11".repeat(32)).unwrap();
+        let outsider = LocalWallet::from_str(&"22".repeat(32)).unwrap();
+
+        for store in stores() {
+            store
+                .init_voter_starters(testnet(), authorized_voters())
+                .await
+                .unwrap();
+            store
+                .set_committee(testnet(), "core-devs".to_string(), vec![member.address()])
+                .await
+                .unwrap();
+
+            let res = store
+                .start_vote(
+                    6u32,
+                    vote_starter(),
+                    testnet(),
+                    vec!["lotus".to_string()],
+                    FipMetadata::default(),
+                )
+                .await;
+            assert!(matches!(res, Err(StoreError::UnknownCommittee)));
+
+            let res = store
+                .start_vote(
+                    6u32,
+                    voter(),
+                    testnet(),
+                    vec!["core-devs".to_string()],
+                    FipMetadata::default(),
+                )
+                .await;
+            assert!(matches!(res, Err(StoreError::UnauthorizedStarter)));
+
+            store
+                .start_vote(
+                    6u32,
+                    vote_starter(),
+                    testnet(),
+                    vec!["core-devs".to_string()],
+                    FipMetadata::default(),
+                )
+                .await
+                .unwrap();
+
+            // Members are those of the committee when the vote started
+            store
+                .set_committee(testnet(), "core-devs".to_string(), vec![outsider.address()])
+                .await
+                .unwrap();
+
+            // A ballot signed for another FIP is not counted on this one
+            let (_, vote) = test_vote(VoteOption::Nay, 5u32).vote().unwrap();
+            let res = store.add_vote(6u32, vote, voter(), testnet(), 69u64).await;
+            assert!(matches!(res, Err(StoreError::FipMismatch)));
+
+            let vote = signed_vote(&member, VoteOption::Nay, 6u32).await;
+            store
+                .add_vote(6u32, vote, member.address(), testnet(), 69u64)
+                .await
+                .unwrap();
+
+            let results = store
+                .vote_results(6u32, testnet(), &GovernanceRules::default())
+                .await
+                .unwrap();
+
+            assert_eq!(results.outcome, Outcome::Rejected);
+
+            let vote = signed_vote(&outsider, VoteOption::Yay, 6u32).await;
+            let res = store
+                .add_vote(6u32, vote, outsider.address(), testnet(), 69u64)
+                .await;
+            assert!(matches!(res, Err(StoreError::NoVotingPower)));
+        }
+    }
+}
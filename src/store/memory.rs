@@ -0,0 +1,573 @@
+use std::{
+    collections::{BTreeMap, BTreeSet, HashMap, HashSet},
+    sync::{Mutex, MutexGuard},
+};
+
+use async_trait::async_trait;
+use ethers::types::Address;
+
+use crate::{
+    audit::AuditEvent,
+    messages::{vote_start::FipMetadata, votes::Vote},
+    results::Tally,
+    storage::Network,
+    webhooks::DeadLetter,
+};
+
+use super::{
+    past_deadline, Ballot, BuildResult, FinalResult, NewVote, StoreError, VoteStore, WalletKind,
+};
+
+/// Keeps everything in memory, used by tests and throwaway servers
+#[derive(Default)]
+pub struct Memory {
+    state: Mutex<State>,
+}
+
+#[derive(Default)]
+struct State {
+    delegates: HashMap<(&'static str, Address), Vec<u32>>,
+    workers: HashMap<(&'static str, Address), String>,
+    wallets: HashMap<(WalletKind, &'static str, Address), Vec<String>>,
+    wallet_owners: HashMap<(WalletKind, &'static str, String), Address>,
+    networks: HashMap<Address, Network>,
+    starters: HashMap<&'static str, Vec<Address>>,
+    committees: HashMap<&'static str, BTreeMap<String, Vec<Address>>>,
+    all_votes: HashMap<&'static str, Vec<u32>>,
+    votes: HashMap<(&'static str, u32), FipVote>,
+    events: Vec<AuditEvent>,
+    used_messages: HashSet<(&'static str, [u8; 32])>,
+    cursors: HashMap<String, u64>,
+    dead_letters: Vec<DeadLetter>,
+}
+
+/// A vote on a FIP and the ballots cast in it
+struct FipVote {
+    start: NewVote,
+    ballots: BTreeMap<Address, Ballot>,
+    tally: Tally,
+    result: Option<FinalResult>,
+}
+
+impl Memory {
+    fn state(&self) -> MutexGuard<'_, State> {
+        // A panic while holding the lock leaves plain data behind, so keep using it
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+#[async_trait]
+impl VoteStore for Memory {
+    async fn voter_delegates(&self, voter: Address, ntw: Network) -> Result<Vec<u32>, StoreError> {
+        let state = self.state();
+        Ok(state
+            .delegates
+            .get(&(ntw.name(), voter))
+            .cloned()
+            .unwrap_or_default())
+    }
+
+    async fn set_voter_delegates(
+        &self,
+        voter: Address,
+        ntw: Network,
+        sp_ids: Vec<u32>,
+    ) -> Result<(), StoreError> {
+        let mut state = self.state();
+        if sp_ids.is_empty() {
+            state.delegates.remove(&(ntw.name(), voter));
+        } else {
+            state.delegates.insert((ntw.name(), voter), sp_ids);
+        }
+        Ok(())
+    }
+
+    async fn voter_worker(
+        &self,
+        voter: Address,
+        ntw: Network,
+    ) -> Result<Option<String>, StoreError> {
+        Ok(self.state().workers.get(&(ntw.name(), voter)).cloned())
+    }
+
+    async fn set_voter_worker(
+        &self,
+        voter: Address,
+        ntw: Network,
+        worker: Option<String>,
+    ) -> Result<(), StoreError> {
+        let mut state = self.state();
+        match worker {
+            Some(worker) => state.workers.insert((ntw.name(), voter), worker),
+            None => state.workers.remove(&(ntw.name(), voter)),
+        };
+        Ok(())
+    }
+
+    async fn wallets(
+        &self,
+        kind: WalletKind,
+        voter: Address,
+        ntw: Network,
+    ) -> Result<Vec<String>, StoreError> {
+        let state = self.state();
+        Ok(state
+            .wallets
+            .get(&(kind, ntw.name(), voter))
+            .cloned()
+            .unwrap_or_default())
+    }
+
+    async fn set_wallets(
+        &self,
+        kind: WalletKind,
+        voter: Address,
+        ntw: Network,
+        wallets: Vec<String>,
+    ) -> Result<(), StoreError> {
+        let mut state = self.state();
+        if wallets.is_empty() {
+            state.wallets.remove(&(kind, ntw.name(), voter));
+        } else {
+            state.wallets.insert((kind, ntw.name(), voter), wallets);
+        }
+        Ok(())
+    }
+
+    async fn claim_wallet(
+        &self,
+        kind: WalletKind,
+        ntw: Network,
+        wallet: &str,
+        voter: Address,
+    ) -> Result<Address, StoreError> {
+        let mut state = self.state();
+        Ok(*state
+            .wallet_owners
+            .entry((kind, ntw.name(), wallet.to_string()))
+            .or_insert(voter))
+    }
+
+    async fn release_wallet(
+        &self,
+        kind: WalletKind,
+        ntw: Network,
+        wallet: &str,
+    ) -> Result<(), StoreError> {
+        let mut state = self.state();
+        state
+            .wallet_owners
+            .remove(&(kind, ntw.name(), wallet.to_string()));
+        Ok(())
+    }
+
+    async fn network(&self, voter: Address) -> Result<Option<Network>, StoreError> {
+        Ok(self.state().networks.get(&voter).copied())
+    }
+
+    async fn set_network(&self, voter: Address, ntw: Option<Network>) -> Result<(), StoreError> {
+        let mut state = self.state();
+        match ntw {
+            Some(ntw) => state.networks.insert(voter, ntw),
+            None => state.networks.remove(&voter),
+        };
+        Ok(())
+    }
+
+    async fn voters(&self, ntw: Network) -> Result<Vec<Address>, StoreError> {
+        let state = self.state();
+        let mut voters = BTreeSet::new();
+        voters.extend(
+            state
+                .delegates
+                .keys()
+                .filter(|(name, _)| *name == ntw.name())
+                .map(|(_, voter)| *voter),
+        );
+        voters.extend(
+            state
+                .wallets
+                .keys()
+                .filter(|(_, name, _)| *name == ntw.name())
+                .map(|(_, _, voter)| *voter),
+        );
+        voters.extend(
+            state
+                .networks
+                .iter()
+                .filter(|(_, n)| **n == ntw)
+                .map(|(voter, _)| *voter),
+        );
+        Ok(voters.into_iter().collect())
+    }
+
+    async fn voter_starters(&self, ntw: Network) -> Result<Vec<Address>, StoreError> {
+        let state = self.state();
+        Ok(state.starters.get(ntw.name()).cloned().unwrap_or_default())
+    }
+
+    async fn set_voter_starters(
+        &self,
+        ntw: Network,
+        starters: Vec<Address>,
+    ) -> Result<(), StoreError> {
+        self.state().starters.insert(ntw.name(), starters);
+        Ok(())
+    }
+
+    async fn init_voter_starters(
+        &self,
+        ntw: Network,
+        starters: Vec<Address>,
+    ) -> Result<bool, StoreError> {
+        let mut state = self.state();
+        if state.starters.contains_key(ntw.name()) {
+            return Ok(false);
+        }
+        state.starters.insert(ntw.name(), starters);
+        Ok(true)
+    }
+
+    async fn revoke_voter_starter(&self, starter: Address, ntw: Network) -> Result<(), StoreError> {
+        let mut state = self.state();
+        let starters = state.starters.entry(ntw.name()).or_default();
+        if !starters.contains(&starter) {
+            return Err(StoreError::UnknownStarter);
+        }
+        if starters.len() == 1 {
+            return Err(StoreError::LastStarter);
+        }
+        starters.retain(|&s| s != starter);
+        Ok(())
+    }
+
+    async fn committees(&self, ntw: Network) -> Result<BTreeMap<String, Vec<Address>>, StoreError> {
+        let state = self.state();
+        Ok(state
+            .committees
+            .get(ntw.name())
+            .cloned()
+            .unwrap_or_default())
+    }
+
+    async fn set_committees(
+        &self,
+        ntw: Network,
+        committees: BTreeMap<String, Vec<Address>>,
+    ) -> Result<(), StoreError> {
+        self.state().committees.insert(ntw.name(), committees);
+        Ok(())
+    }
+
+    async fn set_committee(
+        &self,
+        ntw: Network,
+        name: String,
+        members: Vec<Address>,
+    ) -> Result<(), StoreError> {
+        let mut state = self.state();
+        let committees = state.committees.entry(ntw.name()).or_default();
+        if members.is_empty() {
+            committees.remove(&name);
+        } else {
+            committees.insert(name, members);
+        }
+        Ok(())
+    }
+
+    async fn all_votes(&self, ntw: Network) -> Result<Vec<u32>, StoreError> {
+        let state = self.state();
+        Ok(state.all_votes.get(ntw.name()).cloned().unwrap_or_default())
+    }
+
+    async fn vote_start(&self, fip_number: u32, ntw: Network) -> Result<Option<u64>, StoreError> {
+        let state = self.state();
+        Ok(state
+            .votes
+            .get(&(ntw.name(), fip_number))
+            .map(|v| v.start.timestamp))
+    }
+
+    async fn snapshot_epoch(
+        &self,
+        fip_number: u32,
+        ntw: Network,
+    ) -> Result<Option<i64>, StoreError> {
+        let state = self.state();
+        Ok(state
+            .votes
+            .get(&(ntw.name(), fip_number))
+            .map(|v| v.start.snapshot))
+    }
+
+    async fn fip_committees(
+        &self,
+        fip_number: u32,
+        ntw: Network,
+    ) -> Result<Vec<String>, StoreError> {
+        let state = self.state();
+        Ok(state
+            .votes
+            .get(&(ntw.name(), fip_number))
+            .map(|v| v.start.committees.clone())
+            .unwrap_or_default())
+    }
+
+    async fn fip_committee_members(
+        &self,
+        fip_number: u32,
+        ntw: Network,
+    ) -> Result<BTreeMap<String, Vec<Address>>, StoreError> {
+        let state = self.state();
+        Ok(state
+            .votes
+            .get(&(ntw.name(), fip_number))
+            .map(|v| v.start.members.clone())
+            .unwrap_or_default())
+    }
+
+    async fn fip_metadata(&self, fip_number: u32, ntw: Network) -> Result<FipMetadata, StoreError> {
+        let state = self.state();
+        Ok(state
+            .votes
+            .get(&(ntw.name(), fip_number))
+            .map(|v| v.start.metadata.clone())
+            .unwrap_or_default())
+    }
+
+    async fn ballots(&self, fip_number: u32, ntw: Network) -> Result<Vec<Ballot>, StoreError> {
+        let state = self.state();
+        Ok(state
+            .votes
+            .get(&(ntw.name(), fip_number))
+            .map(|v| v.ballots.values().cloned().collect())
+            .unwrap_or_default())
+    }
+
+    async fn ballot(
+        &self,
+        fip_number: u32,
+        ntw: Network,
+        voter: Address,
+    ) -> Result<Option<Vote>, StoreError> {
+        let state = self.state();
+        Ok(state
+            .votes
+            .get(&(ntw.name(), fip_number))
+            .and_then(|v| v.ballots.get(&voter))
+            .map(|b| b.vote.clone()))
+    }
+
+    async fn ballot_count(&self, fip_number: u32, ntw: Network) -> Result<u64, StoreError> {
+        let state = self.state();
+        Ok(state
+            .votes
+            .get(&(ntw.name(), fip_number))
+            .map_or(0, |v| v.ballots.len() as u64))
+    }
+
+    async fn tally(&self, fip_number: u32, ntw: Network) -> Result<Tally, StoreError> {
+        let state = self.state();
+        Ok(state
+            .votes
+            .get(&(ntw.name(), fip_number))
+            .map(|v| v.tally.clone())
+            .unwrap_or_default())
+    }
+
+    async fn create_vote(
+        &self,
+        fip_number: u32,
+        ntw: Network,
+        vote: NewVote,
+    ) -> Result<(), StoreError> {
+        let mut state = self.state();
+        if state.votes.contains_key(&(ntw.name(), fip_number)) {
+            return Err(StoreError::VoteExists);
+        }
+
+        let all_votes = state.all_votes.entry(ntw.name()).or_default();
+        if !all_votes.contains(&fip_number) {
+            all_votes.push(fip_number);
+        }
+        state.votes.insert(
+            (ntw.name(), fip_number),
+            FipVote {
+                start: vote,
+                ballots: BTreeMap::new(),
+                tally: Tally::default(),
+                result: None,
+            },
+        );
+        Ok(())
+    }
+
+    async fn record_ballot(
+        &self,
+        fip_number: u32,
+        ntw: Network,
+        vote: Vote,
+        weight: Tally,
+        deadline: u64,
+    ) -> Result<(), StoreError> {
+        let mut state = self.state();
+        let fip_vote = state
+            .votes
+            .get_mut(&(ntw.name(), fip_number))
+            .ok_or(StoreError::VoteNotActive)?;
+
+        if fip_vote.result.is_some() {
+            return Err(StoreError::VoteFinalized);
+        }
+        if past_deadline(deadline) {
+            return Err(StoreError::VoteNotActive);
+        }
+        if fip_vote.ballots.contains_key(&vote.voter()) {
+            return Err(StoreError::DuplicateVote);
+        }
+
+        fip_vote.tally.merge(&weight);
+        fip_vote
+            .ballots
+            .insert(vote.voter(), Ballot { vote, weight });
+        Ok(())
+    }
+
+    async fn final_result(
+        &self,
+        fip_number: u32,
+        ntw: Network,
+    ) -> Result<Option<FinalResult>, StoreError> {
+        let state = self.state();
+        Ok(state
+            .votes
+            .get(&(ntw.name(), fip_number))
+            .and_then(|v| v.result.clone()))
+    }
+
+    async fn finalize_vote(
+        &self,
+        fip_number: u32,
+        ntw: Network,
+        build: &BuildResult<'_>,
+    ) -> Result<FinalResult, StoreError> {
+        let mut state = self.state();
+        let fip_vote = state
+            .votes
+            .get_mut(&(ntw.name(), fip_number))
+            .ok_or(StoreError::VoteNotActive)?;
+
+        if let Some(result) = &fip_vote.result {
+            return Ok(result.clone());
+        }
+        let ballots = fip_vote.ballots.values().cloned().collect();
+        let result = build(ballots, fip_vote.tally.clone())?;
+
+        Ok(fip_vote.result.insert(result).clone())
+    }
+
+    async fn set_final_result(
+        &self,
+        fip_number: u32,
+        ntw: Network,
+        result: FinalResult,
+    ) -> Result<FinalResult, StoreError> {
+        let mut state = self.state();
+        let fip_vote = state
+            .votes
+            .get_mut(&(ntw.name(), fip_number))
+            .ok_or(StoreError::VoteNotActive)?;
+
+        Ok(fip_vote.result.get_or_insert(result).clone())
+    }
+
+    async fn append_event(&self, mut event: AuditEvent) -> Result<u64, StoreError> {
+        let mut state = self.state();
+        event.id = state.events.len() as u64 + 1;
+        state.events.push(event);
+        Ok(state.events.len() as u64)
+    }
+
+    async fn events(&self, after: u64, limit: usize) -> Result<Vec<AuditEvent>, StoreError> {
+        let state = self.state();
+        Ok(state
+            .events
+            .iter()
+            .skip(after as usize)
+            .take(limit)
+            .cloned()
+            .collect())
+    }
+
+    async fn use_message(&self, ntw: Network, id: [u8; 32]) -> Result<(), StoreError> {
+        if !self.state().used_messages.insert((ntw.name(), id)) {
+            return Err(StoreError::MessageReused);
+        }
+        Ok(())
+    }
+
+    async fn cursor(&self, name: &str) -> Result<Option<u64>, StoreError> {
+        Ok(self.state().cursors.get(name).copied())
+    }
+
+    async fn set_cursor(&self, name: &str, position: u64) -> Result<(), StoreError> {
+        self.state().cursors.insert(name.to_string(), position);
+        Ok(())
+    }
+
+    async fn add_dead_letter(&self, letter: DeadLetter) -> Result<(), StoreError> {
+        self.state().dead_letters.push(letter);
+        Ok(())
+    }
+
+    async fn dead_letters(&self) -> Result<Vec<DeadLetter>, StoreError> {
+        Ok(self.state().dead_letters.clone())
+    }
+
+    async fn import_vote(
+        &self,
+        fip_number: u32,
+        ntw: Network,
+        vote: NewVote,
+        ballots: Vec<Ballot>,
+        tally: Tally,
+    ) -> Result<(), StoreError> {
+        let mut state = self.state();
+        if state.votes.contains_key(&(ntw.name(), fip_number)) {
+            return Err(StoreError::VoteExists);
+        }
+
+        let all_votes = state.all_votes.entry(ntw.name()).or_default();
+        if !all_votes.contains(&fip_number) {
+            all_votes.push(fip_number);
+        }
+        state.votes.insert(
+            (ntw.name(), fip_number),
+            FipVote {
+                start: vote,
+                ballots: ballots.into_iter().map(|b| (b.vote.voter(), b)).collect(),
+                tally,
+                result: None,
+            },
+        );
+        Ok(())
+    }
+
+    async fn flush_vote(&self, fip_number: u32, ntw: Network) -> Result<(), StoreError> {
+        let mut state = self.state();
+        let key = (ntw.name(), fip_number);
+        if state.votes.get(&key).is_some_and(|v| v.result.is_some()) {
+            return Err(StoreError::VoteFinalized);
+        }
+        state.votes.remove(&key);
+        if let Some(votes) = state.all_votes.get_mut(ntw.name()) {
+            votes.retain(|&fip| fip != fip_number);
+        }
+        Ok(())
+    }
+
+    async fn flush_all(&self) -> Result<(), StoreError> {
+        *self.state() = State::default();
+        Ok(())
+    }
+}
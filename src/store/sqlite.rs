@@ -0,0 +1,1085 @@
+use std::{
+    collections::BTreeMap,
+    path::Path,
+    sync::{Mutex, MutexGuard},
+};
+
+use async_trait::async_trait;
+use ethers::types::Address;
+use rusqlite::{
+    params, Connection, ErrorCode, OptionalExtension, Transaction, TransactionBehavior,
+};
+
+use crate::{
+    audit::AuditEvent,
+    messages::{vote_start::FipMetadata, votes::Vote},
+    results::Tally,
+    storage::Network,
+    webhooks::DeadLetter,
+};
+
+use super::{
+    past_deadline, Ballot, BuildResult, FinalResult, NewVote, StoreError, VoteStore, WalletKind,
+};
+
+const SCHEMA: &str = "
+    CREATE TABLE IF NOT EXISTS delegates (
+        network TEXT NOT NULL,
+        address BLOB NOT NULL,
+        sp_id INTEGER NOT NULL,
+        PRIMARY KEY (network, address, sp_id)
+    );
+    CREATE TABLE IF NOT EXISTS workers (
+        network TEXT NOT NULL,
+        address BLOB NOT NULL,
+        worker TEXT NOT NULL,
+        PRIMARY KEY (network, address)
+    );
+    CREATE TABLE IF NOT EXISTS wallets (
+        network TEXT NOT NULL,
+        address BLOB NOT NULL,
+        kind INTEGER NOT NULL,
+        wallet TEXT NOT NULL,
+        PRIMARY KEY (network, address, kind, wallet)
+    );
+    CREATE TABLE IF NOT EXISTS wallet_owners (
+        network TEXT NOT NULL,
+        kind INTEGER NOT NULL,
+        wallet TEXT NOT NULL,
+        address BLOB NOT NULL,
+        PRIMARY KEY (network, kind, wallet)
+    );
+    CREATE TABLE IF NOT EXISTS voter_networks (
+        address BLOB PRIMARY KEY,
+        network TEXT NOT NULL
+    );
+    CREATE TABLE IF NOT EXISTS starters (
+        network TEXT NOT NULL,
+        address BLOB NOT NULL,
+        PRIMARY KEY (network, address)
+    );
+    CREATE TABLE IF NOT EXISTS starter_networks (
+        network TEXT PRIMARY KEY
+    );
+    CREATE TABLE IF NOT EXISTS committee_members (
+        network TEXT NOT NULL,
+        committee TEXT NOT NULL,
+        address BLOB NOT NULL,
+        PRIMARY KEY (network, committee, address)
+    );
+    CREATE TABLE IF NOT EXISTS votes (
+        network TEXT NOT NULL,
+        fip INTEGER NOT NULL,
+        started INTEGER NOT NULL,
+        snapshot INTEGER NOT NULL,
+        committees TEXT NOT NULL,
+        tally TEXT NOT NULL,
+        PRIMARY KEY (network, fip)
+    );
+    CREATE TABLE IF NOT EXISTS fip_metadata (
+        network TEXT NOT NULL,
+        fip INTEGER NOT NULL,
+        metadata TEXT NOT NULL,
+        PRIMARY KEY (network, fip)
+    );
+    CREATE TABLE IF NOT EXISTS fip_committee_members (
+        network TEXT NOT NULL,
+        fip INTEGER NOT NULL,
+        members TEXT NOT NULL,
+        PRIMARY KEY (network, fip)
+    );
+    CREATE TABLE IF NOT EXISTS ballots (
+        network TEXT NOT NULL,
+        fip INTEGER NOT NULL,
+        address BLOB NOT NULL,
+        vote TEXT NOT NULL,
+        PRIMARY KEY (network, fip, address)
+    );
+    CREATE TABLE IF NOT EXISTS results (
+        network TEXT NOT NULL,
+        fip INTEGER NOT NULL,
+        result TEXT NOT NULL,
+        PRIMARY KEY (network, fip)
+    );
+    CREATE TABLE IF NOT EXISTS events (
+        id INTEGER PRIMARY KEY AUTOINCREMENT,
+        event TEXT NOT NULL
+    );
+    CREATE TABLE IF NOT EXISTS used_messages (
+        network TEXT NOT NULL,
+        id BLOB NOT NULL,
+        PRIMARY KEY (network, id)
+    );
+    CREATE TABLE IF NOT EXISTS cursors (
+        name TEXT PRIMARY KEY,
+        position INTEGER NOT NULL
+    );
+    CREATE TABLE IF NOT EXISTS dead_letters (
+        id INTEGER PRIMARY KEY AUTOINCREMENT,
+        letter TEXT NOT NULL
+    );
+";
+
+/// Relational storage in a SQLite database
+///
+/// Starting a vote and recording a ballot each run in a single transaction
+pub struct Sqlite {
+    con: Mutex<Connection>,
+}
+
+impl Sqlite {
+    /// Opens the database at the path, creating it and its tables if needed
+    pub fn open(path: impl AsRef<Path>) -> Result<Sqlite, StoreError> {
+        Self::init(Connection::open(path)?)
+    }
+
+    pub fn open_in_memory() -> Result<Sqlite, StoreError> {
+        Self::init(Connection::open_in_memory()?)
+    }
+
+    fn init(con: Connection) -> Result<Sqlite, StoreError> {
+        con.execute_batch(SCHEMA)?;
+
+        Ok(Self {
+            con: Mutex::new(con),
+        })
+    }
+
+    fn con(&self) -> MutexGuard<'_, Connection> {
+        self.con.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Addresses from the query, which selects a single address column
+    fn addresses(
+        &self,
+        sql: &str,
+        params: impl rusqlite::Params,
+    ) -> Result<Vec<Address>, StoreError> {
+        let con = self.con();
+        let mut stmt = con.prepare(sql)?;
+        let rows = stmt.query_map(params, |row| row.get::<_, Vec<u8>>(0))?;
+
+        let mut addresses = Vec::new();
+        for bytes in rows {
+            addresses.push(to_address(&bytes?)?);
+        }
+        Ok(addresses)
+    }
+}
+
+fn to_address(bytes: &[u8]) -> Result<Address, StoreError> {
+    if bytes.len() != 20 {
+        return Err(StoreError::Corrupt("invalid address length"));
+    }
+    Ok(Address::from_slice(bytes))
+}
+
+/// Inserts the vote and its metadata, failing with `VoteExists` if the FIP
+/// was already voted on
+fn insert_vote(
+    tx: &Transaction,
+    fip_number: u32,
+    ntw: Network,
+    vote: &NewVote,
+    tally: &Tally,
+) -> Result<(), StoreError> {
+    let res = tx.execute(
+        "INSERT INTO votes (network, fip, started, snapshot, committees, tally)
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+        params![
+            ntw.name(),
+            fip_number,
+            vote.timestamp as i64,
+            vote.snapshot,
+            serde_json::to_string(&vote.committees)?,
+            serde_json::to_string(tally)?,
+        ],
+    );
+    match res {
+        Ok(_) => (),
+        Err(e) if e.sqlite_error_code() == Some(ErrorCode::ConstraintViolation) => {
+            return Err(StoreError::VoteExists)
+        }
+        Err(e) => return Err(e.into()),
+    }
+
+    if !vote.metadata.is_empty() {
+        tx.execute(
+            "INSERT INTO fip_metadata (network, fip, metadata) VALUES (?1, ?2, ?3)",
+            params![
+                ntw.name(),
+                fip_number,
+                serde_json::to_string(&vote.metadata)?
+            ],
+        )?;
+    }
+    if !vote.members.is_empty() {
+        tx.execute(
+            "INSERT INTO fip_committee_members (network, fip, members) VALUES (?1, ?2, ?3)",
+            params![
+                ntw.name(),
+                fip_number,
+                serde_json::to_string(&vote.members)?
+            ],
+        )?;
+    }
+    Ok(())
+}
+
+fn kind_id(kind: WalletKind) -> i64 {
+    match kind {
+        WalletKind::Token => 0,
+        WalletKind::Client => 1,
+    }
+}
+
+/// Adds the starters to the network and records that its starters were set
+fn insert_starters(
+    tx: &Transaction,
+    ntw: Network,
+    starters: Vec<Address>,
+) -> Result<(), StoreError> {
+    tx.execute(
+        "INSERT OR IGNORE INTO starter_networks (network) VALUES (?1)",
+        params![ntw.name()],
+    )?;
+    for starter in starters {
+        tx.execute(
+            "INSERT OR IGNORE INTO starters (network, address) VALUES (?1, ?2)",
+            params![ntw.name(), starter.as_bytes()],
+        )?;
+    }
+    Ok(())
+}
+
+#[async_trait]
+impl VoteStore for Sqlite {
+    async fn voter_delegates(&self, voter: Address, ntw: Network) -> Result<Vec<u32>, StoreError> {
+        let con = self.con();
+        let mut stmt = con.prepare(
+            "SELECT sp_id FROM delegates WHERE network = ?1 AND address = ?2 ORDER BY rowid",
+        )?;
+        let sp_ids = stmt
+            .query_map(params![ntw.name(), voter.as_bytes()], |row| row.get(0))?
+            .collect::<Result<Vec<u32>, _>>()?;
+        Ok(sp_ids)
+    }
+
+    async fn set_voter_delegates(
+        &self,
+        voter: Address,
+        ntw: Network,
+        sp_ids: Vec<u32>,
+    ) -> Result<(), StoreError> {
+        let mut con = self.con();
+        let tx = con.transaction()?;
+        tx.execute(
+            "DELETE FROM delegates WHERE network = ?1 AND address = ?2",
+            params![ntw.name(), voter.as_bytes()],
+        )?;
+        for sp_id in sp_ids {
+            tx.execute(
+                "INSERT OR IGNORE INTO delegates (network, address, sp_id) VALUES (?1, ?2, ?3)",
+                params![ntw.name(), voter.as_bytes(), sp_id],
+            )?;
+        }
+        tx.commit()?;
+        Ok(())
+    }
+
+    async fn voter_worker(
+        &self,
+        voter: Address,
+        ntw: Network,
+    ) -> Result<Option<String>, StoreError> {
+        let worker = self
+            .con()
+            .query_row(
+                "SELECT worker FROM workers WHERE network = ?1 AND address = ?2",
+                params![ntw.name(), voter.as_bytes()],
+                |row| row.get(0),
+            )
+            .optional()?;
+        Ok(worker)
+    }
+
+    async fn set_voter_worker(
+        &self,
+        voter: Address,
+        ntw: Network,
+        worker: Option<String>,
+    ) -> Result<(), StoreError> {
+        let con = self.con();
+        match worker {
+            Some(worker) => con.execute(
+                "INSERT OR REPLACE INTO workers (network, address, worker) VALUES (?1, ?2, ?3)",
+                params![ntw.name(), voter.as_bytes(), worker],
+            )?,
+            None => con.execute(
+                "DELETE FROM workers WHERE network = ?1 AND address = ?2",
+                params![ntw.name(), voter.as_bytes()],
+            )?,
+        };
+        Ok(())
+    }
+
+    async fn wallets(
+        &self,
+        kind: WalletKind,
+        voter: Address,
+        ntw: Network,
+    ) -> Result<Vec<String>, StoreError> {
+        let con = self.con();
+        let mut stmt = con.prepare(
+            "SELECT wallet FROM wallets
+             WHERE network = ?1 AND address = ?2 AND kind = ?3 ORDER BY rowid",
+        )?;
+        let wallets = stmt
+            .query_map(
+                params![ntw.name(), voter.as_bytes(), kind_id(kind)],
+                |row| row.get(0),
+            )?
+            .collect::<Result<Vec<String>, _>>()?;
+        Ok(wallets)
+    }
+
+    async fn set_wallets(
+        &self,
+        kind: WalletKind,
+        voter: Address,
+        ntw: Network,
+        wallets: Vec<String>,
+    ) -> Result<(), StoreError> {
+        let mut con = self.con();
+        let tx = con.transaction()?;
+        tx.execute(
+            "DELETE FROM wallets WHERE network = ?1 AND address = ?2 AND kind = ?3",
+            params![ntw.name(), voter.as_bytes(), kind_id(kind)],
+        )?;
+        for wallet in wallets {
+            tx.execute(
+                "INSERT OR IGNORE INTO wallets (network, address, kind, wallet)
+                 VALUES (?1, ?2, ?3, ?4)",
+                params![ntw.name(), voter.as_bytes(), kind_id(kind), wallet],
+            )?;
+        }
+        tx.commit()?;
+        Ok(())
+    }
+
+    async fn claim_wallet(
+        &self,
+        kind: WalletKind,
+        ntw: Network,
+        wallet: &str,
+        voter: Address,
+    ) -> Result<Address, StoreError> {
+        let mut con = self.con();
+        let tx = con.transaction()?;
+        tx.execute(
+            "INSERT OR IGNORE INTO wallet_owners (network, kind, wallet, address)
+             VALUES (?1, ?2, ?3, ?4)",
+            params![ntw.name(), kind_id(kind), wallet, voter.as_bytes()],
+        )?;
+        let owner: Vec<u8> = tx.query_row(
+            "SELECT address FROM wallet_owners WHERE network = ?1 AND kind = ?2 AND wallet = ?3",
+            params![ntw.name(), kind_id(kind), wallet],
+            |row| row.get(0),
+        )?;
+        tx.commit()?;
+        to_address(&owner)
+    }
+
+    async fn release_wallet(
+        &self,
+        kind: WalletKind,
+        ntw: Network,
+        wallet: &str,
+    ) -> Result<(), StoreError> {
+        self.con().execute(
+            "DELETE FROM wallet_owners WHERE network = ?1 AND kind = ?2 AND wallet = ?3",
+            params![ntw.name(), kind_id(kind), wallet],
+        )?;
+        Ok(())
+    }
+
+    async fn network(&self, voter: Address) -> Result<Option<Network>, StoreError> {
+        let name: Option<String> = self
+            .con()
+            .query_row(
+                "SELECT network FROM voter_networks WHERE address = ?1",
+                params![voter.as_bytes()],
+                |row| row.get(0),
+            )
+            .optional()?;
+
+        match name {
+            Some(name) => Network::from_name(&name)
+                .map(Some)
+                .ok_or(StoreError::Corrupt("unknown network")),
+            None => Ok(None),
+        }
+    }
+
+    async fn set_network(&self, voter: Address, ntw: Option<Network>) -> Result<(), StoreError> {
+        let con = self.con();
+        match ntw {
+            Some(ntw) => con.execute(
+                "INSERT OR REPLACE INTO voter_networks (address, network) VALUES (?1, ?2)",
+                params![voter.as_bytes(), ntw.name()],
+            )?,
+            None => con.execute(
+                "DELETE FROM voter_networks WHERE address = ?1",
+                params![voter.as_bytes()],
+            )?,
+        };
+        Ok(())
+    }
+
+    async fn voters(&self, ntw: Network) -> Result<Vec<Address>, StoreError> {
+        let con = self.con();
+        let mut stmt = con.prepare(
+            "SELECT address FROM delegates WHERE network = ?1
+             UNION SELECT address FROM wallets WHERE network = ?1
+             UNION SELECT address FROM voter_networks WHERE network = ?1",
+        )?;
+        let rows = stmt.query_map(params![ntw.name()], |row| row.get::<_, Vec<u8>>(0))?;
+
+        let mut voters = Vec::new();
+        for voter in rows {
+            voters.push(Address::from_slice(&voter?));
+        }
+        Ok(voters)
+    }
+
+    async fn voter_starters(&self, ntw: Network) -> Result<Vec<Address>, StoreError> {
+        self.addresses(
+            "SELECT address FROM starters WHERE network = ?1 ORDER BY address",
+            params![ntw.name()],
+        )
+    }
+
+    async fn set_voter_starters(
+        &self,
+        ntw: Network,
+        starters: Vec<Address>,
+    ) -> Result<(), StoreError> {
+        let mut con = self.con();
+        let tx = con.transaction()?;
+        tx.execute(
+            "DELETE FROM starters WHERE network = ?1",
+            params![ntw.name()],
+        )?;
+        insert_starters(&tx, ntw, starters)?;
+        tx.commit()?;
+        Ok(())
+    }
+
+    async fn init_voter_starters(
+        &self,
+        ntw: Network,
+        starters: Vec<Address>,
+    ) -> Result<bool, StoreError> {
+        let mut con = self.con();
+        let tx = con.transaction_with_behavior(TransactionBehavior::Immediate)?;
+
+        // Databases from before starter_networks have starters but no row there
+        let set: bool = tx.query_row(
+            "SELECT EXISTS (SELECT 1 FROM starter_networks WHERE network = ?1)
+                 OR EXISTS (SELECT 1 FROM starters WHERE network = ?1)",
+            params![ntw.name()],
+            |row| row.get(0),
+        )?;
+        if set {
+            return Ok(false);
+        }
+
+        insert_starters(&tx, ntw, starters)?;
+        tx.commit()?;
+        Ok(true)
+    }
+
+    async fn revoke_voter_starter(&self, starter: Address, ntw: Network) -> Result<(), StoreError> {
+        let mut con = self.con();
+        let tx = con.transaction_with_behavior(TransactionBehavior::Immediate)?;
+
+        let count: u64 = tx.query_row(
+            "SELECT COUNT(*) FROM starters WHERE network = ?1",
+            params![ntw.name()],
+            |row| row.get(0),
+        )?;
+        let removed = tx.execute(
+            "DELETE FROM starters WHERE network = ?1 AND address = ?2",
+            params![ntw.name(), starter.as_bytes()],
+        )?;
+        if removed == 0 {
+            return Err(StoreError::UnknownStarter);
+        }
+        if count == 1 {
+            return Err(StoreError::LastStarter);
+        }
+
+        tx.commit()?;
+        Ok(())
+    }
+
+    async fn committees(&self, ntw: Network) -> Result<BTreeMap<String, Vec<Address>>, StoreError> {
+        let con = self.con();
+        let mut stmt = con.prepare(
+            "SELECT committee, address FROM committee_members WHERE network = ?1 ORDER BY rowid",
+        )?;
+        let rows = stmt.query_map(params![ntw.name()], |row| {
+            Ok((row.get::<_, String>(0)?, row.get::<_, Vec<u8>>(1)?))
+        })?;
+
+        let mut committees: BTreeMap<String, Vec<Address>> = BTreeMap::new();
+        for row in rows {
+            let (name, member) = row?;
+            committees
+                .entry(name)
+                .or_default()
+                .push(to_address(&member)?);
+        }
+        Ok(committees)
+    }
+
+    async fn set_committees(
+        &self,
+        ntw: Network,
+        committees: BTreeMap<String, Vec<Address>>,
+    ) -> Result<(), StoreError> {
+        let mut con = self.con();
+        let tx = con.transaction()?;
+        tx.execute(
+            "DELETE FROM committee_members WHERE network = ?1",
+            params![ntw.name()],
+        )?;
+        for (name, members) in committees {
+            for member in members {
+                tx.execute(
+                    "INSERT OR IGNORE INTO committee_members (network, committee, address)
+                     VALUES (?1, ?2, ?3)",
+                    params![ntw.name(), name, member.as_bytes()],
+                )?;
+            }
+        }
+        tx.commit()?;
+        Ok(())
+    }
+
+    async fn set_committee(
+        &self,
+        ntw: Network,
+        name: String,
+        members: Vec<Address>,
+    ) -> Result<(), StoreError> {
+        let mut con = self.con();
+        let tx = con.transaction()?;
+        tx.execute(
+            "DELETE FROM committee_members WHERE network = ?1 AND committee = ?2",
+            params![ntw.name(), name],
+        )?;
+        for member in members {
+            tx.execute(
+                "INSERT OR IGNORE INTO committee_members (network, committee, address)
+                 VALUES (?1, ?2, ?3)",
+                params![ntw.name(), name, member.as_bytes()],
+            )?;
+        }
+        tx.commit()?;
+        Ok(())
+    }
+
+    async fn all_votes(&self, ntw: Network) -> Result<Vec<u32>, StoreError> {
+        let con = self.con();
+        let mut stmt = con.prepare("SELECT fip FROM votes WHERE network = ?1 ORDER BY rowid")?;
+        let votes = stmt
+            .query_map(params![ntw.name()], |row| row.get(0))?
+            .collect::<Result<Vec<u32>, _>>()?;
+        Ok(votes)
+    }
+
+    async fn vote_start(&self, fip_number: u32, ntw: Network) -> Result<Option<u64>, StoreError> {
+        let started: Option<i64> = self
+            .con()
+            .query_row(
+                "SELECT started FROM votes WHERE network = ?1 AND fip = ?2",
+                params![ntw.name(), fip_number],
+                |row| row.get(0),
+            )
+            .optional()?;
+        Ok(started.map(|s| s as u64))
+    }
+
+    async fn snapshot_epoch(
+        &self,
+        fip_number: u32,
+        ntw: Network,
+    ) -> Result<Option<i64>, StoreError> {
+        let snapshot = self
+            .con()
+            .query_row(
+                "SELECT snapshot FROM votes WHERE network = ?1 AND fip = ?2",
+                params![ntw.name(), fip_number],
+                |row| row.get(0),
+            )
+            .optional()?;
+        Ok(snapshot)
+    }
+
+    async fn fip_committees(
+        &self,
+        fip_number: u32,
+        ntw: Network,
+    ) -> Result<Vec<String>, StoreError> {
+        let committees: Option<String> = self
+            .con()
+            .query_row(
+                "SELECT committees FROM votes WHERE network = ?1 AND fip = ?2",
+                params![ntw.name(), fip_number],
+                |row| row.get(0),
+            )
+            .optional()?;
+
+        match committees {
+            Some(c) => Ok(serde_json::from_str(&c)?),
+            None => Ok(Vec::new()),
+        }
+    }
+
+    async fn fip_committee_members(
+        &self,
+        fip_number: u32,
+        ntw: Network,
+    ) -> Result<BTreeMap<String, Vec<Address>>, StoreError> {
+        let members: Option<String> = self
+            .con()
+            .query_row(
+                "SELECT members FROM fip_committee_members WHERE network = ?1 AND fip = ?2",
+                params![ntw.name(), fip_number],
+                |row| row.get(0),
+            )
+            .optional()?;
+
+        match members {
+            Some(m) => Ok(serde_json::from_str(&m)?),
+            None => Ok(BTreeMap::new()),
+        }
+    }
+
+    async fn fip_metadata(&self, fip_number: u32, ntw: Network) -> Result<FipMetadata, StoreError> {
+        let metadata: Option<String> = self
+            .con()
+            .query_row(
+                "SELECT metadata FROM fip_metadata WHERE network = ?1 AND fip = ?2",
+                params![ntw.name(), fip_number],
+                |row| row.get(0),
+            )
+            .optional()?;
+
+        match metadata {
+            Some(m) => Ok(serde_json::from_str(&m)?),
+            None => Ok(FipMetadata::default()),
+        }
+    }
+
+    async fn ballots(&self, fip_number: u32, ntw: Network) -> Result<Vec<Ballot>, StoreError> {
+        let con = self.con();
+        let mut stmt =
+            con.prepare("SELECT vote FROM ballots WHERE network = ?1 AND fip = ?2 ORDER BY rowid")?;
+        let rows = stmt.query_map(params![ntw.name(), fip_number], |row| {
+            row.get::<_, String>(0)
+        })?;
+
+        let mut ballots = Vec::new();
+        for ballot in rows {
+            ballots.push(serde_json::from_str(&ballot?)?);
+        }
+        Ok(ballots)
+    }
+
+    async fn ballot(
+        &self,
+        fip_number: u32,
+        ntw: Network,
+        voter: Address,
+    ) -> Result<Option<Vote>, StoreError> {
+        let vote: Option<String> = self
+            .con()
+            .query_row(
+                "SELECT vote FROM ballots WHERE network = ?1 AND fip = ?2 AND address = ?3",
+                params![ntw.name(), fip_number, voter.as_bytes()],
+                |row| row.get(0),
+            )
+            .optional()?;
+
+        match vote {
+            Some(v) => Ok(Some(serde_json::from_str::<Ballot>(&v)?.vote)),
+            None => Ok(None),
+        }
+    }
+
+    async fn ballot_count(&self, fip_number: u32, ntw: Network) -> Result<u64, StoreError> {
+        let count: u64 = self.con().query_row(
+            "SELECT COUNT(*) FROM ballots WHERE network = ?1 AND fip = ?2",
+            params![ntw.name(), fip_number],
+            |row| row.get(0),
+        )?;
+        Ok(count)
+    }
+
+    async fn tally(&self, fip_number: u32, ntw: Network) -> Result<Tally, StoreError> {
+        let tally: Option<String> = self
+            .con()
+            .query_row(
+                "SELECT tally FROM votes WHERE network = ?1 AND fip = ?2",
+                params![ntw.name(), fip_number],
+                |row| row.get(0),
+            )
+            .optional()?;
+
+        match tally {
+            Some(t) => Ok(serde_json::from_str(&t)?),
+            None => Ok(Tally::default()),
+        }
+    }
+
+    async fn create_vote(
+        &self,
+        fip_number: u32,
+        ntw: Network,
+        vote: NewVote,
+    ) -> Result<(), StoreError> {
+        let mut con = self.con();
+        let tx = con.transaction_with_behavior(TransactionBehavior::Immediate)?;
+        insert_vote(&tx, fip_number, ntw, &vote, &Tally::default())?;
+        tx.commit()?;
+        Ok(())
+    }
+
+    async fn record_ballot(
+        &self,
+        fip_number: u32,
+        ntw: Network,
+        vote: Vote,
+        weight: Tally,
+        deadline: u64,
+    ) -> Result<(), StoreError> {
+        let mut con = self.con();
+        let tx = con.transaction_with_behavior(TransactionBehavior::Immediate)?;
+
+        let tally: Option<String> = tx
+            .query_row(
+                "SELECT tally FROM votes WHERE network = ?1 AND fip = ?2",
+                params![ntw.name(), fip_number],
+                |row| row.get(0),
+            )
+            .optional()?;
+        let mut tally: Tally = match tally {
+            Some(t) => serde_json::from_str(&t)?,
+            None => return Err(StoreError::VoteNotActive),
+        };
+
+        let finalized: Option<i64> = tx
+            .query_row(
+                "SELECT 1 FROM results WHERE network = ?1 AND fip = ?2",
+                params![ntw.name(), fip_number],
+                |row| row.get(0),
+            )
+            .optional()?;
+        if finalized.is_some() {
+            return Err(StoreError::VoteFinalized);
+        }
+        if past_deadline(deadline) {
+            return Err(StoreError::VoteNotActive);
+        }
+
+        tally.merge(&weight);
+        let voter = vote.voter();
+        let ballot = Ballot { vote, weight };
+
+        let res = tx.execute(
+            "INSERT INTO ballots (network, fip, address, vote) VALUES (?1, ?2, ?3, ?4)",
+            params![
+                ntw.name(),
+                fip_number,
+                voter.as_bytes(),
+                serde_json::to_string(&ballot)?,
+            ],
+        );
+        match res {
+            Ok(_) => (),
+            Err(e) if e.sqlite_error_code() == Some(ErrorCode::ConstraintViolation) => {
+                return Err(StoreError::DuplicateVote)
+            }
+            Err(e) => return Err(e.into()),
+        }
+
+        tx.execute(
+            "UPDATE votes SET tally = ?3 WHERE network = ?1 AND fip = ?2",
+            params![ntw.name(), fip_number, serde_json::to_string(&tally)?],
+        )?;
+
+        tx.commit()?;
+        Ok(())
+    }
+
+    async fn final_result(
+        &self,
+        fip_number: u32,
+        ntw: Network,
+    ) -> Result<Option<FinalResult>, StoreError> {
+        let result: Option<String> = self
+            .con()
+            .query_row(
+                "SELECT result FROM results WHERE network = ?1 AND fip = ?2",
+                params![ntw.name(), fip_number],
+                |row| row.get(0),
+            )
+            .optional()?;
+
+        match result {
+            Some(r) => Ok(Some(serde_json::from_str(&r)?)),
+            None => Ok(None),
+        }
+    }
+
+    async fn finalize_vote(
+        &self,
+        fip_number: u32,
+        ntw: Network,
+        build: &BuildResult<'_>,
+    ) -> Result<FinalResult, StoreError> {
+        let mut con = self.con();
+        let tx = con.transaction_with_behavior(TransactionBehavior::Immediate)?;
+
+        let kept: Option<String> = tx
+            .query_row(
+                "SELECT result FROM results WHERE network = ?1 AND fip = ?2",
+                params![ntw.name(), fip_number],
+                |row| row.get(0),
+            )
+            .optional()?;
+        if let Some(r) = kept {
+            return Ok(serde_json::from_str(&r)?);
+        }
+
+        let tally: Tally = match tx
+            .query_row(
+                "SELECT tally FROM votes WHERE network = ?1 AND fip = ?2",
+                params![ntw.name(), fip_number],
+                |row| row.get::<_, String>(0),
+            )
+            .optional()?
+        {
+            Some(t) => serde_json::from_str(&t)?,
+            None => return Err(StoreError::VoteNotActive),
+        };
+
+        let mut ballots = Vec::new();
+        {
+            let mut stmt = tx.prepare(
+                "SELECT vote FROM ballots WHERE network = ?1 AND fip = ?2 ORDER BY rowid",
+            )?;
+            let rows = stmt.query_map(params![ntw.name(), fip_number], |row| {
+                row.get::<_, String>(0)
+            })?;
+            for ballot in rows {
+                ballots.push(serde_json::from_str(&ballot?)?);
+            }
+        }
+
+        let result = build(ballots, tally)?;
+        tx.execute(
+            "INSERT INTO results (network, fip, result) VALUES (?1, ?2, ?3)",
+            params![ntw.name(), fip_number, serde_json::to_string(&result)?],
+        )?;
+        tx.commit()?;
+        Ok(result)
+    }
+
+    async fn set_final_result(
+        &self,
+        fip_number: u32,
+        ntw: Network,
+        result: FinalResult,
+    ) -> Result<FinalResult, StoreError> {
+        let mut con = self.con();
+        let tx = con.transaction_with_behavior(TransactionBehavior::Immediate)?;
+
+        tx.execute(
+            "INSERT OR IGNORE INTO results (network, fip, result)
+             SELECT ?1, ?2, ?3 FROM votes WHERE network = ?1 AND fip = ?2",
+            params![ntw.name(), fip_number, serde_json::to_string(&result)?],
+        )?;
+        let kept: Option<String> = tx
+            .query_row(
+                "SELECT result FROM results WHERE network = ?1 AND fip = ?2",
+                params![ntw.name(), fip_number],
+                |row| row.get(0),
+            )
+            .optional()?;
+
+        tx.commit()?;
+        match kept {
+            Some(r) => Ok(serde_json::from_str(&r)?),
+            None => Err(StoreError::VoteNotActive),
+        }
+    }
+
+    async fn append_event(&self, event: AuditEvent) -> Result<u64, StoreError> {
+        let con = self.con();
+        con.execute(
+            "INSERT INTO events (event) VALUES (?1)",
+            params![serde_json::to_string(&event)?],
+        )?;
+        Ok(con.last_insert_rowid() as u64)
+    }
+
+    async fn events(&self, after: u64, limit: usize) -> Result<Vec<AuditEvent>, StoreError> {
+        let con = self.con();
+        let mut stmt =
+            con.prepare("SELECT id, event FROM events WHERE id > ?1 ORDER BY id LIMIT ?2")?;
+        let rows = stmt.query_map(params![after, limit as u64], |row| {
+            Ok((row.get::<_, u64>(0)?, row.get::<_, String>(1)?))
+        })?;
+
+        let mut events = Vec::new();
+        for row in rows {
+            let (id, event) = row?;
+            events.push(AuditEvent {
+                id,
+                ..serde_json::from_str(&event)?
+            });
+        }
+        Ok(events)
+    }
+
+    async fn use_message(&self, ntw: Network, id: [u8; 32]) -> Result<(), StoreError> {
+        let inserted = self.con().execute(
+            "INSERT OR IGNORE INTO used_messages (network, id) VALUES (?1, ?2)",
+            params![ntw.name(), id],
+        )?;
+        if inserted == 0 {
+            return Err(StoreError::MessageReused);
+        }
+        Ok(())
+    }
+
+    async fn cursor(&self, name: &str) -> Result<Option<u64>, StoreError> {
+        Ok(self
+            .con()
+            .query_row(
+                "SELECT position FROM cursors WHERE name = ?1",
+                params![name],
+                |row| row.get(0),
+            )
+            .optional()?)
+    }
+
+    async fn set_cursor(&self, name: &str, position: u64) -> Result<(), StoreError> {
+        self.con().execute(
+            "INSERT INTO cursors (name, position) VALUES (?1, ?2)
+             ON CONFLICT (name) DO UPDATE SET position = excluded.position",
+            params![name, position],
+        )?;
+        Ok(())
+    }
+
+    async fn add_dead_letter(&self, letter: DeadLetter) -> Result<(), StoreError> {
+        self.con().execute(
+            "INSERT INTO dead_letters (letter) VALUES (?1)",
+            params![serde_json::to_string(&letter)?],
+        )?;
+        Ok(())
+    }
+
+    async fn dead_letters(&self) -> Result<Vec<DeadLetter>, StoreError> {
+        let con = self.con();
+        let mut stmt = con.prepare("SELECT letter FROM dead_letters ORDER BY id")?;
+        let letters = stmt
+            .query_map([], |row| row.get::<_, String>(0))?
+            .collect::<Result<Vec<_>, _>>()?;
+        letters
+            .iter()
+            .map(|letter| Ok(serde_json::from_str(letter)?))
+            .collect()
+    }
+
+    async fn import_vote(
+        &self,
+        fip_number: u32,
+        ntw: Network,
+        vote: NewVote,
+        ballots: Vec<Ballot>,
+        tally: Tally,
+    ) -> Result<(), StoreError> {
+        let mut con = self.con();
+        let tx = con.transaction_with_behavior(TransactionBehavior::Immediate)?;
+
+        insert_vote(&tx, fip_number, ntw, &vote, &tally)?;
+
+        for ballot in ballots {
+            tx.execute(
+                "INSERT OR IGNORE INTO ballots (network, fip, address, vote)
+                 VALUES (?1, ?2, ?3, ?4)",
+                params![
+                    ntw.name(),
+                    fip_number,
+                    ballot.vote.voter().as_bytes(),
+                    serde_json::to_string(&ballot)?,
+                ],
+            )?;
+        }
+
+        tx.commit()?;
+        Ok(())
+    }
+
+    async fn flush_vote(&self, fip_number: u32, ntw: Network) -> Result<(), StoreError> {
+        let mut con = self.con();
+        let tx = con.transaction_with_behavior(TransactionBehavior::Immediate)?;
+
+        let finalized: bool = tx.query_row(
+            "SELECT EXISTS(SELECT 1 FROM results WHERE network = ?1 AND fip = ?2)",
+            params![ntw.name(), fip_number],
+            |row| row.get(0),
+        )?;
+        if finalized {
+            return Err(StoreError::VoteFinalized);
+        }
+
+        for table in ["ballots", "fip_metadata", "fip_committee_members", "votes"] {
+            tx.execute(
+                &format!("DELETE FROM {table} WHERE network = ?1 AND fip = ?2"),
+                params![ntw.name(), fip_number],
+            )?;
+        }
+
+        tx.commit()?;
+        Ok(())
+    }
+
+    async fn flush_all(&self) -> Result<(), StoreError> {
+        self.con().execute_batch(
+            "DELETE FROM delegates;
+             DELETE FROM workers;
+             DELETE FROM wallets;
+             DELETE FROM wallet_owners;
+             DELETE FROM voter_networks;
+             DELETE FROM starters;
+             DELETE FROM starter_networks;
+             DELETE FROM committee_members;
+             DELETE FROM votes;
+             DELETE FROM fip_metadata;
+             DELETE FROM fip_committee_members;
+             DELETE FROM ballots;
+             DELETE FROM results;
+             DELETE FROM events;
+             DELETE FROM used_messages;
+             DELETE FROM cursors;
+             DELETE FROM dead_letters;",
+        )?;
+        Ok(())
+    }
+}
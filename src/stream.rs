@@ -0,0 +1,471 @@
+use std::{convert::Infallible, time::Duration};
+
+use actix_web::web::Bytes;
+use ethers::types::Address;
+use futures_util::{stream, Stream};
+use serde::Serialize;
+use tokio::{
+    sync::broadcast::{self, error::RecvError},
+    time::{self, Interval, MissedTickBehavior},
+};
+use utoipa::ToSchema;
+
+use crate::{
+    audit::{Action, AuditEvent},
+    results::{GovernanceRules, Outcome},
+    storage::Network,
+    store::{StoreError, VoteStore},
+};
+
+/// Events a subscriber may fall behind by before it skips the oldest
+const CHANNEL_CAPACITY: usize = 1024;
+
+/// How often idle streams send a comment, so proxies keep them open
+const KEEP_ALIVE: Duration = Duration::from_secs(15);
+
+/// A change to a vote or a registration, as pushed to streams
+#[derive(Debug, Clone, PartialEq, Serialize, ToSchema)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum StreamEvent {
+    VoteStarted {
+        network: String,
+        fip: u32,
+        /// Unix timestamps the vote started and ends at
+        started_at: u64,
+        ends_at: u64,
+    },
+    /// Carries the running count, the ballot itself is in the audit log
+    BallotCast {
+        network: String,
+        fip: u32,
+        ballot_count: u64,
+    },
+    VoteConcluded {
+        network: String,
+        fip: u32,
+        outcome: Outcome,
+    },
+    Registration {
+        network: String,
+        #[schema(value_type = String)]
+        address: Address,
+        action: Action,
+    },
+    StarterAdded {
+        network: String,
+        #[schema(value_type = String)]
+        address: Address,
+    },
+    StarterRemoved {
+        network: String,
+        #[schema(value_type = String)]
+        address: Address,
+    },
+}
+
+impl StreamEvent {
+    /// Name of the event in the `event:` field of the stream
+    pub fn kind(&self) -> &'static str {
+        match self {
+            StreamEvent::VoteStarted { .. } => "vote_started",
+            StreamEvent::BallotCast { .. } => "ballot_cast",
+            StreamEvent::VoteConcluded { .. } => "vote_concluded",
+            StreamEvent::Registration { .. } => "registration",
+            StreamEvent::StarterAdded { .. } => "starter_added",
+            StreamEvent::StarterRemoved { .. } => "starter_removed",
+        }
+    }
+
+    pub fn network(&self) -> &str {
+        match self {
+            StreamEvent::VoteStarted { network, .. }
+            | StreamEvent::BallotCast { network, .. }
+            | StreamEvent::VoteConcluded { network, .. }
+            | StreamEvent::Registration { network, .. }
+            | StreamEvent::StarterAdded { network, .. }
+            | StreamEvent::StarterRemoved { network, .. } => network,
+        }
+    }
+
+    /// The FIP the event is about, registrations and starters are about none
+    pub fn fip(&self) -> Option<u32> {
+        match self {
+            StreamEvent::VoteStarted { fip, .. }
+            | StreamEvent::BallotCast { fip, .. }
+            | StreamEvent::VoteConcluded { fip, .. } => Some(*fip),
+            StreamEvent::Registration { .. }
+            | StreamEvent::StarterAdded { .. }
+            | StreamEvent::StarterRemoved { .. } => None,
+        }
+    }
+
+    /// The event as a Server-Sent Events message
+    fn to_sse(&self) -> Bytes {
+        let data = serde_json::to_string(self).unwrap_or_default();
+        Bytes::from(format!("event: {}\ndata: {}\n\n", self.kind(), data))
+    }
+}
+
+/// Which events a stream receives, every one unless narrowed
+#[derive(Debug, Clone, Copy, Default)]
+pub struct StreamFilter {
+    pub network: Option<Network>,
+    /// Registrations and starters are left out once a FIP is picked
+    pub fip: Option<u32>,
+}
+
+impl StreamFilter {
+    fn matches(&self, event: &StreamEvent) -> bool {
+        self.network.is_none_or(|ntw| ntw.name() == event.network())
+            && self.fip.is_none_or(|fip| event.fip() == Some(fip))
+    }
+}
+
+/// Fans vote and registration events out to every open stream
+#[derive(Clone)]
+pub struct Broadcaster {
+    sender: broadcast::Sender<StreamEvent>,
+    /// Announced with started votes as their end
+    vote_length: u64,
+}
+
+impl Broadcaster {
+    pub fn new(vote_length: u64) -> Self {
+        let (sender, _) = broadcast::channel(CHANNEL_CAPACITY);
+        Self {
+            sender,
+            vote_length,
+        }
+    }
+
+    pub fn publish(&self, event: StreamEvent) {
+        // Nobody listening is not an error
+        let _ = self.sender.send(event);
+    }
+
+    pub fn subscribe(&self) -> broadcast::Receiver<StreamEvent> {
+        self.sender.subscribe()
+    }
+
+    /// Announces what a successful request changed, read from its audit event
+    pub async fn announce(&self, store: &dyn VoteStore, event: &AuditEvent) {
+        if !(200..300).contains(&event.status) {
+            return;
+        }
+        let ntw = match event.network.as_deref().and_then(Network::from_name) {
+            Some(ntw) => ntw,
+            None => return,
+        };
+        let network = ntw.name().to_string();
+
+        let stream_event = match (event.action, event.fip, event.signer) {
+            (Action::StartVote, Some(fip), _) => {
+                let started_at = match store.vote_start(fip, ntw).await {
+                    Ok(Some(started_at)) => started_at,
+                    _ => event.timestamp,
+                };
+                StreamEvent::VoteStarted {
+                    network,
+                    fip,
+                    started_at,
+                    ends_at: started_at + self.vote_length,
+                }
+            }
+            (Action::Vote, Some(fip), _) => return self.announce_ballot(store, fip, ntw).await,
+            (Action::RegisterStarter, ..) => match event.subject {
+                Some(address) => StreamEvent::StarterAdded { network, address },
+                None => return,
+            },
+            (Action::RemoveStarter, ..) => match event.subject {
+                Some(address) => StreamEvent::StarterRemoved { network, address },
+                None => return,
+            },
+            (
+                action @ (Action::RegisterVoter
+                | Action::UnregisterVoter
+                | Action::RegisterTokenHolder
+                | Action::UnregisterTokenHolder
+                | Action::RegisterClient
+                | Action::UnregisterClient),
+                _,
+                Some(address),
+            ) => StreamEvent::Registration {
+                network,
+                address,
+                action,
+            },
+            _ => return,
+        };
+        self.publish(stream_event);
+    }
+
+    /// Announces the ballot count of the vote on the FIP after a ballot was cast
+    pub async fn announce_ballot(&self, store: &dyn VoteStore, fip: u32, ntw: Network) {
+        if let Ok(ballot_count) = store.ballot_count(fip, ntw).await {
+            self.publish(StreamEvent::BallotCast {
+                network: ntw.name().to_string(),
+                fip,
+                ballot_count,
+            });
+        }
+    }
+
+    /// Finalizes and announces the votes on the network that ended after
+    /// `since` and no later than `until`, both unix timestamps
+    pub async fn announce_conclusions(
+        &self,
+        store: &dyn VoteStore,
+        ntw: Network,
+        rules: &GovernanceRules,
+        since: u64,
+        until: u64,
+    ) -> Result<(), StoreError> {
+        let results = store
+            .finalize_ended(ntw, rules, self.vote_length, since, until)
+            .await?;
+        for (fip, result) in results {
+            self.publish(StreamEvent::VoteConcluded {
+                network: ntw.name().to_string(),
+                fip,
+                outcome: result.outcome,
+            });
+        }
+        Ok(())
+    }
+
+    /// Announces the votes on the network that ended since the last call,
+    /// including calls before a restart, up to the unix timestamp `now`
+    ///
+    /// The first call ever only records where to start from.
+    pub async fn announce_concluded(
+        &self,
+        store: &dyn VoteStore,
+        ntw: Network,
+        rules: &GovernanceRules,
+        now: u64,
+    ) -> Result<(), StoreError> {
+        let cursor = format!("announced:{}", ntw.name());
+        if let Some(since) = store.cursor(&cursor).await? {
+            self.announce_conclusions(store, ntw, rules, since, now)
+                .await?;
+        }
+        store.set_cursor(&cursor, now).await
+    }
+
+    /// Server-Sent Events of everything published from now on that passes
+    /// the filter
+    pub fn stream(&self, filter: StreamFilter) -> impl Stream<Item = Result<Bytes, Infallible>> {
+        let mut keep_alive = time::interval_at(time::Instant::now() + KEEP_ALIVE, KEEP_ALIVE);
+        keep_alive.set_missed_tick_behavior(MissedTickBehavior::Delay);
+
+        let opened = Bytes::from_static(b": connected\n\n");
+        let events = stream::unfold(
+            (self.sender.subscribe(), keep_alive),
+            move |(mut receiver, mut keep_alive)| async move {
+                let message = next_message(&mut receiver, &mut keep_alive, filter).await?;
+                Some((Ok(message), (receiver, keep_alive)))
+            },
+        );
+        futures_util::StreamExt::chain(stream::iter([Ok(opened)]), events)
+    }
+}
+
+/// Waits for the next event passing the filter or a keep alive, `None`
+/// once the broadcaster is gone
+async fn next_message(
+    receiver: &mut broadcast::Receiver<StreamEvent>,
+    keep_alive: &mut Interval,
+    filter: StreamFilter,
+) -> Option<Bytes> {
+    loop {
+        tokio::select! {
+            event = receiver.recv() => match event {
+                Ok(event) if filter.matches(&event) => return Some(event.to_sse()),
+                Ok(_) => (),
+                // Slow subscribers miss the oldest events rather than block everyone
+                Err(RecvError::Lagged(missed)) => {
+                    return Some(Bytes::from(format!(": missed {} events\n\n", missed)))
+                }
+                Err(RecvError::Closed) => return None,
+            },
+            _ = keep_alive.tick() => return Some(Bytes::from_static(b": keep-alive\n\n")),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{collections::BTreeMap, sync::Arc};
+
+    use futures_util::StreamExt;
+
+    use super::*;
+    use crate::{
+        messages::vote_start::FipMetadata,
+        store::{memory::Memory, NewVote},
+    };
+
+    fn calibration() -> Network {
+        Network::from_name("calibration").unwrap()
+    }
+
+    fn ballot_cast(network: &str, fip: u32) -> StreamEvent {
+        StreamEvent::BallotCast {
+            network: network.to_string(),
+            fip,
+            ballot_count: 1,
+        }
+    }
+
+    #[tokio::test]
+    async fn stream_filters_events() {
+        let feed = Broadcaster::new(60);
+        let filter = StreamFilter {
+            network: Some(calibration()),
+            fip: Some(5),
+        };
+        let mut stream = Box::pin(feed.stream(filter));
+        assert_eq!(
+            stream.next().await.unwrap().unwrap(),
+            Bytes::from_static(b": connected\n\n")
+        );
+
+        feed.publish(ballot_cast("mainnet", 5));
+        feed.publish(ballot_cast("calibration", 6));
+        feed.publish(StreamEvent::Registration {
+            network: "calibration".to_string(),
+            address: Address::zero(),
+            action: Action::RegisterVoter,
+        });
+        feed.publish(ballot_cast("calibration", 5));
+
+        let message = stream.next().await.unwrap().unwrap();
+        assert_eq!(
+            message,
+            Bytes::from(
+                "event: ballot_cast\ndata: {\"type\":\"ballot_cast\",\"network\":\"calibration\",\"fip\":5,\"ballot_count\":1}\n\n"
+            )
+        );
+    }
+
+    #[tokio::test]
+    async fn stream_announces() {
+        let store: Arc<dyn VoteStore> = Arc::new(Memory::default());
+        let feed = Broadcaster::new(60);
+        let mut receiver = feed.sender.subscribe();
+
+        let start = NewVote {
+            timestamp: 100,
+            snapshot: 0,
+            committees: Vec::new(),
+            members: BTreeMap::new(),
+            metadata: FipMetadata::default(),
+        };
+        store.create_vote(5u32, calibration(), start).await.unwrap();
+
+        let mut event = AuditEvent::new(Action::StartVote, b"{}");
+        event.network(calibration());
+        event.fip(5);
+        event.status = 200;
+        feed.announce(&*store, &event).await;
+        assert_eq!(
+            receiver.try_recv().unwrap(),
+            StreamEvent::VoteStarted {
+                network: "calibration".to_string(),
+                fip: 5,
+                started_at: 100,
+                ends_at: 160,
+            }
+        );
+
+        // Rejected requests changed nothing
+        event.status = 409;
+        feed.announce(&*store, &event).await;
+        assert!(receiver.try_recv().is_err());
+
+        let mut event = AuditEvent::new(Action::RegisterClient, b"{}");
+        event.network(calibration());
+        event.signer(Address::zero());
+        event.status = 200;
+        feed.announce(&*store, &event).await;
+        assert!(matches!(
+            receiver.try_recv().unwrap(),
+            StreamEvent::Registration {
+                action: Action::RegisterClient,
+                ..
+            }
+        ));
+
+        // Starter changes name the starter rather than the signer
+        let mut event = AuditEvent::new(Action::RemoveStarter, b"{}");
+        event.network(calibration());
+        event.signer(Address::zero());
+        event.subject(Address::repeat_byte(1));
+        event.status = 200;
+        feed.announce(&*store, &event).await;
+        assert_eq!(
+            receiver.try_recv().unwrap(),
+            StreamEvent::StarterRemoved {
+                network: "calibration".to_string(),
+                address: Address::repeat_byte(1),
+            }
+        );
+
+        // Only announced once, in the window the vote ended in
+        let rules = GovernanceRules::default();
+        for (since, until) in [(0, 159), (160, 200)] {
+            feed.announce_conclusions(&*store, calibration(), &rules, since, until)
+                .await
+                .unwrap();
+        }
+        assert!(receiver.try_recv().is_err());
+        feed.announce_conclusions(&*store, calibration(), &rules, 159, 160)
+            .await
+            .unwrap();
+        assert!(matches!(
+            receiver.try_recv().unwrap(),
+            StreamEvent::VoteConcluded { fip: 5, .. }
+        ));
+        assert!(store
+            .final_result(5u32, calibration())
+            .await
+            .unwrap()
+            .is_some());
+    }
+
+    #[tokio::test]
+    async fn stream_announces_after_restart() {
+        let store: Arc<dyn VoteStore> = Arc::new(Memory::default());
+        let rules = GovernanceRules::default();
+        let start = NewVote {
+            timestamp: 100,
+            snapshot: 0,
+            committees: Vec::new(),
+            members: BTreeMap::new(),
+            metadata: FipMetadata::default(),
+        };
+        store.create_vote(5u32, calibration(), start).await.unwrap();
+
+        let feed = Broadcaster::new(60);
+        let mut receiver = feed.sender.subscribe();
+        feed.announce_concluded(&*store, calibration(), &rules, 120)
+            .await
+            .unwrap();
+        assert!(receiver.try_recv().is_err());
+
+        // The vote ends while no server runs, the next one still announces it
+        let feed = Broadcaster::new(60);
+        let mut receiver = feed.sender.subscribe();
+        feed.announce_concluded(&*store, calibration(), &rules, 300)
+            .await
+            .unwrap();
+        assert!(matches!(
+            receiver.try_recv().unwrap(),
+            StreamEvent::VoteConcluded { fip: 5, .. }
+        ));
+        feed.announce_concluded(&*store, calibration(), &rules, 400)
+            .await
+            .unwrap();
+        assert!(receiver.try_recv().is_err());
+    }
+}
@@ -0,0 +1,695 @@
+use std::{sync::Arc, time};
+
+use ethers::types::Address;
+use futures_util::future::join_all;
+use hmac::{Hmac, Mac};
+use reqwest::{Client, StatusCode};
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+use thiserror::Error;
+use url::Url;
+
+use crate::{
+    audit::{Action, AuditEvent, MAX_PAGE_SIZE},
+    results::GovernanceRules,
+    storage::Network,
+    store::{FinalResult, FipInfo, StoreError, VoteStore},
+};
+
+/// Deliveries are given up after this many failed attempts
+const ATTEMPTS: u32 = 5;
+
+/// Wait before the first retry, doubled after each one
+const BACKOFF: time::Duration = time::Duration::from_secs(1);
+
+/// How long a receiver may take to answer one attempt
+const TIMEOUT: time::Duration = time::Duration::from_secs(10);
+
+/// How often the audit log and the ended votes are checked for new events
+const POLL: time::Duration = time::Duration::from_secs(5);
+
+/// A URL notified of governance events on one network, as configured
+#[derive(Clone, Debug, PartialEq, Deserialize)]
+pub struct WebhookConfig {
+    /// Name of a configured network
+    pub network: String,
+    pub url: Url,
+    /// Key the payloads are signed with, shared with the receiver
+    pub secret: String,
+}
+
+#[derive(Debug, Error)]
+pub enum WebhookError {
+    #[error("webhook {0} is for unknown network {1}")]
+    UnknownNetwork(Url, String),
+    #[error("webhook {0} has no secret")]
+    MissingSecret(Url),
+}
+
+/// What a webhook is notified of
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(tag = "event", content = "data", rename_all = "snake_case")]
+pub enum WebhookEvent {
+    VoteStarted(FipInfo),
+    VoteConcluded {
+        fip_number: u32,
+        result: FinalResult,
+    },
+    StarterAdded {
+        address: Address,
+    },
+    StarterRemoved {
+        address: Address,
+    },
+}
+
+impl WebhookEvent {
+    /// Name of the event, as sent in the `event` field
+    pub fn kind(&self) -> &'static str {
+        match self {
+            WebhookEvent::VoteStarted(_) => "vote_started",
+            WebhookEvent::VoteConcluded { .. } => "vote_concluded",
+            WebhookEvent::StarterAdded { .. } => "starter_added",
+            WebhookEvent::StarterRemoved { .. } => "starter_removed",
+        }
+    }
+}
+
+/// Body POSTed to webhooks
+#[derive(Debug, Serialize)]
+pub struct WebhookPayload {
+    pub network: String,
+    /// Unix timestamp the event happened at, the same on every retry
+    pub timestamp: u64,
+    #[serde(flatten)]
+    pub event: WebhookEvent,
+}
+
+/// A delivery given up on, kept so it can be looked into or sent again by hand
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct DeadLetter {
+    pub url: String,
+    pub network: String,
+    /// Name of the event, as sent in the `X-Webhook-Event` header
+    pub event: String,
+    /// The payload as it was posted
+    pub body: String,
+    /// Unix timestamp the delivery was given up at
+    pub failed_at: u64,
+}
+
+#[derive(Clone, Debug)]
+struct Webhook {
+    network: Network,
+    url: Url,
+    secret: String,
+}
+
+/// Delivers governance events to the configured webhooks
+///
+/// Payloads are signed with HMAC-SHA256 under the secret of the webhook,
+/// sent hex encoded as `X-Webhook-Signature: sha256=<hex>`. Failed deliveries
+/// are retried with exponential backoff, those still failing or refused are
+/// moved to the dead letters of the store.
+///
+/// Events are read from the audit log and the final results, so none are
+/// missed while the server is down or busy.
+#[derive(Clone, Debug)]
+pub struct Webhooks {
+    hooks: Vec<Webhook>,
+    client: Client,
+    /// Read to describe started votes
+    vote_length: u64,
+    backoff: time::Duration,
+}
+
+/// `sha256=` and the hex encoded HMAC-SHA256 of the body under the secret
+pub fn sign(secret: &str, body: &[u8]) -> String {
+    // HMAC takes keys of any length
+    let mut mac = Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("any key length");
+    mac.update(body);
+    format!("sha256={}", hex::encode(mac.finalize().into_bytes()))
+}
+
+impl Webhooks {
+    pub fn new(configs: Vec<WebhookConfig>, vote_length: u64) -> Result<Self, WebhookError> {
+        let mut hooks = Vec::new();
+        for config in configs {
+            let network = match Network::from_name(&config.network) {
+                Some(ntw) => ntw,
+                None => return Err(WebhookError::UnknownNetwork(config.url, config.network)),
+            };
+            if config.secret.is_empty() {
+                return Err(WebhookError::MissingSecret(config.url));
+            }
+            hooks.push(Webhook {
+                network,
+                url: config.url,
+                secret: config.secret,
+            });
+        }
+
+        let client = Client::builder()
+            .timeout(TIMEOUT)
+            .build()
+            .unwrap_or_default();
+        Ok(Self {
+            hooks,
+            client,
+            vote_length,
+            backoff: BACKOFF,
+        })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.hooks.is_empty()
+    }
+
+    /// Delivers the event to the webhook, moving it to the dead letters if
+    /// the webhook does not accept it, at the unix timestamp `now`
+    async fn send(
+        &self,
+        store: &dyn VoteStore,
+        hook: &Webhook,
+        timestamp: u64,
+        event: WebhookEvent,
+        now: u64,
+    ) -> Result<(), StoreError> {
+        let payload = WebhookPayload {
+            network: hook.network.name().to_string(),
+            timestamp,
+            event,
+        };
+        let body = match serde_json::to_vec(&payload) {
+            Ok(body) => body,
+            Err(e) => {
+                println!("Error serializing webhook payload: {}", e);
+                return Ok(());
+            }
+        };
+        let kind = payload.event.kind();
+        if self.deliver(hook, kind, &body).await {
+            return Ok(());
+        }
+
+        store
+            .add_dead_letter(DeadLetter {
+                url: hook.url.to_string(),
+                network: payload.network,
+                event: kind.to_string(),
+                body: String::from_utf8_lossy(&body).into_owned(),
+                failed_at: now,
+            })
+            .await?;
+        println!(
+            "Moved {} for webhook {} to the dead letters",
+            kind, hook.url
+        );
+        Ok(())
+    }
+
+    /// Posts the body until the webhook accepts it, returns false if the
+    /// webhook refused it or kept failing
+    async fn deliver(&self, hook: &Webhook, kind: &str, body: &[u8]) -> bool {
+        let signature = sign(&hook.secret, body);
+        let mut backoff = self.backoff;
+
+        for attempt in 1..=ATTEMPTS {
+            let res = self
+                .client
+                .post(hook.url.clone())
+                .header("Content-Type", "application/json")
+                .header("X-Webhook-Event", kind)
+                .header("X-Webhook-Signature", &signature)
+                .body(body.to_vec())
+                .send()
+                .await;
+
+            let retry = match res {
+                Ok(res) if res.status().is_success() => {
+                    println!("Delivered {} to webhook {}", kind, hook.url);
+                    return true;
+                }
+                Ok(res) => {
+                    let status = res.status();
+                    println!(
+                        "Webhook {} answered {} with {} (attempt {} of {})",
+                        hook.url, kind, status, attempt, ATTEMPTS
+                    );
+                    // Other client errors will not go away by retrying
+                    status.is_server_error()
+                        || status == StatusCode::REQUEST_TIMEOUT
+                        || status == StatusCode::TOO_MANY_REQUESTS
+                }
+                Err(e) => {
+                    println!(
+                        "Error delivering {} to webhook {} (attempt {} of {}): {}",
+                        kind, hook.url, attempt, ATTEMPTS, e
+                    );
+                    true
+                }
+            };
+            if !retry {
+                println!("Gave up delivering {} to webhook {}", kind, hook.url);
+                return false;
+            }
+            if attempt < ATTEMPTS {
+                tokio::time::sleep(backoff).await;
+                backoff *= 2;
+            }
+        }
+
+        println!("Could not deliver {} to webhook {}", kind, hook.url);
+        false
+    }
+
+    /// Delivers what the audit log and the final results record to every
+    /// webhook, each at its own pace, for as long as the server runs
+    pub async fn run(self, store: Arc<dyn VoteStore>, rules: GovernanceRules) {
+        let follows = self
+            .hooks
+            .iter()
+            .map(|hook| self.follow(&*store, hook, &rules));
+        join_all(follows).await;
+    }
+
+    async fn follow(&self, store: &dyn VoteStore, hook: &Webhook, rules: &GovernanceRules) {
+        let mut interval = tokio::time::interval(POLL);
+        loop {
+            interval.tick().await;
+            let now = time::SystemTime::now()
+                .duration_since(time::UNIX_EPOCH)
+                .map_or(0, |d| d.as_secs());
+            if let Err(e) = self.deliver_pending(store, hook, rules, now).await {
+                println!("Error reading events for webhook {}: {}", hook.url, e);
+            }
+        }
+    }
+
+    /// Delivers the events appended to the audit log and the votes that
+    /// ended since the last delivery to the webhook, up to the unix
+    /// timestamp `now`
+    ///
+    /// Where the webhook got to is kept in the store, so a restart resumes
+    /// there. An event the webhook does not accept is moved to the dead
+    /// letters rather than holding up the ones after it, the first call ever
+    /// only records where to start.
+    async fn deliver_pending(
+        &self,
+        store: &dyn VoteStore,
+        hook: &Webhook,
+        rules: &GovernanceRules,
+        now: u64,
+    ) -> Result<(), StoreError> {
+        let ntw = hook.network;
+
+        let cursor = format!("webhook-events:{}:{}", ntw.name(), hook.url);
+        let mut after = match store.cursor(&cursor).await? {
+            Some(after) => after,
+            None => {
+                let end = log_end(store).await?;
+                store.set_cursor(&cursor, end).await?;
+                end
+            }
+        };
+        loop {
+            let events = store.events(after, MAX_PAGE_SIZE).await?;
+            for event in &events {
+                if let Some(webhook_event) = self.webhook_event(store, ntw, event).await? {
+                    self.send(store, hook, event.timestamp, webhook_event, now)
+                        .await?;
+                }
+                after = event.id;
+                store.set_cursor(&cursor, after).await?;
+            }
+            if events.len() < MAX_PAGE_SIZE {
+                break;
+            }
+        }
+
+        let cursor = format!("webhook-concluded:{}:{}", ntw.name(), hook.url);
+        if let Some(since) = store.cursor(&cursor).await? {
+            let results = store
+                .finalize_ended(ntw, rules, self.vote_length, since, now)
+                .await?;
+            for (fip_number, result) in results {
+                let timestamp = result.finalized_at;
+                let event = WebhookEvent::VoteConcluded { fip_number, result };
+                self.send(store, hook, timestamp, event, now).await?;
+            }
+        }
+        store.set_cursor(&cursor, now).await
+    }
+
+    /// What the webhooks of the network are told about the audit event, if
+    /// anything
+    async fn webhook_event(
+        &self,
+        store: &dyn VoteStore,
+        ntw: Network,
+        event: &AuditEvent,
+    ) -> Result<Option<WebhookEvent>, StoreError> {
+        if !(200..300).contains(&event.status) || event.network.as_deref() != Some(ntw.name()) {
+            return Ok(None);
+        }
+
+        let event = match (event.action, event.fip) {
+            (Action::StartVote, Some(fip)) => store
+                .fip_info(fip, ntw, self.vote_length)
+                .await?
+                .map(WebhookEvent::VoteStarted),
+            (Action::RegisterStarter, _) => event
+                .subject
+                .map(|address| WebhookEvent::StarterAdded { address }),
+            (Action::RemoveStarter, _) => event
+                .subject
+                .map(|address| WebhookEvent::StarterRemoved { address }),
+            _ => None,
+        };
+        Ok(event)
+    }
+}
+
+/// Id of the last event of the audit log, 0 while it is empty
+async fn log_end(store: &dyn VoteStore) -> Result<u64, StoreError> {
+    let mut end = 0;
+    loop {
+        let events = store.events(end, MAX_PAGE_SIZE).await?;
+        match events.last() {
+            Some(last) => end = last.id,
+            None => return Ok(end),
+        }
+        if events.len() < MAX_PAGE_SIZE {
+            return Ok(end);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{collections::BTreeMap, sync::Mutex};
+
+    use actix_web::{web, App, HttpRequest, HttpResponse, HttpServer};
+    use serde_json::Value;
+
+    use super::*;
+    use crate::{
+        messages::vote_start::FipMetadata,
+        store::{memory::Memory, NewVote},
+    };
+
+    /// Requests a receiver got, as `(event, signature, body)`
+    type Received = Arc<Mutex<Vec<(String, String, Vec<u8>)>>>;
+
+    /// Starts a local receiver answering with the statuses in turn, then 200
+    fn receiver(statuses: Vec<u16>) -> (Url, Received) {
+        let received: Received = Arc::default();
+        let statuses = Arc::new(Mutex::new(statuses.into_iter()));
+
+        let log = received.clone();
+        let server = HttpServer::new(move || {
+            let log = log.clone();
+            let statuses = statuses.clone();
+            App::new().default_service(web::to(move |req: HttpRequest, body: web::Bytes| {
+                let log = log.clone();
+                let statuses = statuses.clone();
+                async move {
+                    let header = |name| {
+                        req.headers()
+                            .get(name)
+                            .and_then(|v| v.to_str().ok())
+                            .unwrap_or_default()
+                            .to_string()
+                    };
+                    log.lock().unwrap().push((
+                        header("X-Webhook-Event"),
+                        header("X-Webhook-Signature"),
+                        body.to_vec(),
+                    ));
+                    let status = statuses.lock().unwrap().next().unwrap_or(200);
+                    HttpResponse::build(StatusCode::from_u16(status).unwrap()).finish()
+                }
+            }))
+        })
+        .workers(1)
+        .bind(("127.0.0.1", 0))
+        .unwrap();
+
+        let url = Url::parse(&format!("http://{}/hook", server.addrs()[0])).unwrap();
+        actix_web::rt::spawn(server.run());
+        (url, received)
+    }
+
+    fn webhooks(hooks: &[(&str, &Url)]) -> Webhooks {
+        let configs = hooks
+            .iter()
+            .map(|(network, url)| WebhookConfig {
+                network: network.to_string(),
+                url: (*url).clone(),
+                secret: "secret".to_string(),
+            })
+            .collect();
+        let mut webhooks = Webhooks::new(configs, 60).unwrap();
+        webhooks.backoff = time::Duration::from_millis(10);
+        webhooks
+    }
+
+    #[actix_web::test]
+    async fn webhooks_deliver() {
+        let store = Memory::default();
+        let (url, received) = receiver(vec![500, 503]);
+        let hooks = webhooks(&[("calibration", &url)]);
+
+        let address = Address::repeat_byte(1);
+        let event = WebhookEvent::StarterAdded { address };
+        hooks
+            .send(&store, &hooks.hooks[0], 1000, event, 1010)
+            .await
+            .unwrap();
+        assert!(store.dead_letters().await.unwrap().is_empty());
+
+        // Retried until accepted, signed the same each time
+        let received = received.lock().unwrap().clone();
+        assert_eq!(received.len(), 3);
+        let (event, signature, body) = &received[2];
+        assert_eq!(event, "starter_added");
+        assert_eq!(*signature, sign("secret", body));
+        assert!(received.iter().all(|r| r == &received[2]));
+
+        let payload: Value = serde_json::from_slice(body).unwrap();
+        assert_eq!(payload["network"], "calibration");
+        assert_eq!(payload["event"], "starter_added");
+        assert_eq!(payload["timestamp"], 1000);
+        assert_eq!(payload["data"]["address"], format!("{:?}", address));
+
+        // Rejected payloads are not sent again but kept as dead letters
+        let (url, received) = receiver(vec![400]);
+        let hooks = webhooks(&[("calibration", &url)]);
+        let event = WebhookEvent::StarterRemoved { address };
+        hooks
+            .send(&store, &hooks.hooks[0], 1000, event, 1010)
+            .await
+            .unwrap();
+        let received = received.lock().unwrap().clone();
+        assert_eq!(received.len(), 1);
+        let letters = store.dead_letters().await.unwrap();
+        assert_eq!(
+            letters,
+            vec![DeadLetter {
+                url: url.to_string(),
+                network: "calibration".to_string(),
+                event: "starter_removed".to_string(),
+                body: String::from_utf8(received[0].2.clone()).unwrap(),
+                failed_at: 1010,
+            }]
+        );
+
+        assert!(matches!(
+            Webhooks::new(
+                vec![WebhookConfig {
+                    network: "nope".to_string(),
+                    url: url.clone(),
+                    secret: "secret".to_string(),
+                }],
+                60
+            ),
+            Err(WebhookError::UnknownNetwork(..))
+        ));
+    }
+
+    /// Waits until the receiver got `len` requests, returns their payloads
+    async fn wait_for(received: &Received, len: usize) -> Vec<Value> {
+        for _ in 0..100 {
+            if received.lock().unwrap().len() >= len {
+                break;
+            }
+            tokio::time::sleep(time::Duration::from_millis(10)).await;
+        }
+        let received = received.lock().unwrap();
+        received
+            .iter()
+            .map(|(_, _, body)| serde_json::from_slice(body).unwrap())
+            .collect()
+    }
+
+    #[actix_web::test]
+    async fn webhooks_follow_log() {
+        let calibration = Network::from_name("calibration").unwrap();
+        let store: Arc<dyn VoteStore> = Arc::new(Memory::default());
+        let rules = GovernanceRules::default();
+        let metadata = FipMetadata {
+            title: Some("Sector duration multiplier".to_string()),
+            ..FipMetadata::default()
+        };
+        let start = NewVote {
+            timestamp: 100,
+            snapshot: 0,
+            committees: Vec::new(),
+            members: BTreeMap::new(),
+            metadata,
+        };
+        store.create_vote(5u32, calibration, start).await.unwrap();
+        let append = |action, fip, payload: String, status| {
+            let mut event = AuditEvent::new(action, payload.as_bytes());
+            event.network(calibration);
+            if let Some(fip) = fip {
+                event.fip(fip);
+            }
+            event.status = status;
+            store.append_event(event)
+        };
+
+        // Events from before the webhook was first run are not delivered
+        append(Action::StartVote, Some(5), "{}".to_string(), 200)
+            .await
+            .unwrap();
+        let (url, received) = receiver(vec![500; ATTEMPTS as usize]);
+        let hooks = webhooks(&[("calibration", &url)]);
+        let hook = &hooks.hooks[0];
+        hooks
+            .deliver_pending(&*store, hook, &rules, 120)
+            .await
+            .unwrap();
+        assert!(received.lock().unwrap().is_empty());
+
+        append(Action::Vote, Some(5), "{}".to_string(), 200)
+            .await
+            .unwrap();
+        append(Action::StartVote, Some(5), "{}".to_string(), 409)
+            .await
+            .unwrap();
+        append(Action::StartVote, Some(5), "{}".to_string(), 200)
+            .await
+            .unwrap();
+        // Starters changed through the API or an admin command, only those
+        // of the webhook's network are delivered
+        let mainnet = Network::from_name("mainnet").unwrap();
+        for ntw in [mainnet, calibration] {
+            let command = format!("starters add {:?}", Address::repeat_byte(1));
+            let mut grant = AuditEvent::admin(Action::RegisterStarter, ntw, command);
+            grant.subject(Address::repeat_byte(1));
+            store.append_event(grant).await.unwrap();
+        }
+
+        // An event the webhook keeps failing on is moved to the dead
+        // letters, the ones after it are still delivered
+        hooks
+            .deliver_pending(&*store, hook, &rules, 130)
+            .await
+            .unwrap();
+        let payloads = wait_for(&received, 6).await;
+        assert_eq!(payloads.len(), 6);
+        assert_eq!(payloads[4]["event"], "vote_started");
+        assert_eq!(payloads[4]["data"]["fip_number"], 5);
+        assert_eq!(
+            payloads[4]["data"]["metadata"]["title"],
+            "Sector duration multiplier"
+        );
+        assert_eq!(payloads[5]["event"], "starter_added");
+        assert_eq!(
+            payloads[5]["data"]["address"],
+            format!("{:?}", Address::repeat_byte(1))
+        );
+        let letters = store.dead_letters().await.unwrap();
+        assert_eq!(letters.len(), 1);
+        assert_eq!(letters[0].event, "vote_started");
+        assert_eq!(letters[0].failed_at, 130);
+
+        // A restarted server resumes where the last one stopped
+        let command = format!("starters remove {:?}", Address::repeat_byte(1));
+        let mut revocation = AuditEvent::admin(Action::RemoveStarter, calibration, command);
+        revocation.subject(Address::repeat_byte(1));
+        store.append_event(revocation).await.unwrap();
+        let hooks = webhooks(&[("calibration", &url)]);
+        let hook = &hooks.hooks[0];
+        hooks
+            .deliver_pending(&*store, hook, &rules, 140)
+            .await
+            .unwrap();
+        let payloads = wait_for(&received, 7).await;
+        assert_eq!(payloads.len(), 7);
+        assert_eq!(payloads[6]["event"], "starter_removed");
+
+        // The vote ended while no server ran
+        hooks
+            .deliver_pending(&*store, hook, &rules, 300)
+            .await
+            .unwrap();
+        hooks
+            .deliver_pending(&*store, hook, &rules, 400)
+            .await
+            .unwrap();
+        let payloads = wait_for(&received, 8).await;
+        assert_eq!(payloads.len(), 8);
+        assert_eq!(payloads[7]["event"], "vote_concluded");
+        assert_eq!(payloads[7]["data"]["fip_number"], 5);
+    }
+
+    #[actix_web::test]
+    async fn webhooks_dead_letters() {
+        let calibration = Network::from_name("calibration").unwrap();
+        let store: Arc<dyn VoteStore> = Arc::new(Memory::default());
+        let rules = GovernanceRules::default();
+        let (url, received) = receiver(vec![500; 100]);
+        let hooks = webhooks(&[("calibration", &url)]);
+        let hook = &hooks.hooks[0];
+        hooks
+            .deliver_pending(&*store, hook, &rules, 100)
+            .await
+            .unwrap();
+
+        for byte in 1..=3 {
+            let command = format!("starters add {:?}", Address::repeat_byte(byte));
+            let mut grant = AuditEvent::admin(Action::RegisterStarter, calibration, command);
+            grant.subject(Address::repeat_byte(byte));
+            store.append_event(grant).await.unwrap();
+        }
+
+        // Every event is tried in full, then given up on
+        hooks
+            .deliver_pending(&*store, hook, &rules, 110)
+            .await
+            .unwrap();
+        assert_eq!(received.lock().unwrap().len(), 3 * ATTEMPTS as usize);
+        let letters = store.dead_letters().await.unwrap();
+        assert_eq!(letters.len(), 3);
+        for (byte, letter) in (1..).zip(&letters) {
+            assert_eq!(letter.url, url.to_string());
+            assert_eq!(letter.network, "calibration");
+            assert_eq!(letter.event, "starter_added");
+            let payload: Value = serde_json::from_str(&letter.body).unwrap();
+            assert_eq!(
+                payload["data"]["address"],
+                format!("{:?}", Address::repeat_byte(byte))
+            );
+        }
+
+        // and not tried again
+        hooks
+            .deliver_pending(&*store, hook, &rules, 120)
+            .await
+            .unwrap();
+        assert_eq!(received.lock().unwrap().len(), 3 * ATTEMPTS as usize);
+        assert_eq!(store.dead_letters().await.unwrap().len(), 3);
+    }
+}